@@ -17,6 +17,8 @@ pub struct Model {
     pub discord_id: i64,
     pub fk_guild_id: Option<i64>,
     pub name: String,
+    pub idempotency_key: Option<String>,
+    pub kind: Option<i16>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveColumn)]
@@ -24,6 +26,8 @@ pub enum Column {
     DiscordId,
     FkGuildId,
     Name,
+    IdempotencyKey,
+    Kind,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DerivePrimaryKey)]
@@ -50,6 +54,8 @@ impl ColumnTrait for Column {
             Self::DiscordId => ColumnType::BigInteger.def(),
             Self::FkGuildId => ColumnType::BigInteger.def().null(),
             Self::Name => ColumnType::String(None).def(),
+            Self::IdempotencyKey => ColumnType::String(None).def().null().unique(),
+            Self::Kind => ColumnType::SmallInteger.def().null(),
         }
     }
 }
@@ -16,12 +16,22 @@ impl EntityName for Entity {
 pub struct Model {
     pub id_uuid: Uuid,
     pub payload: Json,
+    pub payload_version: i32,
+    pub created_at: DateTimeWithTimeZone,
+    pub sent: bool,
+    pub channel_id: Option<i64>,
+    pub message_id: Option<i64>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveColumn)]
 pub enum Column {
     IdUuid,
     Payload,
+    PayloadVersion,
+    CreatedAt,
+    Sent,
+    ChannelId,
+    MessageId,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DerivePrimaryKey)]
@@ -45,6 +55,11 @@ impl ColumnTrait for Column {
         match self {
             Self::IdUuid => ColumnType::Uuid.def(),
             Self::Payload => ColumnType::JsonBinary.def(),
+            Self::PayloadVersion => ColumnType::Integer.def(),
+            Self::CreatedAt => ColumnType::TimestampWithTimeZone.def(),
+            Self::Sent => ColumnType::Boolean.def(),
+            Self::ChannelId => ColumnType::BigInteger.def().null(),
+            Self::MessageId => ColumnType::BigInteger.def().null(),
         }
     }
 }
@@ -8,8 +8,13 @@ pub mod currency;
 pub mod guild;
 pub mod message_component_data;
 pub mod player;
-pub mod post;
+pub mod reset_snapshot;
 pub mod role;
 pub mod task;
+pub mod task_archive;
 pub mod team;
+pub mod trade;
+pub mod trade_item;
+pub mod transaction;
 pub mod wallet;
+pub mod wallet_balance;
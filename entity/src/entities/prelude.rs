@@ -3,6 +3,8 @@
 pub use super::{
     category::Entity as Category, channel::Entity as Channel, currency::Entity as Currency,
     guild::Entity as Guild, message_component_data::Entity as MessageComponentData,
-    player::Entity as Player, post::Entity as Post, role::Entity as Role, task::Entity as Task,
-    team::Entity as Team, wallet::Entity as Wallet,
+    player::Entity as Player, reset_snapshot::Entity as ResetSnapshot, role::Entity as Role,
+    task::Entity as Task, task_archive::Entity as TaskArchive, team::Entity as Team,
+    trade::Entity as Trade, trade_item::Entity as TradeItem, transaction::Entity as Transaction,
+    wallet::Entity as Wallet, wallet_balance::Entity as WalletBalance,
 };
@@ -8,22 +8,24 @@ pub struct Entity;
 
 impl EntityName for Entity {
     fn table_name(&self) -> &str {
-        "post"
+        "reset_snapshot"
     }
 }
 
 #[derive(Clone, Debug, PartialEq, DeriveModel, DeriveActiveModel, Eq, Serialize, Deserialize)]
 pub struct Model {
     pub id: i32,
-    pub title: String,
-    pub text: String,
+    pub fk_guild_id: i64,
+    pub created_at: DateTimeWithTimeZone,
+    pub payload: Json,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveColumn)]
 pub enum Column {
     Id,
-    Title,
-    Text,
+    FkGuildId,
+    CreatedAt,
+    Payload,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DerivePrimaryKey)]
@@ -46,8 +48,9 @@ impl ColumnTrait for Column {
     fn def(&self) -> ColumnDef {
         match self {
             Self::Id => ColumnType::Integer.def(),
-            Self::Title => ColumnType::String(None).def(),
-            Self::Text => ColumnType::String(None).def(),
+            Self::FkGuildId => ColumnType::BigInteger.def(),
+            Self::CreatedAt => ColumnType::TimestampWithTimeZone.def(),
+            Self::Payload => ColumnType::JsonBinary.def(),
         }
     }
 }
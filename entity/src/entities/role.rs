@@ -17,6 +17,7 @@ pub struct Model {
     pub discord_id: i64,
     pub fk_guild_id: Option<i64>,
     pub name: String,
+    pub idempotency_key: Option<String>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveColumn)]
@@ -24,6 +25,7 @@ pub enum Column {
     DiscordId,
     FkGuildId,
     Name,
+    IdempotencyKey,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DerivePrimaryKey)]
@@ -51,6 +53,7 @@ impl ColumnTrait for Column {
             Self::DiscordId => ColumnType::BigInteger.def(),
             Self::FkGuildId => ColumnType::BigInteger.def().null(),
             Self::Name => ColumnType::String(None).def(),
+            Self::IdempotencyKey => ColumnType::String(None).def().null().unique(),
         }
     }
 }
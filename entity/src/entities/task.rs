@@ -17,6 +17,21 @@ pub struct Model {
     pub id: i32,
     pub status: Json,
     pub payload: Json,
+    pub attempts: i32,
+    pub next_attempt_at: Option<DateTimeWithTimeZone>,
+    pub last_error: Option<String>,
+    pub dependencies: Option<Json>,
+    pub run_at: Option<DateTimeWithTimeZone>,
+    pub recurrence: Option<Json>,
+    pub lease_owner: Option<String>,
+    pub lease_expires_at: Option<DateTimeWithTimeZone>,
+    pub payload_version: i32,
+    pub idempotency_key: Option<String>,
+    pub priority: i32,
+    pub created_at: DateTimeWithTimeZone,
+    pub started_at: Option<DateTimeWithTimeZone>,
+    pub finished_at: Option<DateTimeWithTimeZone>,
+    pub duration_ms: Option<i64>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveColumn)]
@@ -24,6 +39,21 @@ pub enum Column {
     Id,
     Status,
     Payload,
+    Attempts,
+    NextAttemptAt,
+    LastError,
+    Dependencies,
+    RunAt,
+    Recurrence,
+    LeaseOwner,
+    LeaseExpiresAt,
+    PayloadVersion,
+    IdempotencyKey,
+    Priority,
+    CreatedAt,
+    StartedAt,
+    FinishedAt,
+    DurationMs,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DerivePrimaryKey)]
@@ -48,6 +78,21 @@ impl ColumnTrait for Column {
             Self::Id => ColumnType::Integer.def(),
             Self::Status => ColumnType::JsonBinary.def(),
             Self::Payload => ColumnType::JsonBinary.def(),
+            Self::Attempts => ColumnType::Integer.def(),
+            Self::NextAttemptAt => ColumnType::TimestampWithTimeZone.def().null(),
+            Self::LastError => ColumnType::Text.def().null(),
+            Self::Dependencies => ColumnType::JsonBinary.def().null(),
+            Self::RunAt => ColumnType::TimestampWithTimeZone.def().null(),
+            Self::Recurrence => ColumnType::JsonBinary.def().null(),
+            Self::LeaseOwner => ColumnType::Text.def().null(),
+            Self::LeaseExpiresAt => ColumnType::TimestampWithTimeZone.def().null(),
+            Self::PayloadVersion => ColumnType::Integer.def(),
+            Self::IdempotencyKey => ColumnType::String(None).def().null(),
+            Self::Priority => ColumnType::Integer.def(),
+            Self::CreatedAt => ColumnType::TimestampWithTimeZone.def(),
+            Self::StartedAt => ColumnType::TimestampWithTimeZone.def().null(),
+            Self::FinishedAt => ColumnType::TimestampWithTimeZone.def().null(),
+            Self::DurationMs => ColumnType::BigInteger.def().null(),
         }
     }
 }
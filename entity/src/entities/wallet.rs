@@ -16,12 +16,16 @@ impl EntityName for Entity {
 pub struct Model {
     pub id: i32,
     pub name: String,
+    pub fk_team_id: Option<i32>,
+    pub fk_player_id: Option<i32>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveColumn)]
 pub enum Column {
     Id,
     Name,
+    FkTeamId,
+    FkPlayerId,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DerivePrimaryKey)]
@@ -45,6 +49,8 @@ impl ColumnTrait for Column {
         match self {
             Self::Id => ColumnType::Integer.def(),
             Self::Name => ColumnType::String(None).def(),
+            Self::FkTeamId => ColumnType::Integer.def().null(),
+            Self::FkPlayerId => ColumnType::Integer.def().null(),
         }
     }
 }
@@ -9,6 +9,22 @@ mod m20221203_195037_team;
 mod m20221203_195047_player;
 mod m20221204_194750_task;
 mod m20221227_134343_message_component_data;
+mod m20230107_163012_task_notify;
+mod m20230112_201544_task_retries;
+mod m20230115_142208_task_dependencies;
+mod m20230118_190431_task_schedule;
+mod m20230121_113742_task_lease;
+mod m20230124_205116_payload_version;
+mod m20230127_093318_idempotency_keys;
+mod m20230130_184922_task_priority;
+mod m20230203_201157_task_history;
+mod m20230207_173045_guild_settings;
+mod m20230211_102417_reset_snapshot;
+mod m20230214_160512_wallet_balance;
+mod m20230219_143021_trade;
+mod m20230223_181204_ledger;
+mod m20230226_094512_channel_kind;
+mod m20230301_142610_wallet_owner;
 
 pub struct Migrator;
 
@@ -25,6 +41,22 @@ impl MigratorTrait for Migrator {
             Box::new(m20221203_195047_player::Migration),
             Box::new(m20221204_194750_task::Migration),
             Box::new(m20221227_134343_message_component_data::Migration),
+            Box::new(m20230107_163012_task_notify::Migration),
+            Box::new(m20230112_201544_task_retries::Migration),
+            Box::new(m20230115_142208_task_dependencies::Migration),
+            Box::new(m20230118_190431_task_schedule::Migration),
+            Box::new(m20230121_113742_task_lease::Migration),
+            Box::new(m20230124_205116_payload_version::Migration),
+            Box::new(m20230127_093318_idempotency_keys::Migration),
+            Box::new(m20230130_184922_task_priority::Migration),
+            Box::new(m20230203_201157_task_history::Migration),
+            Box::new(m20230207_173045_guild_settings::Migration),
+            Box::new(m20230211_102417_reset_snapshot::Migration),
+            Box::new(m20230214_160512_wallet_balance::Migration),
+            Box::new(m20230219_143021_trade::Migration),
+            Box::new(m20230223_181204_ledger::Migration),
+            Box::new(m20230226_094512_channel_kind::Migration),
+            Box::new(m20230301_142610_wallet_owner::Migration),
         ]
     }
 }
@@ -17,6 +17,9 @@ pub struct Migration;
 //     State,
 // }
 
+// This makes a `post` table instead of a transaction one, and has already run
+// everywhere, so `m20230223_181204_ledger` drops it and makes the real table
+
 #[async_trait::async_trait]
 impl MigrationTrait for Migration {
     async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
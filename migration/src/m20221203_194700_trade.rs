@@ -12,7 +12,8 @@ pub struct Migration;
 //     Transactions,
 // }
 
-// TODO: Fix this migration
+// This never made anything, and has already run everywhere, so the trade
+// tables are made in `m20230219_143021_trade` instead
 
 #[async_trait::async_trait]
 impl MigrationTrait for Migration {
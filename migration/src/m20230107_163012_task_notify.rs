@@ -0,0 +1,72 @@
+use sea_orm_migration::{
+    prelude::*,
+    sea_orm::{ConnectionTrait, DatabaseBackend, Statement},
+};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+/// Notify `task_inserted` with the id of every new task, and `task_updated`
+/// with the id of every task whose status changes. The bot listens on both
+/// channels so the task runner and anyone awaiting a task can wake up right
+/// away instead of polling the table.
+///
+/// Each statement is executed on its own, since prepared statements can't
+/// contain more than one command.
+///
+/// Other databases don't have anything like `NOTIFY`, so this is Postgres
+/// only. Elsewhere, the bot announces task changes to itself.
+const CREATE_NOTIFY_TRIGGER: &[&str] = &[
+    r#"
+    CREATE OR REPLACE FUNCTION notify_task_change() RETURNS trigger AS $$
+    BEGIN
+        IF TG_OP = 'INSERT' THEN
+            PERFORM pg_notify('task_inserted', NEW.id::text);
+        ELSIF NEW.status IS DISTINCT FROM OLD.status THEN
+            PERFORM pg_notify('task_updated', NEW.id::text);
+        END IF;
+        RETURN NEW;
+    END;
+    $$ LANGUAGE plpgsql
+    "#,
+    "DROP TRIGGER IF EXISTS task_notify ON task",
+    r#"
+    CREATE TRIGGER task_notify
+        AFTER INSERT OR UPDATE ON task
+        FOR EACH ROW EXECUTE PROCEDURE notify_task_change()
+    "#,
+];
+
+const DROP_NOTIFY_TRIGGER: &[&str] = &[
+    "DROP TRIGGER IF EXISTS task_notify ON task",
+    "DROP FUNCTION IF EXISTS notify_task_change()",
+];
+
+async fn execute_all(manager: &SchemaManager<'_>, statements: &[&str]) -> Result<(), DbErr> {
+    if manager.get_database_backend() != DatabaseBackend::Postgres {
+        return Ok(());
+    }
+
+    for statement in statements {
+        manager
+            .get_connection()
+            .execute(Statement::from_string(
+                manager.get_database_backend(),
+                statement.to_string(),
+            ))
+            .await?;
+    }
+
+    Ok(())
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        execute_all(manager, CREATE_NOTIFY_TRIGGER).await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        execute_all(manager, DROP_NOTIFY_TRIGGER).await
+    }
+}
@@ -0,0 +1,138 @@
+use sea_orm_migration::{prelude::*, sea_orm::DatabaseBackend};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[derive(Iden, Clone, Copy)]
+enum Task {
+    Table,
+    IdempotencyKey,
+}
+
+#[derive(Iden, Clone, Copy)]
+enum Category {
+    Table,
+    IdempotencyKey,
+}
+
+#[derive(Iden, Clone, Copy)]
+enum Channel {
+    Table,
+    IdempotencyKey,
+}
+
+#[derive(Iden, Clone, Copy)]
+enum Role {
+    Table,
+    IdempotencyKey,
+}
+
+/// The name Postgres gives the constraint of a `UNIQUE` column
+fn unique_key_name(table: &dyn Iden, column: &dyn Iden) -> String {
+    format!("{}_{}_key", table.to_string(), column.to_string())
+}
+
+/// Add a nullable, unique key column. SQLite can't add a `UNIQUE` column to
+/// an existing table, so there it gets a unique index instead.
+async fn add_unique_key<T, C>(manager: &SchemaManager<'_>, table: T, column: C) -> Result<(), DbErr>
+where
+    T: Iden + Copy + 'static,
+    C: Iden + Copy + 'static,
+{
+    let mut column_def = ColumnDef::new(column);
+    column_def.string().null();
+
+    if manager.get_database_backend() != DatabaseBackend::Sqlite {
+        column_def.unique_key();
+    }
+
+    manager
+        .alter_table(
+            Table::alter()
+                .table(table)
+                .add_column(&mut column_def)
+                .to_owned(),
+        )
+        .await?;
+
+    if manager.get_database_backend() == DatabaseBackend::Sqlite {
+        manager
+            .create_index(
+                Index::create()
+                    .name(&unique_key_name(&table, &column))
+                    .table(table)
+                    .col(column)
+                    .unique()
+                    .to_owned(),
+            )
+            .await?;
+    }
+
+    Ok(())
+}
+
+async fn drop_unique_key<T, C>(
+    manager: &SchemaManager<'_>,
+    table: T,
+    column: C,
+) -> Result<(), DbErr>
+where
+    T: Iden + Copy + 'static,
+    C: Iden + Copy + 'static,
+{
+    if manager.get_database_backend() == DatabaseBackend::Sqlite {
+        manager
+            .drop_index(
+                Index::drop()
+                    .name(&unique_key_name(&table, &column))
+                    .table(table)
+                    .to_owned(),
+            )
+            .await?;
+    }
+
+    manager
+        .alter_table(Table::alter().table(table).drop_column(column).to_owned())
+        .await
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // Stays the same across every attempt of a task. Tasks added before
+        // this don't have one.
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Task::Table)
+                    .add_column(ColumnDef::new(Task::IdempotencyKey).string().null())
+                    .to_owned(),
+            )
+            .await?;
+
+        // The key of the task that created each resource, so a retry of that
+        // task can find it again
+        add_unique_key(manager, Category::Table, Category::IdempotencyKey).await?;
+
+        add_unique_key(manager, Channel::Table, Channel::IdempotencyKey).await?;
+
+        add_unique_key(manager, Role::Table, Role::IdempotencyKey).await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Task::Table)
+                    .drop_column(Task::IdempotencyKey)
+                    .to_owned(),
+            )
+            .await?;
+
+        drop_unique_key(manager, Category::Table, Category::IdempotencyKey).await?;
+
+        drop_unique_key(manager, Channel::Table, Channel::IdempotencyKey).await?;
+
+        drop_unique_key(manager, Role::Table, Role::IdempotencyKey).await
+    }
+}
@@ -0,0 +1,257 @@
+use sea_orm_migration::{prelude::*, sea_orm::DatabaseBackend};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[derive(Iden)]
+enum Task {
+    Table,
+    CreatedAt,
+    StartedAt,
+    FinishedAt,
+    DurationMs,
+}
+
+#[derive(Iden)]
+enum MessageComponentData {
+    Table,
+    CreatedAt,
+    Sent,
+    ChannelId,
+    MessageId,
+}
+
+#[derive(Iden)]
+enum TaskArchive {
+    Table,
+    Id,
+    Status,
+    Payload,
+    PayloadVersion,
+    Attempts,
+    LastError,
+    Priority,
+    CreatedAt,
+    StartedAt,
+    FinishedAt,
+    DurationMs,
+    ArchivedAt,
+}
+
+/// The default of the `created_at` columns added to existing tables. SQLite
+/// can't add a column with a default that isn't a constant, so rows from
+/// before this get the epoch there instead of the time of the migration.
+fn created_at_default(manager: &SchemaManager) -> SimpleExpr {
+    match manager.get_database_backend() {
+        DatabaseBackend::Sqlite => Expr::val("1970-01-01T00:00:00+00:00").into(),
+        _ => Expr::current_timestamp().into(),
+    }
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // Tasks that were added before this get the time of the migration
+        // (see `created_at_default`)
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Task::Table)
+                    .add_column(
+                        ColumnDef::new(Task::CreatedAt)
+                            .timestamp_with_time_zone()
+                            .not_null()
+                            .default(created_at_default(manager)),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        // When the latest attempt started
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Task::Table)
+                    .add_column(
+                        ColumnDef::new(Task::StartedAt)
+                            .timestamp_with_time_zone()
+                            .null(),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        // When the task got its final status
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Task::Table)
+                    .add_column(
+                        ColumnDef::new(Task::FinishedAt)
+                            .timestamp_with_time_zone()
+                            .null(),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        // How long the latest attempt ran for
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Task::Table)
+                    .add_column(ColumnDef::new(Task::DurationMs).big_integer().null())
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(MessageComponentData::Table)
+                    .add_column(
+                        ColumnDef::new(MessageComponentData::CreatedAt)
+                            .timestamp_with_time_zone()
+                            .not_null()
+                            .default(created_at_default(manager)),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        // Whether the component made it into a message. Components from
+        // before this are assumed to have.
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(MessageComponentData::Table)
+                    .add_column(
+                        ColumnDef::new(MessageComponentData::Sent)
+                            .boolean()
+                            .not_null()
+                            .default(true),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        // The message the component was sent in, if it's known
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(MessageComponentData::Table)
+                    .add_column(
+                        ColumnDef::new(MessageComponentData::ChannelId)
+                            .big_integer()
+                            .null(),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(MessageComponentData::Table)
+                    .add_column(
+                        ColumnDef::new(MessageComponentData::MessageId)
+                            .big_integer()
+                            .null(),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        // Finished tasks that were moved out of the queue
+        manager
+            .create_table(
+                Table::create()
+                    .table(TaskArchive::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(TaskArchive::Id)
+                            .integer()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(TaskArchive::Status).json_binary().not_null())
+                    .col(
+                        ColumnDef::new(TaskArchive::Payload)
+                            .json_binary()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(TaskArchive::PayloadVersion)
+                            .integer()
+                            .not_null(),
+                    )
+                    .col(ColumnDef::new(TaskArchive::Attempts).integer().not_null())
+                    .col(ColumnDef::new(TaskArchive::LastError).text().null())
+                    .col(ColumnDef::new(TaskArchive::Priority).integer().not_null())
+                    .col(
+                        ColumnDef::new(TaskArchive::CreatedAt)
+                            .timestamp_with_time_zone()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(TaskArchive::StartedAt)
+                            .timestamp_with_time_zone()
+                            .null(),
+                    )
+                    .col(
+                        ColumnDef::new(TaskArchive::FinishedAt)
+                            .timestamp_with_time_zone()
+                            .null(),
+                    )
+                    .col(ColumnDef::new(TaskArchive::DurationMs).big_integer().null())
+                    .col(
+                        ColumnDef::new(TaskArchive::ArchivedAt)
+                            .timestamp_with_time_zone()
+                            .not_null()
+                            .default(Expr::current_timestamp()),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(TaskArchive::Table).to_owned())
+            .await?;
+
+        for column in [
+            MessageComponentData::CreatedAt,
+            MessageComponentData::Sent,
+            MessageComponentData::ChannelId,
+            MessageComponentData::MessageId,
+        ] {
+            manager
+                .alter_table(
+                    Table::alter()
+                        .table(MessageComponentData::Table)
+                        .drop_column(column)
+                        .to_owned(),
+                )
+                .await?;
+        }
+
+        for column in [
+            Task::CreatedAt,
+            Task::StartedAt,
+            Task::FinishedAt,
+            Task::DurationMs,
+        ] {
+            manager
+                .alter_table(
+                    Table::alter()
+                        .table(Task::Table)
+                        .drop_column(column)
+                        .to_owned(),
+                )
+                .await?;
+        }
+
+        Ok(())
+    }
+}
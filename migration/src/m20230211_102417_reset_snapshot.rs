@@ -0,0 +1,57 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[derive(Iden)]
+enum ResetSnapshot {
+    Table,
+    Id,
+    FkGuildId,
+    CreatedAt,
+    Payload,
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // What a guild's tables looked like right before a reset. The guild
+        // isn't a foreign key, so snapshots outlive anything done to it.
+        manager
+            .create_table(
+                Table::create()
+                    .table(ResetSnapshot::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(ResetSnapshot::Id)
+                            .integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(
+                        ColumnDef::new(ResetSnapshot::FkGuildId)
+                            .big_integer()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(ResetSnapshot::CreatedAt)
+                            .timestamp_with_time_zone()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(ResetSnapshot::Payload)
+                            .json_binary()
+                            .not_null(),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(ResetSnapshot::Table).to_owned())
+            .await
+    }
+}
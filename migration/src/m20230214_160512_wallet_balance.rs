@@ -0,0 +1,79 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[derive(Iden)]
+enum WalletBalance {
+    Table,
+    FkWalletId,
+    FkCurrencyId,
+    Amount,
+}
+
+#[derive(Iden)]
+enum Wallet {
+    Table,
+    Id,
+}
+
+#[derive(Iden)]
+enum Currency {
+    Table,
+    Id,
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // How much of each currency a wallet holds
+        manager
+            .create_table(
+                Table::create()
+                    .table(WalletBalance::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(WalletBalance::FkWalletId)
+                            .integer()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(WalletBalance::FkCurrencyId)
+                            .integer()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(WalletBalance::Amount)
+                            .big_integer()
+                            .not_null()
+                            .default(0),
+                    )
+                    .primary_key(
+                        Index::create()
+                            .col(WalletBalance::FkWalletId)
+                            .col(WalletBalance::FkCurrencyId),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk_wallet_balance_wallet_id")
+                            .from(WalletBalance::Table, WalletBalance::FkWalletId)
+                            .to(Wallet::Table, Wallet::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk_wallet_balance_currency_id")
+                            .from(WalletBalance::Table, WalletBalance::FkCurrencyId)
+                            .to(Currency::Table, Currency::Id),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(WalletBalance::Table).to_owned())
+            .await
+    }
+}
@@ -0,0 +1,152 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[derive(Iden)]
+enum Trade {
+    Table,
+    Id,
+    FkGuildId,
+    FkInitiatingTeamId,
+    FkReceivingTeamId,
+    FkAwaitingTeamId,
+    Status,
+    CreatedAt,
+    UpdatedAt,
+    ProposedAt,
+    ExpiresAt,
+}
+
+#[derive(Iden)]
+enum TradeItem {
+    Table,
+    FkTradeId,
+    FkFromTeamId,
+    FkCurrencyId,
+    Amount,
+}
+
+#[derive(Iden)]
+enum Team {
+    Table,
+    Id,
+}
+
+#[derive(Iden)]
+enum Currency {
+    Table,
+    Id,
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // Trades go away with either of their teams, so a reset doesn't
+        // have to know about them
+        manager
+            .create_table(
+                Table::create()
+                    .table(Trade::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(Trade::Id)
+                            .integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(Trade::FkGuildId).big_integer().not_null())
+                    .col(
+                        ColumnDef::new(Trade::FkInitiatingTeamId)
+                            .integer()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(Trade::FkReceivingTeamId)
+                            .integer()
+                            .not_null(),
+                    )
+                    .col(ColumnDef::new(Trade::FkAwaitingTeamId).integer().null())
+                    .col(ColumnDef::new(Trade::Status).string().not_null())
+                    .col(
+                        ColumnDef::new(Trade::CreatedAt)
+                            .timestamp_with_time_zone()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(Trade::UpdatedAt)
+                            .timestamp_with_time_zone()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(Trade::ProposedAt)
+                            .timestamp_with_time_zone()
+                            .null(),
+                    )
+                    .col(
+                        ColumnDef::new(Trade::ExpiresAt)
+                            .timestamp_with_time_zone()
+                            .null(),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk_trade_initiating_team_id")
+                            .from(Trade::Table, Trade::FkInitiatingTeamId)
+                            .to(Team::Table, Team::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk_trade_receiving_team_id")
+                            .from(Trade::Table, Trade::FkReceivingTeamId)
+                            .to(Team::Table, Team::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        // One row for each currency each side hands over
+        manager
+            .create_table(
+                Table::create()
+                    .table(TradeItem::Table)
+                    .if_not_exists()
+                    .col(ColumnDef::new(TradeItem::FkTradeId).integer().not_null())
+                    .col(ColumnDef::new(TradeItem::FkFromTeamId).integer().not_null())
+                    .col(ColumnDef::new(TradeItem::FkCurrencyId).integer().not_null())
+                    .col(ColumnDef::new(TradeItem::Amount).big_integer().not_null())
+                    .primary_key(
+                        Index::create()
+                            .col(TradeItem::FkTradeId)
+                            .col(TradeItem::FkFromTeamId)
+                            .col(TradeItem::FkCurrencyId),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk_trade_item_trade_id")
+                            .from(TradeItem::Table, TradeItem::FkTradeId)
+                            .to(Trade::Table, Trade::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk_trade_item_currency_id")
+                            .from(TradeItem::Table, TradeItem::FkCurrencyId)
+                            .to(Currency::Table, Currency::Id),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(TradeItem::Table).to_owned())
+            .await?;
+        manager
+            .drop_table(Table::drop().table(Trade::Table).to_owned())
+            .await
+    }
+}
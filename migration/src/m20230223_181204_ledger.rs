@@ -0,0 +1,318 @@
+use sea_orm_migration::{
+    prelude::*,
+    sea_orm::{ConnectionTrait, DatabaseBackend, Statement},
+};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[derive(Iden)]
+enum Transaction {
+    Table,
+    Id,
+    FkFromWalletId,
+    FkToWalletId,
+    FkCurrencyId,
+    Amount,
+    FkInitiatingPlayerId,
+    CreatedAt,
+    Memo,
+}
+
+#[derive(Iden)]
+enum Wallet {
+    Table,
+    Id,
+    FkTeamId,
+    FkPlayerId,
+}
+
+#[derive(Iden)]
+enum Team {
+    Table,
+    Id,
+    Wallet,
+}
+
+#[derive(Iden)]
+enum Player {
+    Table,
+    Id,
+}
+
+#[derive(Iden)]
+enum Currency {
+    Table,
+    Id,
+}
+
+/// Made by the `transaction` migration, which never made a transaction table
+#[derive(Iden)]
+enum Post {
+    Table,
+    Id,
+    Title,
+    Text,
+}
+
+/// Foreign keys added to tables that already exist. SQLite can't add a
+/// constraint to an existing table, so there the columns go without them.
+const WALLET_TEAM_KEY: &str = "fk_wallet_team_id";
+const WALLET_PLAYER_KEY: &str = "fk_wallet_player_id";
+const TEAM_WALLET_KEY: &str = "fk_team_wallet_id";
+
+/// Each statement is executed on its own, since prepared statements can't
+/// hold more than one
+async fn execute_all(manager: &SchemaManager<'_>, statements: &[String]) -> Result<(), DbErr> {
+    for statement in statements {
+        manager
+            .get_connection()
+            .execute(Statement::from_string(
+                manager.get_database_backend(),
+                statement.to_string(),
+            ))
+            .await?;
+    }
+
+    Ok(())
+}
+
+/// Wallets get their owner from the team pointing at them, and whatever was
+/// in them is recorded as minted, so balances match the transactions
+fn backfill(manager: &SchemaManager) -> Vec<String> {
+    let now = match manager.get_database_backend() {
+        DatabaseBackend::Sqlite => "strftime('%Y-%m-%dT%H:%M:%S+00:00', 'now')",
+        _ => "CURRENT_TIMESTAMP",
+    };
+
+    vec![
+        "UPDATE team SET wallet = NULL \
+            WHERE wallet IS NOT NULL AND wallet NOT IN (SELECT id FROM wallet)"
+            .to_string(),
+        "UPDATE wallet SET fk_team_id = \
+            (SELECT team.id FROM team WHERE team.wallet = wallet.id LIMIT 1)"
+            .to_string(),
+        format!(
+            "INSERT INTO \"transaction\" \
+                (fk_to_wallet_id, fk_currency_id, amount, created_at, memo) \
+            SELECT fk_wallet_id, fk_currency_id, amount, {}, 'Opening balance' \
+                FROM wallet_balance WHERE amount > 0",
+            now
+        ),
+    ]
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(Post::Table).if_exists().to_owned())
+            .await?;
+
+        // Every movement of currency. Rows are only ever added, and a
+        // wallet's balances are the sum of what went in minus what went out.
+        // Currency that comes from no wallet was minted, and currency that
+        // goes to no wallet was burned. A wallet with transactions can't be
+        // deleted, since that would take the other side of them with it.
+        manager
+            .create_table(
+                Table::create()
+                    .table(Transaction::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(Transaction::Id)
+                            .integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(Transaction::FkFromWalletId).integer().null())
+                    .col(ColumnDef::new(Transaction::FkToWalletId).integer().null())
+                    .col(
+                        ColumnDef::new(Transaction::FkCurrencyId)
+                            .integer()
+                            .not_null(),
+                    )
+                    .col(ColumnDef::new(Transaction::Amount).big_integer().not_null())
+                    .col(
+                        ColumnDef::new(Transaction::FkInitiatingPlayerId)
+                            .integer()
+                            .null(),
+                    )
+                    .col(
+                        ColumnDef::new(Transaction::CreatedAt)
+                            .timestamp_with_time_zone()
+                            .not_null(),
+                    )
+                    .col(ColumnDef::new(Transaction::Memo).string().null())
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk_transaction_from_wallet_id")
+                            .from(Transaction::Table, Transaction::FkFromWalletId)
+                            .to(Wallet::Table, Wallet::Id)
+                            .on_delete(ForeignKeyAction::Restrict),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk_transaction_to_wallet_id")
+                            .from(Transaction::Table, Transaction::FkToWalletId)
+                            .to(Wallet::Table, Wallet::Id)
+                            .on_delete(ForeignKeyAction::Restrict),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk_transaction_currency_id")
+                            .from(Transaction::Table, Transaction::FkCurrencyId)
+                            .to(Currency::Table, Currency::Id),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk_transaction_initiating_player_id")
+                            .from(Transaction::Table, Transaction::FkInitiatingPlayerId)
+                            .to(Player::Table, Player::Id)
+                            .on_delete(ForeignKeyAction::SetNull),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_transaction_from_wallet_id")
+                    .table(Transaction::Table)
+                    .col(Transaction::FkFromWalletId)
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_transaction_to_wallet_id")
+                    .table(Transaction::Table)
+                    .col(Transaction::FkToWalletId)
+                    .to_owned(),
+            )
+            .await?;
+
+        // Who a wallet belongs to, if anyone. Wallets outlive their owners,
+        // and are left without one when the team or player is deleted.
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Wallet::Table)
+                    .add_column(ColumnDef::new(Wallet::FkTeamId).integer().null())
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Wallet::Table)
+                    .add_column(ColumnDef::new(Wallet::FkPlayerId).integer().null())
+                    .to_owned(),
+            )
+            .await?;
+
+        execute_all(manager, &backfill(manager)).await?;
+
+        if manager.get_database_backend() == DatabaseBackend::Sqlite {
+            return Ok(());
+        }
+
+        manager
+            .create_foreign_key(
+                ForeignKey::create()
+                    .name(WALLET_TEAM_KEY)
+                    .from(Wallet::Table, Wallet::FkTeamId)
+                    .to(Team::Table, Team::Id)
+                    .on_delete(ForeignKeyAction::SetNull)
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_foreign_key(
+                ForeignKey::create()
+                    .name(WALLET_PLAYER_KEY)
+                    .from(Wallet::Table, Wallet::FkPlayerId)
+                    .to(Player::Table, Player::Id)
+                    .on_delete(ForeignKeyAction::SetNull)
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_foreign_key(
+                ForeignKey::create()
+                    .name(TEAM_WALLET_KEY)
+                    .from(Team::Table, Team::Wallet)
+                    .to(Wallet::Table, Wallet::Id)
+                    .on_delete(ForeignKeyAction::SetNull)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        if manager.get_database_backend() != DatabaseBackend::Sqlite {
+            for (table, name) in [
+                (Team::Table.to_string(), TEAM_WALLET_KEY),
+                (Wallet::Table.to_string(), WALLET_PLAYER_KEY),
+                (Wallet::Table.to_string(), WALLET_TEAM_KEY),
+            ] {
+                manager
+                    .drop_foreign_key(
+                        ForeignKey::drop()
+                            .name(name)
+                            .table(Alias::new(&table))
+                            .to_owned(),
+                    )
+                    .await?;
+            }
+        }
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Wallet::Table)
+                    .drop_column(Wallet::FkPlayerId)
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Wallet::Table)
+                    .drop_column(Wallet::FkTeamId)
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .drop_table(Table::drop().table(Transaction::Table).to_owned())
+            .await?;
+
+        // Put back what the `transaction` migration expects to drop
+        manager
+            .create_table(
+                Table::create()
+                    .table(Post::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(Post::Id)
+                            .integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(Post::Title).string().not_null())
+                    .col(ColumnDef::new(Post::Text).string().not_null())
+                    .to_owned(),
+            )
+            .await
+    }
+}
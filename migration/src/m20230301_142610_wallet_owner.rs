@@ -0,0 +1,86 @@
+use sea_orm_migration::{
+    prelude::*,
+    sea_orm::{ConnectionTrait, Statement},
+};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[derive(Iden)]
+enum Wallet {
+    Table,
+}
+
+const WALLET_TEAM_INDEX: &str = "idx_wallet_team_id";
+const WALLET_PLAYER_INDEX: &str = "idx_wallet_player_id";
+
+/// Each statement is executed on its own, since prepared statements can't
+/// hold more than one
+async fn execute_all(manager: &SchemaManager<'_>, statements: &[String]) -> Result<(), DbErr> {
+    for statement in statements {
+        manager
+            .get_connection()
+            .execute(Statement::from_string(
+                manager.get_database_backend(),
+                statement.to_string(),
+            ))
+            .await?;
+    }
+
+    Ok(())
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // Wallets made twice for the same owner are kept without one, like
+        // the wallets of deleted owners. A team keeps the wallet it points
+        // at, and a player keeps their first.
+        execute_all(
+            manager,
+            &[
+                "UPDATE wallet SET fk_team_id = NULL \
+                    WHERE fk_team_id IS NOT NULL AND id NOT IN \
+                        (SELECT team.wallet FROM team \
+                            WHERE team.id = wallet.fk_team_id AND team.wallet IS NOT NULL)"
+                    .to_string(),
+                "UPDATE wallet SET fk_player_id = NULL \
+                    WHERE fk_player_id IS NOT NULL AND id NOT IN \
+                        (SELECT MIN(id) FROM wallet WHERE fk_player_id IS NOT NULL \
+                            GROUP BY fk_player_id)"
+                    .to_string(),
+            ],
+        )
+        .await?;
+
+        // Each team and player has at most one wallet, so two requests that
+        // both find none can't both make one. Both backends allow partial
+        // indexes, which sea-query can't build.
+        execute_all(
+            manager,
+            &[
+                format!(
+                    "CREATE UNIQUE INDEX {} ON wallet (fk_team_id) \
+                        WHERE fk_team_id IS NOT NULL",
+                    WALLET_TEAM_INDEX
+                ),
+                format!(
+                    "CREATE UNIQUE INDEX {} ON wallet (fk_player_id) \
+                        WHERE fk_player_id IS NOT NULL",
+                    WALLET_PLAYER_INDEX
+                ),
+            ],
+        )
+        .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        for name in [WALLET_PLAYER_INDEX, WALLET_TEAM_INDEX] {
+            manager
+                .drop_index(Index::drop().name(name).table(Wallet::Table).to_owned())
+                .await?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,393 @@
+use std::fmt;
+
+use async_trait::async_trait;
+use entity::entities::{currency, player, team, wallet};
+use sea_orm::{ColumnTrait, DbErr, EntityTrait, QueryFilter};
+use serenity::{
+    all::{CommandOptionType, GuildId, ResolvedOption, ResolvedValue, UserId},
+    builder::{CreateCommand, CreateCommandOption},
+    model::Permissions,
+    prelude::Context,
+};
+
+use crate::{
+    db_wrapper::{
+        helpers::get_guild_player,
+        ledger::{player_wallet, team_wallet, LedgerError},
+        DBWrapper,
+    },
+    task_runner::tasks::{DatabaseId, DiscordId},
+};
+
+use super::{CommandResponse, GameCommand};
+
+/// Lets the organizers put currency into the game, take it out and move it
+/// between teams. Every change goes through the ledger.
+pub struct BankCommand;
+
+enum BankError {
+    TeamNotFound,
+    PlayerNotFound,
+    CurrencyNotFound(String),
+    MissingOptions,
+    Ledger(LedgerError),
+}
+
+impl fmt::Display for BankError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BankError::TeamNotFound => write!(f, "That role doesn't belong to a team"),
+            BankError::PlayerNotFound => write!(f, "That user hasn't joined the game"),
+            BankError::CurrencyNotFound(name) => write!(f, "There's no currency called {}", name),
+            BankError::MissingOptions => {
+                write!(f, "Pick a team or a player, a currency and an amount")
+            }
+            BankError::Ledger(why) => write!(f, "{}", why),
+        }
+    }
+}
+
+impl From<LedgerError> for BankError {
+    fn from(why: LedgerError) -> Self {
+        BankError::Ledger(why)
+    }
+}
+
+impl From<DbErr> for BankError {
+    fn from(why: DbErr) -> Self {
+        BankError::Ledger(LedgerError::from(why))
+    }
+}
+
+/// The options shared by `mint` and `burn`
+fn wallet_options(description: &str, name: &str) -> CreateCommandOption {
+    CreateCommandOption::new(CommandOptionType::SubCommand, name, description)
+        .add_sub_option(
+            CreateCommandOption::new(CommandOptionType::String, "currency", "The currency's name")
+                .required(true),
+        )
+        .add_sub_option(
+            CreateCommandOption::new(CommandOptionType::Integer, "amount", "How much")
+                .required(true)
+                .min_int_value(1),
+        )
+        .add_sub_option(CreateCommandOption::new(
+            CommandOptionType::Role,
+            "team",
+            "The role of the team whose wallet it is",
+        ))
+        .add_sub_option(CreateCommandOption::new(
+            CommandOptionType::User,
+            "player",
+            "The player whose wallet it is",
+        ))
+}
+
+#[async_trait]
+impl GameCommand for BankCommand {
+    fn register() -> CreateCommand {
+        CreateCommand::new("bank")
+            .description("Mint, burn and move currency")
+            .default_member_permissions(Permissions::ADMINISTRATOR)
+            .add_option(wallet_options("Add currency to a wallet", "mint"))
+            .add_option(wallet_options("Take currency out of a wallet", "burn"))
+            .add_option(
+                CreateCommandOption::new(
+                    CommandOptionType::SubCommand,
+                    "transfer",
+                    "Move currency from one team to another",
+                )
+                .add_sub_option(
+                    CreateCommandOption::new(
+                        CommandOptionType::Role,
+                        "from",
+                        "The role of the team giving it",
+                    )
+                    .required(true),
+                )
+                .add_sub_option(
+                    CreateCommandOption::new(
+                        CommandOptionType::Role,
+                        "to",
+                        "The role of the team receiving it",
+                    )
+                    .required(true),
+                )
+                .add_sub_option(
+                    CreateCommandOption::new(
+                        CommandOptionType::String,
+                        "currency",
+                        "The currency's name",
+                    )
+                    .required(true),
+                )
+                .add_sub_option(
+                    CreateCommandOption::new(CommandOptionType::Integer, "amount", "How much")
+                        .required(true)
+                        .min_int_value(1),
+                ),
+            )
+            .add_option(CreateCommandOption::new(
+                CommandOptionType::SubCommand,
+                "audit",
+                "Check every wallet's balances against its transactions",
+            ))
+    }
+
+    async fn run(
+        options: &[ResolvedOption],
+        guild_id: GuildId,
+        user_id: UserId,
+        db: DBWrapper,
+        _ctx: Context,
+    ) -> CommandResponse {
+        let guild_id = DiscordId::from(guild_id);
+
+        let Some((subcommand, options)) = options.iter().find_map(|option| match &option.value {
+            ResolvedValue::SubCommand(options) => Some((option.name, options)),
+            _ => None,
+        }) else {
+            return "Pick what to do".to_string().into();
+        };
+
+        // Recorded on the ledger as who did it, if they're in the game
+        let player_id = get_guild_player(&db, guild_id, DiscordId::from(user_id))
+            .await
+            .ok()
+            .flatten()
+            .map(|database_player| DatabaseId(database_player.id));
+
+        let result = match subcommand {
+            "mint" => mint(&db, guild_id, player_id, options).await,
+            "burn" => burn(&db, guild_id, player_id, options).await,
+            "transfer" => transfer(&db, guild_id, player_id, options).await,
+            "audit" => audit(&db, guild_id).await,
+            _ => unreachable!(),
+        };
+
+        match result {
+            Ok(content) => content.into(),
+            Err(why) => why.to_string().into(),
+        }
+    }
+}
+
+fn integer_option(options: &[ResolvedOption], name: &str) -> Option<i64> {
+    options.iter().find_map(|option| match option.value {
+        ResolvedValue::Integer(value) if option.name == name => Some(value),
+        _ => None,
+    })
+}
+
+fn string_option<'a>(options: &[ResolvedOption<'a>], name: &str) -> Option<&'a str> {
+    options.iter().find_map(|option| match option.value {
+        ResolvedValue::String(value) if option.name == name => Some(value),
+        _ => None,
+    })
+}
+
+fn role_option(options: &[ResolvedOption], name: &str) -> Option<DiscordId> {
+    options.iter().find_map(|option| match option.value {
+        ResolvedValue::Role(role) if option.name == name => Some(DiscordId::from(role.id)),
+        _ => None,
+    })
+}
+
+fn user_option(options: &[ResolvedOption], name: &str) -> Option<DiscordId> {
+    options.iter().find_map(|option| match option.value {
+        ResolvedValue::User(user, _) if option.name == name => Some(DiscordId::from(user.id)),
+        _ => None,
+    })
+}
+
+async fn find_currency(db: &DBWrapper, name: &str) -> Result<currency::Model, BankError> {
+    currency::Entity::find()
+        .filter(currency::Column::Name.eq(name))
+        .one(&**db)
+        .await?
+        .ok_or_else(|| BankError::CurrencyNotFound(name.to_string()))
+}
+
+async fn find_team(
+    db: &DBWrapper,
+    guild_id: DiscordId,
+    role_id: DiscordId,
+) -> Result<team::Model, BankError> {
+    team::Entity::find()
+        .filter(team::Column::FkGuildId.eq(*guild_id as i64))
+        .filter(team::Column::FkTeamRoleId.eq(*role_id as i64))
+        .one(&**db)
+        .await?
+        .ok_or(BankError::TeamNotFound)
+}
+
+/// The wallet picked with the `team` or `player` option, and who it belongs to
+async fn chosen_wallet(
+    db: &DBWrapper,
+    guild_id: DiscordId,
+    options: &[ResolvedOption<'_>],
+) -> Result<(DatabaseId, String), BankError> {
+    if let Some(role_id) = role_option(options, "team") {
+        let database_team = find_team(db, guild_id, role_id).await?;
+        return Ok((
+            team_wallet(&**db, database_team.id).await?,
+            database_team.name,
+        ));
+    }
+
+    if let Some(user_id) = user_option(options, "player") {
+        let database_player = get_guild_player(db, guild_id, user_id)
+            .await?
+            .ok_or(BankError::PlayerNotFound)?;
+        return Ok((
+            player_wallet(&**db, database_player.id).await?,
+            database_player.name,
+        ));
+    }
+
+    Err(BankError::MissingOptions)
+}
+
+async fn mint(
+    db: &DBWrapper,
+    guild_id: DiscordId,
+    player_id: Option<DatabaseId>,
+    options: &[ResolvedOption<'_>],
+) -> Result<String, BankError> {
+    let (Some(currency_name), Some(amount)) = (
+        string_option(options, "currency"),
+        integer_option(options, "amount"),
+    ) else {
+        return Err(BankError::MissingOptions);
+    };
+
+    let database_currency = find_currency(db, currency_name).await?;
+    let (wallet_id, owner) = chosen_wallet(db, guild_id, options).await?;
+    db.mint(
+        wallet_id,
+        DatabaseId(database_currency.id),
+        amount,
+        player_id,
+    )
+    .await?;
+
+    Ok(format!(
+        "Minted {} {} for {}",
+        amount, database_currency.name, owner
+    ))
+}
+
+async fn burn(
+    db: &DBWrapper,
+    guild_id: DiscordId,
+    player_id: Option<DatabaseId>,
+    options: &[ResolvedOption<'_>],
+) -> Result<String, BankError> {
+    let (Some(currency_name), Some(amount)) = (
+        string_option(options, "currency"),
+        integer_option(options, "amount"),
+    ) else {
+        return Err(BankError::MissingOptions);
+    };
+
+    let database_currency = find_currency(db, currency_name).await?;
+    let (wallet_id, owner) = chosen_wallet(db, guild_id, options).await?;
+    db.burn(
+        wallet_id,
+        DatabaseId(database_currency.id),
+        amount,
+        player_id,
+    )
+    .await?;
+
+    Ok(format!(
+        "Burned {} {} from {}",
+        amount, database_currency.name, owner
+    ))
+}
+
+async fn transfer(
+    db: &DBWrapper,
+    guild_id: DiscordId,
+    player_id: Option<DatabaseId>,
+    options: &[ResolvedOption<'_>],
+) -> Result<String, BankError> {
+    let (Some(from_role_id), Some(to_role_id), Some(currency_name), Some(amount)) = (
+        role_option(options, "from"),
+        role_option(options, "to"),
+        string_option(options, "currency"),
+        integer_option(options, "amount"),
+    ) else {
+        return Err(BankError::MissingOptions);
+    };
+
+    let database_currency = find_currency(db, currency_name).await?;
+    let from_team = find_team(db, guild_id, from_role_id).await?;
+    let to_team = find_team(db, guild_id, to_role_id).await?;
+
+    db.transfer(
+        team_wallet(&**db, from_team.id).await?,
+        team_wallet(&**db, to_team.id).await?,
+        DatabaseId(database_currency.id),
+        amount,
+        player_id,
+    )
+    .await?;
+
+    Ok(format!(
+        "Moved {} {} from {} to {}",
+        amount, database_currency.name, from_team.name, to_team.name
+    ))
+}
+
+/// Every team and player wallet in the guild whose running balances don't
+/// add up to its transactions. There shouldn't ever be any.
+async fn audit(db: &DBWrapper, guild_id: DiscordId) -> Result<String, BankError> {
+    let team_wallet_ids = team::Entity::find()
+        .filter(team::Column::FkGuildId.eq(*guild_id as i64))
+        .all(&**db)
+        .await?
+        .into_iter()
+        .filter_map(|database_team| database_team.wallet);
+
+    let player_ids: Vec<i32> = player::Entity::find()
+        .filter(player::Column::FkGuildId.eq(*guild_id as i64))
+        .all(&**db)
+        .await?
+        .into_iter()
+        .map(|database_player| database_player.id)
+        .collect();
+    let player_wallet_ids = wallet::Entity::find()
+        .filter(wallet::Column::FkPlayerId.is_in(player_ids))
+        .all(&**db)
+        .await?
+        .into_iter()
+        .map(|database_wallet| database_wallet.id);
+
+    let mut checked = 0;
+    let mut drifted = Vec::new();
+    for wallet_id in team_wallet_ids.chain(player_wallet_ids) {
+        let wallet_id = DatabaseId(wallet_id);
+        let mut balances = db.balances(wallet_id).await?;
+        let mut expected = db.transaction_balances(wallet_id).await?;
+        // A currency that's all been spent can be missing from either side
+        balances.retain(|_, amount| *amount != 0);
+        expected.retain(|_, amount| *amount != 0);
+
+        checked += 1;
+        if balances != expected {
+            drifted.push(format!("#{}", *wallet_id));
+        }
+    }
+
+    if drifted.is_empty() {
+        return Ok(format!("All {} wallets match their transactions", checked));
+    }
+
+    Ok(format!(
+        "{} of {} wallets don't match their transactions: {}",
+        drifted.len(),
+        checked,
+        drifted.join(", ")
+    ))
+}
@@ -0,0 +1,55 @@
+use async_trait::async_trait;
+use serenity::{
+    all::{GuildId, ResolvedOption, UserId},
+    builder::CreateCommand,
+    model::Permissions,
+    prelude::Context,
+};
+
+use crate::{
+    db_wrapper::DBWrapper, game_mechanics::export::export_game, task_runner::tasks::DiscordId,
+};
+
+use super::{CommandAttachment, CommandResponse, GameCommand};
+
+pub struct ExportGame;
+
+#[async_trait]
+impl GameCommand for ExportGame {
+    fn register() -> CreateCommand {
+        CreateCommand::new("export")
+            .description("Save the game to a file that can be imported later")
+            .default_member_permissions(Permissions::ADMINISTRATOR)
+    }
+
+    async fn run(
+        _options: &[ResolvedOption],
+        guild_id: GuildId,
+        _user_id: UserId,
+        db: DBWrapper,
+        _ctx: Context,
+    ) -> CommandResponse {
+        let export = match export_game(&db, DiscordId::from(guild_id)).await {
+            Ok(export) => export,
+            Err(why) => return format!("Couldn't export the game: {}", why).into(),
+        };
+
+        CommandResponse {
+            content: format!(
+                "Exported {} teams, {} players and {} tasks",
+                export.teams.len(),
+                export.players.len(),
+                export.tasks.len()
+            ),
+            attachment: Some(CommandAttachment {
+                filename: format!(
+                    "megagame-{}-{}.json",
+                    guild_id.0,
+                    export.exported_at.format("%Y%m%d%H%M%S")
+                ),
+                data: serde_json::to_vec_pretty(&export).unwrap(),
+            }),
+            ..Default::default()
+        }
+    }
+}
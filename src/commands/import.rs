@@ -0,0 +1,69 @@
+use async_trait::async_trait;
+use serenity::{
+    all::{CommandOptionType, GuildId, ResolvedOption, ResolvedValue, UserId},
+    builder::{CreateCommand, CreateCommandOption},
+    model::Permissions,
+    prelude::Context,
+};
+
+use crate::{
+    db_wrapper::DBWrapper,
+    discord::SerenityBackend,
+    game_mechanics::export::{import_game, GameExport},
+    task_runner::tasks::DiscordId,
+};
+
+use super::{CommandResponse, GameCommand};
+
+pub struct ImportGame;
+
+#[async_trait]
+impl GameCommand for ImportGame {
+    fn register() -> CreateCommand {
+        CreateCommand::new("import")
+            .description("Set up a game from a file made with /export")
+            .default_member_permissions(Permissions::ADMINISTRATOR)
+            .add_option(
+                CreateCommandOption::new(
+                    CommandOptionType::Attachment,
+                    "file",
+                    "The exported game",
+                )
+                .required(true),
+            )
+    }
+
+    async fn run(
+        options: &[ResolvedOption],
+        guild_id: GuildId,
+        _user_id: UserId,
+        db: DBWrapper,
+        ctx: Context,
+    ) -> CommandResponse {
+        let Some(attachment) = options.iter().find_map(|option| match option.value {
+            ResolvedValue::Attachment(attachment) if option.name == "file" => Some(attachment),
+            _ => None,
+        }) else {
+            return "Attach the file to import".to_string().into();
+        };
+
+        let data = match attachment.download().await {
+            Ok(data) => data,
+            Err(why) => {
+                return format!("Couldn't download {}: {}", attachment.filename, why).into()
+            }
+        };
+
+        let export: GameExport = match serde_json::from_slice(&data) {
+            Ok(export) => export,
+            Err(why) => return format!("{} isn't an export: {}", attachment.filename, why).into(),
+        };
+
+        let discord = SerenityBackend::new(ctx);
+        match import_game(&db, &discord, DiscordId::from(guild_id), &export).await {
+            Ok(report) => report.to_string(),
+            Err(why) => format!("Couldn't import the game: {}", why),
+        }
+        .into()
+    }
+}
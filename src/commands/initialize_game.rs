@@ -1,69 +1,138 @@
+use std::path::PathBuf;
+
 use async_trait::async_trait;
 use serenity::{
-    all::{GuildId, ResolvedOption},
-    builder::CreateCommand,
+    all::{ButtonStyle, CommandOptionType, GuildId, ResolvedOption, ResolvedValue, UserId},
+    builder::{CreateButton, CreateCommand, CreateCommandOption},
+    model::Permissions,
     prelude::Context,
 };
 
 use crate::{
     db_wrapper::DBWrapper,
     game_mechanics::{
-        menu::{MenuJobs, MenuMechanicsHandler},
-        team::{TeamJobs, TeamMechanicsHandler},
-        MechanicHandler, MechanicHandlerWrapper,
+        scenario::{
+            plan_scenario, Scenario, ScenarioDirectory, ScenarioFormat, ScenarioJobs,
+            ScenarioMechanicsHandler, DEFAULT_SCENARIO_DIR,
+        },
+        MechanicFunction,
+    },
+    task_runner::tasks::{
+        message::message_component::{MessageComponent, MessageData},
+        DiscordId,
     },
-    task_runner::tasks::DiscordId,
 };
 
-use super::GameCommand;
+use super::{CommandResponse, GameCommand};
 
 pub struct InitializeGame;
 
 #[async_trait]
 impl GameCommand for InitializeGame {
     fn register() -> CreateCommand {
-        CreateCommand::new("initialize").description("Initialize the game")
+        CreateCommand::new("initialize")
+            .description("Set up a game from a scenario")
+            .default_member_permissions(Permissions::ADMINISTRATOR)
+            .add_option(CreateCommandOption::new(
+                CommandOptionType::Attachment,
+                "file",
+                "A TOML or JSON scenario file",
+            ))
+            .add_option(CreateCommandOption::new(
+                CommandOptionType::String,
+                "scenario",
+                "The name of a scenario the bot has stored",
+            ))
+            .add_option(CreateCommandOption::new(
+                CommandOptionType::Boolean,
+                "dry_run",
+                "Only list what would be made",
+            ))
     }
 
     async fn run(
-        _options: &[ResolvedOption],
+        options: &[ResolvedOption],
         guild_id: GuildId,
+        _user_id: UserId,
         db: DBWrapper,
         ctx: Context,
-    ) -> String {
-        let mut names = vec![];
+    ) -> CommandResponse {
+        let guild_id = DiscordId::from(guild_id);
 
-        // Make 3 teams, the Airship, the Galleon, and the Submarine
-        for name in ["Airship", "Galleon", "Submarine"] {
-            // Add a random string to the name
-            let name = format!("{}-{}", name, rand::random::<u16>());
+        let mut file = None;
+        let mut scenario_name = None;
+        let mut dry_run = false;
+        for option in options {
+            match (option.name, &option.value) {
+                ("file", ResolvedValue::Attachment(attachment)) => file = Some(*attachment),
+                ("scenario", ResolvedValue::String(value)) => scenario_name = Some(*value),
+                ("dry_run", ResolvedValue::Boolean(value)) => dry_run = *value,
+                _ => {}
+            }
+        }
 
-            names.push(name.clone());
+        // An uploaded file comes first, then a stored scenario, and without
+        // either the default one is used
+        let scenario = match (file, scenario_name) {
+            (Some(attachment), _) => {
+                let data = match attachment.download().await {
+                    Ok(data) => data,
+                    Err(why) => {
+                        return format!("Couldn't download {}: {}", attachment.filename, why).into()
+                    }
+                };
 
-            TeamMechanicsHandler {
-                task: TeamJobs::CreateTeam {
-                    name: name.to_string(),
-                },
-                guild_id: DiscordId::from(guild_id),
+                match String::from_utf8(data) {
+                    Ok(contents) => Scenario::parse(
+                        &contents,
+                        ScenarioFormat::from_filename(&attachment.filename),
+                    ),
+                    Err(_) => return format!("{} isn't text", attachment.filename).into(),
+                }
             }
-            .handle(MechanicHandlerWrapper {
-                db: db.clone(),
-                interaction: None,
-                ctx: ctx.clone(),
-            })
-            .await;
-        }
+            (None, Some(scenario_name)) => {
+                let scenario_dir = ctx
+                    .data
+                    .read()
+                    .await
+                    .get::<ScenarioDirectory>()
+                    .cloned()
+                    .unwrap_or_else(|| PathBuf::from(DEFAULT_SCENARIO_DIR));
 
-        // Make a general role channel to be able to change your team
-        MenuMechanicsHandler {
-            task: MenuJobs::RoleChangeMenu { team_names: names },
-            guild_id: DiscordId::from(guild_id),
-        }.handle(MechanicHandlerWrapper {
-            db,
-            interaction: None,
-            ctx,
-        }).await;
+                Scenario::load(&scenario_dir, scenario_name)
+            }
+            (None, None) => Ok(Scenario::default_scenario()),
+        };
+
+        let plan = match scenario {
+            Ok(scenario) => plan_scenario(&db, guild_id, scenario).await,
+            Err(why) => Err(why),
+        };
+        let plan = match plan {
+            Ok(plan) => plan,
+            Err(why) => return why.to_string().into(),
+        };
 
-        "Made a team!".to_string()
+        if dry_run {
+            return format!("Initializing would set up {}", plan).into();
+        }
+
+        CommandResponse {
+            content: format!("This will set up {}Press the button to go ahead.", plan),
+            buttons: vec![MessageComponent::new(
+                CreateButton::new("")
+                    .style(ButtonStyle::Primary)
+                    .label("Set up the game"),
+                Some(MessageData::Function(MechanicFunction::Scenario(
+                    ScenarioMechanicsHandler {
+                        guild_id,
+                        task: ScenarioJobs::Confirm {
+                            scenario: plan.scenario,
+                        },
+                    },
+                ))),
+            )],
+            ..Default::default()
+        }
     }
 }
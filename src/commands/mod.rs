@@ -1,20 +1,30 @@
 use async_trait::async_trait;
 
 use serenity::{
-    all::ResolvedOption, builder::CreateCommand, model::prelude::GuildId, prelude::Context,
+    all::ResolvedOption,
+    builder::{CreateButton, CreateCommand},
+    model::prelude::{GuildId, UserId},
+    prelude::Context,
 };
 
-use crate::db_wrapper::DBWrapper;
+use crate::{
+    db_wrapper::DBWrapper, task_runner::tasks::message::message_component::MessageComponent,
+};
 
-pub mod fake_trade;
+pub mod bank;
+pub mod export;
+pub mod import;
 pub mod initialize_game;
 pub mod nuke;
+pub mod reconcile;
+pub mod trade;
+pub mod wallet;
 
 /// The `GameCommand` trait defines methods for registering and running game
 /// commands within the Serenity Discord bot crate. The register method allows a
 /// `CreateApplicationCommand` instance to be registered as a game command, and
-/// the run method takes a list of `CommandDataOptions` as input and returns a
-/// string result. These methods enable developers to easily create and execute
+/// the run method takes a list of `CommandDataOptions` and the user who ran
+/// the command as input and returns a string result. These methods enable developers to easily create and execute
 /// custom game commands within the Discord bot.
 #[async_trait]
 pub trait GameCommand {
@@ -22,7 +32,50 @@ pub trait GameCommand {
     async fn run(
         options: &[ResolvedOption],
         guild_id: GuildId,
+        user_id: UserId,
         db: DBWrapper,
         ctx: Context,
-    ) -> String;
+    ) -> CommandResponse;
+}
+
+/// Discord won't send a message longer than this
+const MAX_MESSAGE_LENGTH: usize = 2000;
+
+/// What a command replies with. The buttons are built and saved once the
+/// reply is sent.
+#[derive(Default)]
+pub struct CommandResponse {
+    pub content: String,
+    pub buttons: Vec<MessageComponent<CreateButton>>,
+    pub attachment: Option<CommandAttachment>,
+}
+
+/// A file sent along with a command's reply
+pub struct CommandAttachment {
+    pub filename: String,
+    pub data: Vec<u8>,
+}
+
+impl CommandResponse {
+    /// The content, cut short if it's too long for Discord
+    pub fn truncated_content(&self) -> String {
+        if self.content.len() <= MAX_MESSAGE_LENGTH {
+            return self.content.clone();
+        }
+
+        let mut end = MAX_MESSAGE_LENGTH - 3;
+        while !self.content.is_char_boundary(end) {
+            end -= 1;
+        }
+        format!("{}...", &self.content[..end])
+    }
+}
+
+impl From<String> for CommandResponse {
+    fn from(content: String) -> Self {
+        Self {
+            content,
+            ..Default::default()
+        }
+    }
 }
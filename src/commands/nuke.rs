@@ -1,19 +1,27 @@
 use async_trait::async_trait;
 use serenity::{
-    all::{CommandOptionType, GuildId, ResolvedOption},
-    builder::{CreateCommand, CreateCommandOption},
+    all::{ButtonStyle, CommandOptionType, GuildId, ResolvedOption, ResolvedValue, UserId},
+    builder::{CreateButton, CreateCommand, CreateCommandOption},
+    model::Permissions,
     prelude::Context,
 };
 
 use crate::{
-    db_wrapper::DBWrapper,
+    db_wrapper::{
+        helpers::{get_database_guild, guild_settings},
+        DBWrapper,
+    },
+    game_mechanics::{
+        reset::{plan_reset, ResetJobs, ResetMechanicsHandler},
+        MechanicFunction,
+    },
     task_runner::tasks::{
-        channel::{ChannelHandler, ChannelTasks},
-        DatabaseId, DiscordId, TaskType,
+        message::message_component::{MessageComponent, MessageData},
+        DiscordId,
     },
 };
 
-use super::GameCommand;
+use super::{CommandResponse, GameCommand};
 
 pub struct Nuke;
 
@@ -21,85 +29,94 @@ pub struct Nuke;
 impl GameCommand for Nuke {
     fn register() -> CreateCommand {
         CreateCommand::new("reset")
-            .description("Nuke the server")
+            .description("Delete everything the bot made for the game")
+            .default_member_permissions(Permissions::ADMINISTRATOR)
+            .add_option(
+                CreateCommandOption::new(
+                    CommandOptionType::String,
+                    "password",
+                    "Confirm that you want to reset the game",
+                )
+                .required(true),
+            )
+            .add_option(CreateCommandOption::new(
+                CommandOptionType::Boolean,
+                "dry_run",
+                "Only list what would be deleted",
+            ))
             .add_option(CreateCommandOption::new(
-                CommandOptionType::String,
-                "password",
-                "Confirm that you want to nuke the server",
+                CommandOptionType::Boolean,
+                "snapshot",
+                "Save the game's tables before deleting anything (on by default)",
             ))
     }
 
     async fn run(
-        _options: &[ResolvedOption],
+        options: &[ResolvedOption],
         guild_id: GuildId,
+        _user_id: UserId,
         db: DBWrapper,
-        ctx: Context,
-    ) -> String {
-        // // Check the password
-        // if let ResolvedValue::String(s) = options
-        //     .iter()
-        //     .find(|option| option.name == "password")
-        //     .unwrap()
-        //     .value
-        // {
-        //     if s != "nuke" {
-        //         return "Invalid password".to_string();
-        //     }
-        // } else {
-        //     return "Invalid password".to_string();
-        // };
-
-        let mut tasks: Vec<DatabaseId> = vec![];
-
-        // Delete every role possible
+        _ctx: Context,
+    ) -> CommandResponse {
+        let guild_id = DiscordId::from(guild_id);
 
-        // Get all the roles
-        let _roles = ctx
-            .http
-            .get_guild_roles(guild_id)
-            .await
-            .expect("Failed to get roles");
-
-        // // Queue up all the deletions
-        // for role in roles {
-        //     tasks.push(
-        //         db.add_task(TaskType::RoleHandler(RoleHandler {
-        //             task: RoleTasks::DeleteRole(DeleteRole {
-        //                 role_id: DiscordId::from(role.id),
-        //             }),
-        //             guild_id: DiscordId::from(guild_id),
-        //         }))
-        //         .await,
-        //     );
-        // }
+        let mut password = None;
+        let mut dry_run = false;
+        let mut snapshot = true;
+        for option in options {
+            match (option.name, &option.value) {
+                ("password", ResolvedValue::String(value)) => password = Some(*value),
+                ("dry_run", ResolvedValue::Boolean(value)) => dry_run = *value,
+                ("snapshot", ResolvedValue::Boolean(value)) => snapshot = *value,
+                _ => {}
+            }
+        }
 
-        // Delete every channel possible
+        // Check the password
+        let settings = match get_database_guild(&db, guild_id).await {
+            Ok(database_guild) => guild_settings(&database_guild),
+            Err(why) => return format!("Couldn't look up the server: {}", why).into(),
+        };
+        let settings = match settings {
+            Ok(settings) => settings,
+            Err(why) => return format!("The server's settings are invalid: {:?}", why).into(),
+        };
+        let Some(reset_password) = settings.reset_password.as_deref() else {
+            return "This server can't be reset until a `reset_password` is set in its settings"
+                .to_string()
+                .into();
+        };
+        if password != Some(reset_password) {
+            return "Invalid password".to_string().into();
+        }
 
-        // Get all the text channels/voice channels/categories
-        let channels = ctx
-            .http
-            .get_channels(guild_id)
-            .await
-            .expect("Failed to get channels");
+        let plan = match plan_reset(&db, guild_id).await {
+            Ok(plan) => plan,
+            Err(why) => return format!("Couldn't work out what to reset: {}", why).into(),
+        };
 
-        // Queue up all the deletions
-        for channel in channels {
-            tasks.push(
-                db.add_task(TaskType::ChannelHandler(ChannelHandler {
-                    task: ChannelTasks::Delete {
-                        id: DiscordId::from(channel.id),
-                    },
-                    guild_id: DiscordId::from(guild_id),
-                }))
-                .await,
-            );
+        if plan.is_empty() {
+            return "There's nothing to reset".to_string().into();
         }
 
-        // Wait for all the tasks to finish
-        for task in tasks {
-            db.await_task(task).await;
+        if dry_run {
+            return format!("A reset would delete {}", plan).into();
         }
 
-        "Nuked the server!".to_string()
+        CommandResponse {
+            content: format!("This will delete {}Press the button to go ahead.", plan),
+            buttons: vec![MessageComponent::new(
+                CreateButton::new("")
+                    .style(ButtonStyle::Danger)
+                    .label("Reset the game"),
+                Some(MessageData::Function(MechanicFunction::Reset(
+                    ResetMechanicsHandler {
+                        guild_id,
+                        task: ResetJobs::Confirm { plan, snapshot },
+                    },
+                ))),
+            )],
+            ..Default::default()
+        }
     }
 }
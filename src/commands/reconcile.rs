@@ -0,0 +1,49 @@
+use async_trait::async_trait;
+use serenity::{
+    all::{CommandOptionType, GuildId, ResolvedOption, ResolvedValue, UserId},
+    builder::{CreateCommand, CreateCommandOption},
+    prelude::Context,
+};
+
+use crate::{
+    db_wrapper::DBWrapper,
+    task_runner::tasks::{reconcile::Reconcile, DiscordId},
+};
+
+use super::{CommandResponse, GameCommand};
+
+pub struct ReconcileGuild;
+
+#[async_trait]
+impl GameCommand for ReconcileGuild {
+    fn register() -> CreateCommand {
+        CreateCommand::new("reconcile")
+            .description("Check that the game's channels and roles match the server")
+            .add_option(CreateCommandOption::new(
+                CommandOptionType::Boolean,
+                "repair",
+                "Fix anything that doesn't match",
+            ))
+    }
+
+    async fn run(
+        options: &[ResolvedOption],
+        guild_id: GuildId,
+        _user_id: UserId,
+        db: DBWrapper,
+        _ctx: Context,
+    ) -> CommandResponse {
+        let repair = options.iter().any(|option| {
+            option.name == "repair" && matches!(option.value, ResolvedValue::Boolean(true))
+        });
+
+        match db
+            .run(DiscordId::from(guild_id), Reconcile { repair })
+            .await
+        {
+            Ok(report) => report.to_string(),
+            Err(why) => format!("Couldn't reconcile the server: {}", why),
+        }
+        .into()
+    }
+}
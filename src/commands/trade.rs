@@ -0,0 +1,223 @@
+use async_trait::async_trait;
+use entity::entities::{team, trade};
+use sea_orm::{ColumnTrait, EntityTrait, QueryFilter};
+use serenity::{
+    all::{CommandOptionType, GuildId, ResolvedOption, ResolvedValue, UserId},
+    builder::{CreateCommand, CreateCommandOption},
+    prelude::Context,
+};
+
+use crate::{
+    db_wrapper::{helpers::get_player_team, DBWrapper},
+    discord::SerenityBackend,
+    game_mechanics::trade::{set_trade_item, start_trade, TradeError, TradeSummary},
+    task_runner::tasks::{DatabaseId, DiscordId},
+};
+
+use super::{CommandResponse, GameCommand};
+
+pub struct TradeCommand;
+
+#[async_trait]
+impl GameCommand for TradeCommand {
+    fn register() -> CreateCommand {
+        CreateCommand::new("trade")
+            .description("Trade with another team")
+            .add_option(
+                CreateCommandOption::new(
+                    CommandOptionType::SubCommand,
+                    "start",
+                    "Start a trade with another team",
+                )
+                .add_sub_option(
+                    CreateCommandOption::new(
+                        CommandOptionType::Role,
+                        "team",
+                        "The role of the team to trade with",
+                    )
+                    .required(true),
+                ),
+            )
+            .add_option(
+                CreateCommandOption::new(
+                    CommandOptionType::SubCommand,
+                    "item",
+                    "Set how much of a currency a team gives in your draft",
+                )
+                .add_sub_option(
+                    CreateCommandOption::new(CommandOptionType::Integer, "trade", "The trade")
+                        .required(true),
+                )
+                .add_sub_option(
+                    CreateCommandOption::new(
+                        CommandOptionType::String,
+                        "currency",
+                        "The currency's name",
+                    )
+                    .required(true),
+                )
+                .add_sub_option(
+                    CreateCommandOption::new(
+                        CommandOptionType::Integer,
+                        "amount",
+                        "How much, or 0 to take it out of the trade",
+                    )
+                    .required(true)
+                    .min_int_value(0),
+                )
+                .add_sub_option(
+                    CreateCommandOption::new(
+                        CommandOptionType::String,
+                        "direction",
+                        "Whether your team gives or receives it",
+                    )
+                    .required(true)
+                    .add_string_choice("give", "give")
+                    .add_string_choice("receive", "receive"),
+                ),
+            )
+            .add_option(
+                CreateCommandOption::new(CommandOptionType::SubCommand, "show", "Show a trade")
+                    .add_sub_option(
+                        CreateCommandOption::new(CommandOptionType::Integer, "trade", "The trade")
+                            .required(true),
+                    ),
+            )
+    }
+
+    async fn run(
+        options: &[ResolvedOption],
+        guild_id: GuildId,
+        user_id: UserId,
+        db: DBWrapper,
+        ctx: Context,
+    ) -> CommandResponse {
+        let guild_id = DiscordId::from(guild_id);
+
+        let Some((subcommand, options)) = options.iter().find_map(|option| match &option.value {
+            ResolvedValue::SubCommand(options) => Some((option.name, options)),
+            _ => None,
+        }) else {
+            return "Pick what to do with the trade".to_string().into();
+        };
+
+        let discord = SerenityBackend::new(ctx);
+        let database_team =
+            match get_player_team(&discord, db.clone(), guild_id, DiscordId::from(user_id)).await {
+                Ok(database_team) => database_team,
+                Err(why) => return TradeError::from(why).to_string().into(),
+            };
+
+        let result = match subcommand {
+            "start" => start(&db, guild_id, &database_team, options).await,
+            "item" => item(&db, &database_team, options).await,
+            "show" => show(&db, &database_team, options).await,
+            _ => unreachable!(),
+        };
+
+        match result {
+            Ok(summary) => CommandResponse {
+                content: summary.to_string(),
+                buttons: summary.buttons(guild_id, database_team.id),
+                ..Default::default()
+            },
+            Err(why) => why.to_string().into(),
+        }
+    }
+}
+
+fn integer_option(options: &[ResolvedOption], name: &str) -> Option<i64> {
+    options.iter().find_map(|option| match option.value {
+        ResolvedValue::Integer(value) if option.name == name => Some(value),
+        _ => None,
+    })
+}
+
+fn string_option<'a>(options: &[ResolvedOption<'a>], name: &str) -> Option<&'a str> {
+    options.iter().find_map(|option| match option.value {
+        ResolvedValue::String(value) if option.name == name => Some(value),
+        _ => None,
+    })
+}
+
+async fn start(
+    db: &DBWrapper,
+    guild_id: DiscordId,
+    database_team: &team::Model,
+    options: &[ResolvedOption<'_>],
+) -> Result<TradeSummary, TradeError> {
+    let role_id = options
+        .iter()
+        .find_map(|option| match option.value {
+            ResolvedValue::Role(role) => Some(DiscordId::from(role.id)),
+            _ => None,
+        })
+        .ok_or(TradeError::TeamNotFound)?;
+
+    let receiving_team = team::Entity::find()
+        .filter(team::Column::FkGuildId.eq(*guild_id as i64))
+        .filter(team::Column::FkTeamRoleId.eq(*role_id as i64))
+        .one(&**db)
+        .await?
+        .ok_or(TradeError::TeamNotFound)?;
+
+    let database_trade = start_trade(
+        db,
+        guild_id,
+        DatabaseId(database_team.id),
+        DatabaseId(receiving_team.id),
+    )
+    .await?;
+
+    TradeSummary::load(db, database_trade).await
+}
+
+async fn item(
+    db: &DBWrapper,
+    database_team: &team::Model,
+    options: &[ResolvedOption<'_>],
+) -> Result<TradeSummary, TradeError> {
+    let (Some(trade_id), Some(currency_name), Some(amount), Some(direction)) = (
+        integer_option(options, "trade"),
+        string_option(options, "currency"),
+        integer_option(options, "amount"),
+        string_option(options, "direction"),
+    ) else {
+        return Err(TradeError::NotFound);
+    };
+
+    let database_trade = set_trade_item(
+        db,
+        DatabaseId(trade_id as i32),
+        DatabaseId(database_team.id),
+        direction == "give",
+        currency_name,
+        amount,
+    )
+    .await?;
+
+    TradeSummary::load(db, database_trade).await
+}
+
+async fn show(
+    db: &DBWrapper,
+    database_team: &team::Model,
+    options: &[ResolvedOption<'_>],
+) -> Result<TradeSummary, TradeError> {
+    let trade_id = integer_option(options, "trade").ok_or(TradeError::NotFound)?;
+
+    let database_trade = trade::Entity::find_by_id(trade_id as i32)
+        .one(&**db)
+        .await?
+        .ok_or(TradeError::NotFound)?;
+    if ![
+        database_trade.fk_initiating_team_id,
+        database_trade.fk_receiving_team_id,
+    ]
+    .contains(&database_team.id)
+    {
+        return Err(TradeError::NotInTrade);
+    }
+
+    TradeSummary::load(db, database_trade).await
+}
@@ -0,0 +1,160 @@
+use std::collections::HashMap;
+
+use async_trait::async_trait;
+use entity::entities::{currency, transaction};
+use sea_orm::{DbErr, EntityTrait};
+use serenity::{
+    all::{GuildId, ResolvedOption, UserId},
+    builder::CreateCommand,
+    prelude::Context,
+};
+
+use crate::{
+    db_wrapper::{
+        helpers::{get_guild_player, get_player_team},
+        ledger::{player_wallet, team_wallet},
+        DBWrapper,
+    },
+    discord::SerenityBackend,
+    task_runner::tasks::{DatabaseId, DiscordId},
+};
+
+use super::{CommandResponse, GameCommand};
+
+/// How many of the team's transactions are shown
+const RECENT_TRANSACTIONS: usize = 10;
+
+pub struct WalletCommand;
+
+#[async_trait]
+impl GameCommand for WalletCommand {
+    fn register() -> CreateCommand {
+        CreateCommand::new("wallet").description("Show your and your team's wallets")
+    }
+
+    async fn run(
+        _options: &[ResolvedOption],
+        guild_id: GuildId,
+        user_id: UserId,
+        db: DBWrapper,
+        ctx: Context,
+    ) -> CommandResponse {
+        let guild_id = DiscordId::from(guild_id);
+        let user_id = DiscordId::from(user_id);
+
+        let database_player = match get_guild_player(&db, guild_id, user_id).await {
+            Ok(Some(database_player)) => database_player,
+            Ok(None) => return "You haven't joined the game".to_string().into(),
+            Err(why) => return format!("Couldn't find your wallet: {}", why).into(),
+        };
+
+        let discord = SerenityBackend::new(ctx);
+        let database_team = get_player_team(&discord, db.clone(), guild_id, user_id)
+            .await
+            .ok();
+
+        match describe_wallets(&db, database_player.id, database_team.map(|team| team.id)).await {
+            Ok(content) => content.into(),
+            Err(why) => format!("Couldn't find your wallet: {}", why).into(),
+        }
+    }
+}
+
+async fn describe_wallets(
+    db: &DBWrapper,
+    player_id: i32,
+    team_id: Option<i32>,
+) -> Result<String, DbErr> {
+    let currency_names: HashMap<i32, String> = currency::Entity::find()
+        .all(&**db)
+        .await?
+        .into_iter()
+        .map(|database_currency| (database_currency.id, database_currency.name))
+        .collect();
+
+    let player_wallet_id = player_wallet(&**db, player_id).await?;
+    let mut content = format!(
+        "**Your wallet**\n{}",
+        describe_balances(&db.balances(player_wallet_id).await?, &currency_names)
+    );
+
+    let Some(team_id) = team_id else {
+        return Ok(content);
+    };
+
+    let team_wallet_id = team_wallet(&**db, team_id).await?;
+    content.push_str(&format!(
+        "\n**Your team's wallet**\n{}",
+        describe_balances(&db.balances(team_wallet_id).await?, &currency_names)
+    ));
+
+    let history = db.wallet_history(team_wallet_id).await?;
+    if !history.is_empty() {
+        content.push_str("\n**Recent transactions**\n");
+        for database_transaction in history.iter().rev().take(RECENT_TRANSACTIONS) {
+            content.push_str(&describe_transaction(
+                database_transaction,
+                team_wallet_id,
+                &currency_names,
+            ));
+        }
+    }
+
+    Ok(content)
+}
+
+fn describe_balances(
+    balances: &HashMap<i32, i64>,
+    currency_names: &HashMap<i32, String>,
+) -> String {
+    let mut lines: Vec<String> = balances
+        .iter()
+        .filter(|(_, amount)| **amount != 0)
+        .map(|(currency_id, amount)| {
+            format!(
+                "{} {}\n",
+                amount,
+                currency_name(currency_names, *currency_id)
+            )
+        })
+        .collect();
+    if lines.is_empty() {
+        return "Empty\n".to_string();
+    }
+
+    lines.sort();
+    lines.concat()
+}
+
+/// One line for a transaction, from the point of view of the wallet
+fn describe_transaction(
+    database_transaction: &transaction::Model,
+    wallet_id: DatabaseId,
+    currency_names: &HashMap<i32, String>,
+) -> String {
+    let sign = if database_transaction.fk_to_wallet_id == Some(*wallet_id) {
+        "+"
+    } else {
+        "-"
+    };
+
+    format!(
+        "#{} {}{} {}{}\n",
+        database_transaction.id,
+        sign,
+        database_transaction.amount,
+        currency_name(currency_names, database_transaction.fk_currency_id),
+        database_transaction
+            .memo
+            .as_ref()
+            .map(|memo| format!(" ({})", memo))
+            .unwrap_or_default()
+    )
+}
+
+fn currency_name(currency_names: &HashMap<i32, String>, currency_id: i32) -> &str {
+    currency_names
+        .get(&currency_id)
+        .map(String::as_str)
+        .unwrap_or("unknown currency")
+}
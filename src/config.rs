@@ -0,0 +1,399 @@
+use std::{
+    env, fmt, fs, io,
+    num::NonZeroU64,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tracing_subscriber::filter::Directive;
+
+use crate::{game_mechanics::scenario::DEFAULT_SCENARIO_DIR, task_runner::DEFAULT_TASK_WORKERS};
+
+/// The config file that's read if no other is given. Unlike one that was
+/// asked for, it's fine for this one not to exist.
+pub const DEFAULT_CONFIG_PATH: &str = "megagame.toml";
+
+/// The database to use if none is configured
+pub const DEFAULT_DATABASE_URL: &str = "postgres://postgres:postgres@db/postgres";
+
+/// Everything the bot can be configured with. This is read from a TOML file,
+/// then anything set in the environment overrides it:
+///
+/// | Field            | Environment variable |
+/// |------------------|----------------------|
+/// | `database_url`   | `DATABASE_URL`       |
+/// | `application_id` | `APPLICATION_ID`     |
+/// | `test_guild_id`  | `TEST_GUILD_ID`      |
+/// | `task_workers`   | `TASK_WORKERS`       |
+/// | `log_filters`    | `LOG_FILTERS`, comma separated |
+/// | `scenario_dir`   | `SCENARIO_DIR`       |
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// With the `sqlite` feature, this can be e.g.
+    /// `sqlite://megagame.db?mode=rwc` to use a local file
+    pub database_url: String,
+    /// Needed to register commands, so the bot won't start without it
+    pub application_id: Option<NonZeroU64>,
+    /// If set, commands are only registered in this guild, so changes to
+    /// them can be tried out without touching any real games
+    pub test_guild_id: Option<NonZeroU64>,
+    /// How many tasks can run at the same time
+    pub task_workers: usize,
+    /// `tracing` directives added on top of `RUST_LOG`, e.g. `serenity=info`
+    pub log_filters: Vec<String>,
+    /// Where the scenarios `/initialize` can be given by name are kept
+    pub scenario_dir: PathBuf,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        // Crates that are too noisy to log everything from
+        let quiet = [
+            "tokio_util",
+            "h2",
+            "rustls",
+            "serenity",
+            "tungstenite",
+            "async_tungstenite",
+            "hyper",
+            "trust_dns_resolver",
+            "trust_dns_proto",
+            "reqwest",
+            "mio",
+            "want",
+            "kube",
+            "tower",
+            "tokio_tungstenite",
+        ];
+
+        Self {
+            database_url: DEFAULT_DATABASE_URL.to_string(),
+            application_id: None,
+            test_guild_id: None,
+            task_workers: DEFAULT_TASK_WORKERS,
+            log_filters: quiet
+                .iter()
+                .map(|target| format!("{}=info", target))
+                .chain(["sqlx=off".to_string()])
+                .collect(),
+            scenario_dir: PathBuf::from(DEFAULT_SCENARIO_DIR),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Read {
+        path: PathBuf,
+        error: io::Error,
+    },
+    Parse {
+        path: PathBuf,
+        error: toml::de::Error,
+    },
+    Env {
+        name: &'static str,
+        value: String,
+        error: String,
+    },
+    Invalid {
+        field: &'static str,
+        reason: String,
+    },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Read { path, error } => {
+                write!(
+                    f,
+                    "Couldn't read the config file {}: {}",
+                    path.display(),
+                    error
+                )
+            }
+            ConfigError::Parse { path, error } => {
+                write!(
+                    f,
+                    "The config file {} is invalid: {}",
+                    path.display(),
+                    error
+                )
+            }
+            ConfigError::Env { name, value, error } => {
+                write!(f, "{}={:?} is invalid: {}", name, value, error)
+            }
+            ConfigError::Invalid { field, reason } => {
+                write!(f, "Invalid {}: {}", field, reason)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl Config {
+    /// Load the config from `path`, or from `MEGAGAME_CONFIG` if that's set,
+    /// or from `DEFAULT_CONFIG_PATH` if it exists. The environment overrides
+    /// are applied on top, and the result is checked before it's returned.
+    pub fn load(path: Option<&Path>) -> Result<Self, ConfigError> {
+        let path = path
+            .map(Path::to_path_buf)
+            .or_else(|| env::var_os("MEGAGAME_CONFIG").map(PathBuf::from));
+
+        let mut config = match path {
+            Some(path) => Self::from_file(&path)?,
+            None if Path::new(DEFAULT_CONFIG_PATH).exists() => {
+                Self::from_file(Path::new(DEFAULT_CONFIG_PATH))?
+            }
+            None => Self::default(),
+        };
+
+        config.apply_env()?;
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self, ConfigError> {
+        let contents = fs::read_to_string(path).map_err(|error| ConfigError::Read {
+            path: path.to_path_buf(),
+            error,
+        })?;
+
+        toml::from_str(&contents).map_err(|error| ConfigError::Parse {
+            path: path.to_path_buf(),
+            error,
+        })
+    }
+
+    /// Override anything that's set in the environment
+    fn apply_env(&mut self) -> Result<(), ConfigError> {
+        if let Some(database_url) = env_var("DATABASE_URL")? {
+            self.database_url = database_url;
+        }
+        if let Some(application_id) = env_var("APPLICATION_ID")? {
+            self.application_id = Some(application_id);
+        }
+        if let Some(test_guild_id) = env_var("TEST_GUILD_ID")? {
+            self.test_guild_id = Some(test_guild_id);
+        }
+        if let Some(task_workers) = env_var("TASK_WORKERS")? {
+            self.task_workers = task_workers;
+        }
+        if let Some(log_filters) = env_var::<String>("LOG_FILTERS")? {
+            self.log_filters = log_filters
+                .split(',')
+                .map(str::trim)
+                .filter(|filter| !filter.is_empty())
+                .map(str::to_string)
+                .collect();
+        }
+        if let Some(scenario_dir) = env_var("SCENARIO_DIR")? {
+            self.scenario_dir = scenario_dir;
+        }
+
+        Ok(())
+    }
+
+    /// Check everything that can be checked before connecting to anything
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let scheme = self.database_url.split(':').next().unwrap_or_default();
+        match scheme {
+            "postgres" | "postgresql" => {}
+            "sqlite" if cfg!(feature = "sqlite") => {}
+            "sqlite" => {
+                return Err(ConfigError::Invalid {
+                    field: "database_url",
+                    reason: "SQLite databases need the bot to be built with the `sqlite` feature"
+                        .to_string(),
+                })
+            }
+            _ => {
+                return Err(ConfigError::Invalid {
+                    field: "database_url",
+                    reason: format!("{:?} isn't a Postgres or SQLite URL", self.database_url),
+                })
+            }
+        }
+
+        if self.task_workers == 0 {
+            return Err(ConfigError::Invalid {
+                field: "task_workers",
+                reason: "at least one worker is needed to run tasks".to_string(),
+            });
+        }
+
+        for filter in &self.log_filters {
+            if let Err(why) = filter.parse::<Directive>() {
+                return Err(ConfigError::Invalid {
+                    field: "log_filters",
+                    reason: format!("{:?} isn't a valid filter: {}", filter, why),
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The application id, which has to be set for the bot to start
+    pub fn application_id(&self) -> Result<NonZeroU64, ConfigError> {
+        self.application_id.ok_or_else(|| ConfigError::Invalid {
+            field: "application_id",
+            reason: "it has to be set, either in the config file or with APPLICATION_ID"
+                .to_string(),
+        })
+    }
+
+    /// The filters to log with, on top of whatever `RUST_LOG` says
+    pub fn log_directives(&self) -> Vec<Directive> {
+        // These were checked in `validate`
+        self.log_filters
+            .iter()
+            .filter_map(|filter| filter.parse().ok())
+            .collect()
+    }
+}
+
+/// Read and parse an environment variable, if it's set
+fn env_var<T>(name: &'static str) -> Result<Option<T>, ConfigError>
+where
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    let Ok(value) = env::var(name) else {
+        return Ok(None);
+    };
+
+    value
+        .parse()
+        .map(Some)
+        .map_err(|why: T::Err| ConfigError::Env {
+            name,
+            error: why.to_string(),
+            value,
+        })
+}
+
+/// Settings that can differ between guilds. These are stored as JSON on the
+/// guild's row, and anything left out there uses its default.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct GuildSettings {
+    /// The color of the role each team gets
+    pub team_role_color: u32,
+    /// What has to be given to `/reset` for it to do anything. Until one is
+    /// set, the guild can't be reset.
+    pub reset_password: Option<String>,
+}
+
+impl Default for GuildSettings {
+    fn default() -> Self {
+        Self {
+            team_role_color: 0x00ff00,
+            reset_password: None,
+        }
+    }
+}
+
+impl GuildSettings {
+    /// Read settings stored on a guild's row, checking them as well
+    pub fn from_json(value: Value) -> Result<Self, ConfigError> {
+        let settings: Self = serde_json::from_value(value).map_err(|why| ConfigError::Invalid {
+            field: "guild settings",
+            reason: why.to_string(),
+        })?;
+
+        settings.validate()?;
+
+        Ok(settings)
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.team_role_color > 0xffffff {
+            return Err(ConfigError::Invalid {
+                field: "team_role_color",
+                reason: format!("{:#x} isn't an RGB color", self.team_role_color),
+            });
+        }
+
+        if self.reset_password.as_deref() == Some("") {
+            return Err(ConfigError::Invalid {
+                field: "reset_password",
+                reason: "it can't be empty".to_string(),
+            });
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_use_defaults_for_missing_fields() {
+        let config: Config = toml::from_str("task_workers = 8").unwrap();
+        assert_eq!(config.task_workers, 8);
+        assert_eq!(config.database_url, DEFAULT_DATABASE_URL);
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn should_reject_unknown_fields() {
+        assert!(toml::from_str::<Config>("task_worker = 8").is_err());
+    }
+
+    #[test]
+    fn should_reject_zero_task_workers() {
+        let config = Config {
+            task_workers: 0,
+            ..Default::default()
+        };
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn should_reject_bad_log_filters() {
+        let config = Config {
+            log_filters: vec!["serenity=loud".to_string()],
+            ..Default::default()
+        };
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn should_require_application_id() {
+        assert!(Config::default().application_id().is_err());
+        assert!(toml::from_str::<Config>("application_id = 0").is_err());
+    }
+
+    #[test]
+    fn should_read_empty_guild_settings_as_default() {
+        let settings = GuildSettings::from_json(serde_json::json!({})).unwrap();
+        assert_eq!(settings, GuildSettings::default());
+    }
+
+    #[test]
+    fn should_reject_bad_guild_settings() {
+        assert!(
+            GuildSettings::from_json(serde_json::json!({ "team_role_color": 0x1000000 })).is_err()
+        );
+        assert!(GuildSettings::from_json(serde_json::json!({ "team_colour": 0 })).is_err());
+        assert!(GuildSettings::from_json(serde_json::json!({ "reset_password": "" })).is_err());
+    }
+
+    #[test]
+    fn should_have_no_reset_password_by_default() {
+        assert_eq!(GuildSettings::default().reset_password, None);
+
+        let settings =
+            GuildSettings::from_json(serde_json::json!({ "reset_password": "hunter2" })).unwrap();
+        assert_eq!(settings.reset_password.as_deref(), Some("hunter2"));
+    }
+}
@@ -1,56 +1,423 @@
-use entity::entities::{guild, player, team};
-use sea_orm::{ActiveModelTrait, ColumnTrait, EntityTrait, QueryFilter, Set};
+use entity::entities::{category, channel, guild, player, role, team};
+use sea_orm::{
+    sea_query::{Expr, OnConflict},
+    ActiveModelTrait, ColumnTrait, Condition, ConnectionTrait, DbErr, EntityTrait, QueryFilter,
+    Set, TransactionTrait,
+};
+use serde::{Deserialize, Serialize};
 
-use serenity::{client::Context, model::prelude::Guild};
+use serenity::model::prelude::ChannelType;
 
-use crate::{db_wrapper::DBWrapper, task_runner::tasks::DiscordId};
+use crate::{
+    config::{ConfigError, GuildSettings},
+    db_wrapper::DBWrapper,
+    discord::{DiscordBackend, DiscordChannel, DiscordGuild, DiscordRole},
+    task_runner::{error::TaskError, tasks::DiscordId},
+};
 
 #[derive(Debug)]
 pub enum GameDatabaseError {
     PlayerNotFound,
     TeamNotFound,
     GuildNotFound,
+    InvalidGuildSettings(String),
+    Database(DbErr),
 }
 
-/// Get the guild from the cache and the database. If the guild is not in the
+impl From<DbErr> for GameDatabaseError {
+    fn from(why: DbErr) -> Self {
+        GameDatabaseError::Database(why)
+    }
+}
+
+impl From<GameDatabaseError> for TaskError {
+    fn from(why: GameDatabaseError) -> Self {
+        match why {
+            GameDatabaseError::Database(why) => why.into(),
+            why => TaskError::permanent(format!("{:?}", why)),
+        }
+    }
+}
+
+/// Get the guild from Discord and the database. If the guild is not in the
 /// database, it will be created.
-pub async fn get_guild(ctx: Context, db: DBWrapper, guild_id: DiscordId) -> (Guild, guild::Model) {
-    let discord_guild = ctx.cache.guild(guild_id).map(|g| g.clone()).unwrap();
+pub async fn get_guild(
+    discord: &dyn DiscordBackend,
+    db: DBWrapper,
+    guild_id: DiscordId,
+) -> Result<(DiscordGuild, guild::Model), GameDatabaseError> {
+    let discord_guild = discord
+        .guild(guild_id)
+        .await
+        .ok_or(GameDatabaseError::GuildNotFound)?;
 
-    // Get or create the guild
+    let database_guild = get_database_guild(&db, guild_id).await?;
+
+    Ok((discord_guild, database_guild))
+}
+
+/// Get the guild's row, creating it with the default settings if it doesn't
+/// exist yet
+pub async fn get_database_guild(
+    db: &DBWrapper,
+    guild_id: DiscordId,
+) -> Result<guild::Model, DbErr> {
     let guild_option = guild::Entity::find()
         .filter(guild::Column::DiscordId.eq(*guild_id as i64))
-        .one(&*db)
-        .await
-        .unwrap();
+        .one(&**db)
+        .await?;
 
-    let database_guild = match guild_option {
-        Some(guild) => guild,
-        None => guild::ActiveModel {
-            discord_id: Set(*guild_id as i64),
-            ..Default::default()
+    match guild_option {
+        Some(guild) => Ok(guild),
+        None => {
+            guild::ActiveModel {
+                discord_id: Set(*guild_id as i64),
+                settings: Set(serde_json::to_value(GuildSettings::default()).unwrap()),
+            }
+            .insert(&**db)
+            .await
         }
-        .insert(&*db)
+    }
+}
+
+/// Read the settings stored for a guild
+pub fn guild_settings(database_guild: &guild::Model) -> Result<GuildSettings, GameDatabaseError> {
+    GuildSettings::from_json(database_guild.settings.clone())
+        .map_err(|why| GameDatabaseError::InvalidGuildSettings(why.to_string()))
+}
+
+/// Find a channel in the guild that looks like the one a task was going to
+/// create, but that no task has saved. A retried task uses this to pick up a
+/// channel that an earlier attempt created right before the bot stopped. The
+/// channel might have been saved from a gateway event in the meantime, which
+/// leaves it without an idempotency key.
+pub async fn find_untracked_channel(
+    discord_guild: &DiscordGuild,
+    db: DBWrapper,
+    name: &str,
+    kind: ChannelType,
+    parent_id: Option<DiscordId>,
+) -> Result<Option<DiscordChannel>, DbErr> {
+    let name = channel_name(name, kind);
+    let candidates = discord_guild.channels.iter().filter(|discord_channel| {
+        channel_name(&discord_channel.name, kind) == name
+            && discord_channel.kind == kind
+            && discord_channel.parent_id == parent_id
+    });
+
+    for discord_channel in candidates {
+        let discord_id = *discord_channel.id as i64;
+
+        // Categories are channels as far as Discord is concerned
+        let is_claimed = channel::Entity::find_by_id(discord_id)
+            .one(&*db)
+            .await?
+            .map_or(false, |channel| channel.idempotency_key.is_some())
+            || category::Entity::find_by_id(discord_id)
+                .one(&*db)
+                .await?
+                .map_or(false, |category| category.idempotency_key.is_some());
+
+        if !is_claimed {
+            return Ok(Some(discord_channel.clone()));
+        }
+    }
+
+    Ok(None)
+}
+
+/// The name Discord gives a channel that's created as `name`. Text channel
+/// names are lowercased with dashes for spaces, so "Team Chat" comes back as
+/// "team-chat".
+fn channel_name(name: &str, kind: ChannelType) -> String {
+    if kind != ChannelType::Text {
+        return name.to_string();
+    }
+
+    name.split_whitespace()
+        .collect::<Vec<_>>()
+        .join("-")
+        .to_lowercase()
+}
+
+/// Find a role in the guild with the given name that no task has saved, the
+/// same way as `find_untracked_channel`
+pub async fn find_untracked_role(
+    discord_guild: &DiscordGuild,
+    db: DBWrapper,
+    name: &str,
+) -> Result<Option<DiscordRole>, DbErr> {
+    let candidates = discord_guild
+        .roles
+        .iter()
+        .filter(|discord_role| discord_role.name == name);
+
+    for discord_role in candidates {
+        if role::Entity::find_by_id(*discord_role.id as i64)
+            .one(&*db)
+            .await?
+            .map_or(true, |role| role.idempotency_key.is_none())
+        {
+            return Ok(Some(discord_role.clone()));
+        }
+    }
+
+    Ok(None)
+}
+
+/// The kinds of guild resources that are kept track of in the database
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TrackedKind {
+    Channel,
+    Category,
+    Role,
+}
+
+impl TrackedKind {
+    /// Categories are channels as far as Discord is concerned, but they have
+    /// a table of their own
+    pub fn of_channel(kind: ChannelType) -> Self {
+        if kind == ChannelType::Category {
+            TrackedKind::Category
+        } else {
+            TrackedKind::Channel
+        }
+    }
+
+    /// The team columns that can point at this kind of resource
+    fn team_columns(self) -> &'static [team::Column] {
+        match self {
+            TrackedKind::Channel => &[
+                team::Column::FkGeneralChannelId,
+                team::Column::FkTradeChannelId,
+                team::Column::FkMenuChannelId,
+            ],
+            TrackedKind::Category => &[team::Column::FkTeamCategoryId],
+            TrackedKind::Role => &[team::Column::FkTeamRoleId],
+        }
+    }
+}
+
+/// The team in the guild that uses a resource, if any does
+pub async fn team_using<C: ConnectionTrait>(
+    db: &C,
+    kind: TrackedKind,
+    guild_id: DiscordId,
+    id: DiscordId,
+) -> Result<Option<team::Model>, DbErr> {
+    let mut uses_resource = Condition::any();
+    for column in kind.team_columns() {
+        uses_resource = uses_resource.add(column.eq(*id as i64));
+    }
+
+    team::Entity::find()
+        .filter(team::Column::FkGuildId.eq(*guild_id as i64))
+        .filter(uses_resource)
+        .one(db)
         .await
-        .unwrap(),
+}
+
+/// Point every team column that uses `old_id` at `new_id` instead, or clear
+/// it if there's no new id
+async fn relink_teams<C: ConnectionTrait>(
+    db: &C,
+    kind: TrackedKind,
+    old_id: DiscordId,
+    new_id: Option<DiscordId>,
+) -> Result<(), DbErr> {
+    for column in kind.team_columns() {
+        team::Entity::update_many()
+            .col_expr(*column, Expr::value(new_id.map(|id| *id as i64)))
+            .filter(column.eq(*old_id as i64))
+            .exec(db)
+            .await?;
+    }
+
+    Ok(())
+}
+
+/// Remember what kind of channel a tracked channel is
+pub async fn save_channel_kind<C: ConnectionTrait>(
+    db: &C,
+    id: DiscordId,
+    kind: ChannelType,
+) -> Result<(), DbErr> {
+    channel::Entity::update_many()
+        .col_expr(channel::Column::Kind, Expr::value(u8::from(kind) as i16))
+        .filter(channel::Column::DiscordId.eq(*id as i64))
+        .exec(db)
+        .await?;
+
+    Ok(())
+}
+
+/// What kind of channel a tracked channel is. Channels saved before their
+/// kind was kept are taken to be text channels, which is all the bot made.
+pub fn saved_channel_kind(database_channel: &channel::Model) -> ChannelType {
+    database_channel
+        .kind
+        .map_or(ChannelType::Text, |kind| ChannelType::from(kind as u8))
+}
+
+/// Save a resource's row, or update it if it's already there. Gateway events
+/// can save a resource as soon as it's created, before the task that created
+/// it gets to, so the task takes over the row instead. The idempotency key is
+/// only changed if one is given.
+pub async fn save_resource<C: ConnectionTrait>(
+    db: &C,
+    kind: TrackedKind,
+    guild_id: DiscordId,
+    id: DiscordId,
+    name: &str,
+    idempotency_key: Option<String>,
+) -> Result<(), DbErr> {
+    let keep_key = idempotency_key.is_none();
+
+    match kind {
+        TrackedKind::Channel => {
+            let mut update = vec![channel::Column::FkGuildId, channel::Column::Name];
+            if !keep_key {
+                update.push(channel::Column::IdempotencyKey);
+            }
+
+            channel::Entity::insert(channel::ActiveModel {
+                discord_id: Set(*id as i64),
+                fk_guild_id: Set(Some(*guild_id as i64)),
+                name: Set(name.to_string()),
+                idempotency_key: Set(idempotency_key),
+                ..Default::default()
+            })
+            .on_conflict(
+                OnConflict::column(channel::Column::DiscordId)
+                    .update_columns(update)
+                    .to_owned(),
+            )
+            .exec(db)
+            .await?;
+        }
+        TrackedKind::Category => {
+            let mut update = vec![category::Column::FkGuildId, category::Column::Name];
+            if !keep_key {
+                update.push(category::Column::IdempotencyKey);
+            }
+
+            category::Entity::insert(category::ActiveModel {
+                discord_id: Set(*id as i64),
+                fk_guild_id: Set(Some(*guild_id as i64)),
+                name: Set(name.to_string()),
+                idempotency_key: Set(idempotency_key),
+            })
+            .on_conflict(
+                OnConflict::column(category::Column::DiscordId)
+                    .update_columns(update)
+                    .to_owned(),
+            )
+            .exec(db)
+            .await?;
+        }
+        TrackedKind::Role => {
+            let mut update = vec![role::Column::FkGuildId, role::Column::Name];
+            if !keep_key {
+                update.push(role::Column::IdempotencyKey);
+            }
+
+            role::Entity::insert(role::ActiveModel {
+                discord_id: Set(*id as i64),
+                fk_guild_id: Set(Some(*guild_id as i64)),
+                name: Set(name.to_string()),
+                idempotency_key: Set(idempotency_key),
+            })
+            .on_conflict(
+                OnConflict::column(role::Column::DiscordId)
+                    .update_columns(update)
+                    .to_owned(),
+            )
+            .exec(db)
+            .await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Delete a resource's row from the database, first unlinking any team that
+/// uses it. This returns whether there was a row to delete.
+pub async fn forget_resource(
+    db: &DBWrapper,
+    kind: TrackedKind,
+    id: DiscordId,
+) -> Result<bool, DbErr> {
+    let txn = db.begin().await?;
+
+    relink_teams(&txn, kind, id, None).await?;
+
+    let deleted = match kind {
+        TrackedKind::Channel => channel::Entity::delete_by_id(*id as i64).exec(&txn).await?,
+        TrackedKind::Category => {
+            category::Entity::delete_by_id(*id as i64)
+                .exec(&txn)
+                .await?
+        }
+        TrackedKind::Role => role::Entity::delete_by_id(*id as i64).exec(&txn).await?,
+    }
+    .rows_affected;
+
+    txn.commit().await?;
+
+    Ok(deleted > 0)
+}
+
+/// Track a different resource in place of `old_id`, e.g. one that replaced
+/// it in the guild. Teams that used the old one use the new one afterwards.
+pub async fn move_resource(
+    db: &DBWrapper,
+    kind: TrackedKind,
+    guild_id: DiscordId,
+    old_id: DiscordId,
+    new_id: DiscordId,
+    name: &str,
+) -> Result<(), DbErr> {
+    let txn = db.begin().await?;
+
+    // The new row has to exist before teams can point at it
+    save_resource(&txn, kind, guild_id, new_id, name, None).await?;
+
+    relink_teams(&txn, kind, old_id, Some(new_id)).await?;
+
+    match kind {
+        TrackedKind::Channel => {
+            channel::Entity::delete_by_id(*old_id as i64)
+                .exec(&txn)
+                .await?
+        }
+        TrackedKind::Category => {
+            category::Entity::delete_by_id(*old_id as i64)
+                .exec(&txn)
+                .await?
+        }
+        TrackedKind::Role => {
+            role::Entity::delete_by_id(*old_id as i64)
+                .exec(&txn)
+                .await?
+        }
     };
 
-    (discord_guild, database_guild)
+    txn.commit().await
 }
 
 pub async fn get_player_team(
-    ctx: Context,
+    discord: &dyn DiscordBackend,
     db: DBWrapper,
     guild_id: DiscordId,
     player_id: DiscordId,
 ) -> Result<team::Model, GameDatabaseError> {
-    let guild = get_guild(ctx, db.clone(), guild_id).await.1;
+    let guild = get_guild(discord, db.clone(), guild_id).await?.1;
 
     let player = player::Entity::find()
         .filter(player::Column::DiscordId.eq(*player_id as i64))
+        .filter(player::Column::FkGuildId.eq(*guild_id as i64))
         .one(&*db)
-        .await
-        .unwrap();
+        .await?;
 
     let player = match player {
         Some(player) => player,
@@ -60,8 +427,7 @@ pub async fn get_player_team(
     let team = team::Entity::find()
         .filter(team::Column::Id.eq(player.fk_team_id))
         .one(&*db)
-        .await
-        .unwrap();
+        .await?;
 
     let team = match team {
         Some(team) => team,
@@ -75,8 +441,21 @@ pub async fn get_player_team(
     Ok(team)
 }
 
+/// The player with this Discord id in the guild, if they've joined the game
+pub async fn get_guild_player(
+    db: &DBWrapper,
+    guild_id: DiscordId,
+    player_id: DiscordId,
+) -> Result<Option<player::Model>, DbErr> {
+    player::Entity::find()
+        .filter(player::Column::DiscordId.eq(*player_id as i64))
+        .filter(player::Column::FkGuildId.eq(*guild_id as i64))
+        .one(&**db)
+        .await
+}
+
 pub async fn get_or_create_player(
-    _ctx: Context,
+    _discord: &dyn DiscordBackend,
     db: DBWrapper,
     guild_id: DiscordId,
     player_id: DiscordId,
@@ -104,3 +483,36 @@ pub async fn get_or_create_player(
 
     Ok(database_player)
 }
+
+impl DBWrapper {
+    /// Check the settings of every guild, returning the id of each one whose
+    /// settings can't be used along with why
+    pub async fn check_guild_settings(&self) -> Result<Vec<(DiscordId, ConfigError)>, DbErr> {
+        Ok(guild::Entity::find()
+            .all(&self.db)
+            .await?
+            .into_iter()
+            .filter_map(|database_guild| {
+                GuildSettings::from_json(database_guild.settings)
+                    .err()
+                    .map(|why| (DiscordId::from(database_guild.discord_id), why))
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serenity::model::prelude::ChannelType;
+
+    use super::channel_name;
+
+    #[test]
+    fn should_match_discord_text_channel_names() {
+        assert_eq!(channel_name("Team Chat", ChannelType::Text), "team-chat");
+        assert_eq!(channel_name(" Red  Team ", ChannelType::Text), "red-team");
+        assert_eq!(channel_name("team-chat", ChannelType::Text), "team-chat");
+        assert_eq!(channel_name("Team Chat", ChannelType::Voice), "Team Chat");
+        assert_eq!(channel_name("Red Team", ChannelType::Category), "Red Team");
+    }
+}
@@ -0,0 +1,185 @@
+use std::time::Duration;
+
+use entity::entities::task;
+use sea_orm::{prelude::*, sea_query::Expr, QueryOrder, QuerySelect};
+
+use super::DBWrapper;
+
+/// A finished task and how long it spent waiting and running
+#[derive(Debug, Clone)]
+pub struct TaskHistory {
+    pub task: task::Model,
+    /// From being added to its latest attempt starting. This includes any
+    /// time spent scheduled for later or backing off between retries.
+    pub waited: Option<Duration>,
+    /// How long its latest attempt ran for
+    pub ran: Option<Duration>,
+}
+
+impl From<task::Model> for TaskHistory {
+    fn from(task: task::Model) -> Self {
+        Self {
+            waited: task
+                .started_at
+                .and_then(|started_at| (started_at - task.created_at).to_std().ok()),
+            ran: task
+                .duration_ms
+                .map(|duration_ms| Duration::from_millis(duration_ms.max(0) as u64)),
+            task,
+        }
+    }
+}
+
+impl DBWrapper {
+    /// The tasks that most recently failed or were dead-lettered, newest
+    /// first
+    pub async fn recent_failures(&self, limit: u64) -> Result<Vec<TaskHistory>, DbErr> {
+        Ok(task::Entity::find()
+            .filter(Expr::cust(
+                "(status -> 'Error' IS NOT NULL OR status -> 'DeadLetter' IS NOT NULL)",
+            ))
+            .filter(task::Column::FinishedAt.is_not_null())
+            .order_by_desc(task::Column::FinishedAt)
+            .limit(limit)
+            .all(&self.db)
+            .await?
+            .into_iter()
+            .map(TaskHistory::from)
+            .collect())
+    }
+
+    /// The tasks whose latest attempt ran for at least `min_duration`,
+    /// slowest first
+    pub async fn slow_tasks(
+        &self,
+        min_duration: Duration,
+        limit: u64,
+    ) -> Result<Vec<TaskHistory>, DbErr> {
+        Ok(task::Entity::find()
+            .filter(task::Column::DurationMs.gte(min_duration.as_millis() as i64))
+            .order_by_desc(task::Column::DurationMs)
+            .limit(limit)
+            .all(&self.db)
+            .await?
+            .into_iter()
+            .map(TaskHistory::from)
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use entity::entities::task;
+    use sea_orm::{ActiveModelTrait, EntityTrait, Set};
+    use serenity::model::prelude::ChannelType;
+
+    use crate::{
+        db_wrapper::TaskResult,
+        task_runner::{
+            error::TaskError,
+            tasks::{channel::ChannelCreateData, test_helpers::TestHelpers},
+        },
+    };
+
+    #[tokio::test]
+    async fn should_time_tasks() {
+        let test_helper = TestHelpers::new().await;
+
+        test_helper
+            .db
+            .run(
+                test_helper.guild_id,
+                ChannelCreateData {
+                    name: TestHelpers::generate_name(),
+                    category_id: None,
+                    kind: ChannelType::Text,
+                },
+            )
+            .await
+            .unwrap();
+
+        let db_task = task::Entity::find()
+            .one(&*test_helper.db)
+            .await
+            .unwrap()
+            .unwrap();
+        let started_at = db_task.started_at.unwrap();
+        let finished_at = db_task.finished_at.unwrap();
+        assert!(db_task.created_at <= started_at);
+        assert!(started_at <= finished_at);
+
+        let history = test_helper.db.slow_tasks(Duration::ZERO, 10).await.unwrap();
+        assert_eq!(history.len(), 1);
+        assert!(history[0].waited.is_some());
+        assert!(history[0].ran.is_some());
+
+        test_helper.cleanup().await;
+    }
+
+    #[tokio::test]
+    async fn should_list_the_newest_failures_first() {
+        let test_helper = TestHelpers::new().await;
+
+        let mut ids = Vec::new();
+        for (status, finished_ago) in [
+            (TaskResult::Error(TaskError::permanent("first")), 3),
+            (TaskResult::Cancelled, 2),
+            (TaskResult::DeadLetter(TaskError::permanent("second")), 1),
+        ] {
+            let db_task =
+                TestHelpers::finished_task(status, chrono::Duration::minutes(finished_ago))
+                    .insert(&*test_helper.db)
+                    .await
+                    .unwrap();
+            ids.push(db_task.id);
+        }
+
+        let failures: Vec<i32> = test_helper
+            .db
+            .recent_failures(10)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|history| history.task.id)
+            .collect();
+        assert_eq!(failures, vec![ids[2], ids[0]]);
+
+        let failures = test_helper.db.recent_failures(1).await.unwrap();
+        assert_eq!(failures.len(), 1);
+
+        test_helper.cleanup().await;
+    }
+
+    #[tokio::test]
+    async fn should_list_the_slowest_tasks_first() {
+        let test_helper = TestHelpers::new().await;
+
+        let mut ids = Vec::new();
+        for duration_ms in [500, 5000, 2000] {
+            let mut db_task =
+                TestHelpers::finished_task(TaskResult::Cancelled, chrono::Duration::zero());
+            db_task.duration_ms = Set(Some(duration_ms));
+            ids.push(db_task.insert(&*test_helper.db).await.unwrap().id);
+        }
+
+        let slow: Vec<(i32, Option<Duration>)> = test_helper
+            .db
+            .slow_tasks(Duration::from_secs(1), 10)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|history| (history.task.id, history.ran))
+            .collect();
+        assert_eq!(
+            slow,
+            vec![
+                (ids[1], Some(Duration::from_secs(5))),
+                (ids[2], Some(Duration::from_secs(2)))
+            ]
+        );
+
+        test_helper.cleanup().await;
+    }
+}
@@ -0,0 +1,558 @@
+use std::{collections::HashMap, fmt};
+
+use chrono::Utc;
+use entity::entities::{player, team, transaction, wallet, wallet_balance};
+use sea_orm::{
+    prelude::*, sea_query::Expr, ActiveModelTrait, Condition, ConnectionTrait, QueryOrder, Set,
+    Statement, TransactionTrait,
+};
+
+use crate::task_runner::tasks::DatabaseId;
+
+use super::DBWrapper;
+
+/// Currency moving into and out of wallets. Currency that comes from no
+/// wallet is minted, and currency that goes to no wallet is burned.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LedgerEntry {
+    pub from_wallet_id: Option<DatabaseId>,
+    pub to_wallet_id: Option<DatabaseId>,
+    pub currency_id: DatabaseId,
+    pub amount: i64,
+    /// The player who made this happen, if it wasn't the game itself
+    pub initiating_player_id: Option<DatabaseId>,
+    pub memo: Option<String>,
+}
+
+impl LedgerEntry {
+    pub fn transfer(
+        from_wallet_id: DatabaseId,
+        to_wallet_id: DatabaseId,
+        currency_id: DatabaseId,
+        amount: i64,
+    ) -> Self {
+        Self {
+            from_wallet_id: Some(from_wallet_id),
+            to_wallet_id: Some(to_wallet_id),
+            currency_id,
+            amount,
+            initiating_player_id: None,
+            memo: None,
+        }
+    }
+
+    pub fn mint(to_wallet_id: DatabaseId, currency_id: DatabaseId, amount: i64) -> Self {
+        Self {
+            from_wallet_id: None,
+            to_wallet_id: Some(to_wallet_id),
+            currency_id,
+            amount,
+            initiating_player_id: None,
+            memo: None,
+        }
+    }
+
+    pub fn burn(from_wallet_id: DatabaseId, currency_id: DatabaseId, amount: i64) -> Self {
+        Self {
+            from_wallet_id: Some(from_wallet_id),
+            to_wallet_id: None,
+            currency_id,
+            amount,
+            initiating_player_id: None,
+            memo: None,
+        }
+    }
+
+    pub fn initiated_by(mut self, player_id: Option<DatabaseId>) -> Self {
+        self.initiating_player_id = player_id;
+        self
+    }
+
+    pub fn memo(mut self, memo: impl Into<String>) -> Self {
+        self.memo = Some(memo.into());
+        self
+    }
+}
+
+#[derive(Debug)]
+pub enum LedgerError {
+    /// Entries have to move more than 0
+    InvalidAmount(i64),
+    /// Entries have to move currency from one place to another
+    SameWallet,
+    InsufficientFunds {
+        wallet_id: DatabaseId,
+        currency_id: DatabaseId,
+    },
+    Database(DbErr),
+}
+
+impl fmt::Display for LedgerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LedgerError::InvalidAmount(amount) => {
+                write!(f, "Can't move {} of a currency", amount)
+            }
+            LedgerError::SameWallet => write!(f, "Can't move currency to where it already is"),
+            LedgerError::InsufficientFunds {
+                wallet_id,
+                currency_id,
+            } => write!(
+                f,
+                "Wallet {} doesn't have enough of currency {}",
+                **wallet_id, **currency_id
+            ),
+            LedgerError::Database(why) => write!(f, "{}", why),
+        }
+    }
+}
+
+impl From<DbErr> for LedgerError {
+    fn from(why: DbErr) -> Self {
+        LedgerError::Database(why)
+    }
+}
+
+/// Record an entry and move its currency. No wallet can go below 0, so this
+/// fails without moving anything if the wallet it comes from doesn't have
+/// enough. This should run inside a database transaction along with anything
+/// else that has to happen with it; `DBWrapper::record_entries` opens one.
+pub async fn record<C: ConnectionTrait>(
+    db: &C,
+    entry: &LedgerEntry,
+) -> Result<transaction::Model, LedgerError> {
+    if entry.amount <= 0 {
+        return Err(LedgerError::InvalidAmount(entry.amount));
+    }
+    if entry.from_wallet_id == entry.to_wallet_id {
+        return Err(LedgerError::SameWallet);
+    }
+
+    // Only take what's there. The balance row is locked from here until the
+    // transaction finishes, so two entries can't both spend the same funds.
+    if let Some(from_wallet_id) = entry.from_wallet_id {
+        let debited = wallet_balance::Entity::update_many()
+            .col_expr(
+                wallet_balance::Column::Amount,
+                Expr::col(wallet_balance::Column::Amount).sub(entry.amount),
+            )
+            .filter(wallet_balance::Column::FkWalletId.eq(*from_wallet_id))
+            .filter(wallet_balance::Column::FkCurrencyId.eq(*entry.currency_id))
+            .filter(wallet_balance::Column::Amount.gte(entry.amount))
+            .exec(db)
+            .await?;
+        if debited.rows_affected == 0 {
+            return Err(LedgerError::InsufficientFunds {
+                wallet_id: from_wallet_id,
+                currency_id: entry.currency_id,
+            });
+        }
+    }
+
+    if let Some(to_wallet_id) = entry.to_wallet_id {
+        let credited = wallet_balance::Entity::update_many()
+            .col_expr(
+                wallet_balance::Column::Amount,
+                Expr::col(wallet_balance::Column::Amount).add(entry.amount),
+            )
+            .filter(wallet_balance::Column::FkWalletId.eq(*to_wallet_id))
+            .filter(wallet_balance::Column::FkCurrencyId.eq(*entry.currency_id))
+            .exec(db)
+            .await?;
+        if credited.rows_affected == 0 {
+            wallet_balance::ActiveModel {
+                fk_wallet_id: Set(*to_wallet_id),
+                fk_currency_id: Set(*entry.currency_id),
+                amount: Set(entry.amount),
+            }
+            .insert(db)
+            .await?;
+        }
+    }
+
+    let database_transaction = transaction::ActiveModel {
+        fk_from_wallet_id: Set(entry.from_wallet_id.map(|id| *id)),
+        fk_to_wallet_id: Set(entry.to_wallet_id.map(|id| *id)),
+        fk_currency_id: Set(*entry.currency_id),
+        amount: Set(entry.amount),
+        fk_initiating_player_id: Set(entry.initiating_player_id.map(|id| *id)),
+        created_at: Set(Utc::now().into()),
+        memo: Set(entry.memo.clone()),
+        ..Default::default()
+    }
+    .insert(db)
+    .await?;
+
+    Ok(database_transaction)
+}
+
+/// Give a wallet to an owner that doesn't have one yet. Each team and player
+/// can only have one wallet, so if someone else gave them theirs first,
+/// nothing is inserted and `None` comes back instead.
+const INSERT_OWNED_WALLET: &str = r#"
+    INSERT INTO wallet (name, {owner}) VALUES ($1, $2)
+    ON CONFLICT DO NOTHING
+    RETURNING *
+"#;
+
+/// The wallet `owner` points at `owner_id` from, made now if there isn't one.
+/// Two callers that both find none can't both make one: whoever inserts
+/// second waits for the first and then finds their wallet.
+async fn owned_wallet<C: ConnectionTrait>(
+    db: &C,
+    owner: wallet::Column,
+    owner_id: i32,
+    name: &str,
+) -> Result<wallet::Model, DbErr> {
+    let existing = wallet::Entity::find()
+        .filter(owner.eq(owner_id))
+        .one(db)
+        .await?;
+    if let Some(database_wallet) = existing {
+        return Ok(database_wallet);
+    }
+
+    let inserted = wallet::Entity::find()
+        .from_raw_sql(Statement::from_sql_and_values(
+            db.get_database_backend(),
+            &INSERT_OWNED_WALLET.replace("{owner}", owner.as_str()),
+            vec![name.into(), owner_id.into()],
+        ))
+        .one(db)
+        .await?;
+    if let Some(database_wallet) = inserted {
+        return Ok(database_wallet);
+    }
+
+    wallet::Entity::find()
+        .filter(owner.eq(owner_id))
+        .one(db)
+        .await?
+        .ok_or_else(|| DbErr::RecordNotFound(format!("wallet of {} {}", owner.as_str(), owner_id)))
+}
+
+/// The team's wallet, which it's given if it doesn't have one yet
+pub async fn team_wallet<C: ConnectionTrait>(db: &C, team_id: i32) -> Result<DatabaseId, DbErr> {
+    let database_team = team::Entity::find_by_id(team_id)
+        .one(db)
+        .await?
+        .ok_or_else(|| DbErr::RecordNotFound(format!("team {}", team_id)))?;
+
+    if let Some(wallet_id) = database_team.wallet {
+        return Ok(DatabaseId(wallet_id));
+    }
+
+    let database_wallet =
+        owned_wallet(db, wallet::Column::FkTeamId, team_id, &database_team.name).await?;
+
+    team::Entity::update_many()
+        .col_expr(team::Column::Wallet, Expr::value(database_wallet.id))
+        .filter(team::Column::Id.eq(team_id))
+        .exec(db)
+        .await?;
+
+    Ok(DatabaseId(database_wallet.id))
+}
+
+/// The player's own wallet, which they're given if they don't have one yet
+pub async fn player_wallet<C: ConnectionTrait>(
+    db: &C,
+    player_id: i32,
+) -> Result<DatabaseId, DbErr> {
+    let database_player = player::Entity::find_by_id(player_id)
+        .one(db)
+        .await?
+        .ok_or_else(|| DbErr::RecordNotFound(format!("player {}", player_id)))?;
+
+    let database_wallet = owned_wallet(
+        db,
+        wallet::Column::FkPlayerId,
+        player_id,
+        &database_player.name,
+    )
+    .await?;
+
+    Ok(DatabaseId(database_wallet.id))
+}
+
+/// Leave the wallets of teams and players that are about to be deleted
+/// without an owner. Wallets are never deleted, so the transactions in and
+/// out of them, and the balances of the wallets on the other side, stay whole.
+/// Nobody can spend what's left in them, so it's burned. Importing a snapshot
+/// of the game mints it again, so restoring one doesn't double it.
+pub async fn archive_wallets<C: ConnectionTrait>(
+    db: &C,
+    team_ids: &[i32],
+    player_ids: &[i32],
+) -> Result<(), DbErr> {
+    let wallet_ids: Vec<i32> = wallet::Entity::find()
+        .filter(
+            Condition::any()
+                .add(wallet::Column::FkTeamId.is_in(team_ids.iter().copied()))
+                .add(wallet::Column::FkPlayerId.is_in(player_ids.iter().copied())),
+        )
+        .all(db)
+        .await?
+        .into_iter()
+        .map(|database_wallet| database_wallet.id)
+        .collect();
+
+    for balance in wallet_balance::Entity::find()
+        .filter(wallet_balance::Column::FkWalletId.is_in(wallet_ids))
+        .filter(wallet_balance::Column::Amount.gt(0))
+        .all(db)
+        .await?
+    {
+        let entry = LedgerEntry::burn(
+            DatabaseId(balance.fk_wallet_id),
+            DatabaseId(balance.fk_currency_id),
+            balance.amount,
+        )
+        .memo("Owner deleted");
+        record(db, &entry).await.map_err(|why| match why {
+            LedgerError::Database(why) => why,
+            why => DbErr::Custom(why.to_string()),
+        })?;
+    }
+
+    wallet::Entity::update_many()
+        .col_expr(wallet::Column::FkTeamId, Expr::value(Option::<i32>::None))
+        .filter(wallet::Column::FkTeamId.is_in(team_ids.iter().copied()))
+        .exec(db)
+        .await?;
+    wallet::Entity::update_many()
+        .col_expr(wallet::Column::FkPlayerId, Expr::value(Option::<i32>::None))
+        .filter(wallet::Column::FkPlayerId.is_in(player_ids.iter().copied()))
+        .exec(db)
+        .await?;
+
+    Ok(())
+}
+
+/// Moving currency between wallets. Every movement is a row in `transaction`
+/// that's never changed, and `wallet_balance` keeps the running total of
+/// those rows for each wallet so overdrafts can be stopped without summing a
+/// wallet's whole history.
+impl DBWrapper {
+    /// Record entries together, so either all of them go through or none do
+    pub async fn record_entries(
+        &self,
+        entries: &[LedgerEntry],
+    ) -> Result<Vec<transaction::Model>, LedgerError> {
+        let txn = self.begin().await?;
+
+        let mut recorded = Vec::new();
+        for entry in entries {
+            // Dropping the transaction rolls back anything recorded so far
+            recorded.push(record(&txn, entry).await?);
+        }
+
+        txn.commit().await?;
+
+        Ok(recorded)
+    }
+
+    pub async fn transfer(
+        &self,
+        from_wallet_id: DatabaseId,
+        to_wallet_id: DatabaseId,
+        currency_id: DatabaseId,
+        amount: i64,
+        initiating_player_id: Option<DatabaseId>,
+    ) -> Result<transaction::Model, LedgerError> {
+        self.record_one(
+            LedgerEntry::transfer(from_wallet_id, to_wallet_id, currency_id, amount)
+                .initiated_by(initiating_player_id),
+        )
+        .await
+    }
+
+    /// Add currency to a wallet that doesn't come from anywhere
+    pub async fn mint(
+        &self,
+        to_wallet_id: DatabaseId,
+        currency_id: DatabaseId,
+        amount: i64,
+        initiating_player_id: Option<DatabaseId>,
+    ) -> Result<transaction::Model, LedgerError> {
+        self.record_one(
+            LedgerEntry::mint(to_wallet_id, currency_id, amount).initiated_by(initiating_player_id),
+        )
+        .await
+    }
+
+    /// Take currency out of a wallet and out of the game
+    pub async fn burn(
+        &self,
+        from_wallet_id: DatabaseId,
+        currency_id: DatabaseId,
+        amount: i64,
+        initiating_player_id: Option<DatabaseId>,
+    ) -> Result<transaction::Model, LedgerError> {
+        self.record_one(
+            LedgerEntry::burn(from_wallet_id, currency_id, amount)
+                .initiated_by(initiating_player_id),
+        )
+        .await
+    }
+
+    async fn record_one(&self, entry: LedgerEntry) -> Result<transaction::Model, LedgerError> {
+        let mut recorded = self.record_entries(&[entry]).await?;
+        Ok(recorded.remove(0))
+    }
+
+    /// How much of each currency is in a wallet, by currency id
+    pub async fn balances(&self, wallet_id: DatabaseId) -> Result<HashMap<i32, i64>, DbErr> {
+        Ok(wallet_balance::Entity::find()
+            .filter(wallet_balance::Column::FkWalletId.eq(*wallet_id))
+            .all(&self.db)
+            .await?
+            .into_iter()
+            .map(|balance| (balance.fk_currency_id, balance.amount))
+            .collect())
+    }
+
+    /// Everything that went into or out of a wallet, oldest first
+    pub async fn wallet_history(
+        &self,
+        wallet_id: DatabaseId,
+    ) -> Result<Vec<transaction::Model>, DbErr> {
+        transaction::Entity::find()
+            .filter(
+                Condition::any()
+                    .add(transaction::Column::FkFromWalletId.eq(*wallet_id))
+                    .add(transaction::Column::FkToWalletId.eq(*wallet_id)),
+            )
+            .order_by_asc(transaction::Column::Id)
+            .all(&self.db)
+            .await
+    }
+
+    /// What a wallet's balances come to from its transactions alone. This
+    /// always matches `balances`.
+    pub async fn transaction_balances(
+        &self,
+        wallet_id: DatabaseId,
+    ) -> Result<HashMap<i32, i64>, DbErr> {
+        let mut balances = HashMap::new();
+        for database_transaction in self.wallet_history(wallet_id).await? {
+            let balance = balances
+                .entry(database_transaction.fk_currency_id)
+                .or_insert(0);
+            if database_transaction.fk_to_wallet_id == Some(*wallet_id) {
+                *balance += database_transaction.amount;
+            }
+            if database_transaction.fk_from_wallet_id == Some(*wallet_id) {
+                *balance -= database_transaction.amount;
+            }
+        }
+
+        Ok(balances)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use entity::entities::wallet;
+    use sea_orm::{ActiveModelTrait, ColumnTrait, EntityTrait, QueryFilter, Set};
+
+    use crate::{
+        db_wrapper::ledger::{team_wallet, LedgerEntry, LedgerError},
+        task_runner::tasks::{test_helpers::TestHelpers, DatabaseId},
+    };
+
+    #[tokio::test]
+    async fn should_keep_balances_in_step_with_transactions() {
+        let test_helper = TestHelpers::new().await;
+        let db = &test_helper.db;
+
+        let first_wallet = test_helper.create_wallet().await;
+        let second_wallet = test_helper.create_wallet().await;
+        let currency_id = DatabaseId(test_helper.create_currency().await.id);
+
+        db.mint(first_wallet, currency_id, 100, None).await.unwrap();
+        db.transfer(first_wallet, second_wallet, currency_id, 40, None)
+            .await
+            .unwrap();
+        db.burn(second_wallet, currency_id, 15, None).await.unwrap();
+
+        for (wallet_id, expected) in [(first_wallet, 60), (second_wallet, 25)] {
+            let balances = db.balances(wallet_id).await.unwrap();
+            assert_eq!(balances.get(&*currency_id), Some(&expected));
+            assert_eq!(db.transaction_balances(wallet_id).await.unwrap(), balances);
+        }
+        assert_eq!(db.wallet_history(first_wallet).await.unwrap().len(), 2);
+
+        test_helper.cleanup().await;
+    }
+
+    #[tokio::test]
+    async fn should_not_overdraw_a_wallet() {
+        let test_helper = TestHelpers::new().await;
+        let db = &test_helper.db;
+
+        let first_wallet = test_helper.create_wallet().await;
+        let second_wallet = test_helper.create_wallet().await;
+        let currency_id = DatabaseId(test_helper.create_currency().await.id);
+
+        db.mint(first_wallet, currency_id, 50, None).await.unwrap();
+
+        let result = db
+            .transfer(first_wallet, second_wallet, currency_id, 51, None)
+            .await;
+        assert!(matches!(result, Err(LedgerError::InsufficientFunds { .. })));
+
+        // The second entry fails, so the first one doesn't happen either
+        let result = db
+            .record_entries(&[
+                LedgerEntry::transfer(first_wallet, second_wallet, currency_id, 30),
+                LedgerEntry::burn(first_wallet, currency_id, 30),
+            ])
+            .await;
+        assert!(matches!(result, Err(LedgerError::InsufficientFunds { .. })));
+
+        assert_eq!(
+            db.balances(first_wallet).await.unwrap().get(&*currency_id),
+            Some(&50)
+        );
+        assert!(db.wallet_history(second_wallet).await.unwrap().is_empty());
+
+        let result = db.mint(first_wallet, currency_id, 0, None).await;
+        assert!(matches!(result, Err(LedgerError::InvalidAmount(0))));
+
+        test_helper.cleanup().await;
+    }
+
+    #[tokio::test]
+    async fn should_give_a_team_one_wallet_when_asked_at_once() {
+        let test_helper = TestHelpers::new().await;
+        let db = &test_helper.db;
+
+        let database_team = test_helper.create_team(&TestHelpers::generate_name()).await;
+
+        let (first, second) = tokio::join!(
+            team_wallet(&**db, database_team.id),
+            team_wallet(&**db, database_team.id)
+        );
+        assert_eq!(first.unwrap(), second.unwrap());
+
+        let wallets = wallet::Entity::find()
+            .filter(wallet::Column::FkTeamId.eq(database_team.id))
+            .all(&**db)
+            .await
+            .unwrap();
+        assert_eq!(wallets.len(), 1);
+
+        // Nothing else can give the team a second one either
+        let result = wallet::ActiveModel {
+            name: Set(TestHelpers::generate_name()),
+            fk_team_id: Set(Some(database_team.id)),
+            ..Default::default()
+        }
+        .insert(&**db)
+        .await;
+        assert!(result.is_err());
+
+        test_helper.cleanup().await;
+    }
+}
@@ -1,23 +1,103 @@
-use std::ops::Deref;
+use std::{ops::Deref, sync::Arc, time::Duration};
 
+use chrono::Utc;
 use entity::entities::{category, channel, role, task};
-use sea_orm::{prelude::*, Database, Set};
+use futures::{stream::FuturesUnordered, Stream};
+use sea_orm::{prelude::*, sea_query::Expr, Database, Set, TransactionTrait};
 use serde::{Deserialize, Serialize};
+use uuid::Uuid;
 
-use crate::task_runner::tasks::{DatabaseId, DiscordId, TaskType};
+use crate::{
+    config::Config,
+    task_runner::{
+        error::TaskError,
+        graph::{TaskDependencies, TaskGraph, TaskGraphIds, TaskGraphResults},
+        schedule::{NewTask, TaskPriority},
+        tasks::{reconcile::ReconcileReport, DatabaseId, DiscordId, TypedTask},
+    },
+};
+
+use self::{notifications::TaskNotifications, payload::PAYLOAD_VERSION};
 
 pub mod helpers;
+pub mod history;
+pub mod ledger;
+pub mod notifications;
+pub mod payload;
+pub mod retention;
+pub mod sync;
+
+/// How often `await_task` re-checks a task if it hasn't heard about it being
+/// updated. Notifications should wake it well before this.
+const AWAIT_TASK_FALLBACK_POLL: Duration = Duration::from_secs(1);
 
 #[derive(Debug, Clone)]
 pub struct DBWrapper {
     pub db: DatabaseConnection,
+    pub notifications: Arc<TaskNotifications>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum TaskResult {
     Pending,
+    /// A runner has claimed the task and is working on it
+    Running,
     Completed(TaskReturnData),
-    Error(String),
+    Error(TaskError),
+    /// The task was cancelled before it ran
+    Cancelled,
+    /// The task can't be run, either because its payload couldn't be read or
+    /// because it ran out of retries. These need someone to look at them.
+    DeadLetter(TaskError),
+}
+
+impl TaskResult {
+    /// Whether the task is done, one way or another, and won't change again
+    pub fn is_finished(&self) -> bool {
+        match self {
+            TaskResult::Pending | TaskResult::Running => false,
+            TaskResult::Completed(_)
+            | TaskResult::Error(_)
+            | TaskResult::Cancelled
+            | TaskResult::DeadLetter(_) => true,
+        }
+    }
+}
+
+impl TaskResult {
+    /// The output of a finished typed task, or why there isn't one
+    pub fn output<T: TypedTask>(self) -> Result<T::Output, TaskError> {
+        match self {
+            TaskResult::Completed(data) => T::output(data),
+            status => Err(status.error().expect("Only completed tasks have no error")),
+        }
+    }
+
+    /// Why the task hasn't completed, or `None` if it has
+    pub fn error(&self) -> Option<TaskError> {
+        match self {
+            TaskResult::Completed(_) => None,
+            TaskResult::Error(error) | TaskResult::DeadLetter(error) => Some(error.clone()),
+            TaskResult::Cancelled => Some(TaskError::permanent("The task was cancelled")),
+            TaskResult::Pending | TaskResult::Running => {
+                Some(TaskError::retryable("The task hasn't finished yet"))
+            }
+        }
+    }
+}
+
+/// A task didn't finish in the time it was given. It might still finish
+/// later.
+#[derive(Debug, Clone, Copy)]
+pub struct TaskTimedOut(pub DatabaseId);
+
+impl From<Result<TaskReturnData, TaskError>> for TaskResult {
+    fn from(result: Result<TaskReturnData, TaskError>) -> Self {
+        match result {
+            Ok(data) => TaskResult::Completed(data),
+            Err(error) => TaskResult::Error(error),
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -28,75 +108,300 @@ pub enum TaskReturnData {
     UserId(DatabaseId),
     RoleModel(role::Model),
     MessageId(DiscordId),
+    ReconcileReport(ReconcileReport),
     None,
 }
 
+impl TaskReturnData {
+    /// The Discord id of whatever the task created, if it created something
+    /// on Discord
+    pub fn discord_id(&self) -> Option<DiscordId> {
+        match self {
+            TaskReturnData::ChannelModel(channel) => Some(DiscordId::from(channel.discord_id)),
+            TaskReturnData::CategoryModel(category) => Some(DiscordId::from(category.discord_id)),
+            TaskReturnData::RoleModel(role) => Some(DiscordId::from(role.discord_id)),
+            TaskReturnData::MessageId(id) => Some(*id),
+            TaskReturnData::TeamId(_)
+            | TaskReturnData::UserId(_)
+            | TaskReturnData::ReconcileReport(_)
+            | TaskReturnData::None => None,
+        }
+    }
+}
+
 impl DBWrapper {
     pub fn new(db: DatabaseConnection) -> Self {
-        Self { db }
+        Self {
+            db,
+            notifications: Arc::default(),
+        }
     }
 
     pub async fn new_default_db() -> Self {
-        let db: DatabaseConnection =
-            match Database::connect("postgres://postgres:postgres@db/postgres").await {
-                Ok(db) => db,
-                Err(err) => panic!("Error connecting to database: {:?}", err),
-            };
+        let config = match Config::load(None) {
+            Ok(config) => config,
+            Err(err) => panic!("Error loading the config: {}", err),
+        };
+
+        let db: DatabaseConnection = match Database::connect(config.database_url).await {
+            Ok(db) => db,
+            Err(err) => panic!("Error connecting to database: {:?}", err),
+        };
 
-        Self { db }
+        Self::new(db)
     }
 
-    pub async fn add_task(&self, task: TaskType) -> DatabaseId {
+    /// Adds a task to the database. This takes either a `TaskType`, which
+    /// runs as soon as possible, or a `NewTask` that says when to run it.
+    pub async fn add_task(&self, task: impl Into<NewTask>) -> DatabaseId {
         // Add a task to the database
-        DatabaseId(
-            task::ActiveModel {
-                payload: Set(serde_json::to_value(&task).unwrap()),
+        let id = DatabaseId(
+            Self::new_task_model(task.into())
+                .insert(&self.db)
+                .await
+                .unwrap()
+                .id,
+        );
+
+        self.announce_task_inserted();
+
+        id
+    }
+
+    /// Adds several tasks to the database at once. Either all of them are
+    /// added or none are.
+    pub async fn add_tasks<T: Into<NewTask>>(&self, tasks: Vec<T>) -> Vec<DatabaseId> {
+        let txn = self.db.begin().await.unwrap();
+        let mut ids = Vec::new();
+
+        for task in tasks {
+            ids.push(DatabaseId(
+                Self::new_task_model(task.into())
+                    .insert(&txn)
+                    .await
+                    .unwrap()
+                    .id,
+            ));
+        }
+
+        txn.commit().await.unwrap();
+
+        self.announce_task_inserted();
+
+        ids
+    }
+
+    fn new_task_model(task: NewTask) -> task::ActiveModel {
+        task::ActiveModel {
+            payload: Set(serde_json::to_value(&task.task).unwrap()),
+            payload_version: Set(PAYLOAD_VERSION),
+            idempotency_key: Set(Some(Uuid::new_v4().to_string())),
+            status: Set(serde_json::to_value(&TaskResult::Pending).unwrap()),
+            run_at: Set(task.run_at.map(Into::into)),
+            recurrence: Set(task
+                .recurrence
+                .map(|recurrence| serde_json::to_value(recurrence).unwrap())),
+            priority: Set(task.priority.into()),
+            created_at: Set(Utc::now().into()),
+            ..Default::default()
+        }
+    }
+
+    /// Adds every task in a graph to the database at once, all at the same
+    /// priority. Tasks won't run until the tasks they depend on have
+    /// completed, and fail if any of them fail.
+    pub async fn add_task_graph(
+        &self,
+        graph: TaskGraph,
+        priority: TaskPriority,
+    ) -> Result<TaskGraphIds, DbErr> {
+        let txn = self.db.begin().await?;
+        let mut ids = Vec::new();
+
+        let (nodes, placeholders) = graph.into_parts();
+        for node in nodes {
+            let payload = serde_json::to_value(&node.task).unwrap();
+            let dependencies = TaskDependencies::for_node(&node, &payload, &placeholders, &ids)?;
+
+            let task = task::ActiveModel {
+                payload: Set(payload),
+                payload_version: Set(PAYLOAD_VERSION),
+                idempotency_key: Set(Some(Uuid::new_v4().to_string())),
                 status: Set(serde_json::to_value(&TaskResult::Pending).unwrap()),
+                dependencies: Set((!dependencies.is_empty())
+                    .then(|| serde_json::to_value(&dependencies).unwrap())),
+                priority: Set(priority.into()),
+                created_at: Set(Utc::now().into()),
                 ..Default::default()
             }
-            .insert(&self.db)
+            .insert(&txn)
+            .await?;
+
+            ids.push(DatabaseId(task.id));
+        }
+
+        txn.commit().await?;
+
+        self.announce_task_inserted();
+
+        Ok(TaskGraphIds(ids))
+    }
+
+    /// Cancels a task if it hasn't started yet. This returns whether it was
+    /// cancelled, which it won't be if a runner has already picked it up.
+    pub async fn cancel_task(&self, id: DatabaseId) -> Result<bool, DbErr> {
+        let result = task::Entity::update_many()
+            .col_expr(
+                task::Column::Status,
+                Expr::value(serde_json::to_value(&TaskResult::Cancelled).unwrap()),
+            )
+            .col_expr(
+                task::Column::FinishedAt,
+                Expr::value(DateTimeWithTimeZone::from(Utc::now())),
+            )
+            .filter(task::Column::Id.eq(id.0))
+            .filter(task::Column::Status.eq(serde_json::to_value(&TaskResult::Pending).unwrap()))
+            .exec(&self.db)
+            .await?;
+
+        if result.rows_affected > 0 {
+            self.announce_task_updated(id);
+        }
+
+        Ok(result.rows_affected > 0)
+    }
+
+    /// Waits for a task to be completed, or gives up once `timeout` has
+    /// passed
+    pub async fn await_task_with_timeout(
+        &self,
+        id: DatabaseId,
+        timeout: Duration,
+    ) -> Result<TaskResult, TaskTimedOut> {
+        tokio::time::timeout(timeout, self.await_task(id))
             .await
-            .unwrap()
-            .id,
-        )
+            .map_err(|_| TaskTimedOut(id))
     }
 
-    /// Waits for a task to be completed. This will poll the database once a
-    /// second, and will cause a deadlock if the task is never completed.
+    /// Waits for a task to be completed. This wakes up whenever the task is
+    /// updated, falling back to polling the database once a second. It never
+    /// returns if the task is never completed, so use
+    /// `await_task_with_timeout` if that could happen. A task that's deleted
+    /// or can't be read counts as failed.
     pub async fn await_task(&self, id: DatabaseId) -> TaskResult {
         async fn check_progress(id: DatabaseId, db: &DatabaseConnection) -> TaskResult {
-            // Check if the task is completed
-            serde_json::from_value(
-                task::Entity::find_by_id(id.0)
-                    .one(db)
-                    .await
-                    .unwrap()
-                    .unwrap()
-                    .status,
-            )
-            .unwrap()
+            // Check if the task is completed. It can be gone if it was pruned
+            // or reset while it was being awaited.
+            let status = match task::Entity::find_by_id(id.0).one(db).await {
+                Ok(Some(db_task)) => db_task.status,
+                Ok(None) => {
+                    return TaskResult::DeadLetter(TaskError::permanent(format!(
+                        "Task {} no longer exists",
+                        id.0
+                    )))
+                }
+                Err(why) => return TaskResult::Error(TaskError::from(why)),
+            };
+            serde_json::from_value(status).unwrap_or_else(|why| {
+                TaskResult::DeadLetter(TaskError::permanent(format!(
+                    "Task {} has an unreadable status: {}",
+                    id.0, why
+                )))
+            })
         }
 
+        let mut updates = self.notifications.subscribe();
+
         loop {
             let status = check_progress(id, &self.db).await;
-            // If it's not pending, return
-            let TaskResult::Pending = status else {
+            // If it's finished, return
+            if status.is_finished() {
                 return status;
-            };
+            }
 
-            // Sleep for 1 second
-            tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+            // Wait for the task to be updated, or for the fallback poll
+            let _ = tokio::time::timeout(AWAIT_TASK_FALLBACK_POLL, updates.wait_for(id)).await;
         }
     }
 
+    /// Waits for several tasks to be completed, returning their results in
+    /// the same order as `ids`
+    pub async fn await_tasks(&self, ids: &[DatabaseId]) -> Vec<TaskResult> {
+        futures::future::join_all(ids.iter().map(|id| self.await_task(*id))).await
+    }
+
+    /// Waits for several tasks to be completed, yielding each one's result as
+    /// soon as it's done
+    pub fn await_tasks_unordered(
+        &self,
+        ids: &[DatabaseId],
+    ) -> impl Stream<Item = (DatabaseId, TaskResult)> + '_ {
+        ids.iter()
+            .map(|id| {
+                let id = *id;
+                async move { (id, self.await_task(id).await) }
+            })
+            .collect::<FuturesUnordered<_>>()
+    }
+
+    /// Adds a task to the database, waits for it to be completed and returns
+    /// its output
+    pub async fn run<T: TypedTask>(
+        &self,
+        guild_id: DiscordId,
+        task: T,
+    ) -> Result<T::Output, TaskError> {
+        self.run_with_priority(guild_id, task, TaskPriority::default())
+            .await
+    }
+
+    /// `run`, for a task that's more or less urgent than the game's own
+    pub async fn run_with_priority<T: TypedTask>(
+        &self,
+        guild_id: DiscordId,
+        task: T,
+        priority: TaskPriority,
+    ) -> Result<T::Output, TaskError> {
+        self.add_await_task(NewTask::new(task.into_task(guild_id)).priority(priority))
+            .await
+            .output::<T>()
+    }
+
     /// Adds a task to the database and waits for it to be completed
-    ///
-    /// TODO: Find a way to make this proccess multiple tasks at once
-    pub async fn add_await_task(&self, task: TaskType) -> TaskResult {
+    pub async fn add_await_task(&self, task: impl Into<NewTask>) -> TaskResult {
         let id = self.add_task(task).await;
         self.await_task(id).await
     }
 
+    /// Adds several tasks to the database at once and waits for all of them
+    /// to be completed, returning their results in the same order as `tasks`
+    pub async fn add_await_tasks<T: Into<NewTask>>(&self, tasks: Vec<T>) -> Vec<TaskResult> {
+        let ids = self.add_tasks(tasks).await;
+        self.await_tasks(&ids).await
+    }
+
+    /// Adds several tasks to the database at once, yielding each one's
+    /// result as soon as it's done
+    pub async fn add_await_tasks_unordered<T: Into<NewTask>>(
+        &self,
+        tasks: Vec<T>,
+    ) -> impl Stream<Item = (DatabaseId, TaskResult)> + '_ {
+        let ids = self.add_tasks(tasks).await;
+        self.await_tasks_unordered(&ids)
+    }
+
+    /// Adds a graph of tasks to the database and waits for all of them to
+    /// finish
+    pub async fn add_await_task_graph(
+        &self,
+        graph: TaskGraph,
+        priority: TaskPriority,
+    ) -> Result<TaskGraphResults, DbErr> {
+        let ids = self.add_task_graph(graph, priority).await?;
+
+        Ok(TaskGraphResults(self.await_tasks(&ids.0).await))
+    }
+
     // TODO: Try to make this work?
     // /// Helper to find a model by its ID
     // async fn find_by_id<T: EntityTrait>(
@@ -114,3 +419,48 @@ impl Deref for DBWrapper {
         &self.db
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use entity::entities::task;
+    use sea_orm::EntityTrait;
+
+    use super::TaskResult;
+    use crate::task_runner::{
+        schedule::NewTask,
+        tasks::{message::SendChannelMessage, test_helpers::TestHelpers, DiscordId, TypedTask},
+    };
+
+    #[tokio::test]
+    async fn should_fail_awaiting_a_deleted_task() {
+        let test_helper = TestHelpers::new().await;
+
+        let id = test_helper
+            .db
+            .add_task(
+                NewTask::new(
+                    SendChannelMessage {
+                        channel_id: DiscordId(1),
+                        message: "Hello".to_string(),
+                        ..Default::default()
+                    }
+                    .into_task(test_helper.guild_id),
+                )
+                .run_in(Duration::from_secs(60 * 60)),
+            )
+            .await;
+        task::Entity::delete_by_id(id.0)
+            .exec(&*test_helper.db)
+            .await
+            .unwrap();
+
+        assert!(matches!(
+            test_helper.db.await_task(id).await,
+            TaskResult::DeadLetter(_)
+        ));
+
+        test_helper.cleanup().await;
+    }
+}
@@ -0,0 +1,156 @@
+use std::time::Duration;
+
+use sea_orm::{ConnectionTrait, DbBackend};
+use sqlx::postgres::PgListener;
+use tokio::sync::{
+    broadcast::{self, error::RecvError},
+    Notify,
+};
+use tracing::log;
+
+use super::DBWrapper;
+use crate::task_runner::tasks::DatabaseId;
+
+/// Channel notified with the id of every inserted task
+pub const TASK_INSERTED_CHANNEL: &str = "task_inserted";
+/// Channel notified with the id of every task whose status changed
+pub const TASK_UPDATED_CHANNEL: &str = "task_updated";
+
+/// How long to wait before reconnecting the listener after it drops
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// How many task updates can be buffered for a slow subscriber before it
+/// starts missing them
+const UPDATE_BUFFER_SIZE: usize = 256;
+
+/// Wake-ups for the task runner and anyone awaiting a task. These are fed by
+/// the `NOTIFY`s the task table trigger sends, so nobody has to poll the
+/// table in the common case. Databases without `NOTIFY` only hear about the
+/// changes this bot makes itself.
+#[derive(Debug)]
+pub struct TaskNotifications {
+    new_task: Notify,
+    task_updated: broadcast::Sender<DatabaseId>,
+}
+
+impl Default for TaskNotifications {
+    fn default() -> Self {
+        Self {
+            new_task: Notify::new(),
+            task_updated: broadcast::channel(UPDATE_BUFFER_SIZE).0,
+        }
+    }
+}
+
+impl TaskNotifications {
+    /// Wait until a new task is added. If a task was added since the last
+    /// call, this returns immediately.
+    pub async fn new_task(&self) {
+        self.new_task.notified().await
+    }
+
+    pub fn notify_new_task(&self) {
+        self.new_task.notify_one();
+    }
+
+    /// Subscribe to task status changes. Subscribe before checking a task's
+    /// status so that an update can't slip in between.
+    pub fn subscribe(&self) -> TaskUpdates {
+        TaskUpdates(self.task_updated.subscribe())
+    }
+
+    fn notify_task_updated(&self, id: DatabaseId) {
+        // Nobody might be listening, which is fine
+        let _ = self.task_updated.send(id);
+    }
+}
+
+pub struct TaskUpdates(broadcast::Receiver<DatabaseId>);
+
+impl TaskUpdates {
+    /// Wait until the task with the given id is updated. This can also return
+    /// early if updates were missed, so the caller should always re-check the
+    /// task afterwards.
+    pub async fn wait_for(&mut self, id: DatabaseId) {
+        loop {
+            match self.0.recv().await {
+                Ok(updated_id) if updated_id == id => return,
+                Ok(_) => continue,
+                Err(RecvError::Lagged(_)) => return,
+                // The sender lives as long as the `DBWrapper`, so this can't
+                // really happen
+                Err(RecvError::Closed) => std::future::pending().await,
+            }
+        }
+    }
+}
+
+impl DBWrapper {
+    /// Whether the database sends a `NOTIFY` for every task change
+    fn notifies_task_changes(&self) -> bool {
+        self.db.get_database_backend() == DbBackend::Postgres
+    }
+
+    /// Wake the task runner after adding tasks, if the database won't
+    pub(crate) fn announce_task_inserted(&self) {
+        if !self.notifies_task_changes() {
+            self.notifications.notify_new_task();
+        }
+    }
+
+    /// Wake anyone awaiting a task after changing its status, if the database
+    /// won't
+    pub(crate) fn announce_task_updated(&self, id: DatabaseId) {
+        if !self.notifies_task_changes() {
+            self.notifications.notify_task_updated(id);
+        }
+    }
+
+    /// Listen for `NOTIFY`s from the task table and forward them to
+    /// `self.notifications`. This reconnects whenever the connection drops,
+    /// and never returns. On databases other than Postgres there's nothing
+    /// to listen to, so this returns right away.
+    pub async fn listen_for_task_notifications(&self) {
+        if !self.notifies_task_changes() {
+            return;
+        }
+
+        loop {
+            if let Err(why) = self.forward_task_notifications().await {
+                log::error!("Task listener disconnected: {:?}", why);
+            }
+
+            // Anything could have happened while we weren't listening
+            self.notifications.notify_new_task();
+
+            tokio::time::sleep(RECONNECT_DELAY).await;
+        }
+    }
+
+    async fn forward_task_notifications(&self) -> Result<(), sqlx::Error> {
+        let mut listener = PgListener::connect_with(self.db.get_postgres_connection_pool()).await?;
+
+        listener
+            .listen_all([TASK_INSERTED_CHANNEL, TASK_UPDATED_CHANNEL])
+            .await?;
+
+        log::info!("Listening for task notifications");
+
+        loop {
+            let notification = listener.recv().await?;
+
+            match notification.channel() {
+                TASK_INSERTED_CHANNEL => self.notifications.notify_new_task(),
+                TASK_UPDATED_CHANNEL => match notification.payload().parse() {
+                    Ok(id) => self.notifications.notify_task_updated(DatabaseId(id)),
+                    Err(why) => log::error!(
+                        "Bad task update payload {:?}: {:?}",
+                        notification.payload(),
+                        why
+                    ),
+                },
+                _ => {}
+            }
+        }
+    }
+}
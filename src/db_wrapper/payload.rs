@@ -0,0 +1,242 @@
+use std::fmt::{self, Display};
+
+use entity::entities::{message_component_data, task};
+use sea_orm::{prelude::*, Condition};
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+use super::{DBWrapper, TaskResult};
+use crate::task_runner::tasks::{message::message_component::MessageData, TaskType};
+
+/// The version of the payload shapes that are written now. Whenever a change
+/// to `TaskType` or `MessageData` (or anything inside them) would stop old
+/// payloads from deserializing, bump this and add an upgrade to `UPGRADES`.
+pub const PAYLOAD_VERSION: i32 = 1;
+
+/// Turns a payload written in one version into the shape of the next
+type Upgrade = fn(PayloadKind, Value) -> Result<Value, String>;
+
+/// `UPGRADES[n]` upgrades a payload from version `n + 1` to version `n + 2`,
+/// so there should always be `PAYLOAD_VERSION - 1` of these.
+///
+/// Components can hold a `TaskType` as well, so an upgrade that changes a
+/// task has to change it in both kinds of payload.
+const UPGRADES: &[Upgrade] = &[];
+
+/// What a payload holds
+#[derive(Debug, Clone, Copy)]
+pub enum PayloadKind {
+    /// A `TaskType` in `task.payload`
+    Task,
+    /// An `Option<MessageData>` in `message_component_data.payload`
+    Component,
+}
+
+#[derive(Debug)]
+pub enum PayloadError {
+    /// The payload was written by a newer version of the bot
+    UnknownVersion(i32),
+    /// An upgrade couldn't transform the payload
+    Upgrade { from_version: i32, message: String },
+    /// The upgraded payload still doesn't fit the current shape
+    Deserialize(serde_json::Error),
+}
+
+impl Display for PayloadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PayloadError::UnknownVersion(version) => {
+                write!(f, "Unknown payload version {}", version)
+            }
+            PayloadError::Upgrade {
+                from_version,
+                message,
+            } => write!(
+                f,
+                "Couldn't upgrade the payload from version {}: {}",
+                from_version, message
+            ),
+            PayloadError::Deserialize(why) => write!(f, "Couldn't read the payload: {}", why),
+        }
+    }
+}
+
+/// Bring a payload written in `version` up to the current shape
+pub fn upgrade_payload(
+    kind: PayloadKind,
+    version: i32,
+    payload: Value,
+) -> Result<Value, PayloadError> {
+    apply_upgrades(UPGRADES, kind, version, payload)
+}
+
+/// Run the upgrades a payload written in `version` needs, where the newest
+/// version is the one after the last of `upgrades`
+fn apply_upgrades(
+    upgrades: &[Upgrade],
+    kind: PayloadKind,
+    version: i32,
+    mut payload: Value,
+) -> Result<Value, PayloadError> {
+    if !(1..=upgrades.len() as i32 + 1).contains(&version) {
+        return Err(PayloadError::UnknownVersion(version));
+    }
+
+    for (from_version, upgrade) in (version..).zip(&upgrades[(version - 1) as usize..]) {
+        payload = upgrade(kind, payload).map_err(|message| PayloadError::Upgrade {
+            from_version,
+            message,
+        })?;
+    }
+
+    Ok(payload)
+}
+
+/// Upgrade a payload written in `version` and deserialize it
+pub fn read_payload<T: DeserializeOwned>(
+    kind: PayloadKind,
+    version: i32,
+    payload: Value,
+) -> Result<T, PayloadError> {
+    serde_json::from_value(upgrade_payload(kind, version, payload)?)
+        .map_err(PayloadError::Deserialize)
+}
+
+/// A row whose payload can't be read anymore
+#[derive(Debug)]
+pub struct BadPayload {
+    pub table: &'static str,
+    pub id: String,
+    pub error: PayloadError,
+}
+
+impl DBWrapper {
+    /// Find every unfinished task and every message component whose payload
+    /// can't be read in the current version
+    pub async fn check_payloads(&self) -> Result<Vec<BadPayload>, DbErr> {
+        let mut bad_payloads = Vec::new();
+
+        let unfinished_tasks = task::Entity::find()
+            .filter(
+                Condition::any()
+                    .add(
+                        task::Column::Status.eq(serde_json::to_value(TaskResult::Pending).unwrap()),
+                    )
+                    .add(
+                        task::Column::Status.eq(serde_json::to_value(TaskResult::Running).unwrap()),
+                    ),
+            )
+            .all(&self.db)
+            .await?;
+
+        for db_task in unfinished_tasks {
+            if let Err(error) = read_payload::<TaskType>(
+                PayloadKind::Task,
+                db_task.payload_version,
+                db_task.payload,
+            ) {
+                bad_payloads.push(BadPayload {
+                    table: "task",
+                    id: db_task.id.to_string(),
+                    error,
+                });
+            }
+        }
+
+        let components = message_component_data::Entity::find().all(&self.db).await?;
+
+        for component in components {
+            if let Err(error) = read_payload::<Option<MessageData>>(
+                PayloadKind::Component,
+                component.payload_version,
+                component.payload,
+            ) {
+                bad_payloads.push(BadPayload {
+                    table: "message_component_data",
+                    id: component.id_uuid.to_string(),
+                    error,
+                });
+            }
+        }
+
+        Ok(bad_payloads)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    /// Version 2 renamed `name` to `title`
+    fn rename_name(_kind: PayloadKind, mut payload: Value) -> Result<Value, String> {
+        let object = payload.as_object_mut().ok_or("Not an object")?;
+        let name = object.remove("name").ok_or("No name")?;
+        object.insert("title".to_string(), name);
+
+        Ok(payload)
+    }
+
+    /// Version 3 added `count`
+    fn add_count(_kind: PayloadKind, mut payload: Value) -> Result<Value, String> {
+        payload["count"] = json!(0);
+
+        Ok(payload)
+    }
+
+    const TEST_UPGRADES: &[Upgrade] = &[rename_name, add_count];
+
+    #[test]
+    fn should_have_an_upgrade_for_every_version() {
+        assert_eq!(UPGRADES.len() as i32, PAYLOAD_VERSION - 1);
+    }
+
+    #[test]
+    fn should_upgrade_old_payloads() {
+        let upgraded =
+            apply_upgrades(TEST_UPGRADES, PayloadKind::Task, 1, json!({ "name": "a" })).unwrap();
+        assert_eq!(upgraded, json!({ "title": "a", "count": 0 }));
+
+        let upgraded =
+            apply_upgrades(TEST_UPGRADES, PayloadKind::Task, 2, json!({ "title": "a" })).unwrap();
+        assert_eq!(upgraded, json!({ "title": "a", "count": 0 }));
+    }
+
+    #[test]
+    fn should_leave_current_payloads_alone() {
+        let payload = json!({ "title": "a", "count": 1 });
+        let upgraded =
+            apply_upgrades(TEST_UPGRADES, PayloadKind::Component, 3, payload.clone()).unwrap();
+        assert_eq!(upgraded, payload);
+    }
+
+    #[test]
+    fn should_reject_unknown_versions() {
+        for version in [0, -1, 4] {
+            let result = apply_upgrades(TEST_UPGRADES, PayloadKind::Task, version, json!({}));
+            assert!(matches!(result, Err(PayloadError::UnknownVersion(v)) if v == version));
+        }
+    }
+
+    #[test]
+    fn should_not_read_payloads_from_newer_versions() {
+        let result = read_payload::<Value>(PayloadKind::Task, PAYLOAD_VERSION + 1, json!({}));
+        assert!(matches!(result, Err(PayloadError::UnknownVersion(_))));
+
+        let payload = read_payload::<Value>(PayloadKind::Task, PAYLOAD_VERSION, json!({}));
+        assert_eq!(payload.ok(), Some(json!({})));
+    }
+
+    #[test]
+    fn should_report_which_upgrade_failed() {
+        let result = apply_upgrades(TEST_UPGRADES, PayloadKind::Task, 1, json!({}));
+        assert!(matches!(
+            result,
+            Err(PayloadError::Upgrade {
+                from_version: 1,
+                ..
+            })
+        ));
+    }
+}
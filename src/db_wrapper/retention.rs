@@ -0,0 +1,375 @@
+use std::time::Duration;
+
+use chrono::Utc;
+use entity::entities::message_component_data;
+use sea_orm::{
+    prelude::*, ConnectionTrait, DbBackend, FromQueryResult, QuerySelect, Statement,
+    TransactionTrait,
+};
+use serde::{Deserialize, Serialize};
+
+use super::DBWrapper;
+
+/// Which finished tasks to keep. A task is never pruned while another task
+/// that depends on it hasn't finished.
+const PRUNABLE_TASKS: &str = r#"
+    finished_at < $1
+    AND NOT EXISTS (
+        SELECT 1 FROM task AS child
+        WHERE child.finished_at IS NULL
+        AND child.dependencies -> 'parents' @> to_jsonb(task.id)
+    )
+"#;
+
+/// `PRUNABLE_TASKS` for SQLite, which has no `jsonb`
+const PRUNABLE_TASKS_SQLITE: &str = r#"
+    finished_at < $1
+    AND NOT EXISTS (
+        SELECT 1 FROM task AS child, json_each(child.dependencies, '$.parents') AS parent
+        WHERE child.finished_at IS NULL
+        AND parent.value = task.id
+    )
+"#;
+
+/// The columns a task keeps when it's archived
+const ARCHIVED_COLUMNS: &str = "id, status, payload, payload_version, attempts, last_error, \
+    priority, created_at, started_at, finished_at, duration_ms";
+
+/// How long to keep finished tasks and components that never made it into a
+/// message
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RetentionPolicy {
+    pub keep_tasks_for: Duration,
+    /// Move pruned tasks to `task_archive` instead of deleting them
+    pub archive_tasks: bool,
+    /// Components are built right before their message is sent, so one
+    /// that's still unsent after this never will be
+    pub keep_unsent_components_for: Duration,
+}
+
+impl Default for RetentionPolicy {
+    fn default() -> Self {
+        Self {
+            keep_tasks_for: Duration::from_secs(7 * 24 * 60 * 60),
+            archive_tasks: false,
+            keep_unsent_components_for: Duration::from_secs(60 * 60),
+        }
+    }
+}
+
+#[derive(FromQueryResult)]
+struct ComponentChannel {
+    channel_id: i64,
+}
+
+#[derive(FromQueryResult)]
+struct PrunedCount {
+    pruned: i64,
+}
+
+/// The time `keep_for` ago. This fails for retention periods too long for
+/// chrono to represent, instead of keeping everything forever.
+fn cutoff_for(keep_for: Duration) -> Result<DateTimeWithTimeZone, DbErr> {
+    chrono::Duration::from_std(keep_for)
+        .ok()
+        .and_then(|keep_for| Utc::now().checked_sub_signed(keep_for))
+        .map(Into::into)
+        .ok_or_else(|| DbErr::Custom(format!("Can't keep things for {:?}", keep_for)))
+}
+
+impl DBWrapper {
+    /// Delete or archive the tasks that finished longer ago than the policy
+    /// keeps them, returning how many there were
+    pub async fn prune_tasks(&self, policy: &RetentionPolicy) -> Result<u64, DbErr> {
+        let cutoff = cutoff_for(policy.keep_tasks_for)?;
+
+        if self.db.get_database_backend() == DbBackend::Sqlite {
+            return self.prune_tasks_sqlite(policy, cutoff).await;
+        }
+
+        if !policy.archive_tasks {
+            return Ok(self
+                .db
+                .execute(Statement::from_sql_and_values(
+                    DbBackend::Postgres,
+                    &format!("DELETE FROM task WHERE {}", PRUNABLE_TASKS),
+                    vec![cutoff.into()],
+                ))
+                .await?
+                .rows_affected());
+        }
+
+        // A task that's already in the archive fails the whole statement, so
+        // nothing is deleted without being archived
+        let pruned = PrunedCount::find_by_statement(Statement::from_sql_and_values(
+            DbBackend::Postgres,
+            &format!(
+                r#"
+                WITH pruned AS (
+                    DELETE FROM task WHERE {} RETURNING *
+                ), archived AS (
+                    INSERT INTO task_archive ({columns})
+                    SELECT {columns} FROM pruned
+                )
+                SELECT COUNT(*) AS pruned FROM pruned
+                "#,
+                PRUNABLE_TASKS,
+                columns = ARCHIVED_COLUMNS
+            ),
+            vec![cutoff.into()],
+        ))
+        .one(&self.db)
+        .await?
+        .map_or(0, |count| count.pruned);
+
+        Ok(pruned as u64)
+    }
+
+    /// `prune_tasks` for SQLite, which can't delete inside a `WITH`. The
+    /// tasks are copied to the archive first, then deleted in the same
+    /// transaction, so a task that's already in the archive fails both.
+    async fn prune_tasks_sqlite(
+        &self,
+        policy: &RetentionPolicy,
+        cutoff: DateTimeWithTimeZone,
+    ) -> Result<u64, DbErr> {
+        let txn = self.db.begin().await?;
+
+        if policy.archive_tasks {
+            let archived_at: DateTimeWithTimeZone = Utc::now().into();
+
+            txn.execute(Statement::from_sql_and_values(
+                DbBackend::Sqlite,
+                &format!(
+                    r#"
+                    INSERT INTO task_archive ({columns}, archived_at)
+                    SELECT {columns}, $2 FROM task WHERE {}
+                    "#,
+                    PRUNABLE_TASKS_SQLITE,
+                    columns = ARCHIVED_COLUMNS
+                ),
+                vec![cutoff.into(), archived_at.into()],
+            ))
+            .await?;
+        }
+
+        let pruned = txn
+            .execute(Statement::from_sql_and_values(
+                DbBackend::Sqlite,
+                &format!("DELETE FROM task WHERE {}", PRUNABLE_TASKS_SQLITE),
+                vec![cutoff.into()],
+            ))
+            .await?
+            .rows_affected();
+
+        txn.commit().await?;
+
+        Ok(pruned)
+    }
+
+    /// Delete components that were built but never sent, returning how many
+    /// there were
+    pub async fn prune_unsent_components(&self, policy: &RetentionPolicy) -> Result<u64, DbErr> {
+        let cutoff = cutoff_for(policy.keep_unsent_components_for)?;
+
+        Ok(message_component_data::Entity::delete_many()
+            .filter(message_component_data::Column::Sent.eq(false))
+            .filter(message_component_data::Column::CreatedAt.lt(cutoff))
+            .exec(&self.db)
+            .await?
+            .rows_affected)
+    }
+
+    /// Every channel that has components sent in it
+    pub async fn component_channels(&self) -> Result<Vec<i64>, DbErr> {
+        Ok(message_component_data::Entity::find()
+            .select_only()
+            .column(message_component_data::Column::ChannelId)
+            .distinct()
+            .filter(message_component_data::Column::ChannelId.is_not_null())
+            .into_model::<ComponentChannel>()
+            .all(&self.db)
+            .await?
+            .into_iter()
+            .map(|component| component.channel_id)
+            .collect())
+    }
+
+    /// Delete the components that were sent in any of the given channels
+    pub async fn delete_components_in_channels(&self, channel_ids: Vec<i64>) -> Result<u64, DbErr> {
+        if channel_ids.is_empty() {
+            return Ok(0);
+        }
+
+        Ok(message_component_data::Entity::delete_many()
+            .filter(message_component_data::Column::ChannelId.is_in(channel_ids))
+            .exec(&self.db)
+            .await?
+            .rows_affected)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use chrono::Utc;
+    use entity::entities::{task, task_archive};
+    use sea_orm::{ActiveModelTrait, EntityTrait, Set};
+
+    use super::RetentionPolicy;
+    use crate::{
+        db_wrapper::TaskResult,
+        task_runner::{
+            graph::TaskDependencies,
+            tasks::{test_helpers::TestHelpers, DatabaseId},
+        },
+    };
+
+    fn policy(archive_tasks: bool) -> RetentionPolicy {
+        RetentionPolicy {
+            keep_tasks_for: Duration::from_secs(24 * 60 * 60),
+            archive_tasks,
+            ..Default::default()
+        }
+    }
+
+    /// A task that finished a week ago and one that finished just now
+    async fn add_old_and_new_tasks(test_helper: &TestHelpers) -> (task::Model, task::Model) {
+        let old_task = TestHelpers::finished_task(TaskResult::Cancelled, chrono::Duration::days(7))
+            .insert(&*test_helper.db)
+            .await
+            .unwrap();
+        let new_task = TestHelpers::finished_task(TaskResult::Cancelled, chrono::Duration::zero())
+            .insert(&*test_helper.db)
+            .await
+            .unwrap();
+
+        (old_task, new_task)
+    }
+
+    #[tokio::test]
+    async fn should_delete_old_tasks() {
+        let test_helper = TestHelpers::new().await;
+        let (old_task, new_task) = add_old_and_new_tasks(&test_helper).await;
+
+        assert_eq!(test_helper.db.prune_tasks(&policy(false)).await.unwrap(), 1);
+
+        let remaining: Vec<i32> = task::Entity::find()
+            .all(&*test_helper.db)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|task| task.id)
+            .collect();
+        assert_eq!(remaining, vec![new_task.id]);
+        assert!(task_archive::Entity::find_by_id(old_task.id)
+            .one(&*test_helper.db)
+            .await
+            .unwrap()
+            .is_none());
+
+        test_helper.cleanup().await;
+    }
+
+    #[tokio::test]
+    async fn should_archive_old_tasks() {
+        let test_helper = TestHelpers::new().await;
+        let (old_task, new_task) = add_old_and_new_tasks(&test_helper).await;
+
+        assert_eq!(test_helper.db.prune_tasks(&policy(true)).await.unwrap(), 1);
+
+        let archived = task_archive::Entity::find()
+            .all(&*test_helper.db)
+            .await
+            .unwrap();
+        assert_eq!(archived.len(), 1);
+        assert_eq!(archived[0].id, old_task.id);
+        assert_eq!(archived[0].payload, old_task.payload);
+        assert!(task::Entity::find_by_id(new_task.id)
+            .one(&*test_helper.db)
+            .await
+            .unwrap()
+            .is_some());
+
+        test_helper.cleanup().await;
+    }
+
+    #[tokio::test]
+    async fn should_keep_old_tasks_that_are_already_archived() {
+        let test_helper = TestHelpers::new().await;
+        let (old_task, _) = add_old_and_new_tasks(&test_helper).await;
+
+        task_archive::ActiveModel {
+            id: Set(old_task.id),
+            status: Set(old_task.status.clone()),
+            payload: Set(old_task.payload.clone()),
+            payload_version: Set(old_task.payload_version),
+            attempts: Set(old_task.attempts),
+            priority: Set(old_task.priority),
+            created_at: Set(old_task.created_at),
+            archived_at: Set(Utc::now().into()),
+            ..Default::default()
+        }
+        .insert(&*test_helper.db)
+        .await
+        .unwrap();
+
+        // Archiving it again fails instead of deleting it without a copy
+        assert!(test_helper.db.prune_tasks(&policy(true)).await.is_err());
+        assert!(task::Entity::find_by_id(old_task.id)
+            .one(&*test_helper.db)
+            .await
+            .unwrap()
+            .is_some());
+
+        test_helper.cleanup().await;
+    }
+
+    #[tokio::test]
+    async fn should_keep_parents_of_unfinished_tasks() {
+        let test_helper = TestHelpers::new().await;
+        let (old_task, _) = add_old_and_new_tasks(&test_helper).await;
+
+        // Still running, with a lease nobody takes over
+        let mut child = TestHelpers::finished_task(TaskResult::Running, chrono::Duration::zero());
+        child.finished_at = Set(None);
+        child.lease_expires_at = Set(Some((Utc::now() + chrono::Duration::hours(1)).into()));
+        child.dependencies = Set(Some(
+            serde_json::to_value(TaskDependencies {
+                parents: vec![DatabaseId(old_task.id)],
+                ..Default::default()
+            })
+            .unwrap(),
+        ));
+        child.insert(&*test_helper.db).await.unwrap();
+
+        assert_eq!(test_helper.db.prune_tasks(&policy(false)).await.unwrap(), 0);
+        assert!(task::Entity::find_by_id(old_task.id)
+            .one(&*test_helper.db)
+            .await
+            .unwrap()
+            .is_some());
+
+        test_helper.cleanup().await;
+    }
+
+    #[tokio::test]
+    async fn should_reject_retention_periods_too_long_to_represent() {
+        let test_helper = TestHelpers::new().await;
+
+        let policy = RetentionPolicy {
+            keep_tasks_for: Duration::MAX,
+            keep_unsent_components_for: Duration::MAX,
+            ..Default::default()
+        };
+        assert!(test_helper.db.prune_tasks(&policy).await.is_err());
+        assert!(test_helper
+            .db
+            .prune_unsent_components(&policy)
+            .await
+            .is_err());
+
+        test_helper.cleanup().await;
+    }
+}
@@ -0,0 +1,519 @@
+use entity::entities::{category, channel, player, role, team};
+use sea_orm::{
+    sea_query::Expr, ActiveModelTrait, ColumnTrait, DbErr, EntityTrait, QueryFilter, QueryOrder,
+    Set,
+};
+use tracing::log;
+
+use crate::{
+    discord::{DiscordChannel, DiscordMember, DiscordRole},
+    task_runner::tasks::DiscordId,
+};
+
+use super::{
+    helpers::{
+        forget_resource, get_database_guild, save_channel_kind, save_resource, team_using,
+        TrackedKind,
+    },
+    DBWrapper,
+};
+
+/// Keeping the tables that mirror the guild up to date with gateway events,
+/// so changes made outside the bot reach the database. Only resources the bot
+/// already tracks are changed. Anything in those tables is the bot's to reset,
+/// so channels and roles made by hand are never added to them, unless they're
+/// made inside a team's category and so belong to the team.
+impl DBWrapper {
+    /// A channel or category was made. A channel made in a team's category is
+    /// tracked like the team's other channels, anything else is only followed
+    /// if the bot already tracks it.
+    pub async fn sync_channel_create(
+        &self,
+        guild_id: DiscordId,
+        discord_channel: &DiscordChannel,
+    ) -> Result<(), DbErr> {
+        let kind = TrackedKind::of_channel(discord_channel.kind);
+        let team = match (kind, discord_channel.parent_id) {
+            (TrackedKind::Channel, Some(parent_id)) => {
+                team_using(&**self, TrackedKind::Category, guild_id, parent_id).await?
+            }
+            _ => None,
+        };
+
+        if team.is_none() {
+            log::debug!(
+                "Channel {} isn't in a team category, so it's left untracked",
+                *discord_channel.id
+            );
+            return self.sync_channel(discord_channel).await;
+        }
+
+        save_resource(
+            &**self,
+            TrackedKind::Channel,
+            guild_id,
+            discord_channel.id,
+            &discord_channel.name,
+            None,
+        )
+        .await?;
+        save_channel_kind(&**self, discord_channel.id, discord_channel.kind).await
+    }
+
+    /// A channel or category changed
+    pub async fn sync_channel(&self, discord_channel: &DiscordChannel) -> Result<(), DbErr> {
+        let kind = TrackedKind::of_channel(discord_channel.kind);
+        rename_resource(self, kind, discord_channel.id, &discord_channel.name).await?;
+
+        // Channels saved before their kind was kept get it here
+        if kind == TrackedKind::Channel {
+            save_channel_kind(&**self, discord_channel.id, discord_channel.kind).await?;
+        }
+
+        Ok(())
+    }
+
+    /// A channel or category was deleted
+    pub async fn sync_channel_delete(&self, discord_channel: &DiscordChannel) -> Result<(), DbErr> {
+        forget_resource(
+            self,
+            TrackedKind::of_channel(discord_channel.kind),
+            discord_channel.id,
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    /// A role was made. Roles can't be put in a team's category, so a role
+    /// made by hand is never tracked and only ones the bot tracks are followed.
+    pub async fn sync_role_create(&self, discord_role: &DiscordRole) -> Result<(), DbErr> {
+        log::debug!(
+            "Role {} was made, only following it if it's tracked",
+            *discord_role.id
+        );
+        self.sync_role(discord_role).await
+    }
+
+    /// A role changed
+    pub async fn sync_role(&self, discord_role: &DiscordRole) -> Result<(), DbErr> {
+        rename_resource(self, TrackedKind::Role, discord_role.id, &discord_role.name).await
+    }
+
+    /// A role was deleted
+    pub async fn sync_role_delete(&self, role_id: DiscordId) -> Result<(), DbErr> {
+        forget_resource(self, TrackedKind::Role, role_id).await?;
+
+        Ok(())
+    }
+
+    /// A member's name or roles changed. Whoever has a team's role is on
+    /// that team, so this is also how players join and leave teams. Members
+    /// that aren't players only become one once they have a team role.
+    pub async fn sync_member(
+        &self,
+        guild_id: DiscordId,
+        member: &DiscordMember,
+    ) -> Result<(), DbErr> {
+        let role_ids: Vec<i64> = member
+            .roles
+            .iter()
+            .map(|role_id| **role_id as i64)
+            .collect();
+
+        let teams = team::Entity::find()
+            .filter(team::Column::FkGuildId.eq(*guild_id as i64))
+            .filter(team::Column::FkTeamRoleId.is_in(role_ids))
+            .order_by_asc(team::Column::Id)
+            .all(&self.db)
+            .await?;
+
+        if teams.len() > 1 {
+            log::warn!(
+                "Member {} has the roles of {} teams, so they're put on the first",
+                *member.id,
+                teams.len()
+            );
+        }
+        let team_id = teams.first().map(|team| team.id);
+
+        let database_player = player::Entity::find()
+            .filter(player::Column::DiscordId.eq(*member.id as i64))
+            .filter(player::Column::FkGuildId.eq(*guild_id as i64))
+            .one(&self.db)
+            .await?;
+
+        match database_player {
+            Some(database_player) => {
+                if database_player.name != member.name || database_player.fk_team_id != team_id {
+                    let mut database_player: player::ActiveModel = database_player.into();
+                    database_player.name = Set(member.name.clone());
+                    database_player.fk_team_id = Set(team_id);
+                    database_player.update(&self.db).await?;
+                }
+            }
+            None if team_id.is_some() => {
+                get_database_guild(self, guild_id).await?;
+
+                player::ActiveModel {
+                    discord_id: Set(*member.id as i64),
+                    fk_guild_id: Set(*guild_id as i64),
+                    name: Set(member.name.clone()),
+                    fk_team_id: Set(team_id),
+                    ..Default::default()
+                }
+                .insert(&self.db)
+                .await?;
+            }
+            None => {}
+        }
+
+        Ok(())
+    }
+}
+
+/// Give a tracked resource its new name. Resources that aren't tracked are
+/// left alone.
+async fn rename_resource(
+    db: &DBWrapper,
+    kind: TrackedKind,
+    id: DiscordId,
+    name: &str,
+) -> Result<(), DbErr> {
+    match kind {
+        TrackedKind::Channel => {
+            channel::Entity::update_many()
+                .col_expr(channel::Column::Name, Expr::value(name))
+                .filter(channel::Column::DiscordId.eq(*id as i64))
+                .exec(&**db)
+                .await?;
+        }
+        TrackedKind::Category => {
+            category::Entity::update_many()
+                .col_expr(category::Column::Name, Expr::value(name))
+                .filter(category::Column::DiscordId.eq(*id as i64))
+                .exec(&**db)
+                .await?;
+        }
+        TrackedKind::Role => {
+            role::Entity::update_many()
+                .col_expr(role::Column::Name, Expr::value(name))
+                .filter(role::Column::DiscordId.eq(*id as i64))
+                .exec(&**db)
+                .await?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use entity::entities::{channel, player, team};
+    use sea_orm::{ActiveModelTrait, ColumnTrait, EntityTrait, QueryFilter, Set};
+    use serenity::model::prelude::ChannelType;
+
+    use crate::{
+        discord::{DiscordBackend, DiscordChannel, DiscordMember, DiscordRole},
+        task_runner::tasks::{
+            channel::ChannelCreateData,
+            test_helpers::{DatabaseConstruct, DatabaseStatus, TestHelpers},
+            DiscordId,
+        },
+    };
+
+    #[tokio::test]
+    async fn should_follow_channel_changes() {
+        let test_helper = TestHelpers::new().await;
+
+        let channel_model = test_helper
+            .db
+            .run(
+                test_helper.guild_id,
+                ChannelCreateData {
+                    name: TestHelpers::generate_name(),
+                    category_id: None,
+                    kind: ChannelType::Text,
+                },
+            )
+            .await
+            .unwrap();
+
+        let discord_channel = DiscordChannel {
+            id: DiscordId::from(channel_model.discord_id),
+            name: TestHelpers::generate_name(),
+            kind: ChannelType::Text,
+            parent_id: None,
+        };
+        test_helper.db.sync_channel(&discord_channel).await.unwrap();
+        assert_eq!(
+            test_helper
+                .check_database_status(DatabaseConstruct::Channel {
+                    name: discord_channel.name.clone()
+                })
+                .await,
+            DatabaseStatus::Exists
+        );
+
+        test_helper
+            .db
+            .sync_channel_delete(&discord_channel)
+            .await
+            .unwrap();
+        assert_eq!(
+            test_helper
+                .check_database_status(DatabaseConstruct::Channel {
+                    name: discord_channel.name
+                })
+                .await,
+            DatabaseStatus::DoesNotExist
+        );
+
+        test_helper.cleanup().await;
+    }
+
+    #[tokio::test]
+    async fn should_not_track_resources_made_by_hand() {
+        let test_helper = TestHelpers::new().await;
+        let channel_name = TestHelpers::generate_name();
+        let role_name = TestHelpers::generate_name();
+
+        let discord_channel = test_helper
+            .discord
+            .create_channel(test_helper.guild_id, &channel_name, ChannelType::Text, None)
+            .await
+            .unwrap();
+        let discord_role = test_helper
+            .discord
+            .create_role(test_helper.guild_id, &role_name, 0)
+            .await
+            .unwrap();
+
+        test_helper.db.sync_channel(&discord_channel).await.unwrap();
+        test_helper.db.sync_role(&discord_role).await.unwrap();
+
+        assert_eq!(
+            test_helper
+                .check_database_status(DatabaseConstruct::Channel { name: channel_name })
+                .await,
+            DatabaseStatus::DoesNotExist
+        );
+        assert_eq!(
+            test_helper
+                .check_database_status(DatabaseConstruct::Role { name: role_name })
+                .await,
+            DatabaseStatus::DoesNotExist
+        );
+
+        test_helper.cleanup().await;
+    }
+
+    #[tokio::test]
+    async fn should_track_channels_made_in_a_team_category() {
+        let test_helper = TestHelpers::new().await;
+        let team = test_helper.create_team(&TestHelpers::generate_name()).await;
+        let channel_name = TestHelpers::generate_name();
+
+        let discord_channel = test_helper
+            .discord
+            .create_channel(
+                test_helper.guild_id,
+                &channel_name,
+                ChannelType::Voice,
+                team.fk_team_category_id.map(DiscordId::from),
+            )
+            .await
+            .unwrap();
+        test_helper
+            .db
+            .sync_channel_create(test_helper.guild_id, &discord_channel)
+            .await
+            .unwrap();
+
+        let database_channel = channel::Entity::find()
+            .filter(channel::Column::DiscordId.eq(*discord_channel.id as i64))
+            .one(&*test_helper.db)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(database_channel.name, channel_name);
+        assert_eq!(
+            database_channel.kind,
+            Some(u8::from(ChannelType::Voice) as i16)
+        );
+
+        test_helper.cleanup().await;
+    }
+
+    #[tokio::test]
+    async fn should_not_track_created_resources_made_by_hand() {
+        let test_helper = TestHelpers::new().await;
+        let channel_name = TestHelpers::generate_name();
+        let role_name = TestHelpers::generate_name();
+
+        let discord_channel = test_helper
+            .discord
+            .create_channel(test_helper.guild_id, &channel_name, ChannelType::Text, None)
+            .await
+            .unwrap();
+        let discord_role = test_helper
+            .discord
+            .create_role(test_helper.guild_id, &role_name, 0)
+            .await
+            .unwrap();
+
+        test_helper
+            .db
+            .sync_channel_create(test_helper.guild_id, &discord_channel)
+            .await
+            .unwrap();
+        test_helper
+            .db
+            .sync_role_create(&discord_role)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            test_helper
+                .check_database_status(DatabaseConstruct::Channel { name: channel_name })
+                .await,
+            DatabaseStatus::DoesNotExist
+        );
+        assert_eq!(
+            test_helper
+                .check_database_status(DatabaseConstruct::Role { name: role_name })
+                .await,
+            DatabaseStatus::DoesNotExist
+        );
+
+        test_helper.cleanup().await;
+    }
+
+    #[tokio::test]
+    async fn should_keep_task_key_when_event_arrives_late() {
+        let test_helper = TestHelpers::new().await;
+        let name = TestHelpers::generate_name();
+
+        let channel_model = test_helper
+            .db
+            .run(
+                test_helper.guild_id,
+                ChannelCreateData {
+                    name: name.clone(),
+                    category_id: None,
+                    kind: ChannelType::Text,
+                },
+            )
+            .await
+            .unwrap();
+
+        test_helper
+            .db
+            .sync_channel(&DiscordChannel {
+                id: DiscordId::from(channel_model.discord_id),
+                name,
+                kind: ChannelType::Text,
+                parent_id: None,
+            })
+            .await
+            .unwrap();
+
+        let synced = channel::Entity::find_by_id(channel_model.discord_id)
+            .one(&*test_helper.db)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(synced.idempotency_key, channel_model.idempotency_key);
+
+        test_helper.cleanup().await;
+    }
+
+    #[tokio::test]
+    async fn should_ignore_everyone_role() {
+        let test_helper = TestHelpers::new().await;
+
+        test_helper
+            .db
+            .sync_role(&DiscordRole {
+                id: test_helper.guild_id,
+                name: "@everyone".to_string(),
+                color: 0,
+            })
+            .await
+            .unwrap();
+
+        assert_eq!(
+            test_helper
+                .check_database_status(DatabaseConstruct::Role {
+                    name: "@everyone".to_string()
+                })
+                .await,
+            DatabaseStatus::DoesNotExist
+        );
+
+        test_helper.cleanup().await;
+    }
+
+    #[tokio::test]
+    async fn should_put_members_on_teams_by_role() {
+        let test_helper = TestHelpers::new().await;
+
+        let team_role = test_helper
+            .discord
+            .create_role(test_helper.guild_id, &TestHelpers::generate_name(), 0)
+            .await
+            .unwrap();
+
+        let team_model = team::ActiveModel {
+            name: Set(TestHelpers::generate_name()),
+            fk_guild_id: Set(*test_helper.guild_id as i64),
+            fk_team_role_id: Set(Some(*team_role.id as i64)),
+            ..Default::default()
+        }
+        .insert(&*test_helper.db)
+        .await
+        .unwrap();
+
+        let mut member = DiscordMember {
+            id: DiscordId::from(1234_i64),
+            name: TestHelpers::generate_name(),
+            roles: vec![],
+        };
+
+        let find_player = || {
+            player::Entity::find()
+                .filter(player::Column::DiscordId.eq(1234))
+                .one(&*test_helper.db)
+        };
+
+        // Members without a team role aren't players
+        test_helper
+            .db
+            .sync_member(test_helper.guild_id, &member)
+            .await
+            .unwrap();
+        assert!(find_player().await.unwrap().is_none());
+
+        member.roles.push(team_role.id);
+        test_helper
+            .db
+            .sync_member(test_helper.guild_id, &member)
+            .await
+            .unwrap();
+        assert_eq!(
+            find_player().await.unwrap().unwrap().fk_team_id,
+            Some(team_model.id)
+        );
+
+        // Losing the role takes them off the team
+        member.roles.clear();
+        test_helper
+            .db
+            .sync_member(test_helper.guild_id, &member)
+            .await
+            .unwrap();
+        assert_eq!(find_player().await.unwrap().unwrap().fk_team_id, None);
+
+        test_helper.cleanup().await;
+    }
+}
@@ -0,0 +1,357 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+};
+
+use async_trait::async_trait;
+use serenity::model::channel::ChannelType;
+
+use super::{
+    DiscordBackend, DiscordChannel, DiscordGuild, DiscordMember, DiscordRole, OutgoingMessage,
+};
+use crate::task_runner::{error::TaskError, tasks::DiscordId};
+
+/// An in-memory Discord that records what tasks do to it, so the task layer
+/// can be run without a bot or a network connection
+pub struct FakeDiscord {
+    state: Mutex<FakeState>,
+    next_id: AtomicU64,
+}
+
+#[derive(Default)]
+struct FakeState {
+    guilds: HashMap<DiscordId, FakeGuild>,
+    messages: Vec<FakeMessage>,
+}
+
+#[derive(Default)]
+struct FakeGuild {
+    channels: Vec<DiscordChannel>,
+    roles: Vec<DiscordRole>,
+    /// Each member's name and roles
+    members: HashMap<DiscordId, FakeMember>,
+}
+
+#[derive(Default)]
+struct FakeMember {
+    name: String,
+    roles: HashSet<DiscordId>,
+}
+
+/// A message sent through `FakeDiscord`
+#[derive(Debug, Clone)]
+pub struct FakeMessage {
+    pub id: DiscordId,
+    pub channel_id: DiscordId,
+    pub content: String,
+    pub has_select_menu: bool,
+    pub buttons: usize,
+}
+
+impl Default for FakeDiscord {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FakeDiscord {
+    pub fn new() -> Self {
+        Self {
+            state: Mutex::default(),
+            // Low ids look nothing like real snowflakes, which makes mix-ups
+            // easy to spot
+            next_id: AtomicU64::new(1000),
+        }
+    }
+
+    fn new_id(&self) -> DiscordId {
+        DiscordId(self.next_id.fetch_add(1, Ordering::Relaxed))
+    }
+
+    /// Add an empty guild with an @everyone role, returning its id
+    pub fn add_guild(&self) -> DiscordId {
+        let guild_id = self.new_id();
+
+        let guild = FakeGuild {
+            roles: vec![DiscordRole {
+                // Discord gives @everyone the id of the guild
+                id: guild_id,
+                name: "@everyone".to_string(),
+                color: 0,
+            }],
+            ..Default::default()
+        };
+
+        self.state.lock().unwrap().guilds.insert(guild_id, guild);
+
+        guild_id
+    }
+
+    /// Add a member with no roles to a guild, returning their id
+    pub fn add_member(&self, guild_id: DiscordId) -> DiscordId {
+        let user_id = self.new_id();
+
+        if let Some(guild) = self.state.lock().unwrap().guilds.get_mut(&guild_id) {
+            guild.members.insert(
+                user_id,
+                FakeMember {
+                    name: format!("member-{}", *user_id),
+                    ..Default::default()
+                },
+            );
+        }
+
+        user_id
+    }
+
+    /// Every message sent so far, oldest first
+    pub fn messages(&self) -> Vec<FakeMessage> {
+        self.state.lock().unwrap().messages.clone()
+    }
+
+    /// Rename a channel the way an admin would by hand
+    pub fn rename_channel(&self, channel_id: DiscordId, name: &str) {
+        let mut state = self.state.lock().unwrap();
+        let channel = state
+            .guilds
+            .values_mut()
+            .flat_map(|guild| guild.channels.iter_mut())
+            .find(|channel| channel.id == channel_id)
+            .expect("Unknown channel");
+
+        channel.name = name.to_string();
+    }
+
+    fn with_guild<T>(
+        &self,
+        guild_id: DiscordId,
+        f: impl FnOnce(&mut FakeGuild) -> Result<T, TaskError>,
+    ) -> Result<T, TaskError> {
+        let mut state = self.state.lock().unwrap();
+        let guild = state
+            .guilds
+            .get_mut(&guild_id)
+            .ok_or_else(|| TaskError::permanent(format!("Unknown guild {}", *guild_id)))?;
+
+        f(guild)
+    }
+
+    fn with_member<T>(
+        &self,
+        guild_id: DiscordId,
+        user_id: DiscordId,
+        role_id: DiscordId,
+        f: impl FnOnce(&mut HashSet<DiscordId>) -> T,
+    ) -> Result<T, TaskError> {
+        self.with_guild(guild_id, |guild| {
+            if !guild.roles.iter().any(|role| role.id == role_id) {
+                return Err(TaskError::permanent(format!("Unknown role {}", *role_id)));
+            }
+
+            let member = guild
+                .members
+                .get_mut(&user_id)
+                .ok_or_else(|| TaskError::permanent(format!("Unknown member {}", *user_id)))?;
+
+            Ok(f(&mut member.roles))
+        })
+    }
+}
+
+#[async_trait]
+impl DiscordBackend for FakeDiscord {
+    async fn guild(&self, guild_id: DiscordId) -> Option<DiscordGuild> {
+        let state = self.state.lock().unwrap();
+        let guild = state.guilds.get(&guild_id)?;
+
+        Some(DiscordGuild {
+            id: guild_id,
+            channels: guild.channels.clone(),
+            roles: guild.roles.clone(),
+        })
+    }
+
+    async fn channel(&self, channel_id: DiscordId) -> Option<DiscordChannel> {
+        let state = self.state.lock().unwrap();
+
+        state
+            .guilds
+            .values()
+            .flat_map(|guild| guild.channels.iter())
+            .find(|channel| channel.id == channel_id)
+            .cloned()
+    }
+
+    async fn member(
+        &self,
+        guild_id: DiscordId,
+        user_id: DiscordId,
+    ) -> Result<Option<DiscordMember>, TaskError> {
+        self.with_guild(guild_id, |guild| {
+            Ok(guild.members.get(&user_id).map(|member| DiscordMember {
+                id: user_id,
+                name: member.name.clone(),
+                roles: member.roles.iter().copied().collect(),
+            }))
+        })
+    }
+
+    async fn channel_exists(&self, channel_id: DiscordId) -> Result<bool, TaskError> {
+        Ok(self.channel(channel_id).await.is_some())
+    }
+
+    async fn create_category(
+        &self,
+        guild_id: DiscordId,
+        name: &str,
+    ) -> Result<DiscordChannel, TaskError> {
+        self.create_channel(guild_id, name, ChannelType::Category, None)
+            .await
+    }
+
+    async fn create_channel(
+        &self,
+        guild_id: DiscordId,
+        name: &str,
+        kind: ChannelType,
+        parent_id: Option<DiscordId>,
+    ) -> Result<DiscordChannel, TaskError> {
+        let channel = DiscordChannel {
+            id: self.new_id(),
+            name: name.to_string(),
+            kind,
+            parent_id,
+        };
+
+        self.with_guild(guild_id, |guild| {
+            if let Some(parent_id) = parent_id {
+                if !guild
+                    .channels
+                    .iter()
+                    .any(|c| c.id == parent_id && c.kind == ChannelType::Category)
+                {
+                    return Err(TaskError::permanent(format!(
+                        "Unknown category {}",
+                        *parent_id
+                    )));
+                }
+            }
+
+            guild.channels.push(channel.clone());
+
+            Ok(channel)
+        })
+    }
+
+    async fn delete_channel(&self, channel_id: DiscordId) -> Result<(), TaskError> {
+        let mut state = self.state.lock().unwrap();
+
+        for guild in state.guilds.values_mut() {
+            if let Some(index) = guild.channels.iter().position(|c| c.id == channel_id) {
+                guild.channels.remove(index);
+
+                // Like Discord, deleting a category leaves its channels
+                // without one
+                for channel in guild.channels.iter_mut() {
+                    if channel.parent_id == Some(channel_id) {
+                        channel.parent_id = None;
+                    }
+                }
+
+                return Ok(());
+            }
+        }
+
+        Err(TaskError::permanent(format!(
+            "Unknown channel {}",
+            *channel_id
+        )))
+    }
+
+    async fn create_role(
+        &self,
+        guild_id: DiscordId,
+        name: &str,
+        color: u32,
+    ) -> Result<DiscordRole, TaskError> {
+        let role = DiscordRole {
+            id: self.new_id(),
+            name: name.to_string(),
+            color,
+        };
+
+        self.with_guild(guild_id, |guild| {
+            guild.roles.push(role.clone());
+
+            Ok(role)
+        })
+    }
+
+    async fn delete_role(&self, guild_id: DiscordId, role_id: DiscordId) -> Result<(), TaskError> {
+        self.with_guild(guild_id, |guild| {
+            let index = guild
+                .roles
+                .iter()
+                .position(|role| role.id == role_id)
+                .ok_or_else(|| TaskError::permanent(format!("Unknown role {}", *role_id)))?;
+
+            guild.roles.remove(index);
+
+            for member in guild.members.values_mut() {
+                member.roles.remove(&role_id);
+            }
+
+            Ok(())
+        })
+    }
+
+    async fn add_member_role(
+        &self,
+        guild_id: DiscordId,
+        user_id: DiscordId,
+        role_id: DiscordId,
+    ) -> Result<(), TaskError> {
+        self.with_member(guild_id, user_id, role_id, |roles| {
+            roles.insert(role_id);
+        })
+    }
+
+    async fn remove_member_role(
+        &self,
+        guild_id: DiscordId,
+        user_id: DiscordId,
+        role_id: DiscordId,
+    ) -> Result<(), TaskError> {
+        self.with_member(guild_id, user_id, role_id, |roles| {
+            roles.remove(&role_id);
+        })
+    }
+
+    async fn send_message(
+        &self,
+        channel_id: DiscordId,
+        message: OutgoingMessage,
+    ) -> Result<DiscordId, TaskError> {
+        if self.channel(channel_id).await.is_none() {
+            return Err(TaskError::permanent(format!(
+                "Unknown channel {}",
+                *channel_id
+            )));
+        }
+
+        let id = self.new_id();
+
+        self.state.lock().unwrap().messages.push(FakeMessage {
+            id,
+            channel_id,
+            content: message.content,
+            has_select_menu: message.select_menu.is_some(),
+            buttons: message.buttons.len(),
+        });
+
+        Ok(id)
+    }
+}
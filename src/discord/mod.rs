@@ -0,0 +1,127 @@
+use async_trait::async_trait;
+use serenity::{
+    builder::{CreateButton, CreateSelectMenu},
+    model::channel::ChannelType,
+};
+
+use crate::task_runner::{error::TaskError, tasks::DiscordId};
+
+pub use self::{fake::FakeDiscord, serenity_backend::SerenityBackend};
+
+pub mod fake;
+pub mod serenity_backend;
+
+/// The Discord operations that tasks use. Tasks only talk to Discord through
+/// this, so they can be run against `FakeDiscord` instead of a live guild.
+#[async_trait]
+pub trait DiscordBackend: Send + Sync {
+    /// The channels and roles of a guild, or `None` if it isn't known
+    async fn guild(&self, guild_id: DiscordId) -> Option<DiscordGuild>;
+
+    /// Any channel, including categories
+    async fn channel(&self, channel_id: DiscordId) -> Option<DiscordChannel>;
+
+    /// A member of a guild, or `None` if they aren't in it. This asks Discord
+    /// about members it doesn't have cached.
+    async fn member(
+        &self,
+        guild_id: DiscordId,
+        user_id: DiscordId,
+    ) -> Result<Option<DiscordMember>, TaskError>;
+
+    /// Whether a channel or thread still exists. Unlike `channel`, this asks
+    /// Discord about channels it doesn't have cached, so `false` means the
+    /// channel is really gone.
+    async fn channel_exists(&self, channel_id: DiscordId) -> Result<bool, TaskError>;
+
+    /// Create a category that everyone can see
+    async fn create_category(
+        &self,
+        guild_id: DiscordId,
+        name: &str,
+    ) -> Result<DiscordChannel, TaskError>;
+
+    async fn create_channel(
+        &self,
+        guild_id: DiscordId,
+        name: &str,
+        kind: ChannelType,
+        parent_id: Option<DiscordId>,
+    ) -> Result<DiscordChannel, TaskError>;
+
+    /// Delete a channel or category
+    async fn delete_channel(&self, channel_id: DiscordId) -> Result<(), TaskError>;
+
+    /// Create a role with an RGB color, e.g. `0x00ff00`
+    async fn create_role(
+        &self,
+        guild_id: DiscordId,
+        name: &str,
+        color: u32,
+    ) -> Result<DiscordRole, TaskError>;
+
+    async fn delete_role(&self, guild_id: DiscordId, role_id: DiscordId) -> Result<(), TaskError>;
+
+    async fn add_member_role(
+        &self,
+        guild_id: DiscordId,
+        user_id: DiscordId,
+        role_id: DiscordId,
+    ) -> Result<(), TaskError>;
+
+    async fn remove_member_role(
+        &self,
+        guild_id: DiscordId,
+        user_id: DiscordId,
+        role_id: DiscordId,
+    ) -> Result<(), TaskError>;
+
+    /// Send a message, returning its id
+    async fn send_message(
+        &self,
+        channel_id: DiscordId,
+        message: OutgoingMessage,
+    ) -> Result<DiscordId, TaskError>;
+}
+
+/// What tasks need to know about a guild
+#[derive(Debug, Clone)]
+pub struct DiscordGuild {
+    pub id: DiscordId,
+    pub channels: Vec<DiscordChannel>,
+    pub roles: Vec<DiscordRole>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiscordChannel {
+    pub id: DiscordId,
+    pub name: String,
+    pub kind: ChannelType,
+    /// The category the channel is in
+    pub parent_id: Option<DiscordId>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiscordRole {
+    pub id: DiscordId,
+    pub name: String,
+    /// RGB, or 0 for no color
+    pub color: u32,
+}
+
+/// What the bot keeps track of about a member
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiscordMember {
+    pub id: DiscordId,
+    /// The member's nickname in the guild, or their username
+    pub name: String,
+    pub roles: Vec<DiscordId>,
+}
+
+/// A message to send, with its components already built
+#[derive(Debug, Clone, Default)]
+pub struct OutgoingMessage {
+    pub content: String,
+    pub select_menu: Option<CreateSelectMenu>,
+    pub buttons: Vec<CreateButton>,
+}
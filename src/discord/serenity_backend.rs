@@ -0,0 +1,247 @@
+use async_trait::async_trait;
+use serenity::{
+    builder::{CreateChannel, CreateMessage, EditRole},
+    client::Context,
+    model::{
+        channel::{ChannelType, GuildChannel, PermissionOverwriteType},
+        permissions::Permissions,
+        prelude::{ChannelId, GuildId, Member, PermissionOverwrite, Role, UserId},
+    },
+};
+
+use super::{
+    DiscordBackend, DiscordChannel, DiscordGuild, DiscordMember, DiscordRole, OutgoingMessage,
+};
+use crate::task_runner::{error::TaskError, tasks::DiscordId};
+
+/// Talks to Discord through serenity, reading from its cache where it can
+#[derive(Clone)]
+pub struct SerenityBackend {
+    pub ctx: Context,
+}
+
+impl SerenityBackend {
+    pub fn new(ctx: Context) -> Self {
+        Self { ctx }
+    }
+}
+
+impl From<&GuildChannel> for DiscordChannel {
+    fn from(channel: &GuildChannel) -> Self {
+        Self {
+            id: DiscordId::from(channel.id),
+            name: channel.name.clone(),
+            kind: channel.kind,
+            parent_id: channel.parent_id.map(DiscordId::from),
+        }
+    }
+}
+
+impl From<&Role> for DiscordRole {
+    fn from(role: &Role) -> Self {
+        Self {
+            id: DiscordId::from(role.id),
+            name: role.name.clone(),
+            color: role.colour.0,
+        }
+    }
+}
+
+impl From<&Member> for DiscordMember {
+    fn from(member: &Member) -> Self {
+        Self {
+            id: DiscordId::from(member.user.id),
+            name: member
+                .nick
+                .clone()
+                .unwrap_or_else(|| member.user.name.clone()),
+            roles: member.roles.iter().copied().map(DiscordId::from).collect(),
+        }
+    }
+}
+
+#[async_trait]
+impl DiscordBackend for SerenityBackend {
+    async fn guild(&self, guild_id: DiscordId) -> Option<DiscordGuild> {
+        let guild = self.ctx.cache.guild(guild_id).map(|g| g.clone())?;
+
+        Some(DiscordGuild {
+            id: guild_id,
+            channels: guild.channels.values().map(DiscordChannel::from).collect(),
+            roles: guild.roles.values().map(DiscordRole::from).collect(),
+        })
+    }
+
+    async fn channel(&self, channel_id: DiscordId) -> Option<DiscordChannel> {
+        self.ctx
+            .cache
+            .channel(channel_id)
+            .map(|channel| DiscordChannel::from(&channel.clone()))
+    }
+
+    async fn member(
+        &self,
+        guild_id: DiscordId,
+        user_id: DiscordId,
+    ) -> Result<Option<DiscordMember>, TaskError> {
+        let guild_id: GuildId = guild_id.into();
+        let user_id: UserId = user_id.into();
+
+        // Members come from the cache if they're in it and from Discord
+        // otherwise, where a 404 means they aren't in the guild
+        match guild_id.member(&self.ctx, user_id).await {
+            Ok(member) => Ok(Some(DiscordMember::from(&member))),
+            Err(serenity::Error::Http(http_error))
+                if http_error.status_code().map(|status| status.as_u16()) == Some(404) =>
+            {
+                Ok(None)
+            }
+            Err(why) => Err(why.into()),
+        }
+    }
+
+    async fn channel_exists(&self, channel_id: DiscordId) -> Result<bool, TaskError> {
+        let channel_id: ChannelId = channel_id.into();
+
+        // The cache doesn't hold threads and is empty until it's built, so
+        // only a 404 from Discord counts as the channel being gone
+        match channel_id.to_channel(&self.ctx).await {
+            Ok(_) => Ok(true),
+            Err(serenity::Error::Http(http_error))
+                if http_error.status_code().map(|status| status.as_u16()) == Some(404) =>
+            {
+                Ok(false)
+            }
+            Err(why) => Err(why.into()),
+        }
+    }
+
+    async fn create_category(
+        &self,
+        guild_id: DiscordId,
+        name: &str,
+    ) -> Result<DiscordChannel, TaskError> {
+        let everyone_role = self
+            .ctx
+            .cache
+            .guild(guild_id)
+            .and_then(|guild| guild.role_by_name("@everyone").map(|role| role.id))
+            .ok_or_else(|| TaskError::permanent("The guild has no @everyone role"))?;
+
+        let guild_id: GuildId = guild_id.into();
+        let category = guild_id
+            .create_channel(
+                &self.ctx.http,
+                CreateChannel::new(name)
+                    .kind(ChannelType::Category)
+                    .permissions(vec![PermissionOverwrite {
+                        allow: Permissions::VIEW_CHANNEL,
+                        deny: Permissions::SEND_TTS_MESSAGES,
+                        kind: PermissionOverwriteType::Role(everyone_role),
+                    }]),
+            )
+            .await?;
+
+        Ok(DiscordChannel::from(&category))
+    }
+
+    async fn create_channel(
+        &self,
+        guild_id: DiscordId,
+        name: &str,
+        kind: ChannelType,
+        parent_id: Option<DiscordId>,
+    ) -> Result<DiscordChannel, TaskError> {
+        let mut channel_builder = CreateChannel::new(name).kind(kind);
+
+        if let Some(category) = parent_id {
+            channel_builder = channel_builder.category(category);
+        }
+
+        let guild_id: GuildId = guild_id.into();
+        let channel = guild_id
+            .create_channel(&self.ctx.http, channel_builder)
+            .await?;
+
+        Ok(DiscordChannel::from(&channel))
+    }
+
+    async fn delete_channel(&self, channel_id: DiscordId) -> Result<(), TaskError> {
+        let channel_id: ChannelId = channel_id.into();
+        channel_id.delete(&self.ctx.http).await?;
+
+        Ok(())
+    }
+
+    async fn create_role(
+        &self,
+        guild_id: DiscordId,
+        name: &str,
+        color: u32,
+    ) -> Result<DiscordRole, TaskError> {
+        let guild_id: GuildId = guild_id.into();
+        let role = guild_id
+            .create_role(&self.ctx.http, EditRole::new().name(name).colour(color))
+            .await?;
+
+        Ok(DiscordRole::from(&role))
+    }
+
+    async fn delete_role(&self, guild_id: DiscordId, role_id: DiscordId) -> Result<(), TaskError> {
+        let guild_id: GuildId = guild_id.into();
+        guild_id.delete_role(&self.ctx.http, role_id).await?;
+
+        Ok(())
+    }
+
+    async fn add_member_role(
+        &self,
+        guild_id: DiscordId,
+        user_id: DiscordId,
+        role_id: DiscordId,
+    ) -> Result<(), TaskError> {
+        self.ctx
+            .http
+            .add_member_role(guild_id.into(), user_id.into(), role_id.into(), None)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn remove_member_role(
+        &self,
+        guild_id: DiscordId,
+        user_id: DiscordId,
+        role_id: DiscordId,
+    ) -> Result<(), TaskError> {
+        self.ctx
+            .http
+            .remove_member_role(guild_id.into(), user_id.into(), role_id.into(), None)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn send_message(
+        &self,
+        channel_id: DiscordId,
+        message: OutgoingMessage,
+    ) -> Result<DiscordId, TaskError> {
+        let mut message_builder = CreateMessage::new().content(message.content);
+
+        if let Some(select_menu) = message.select_menu {
+            message_builder = message_builder.select_menu(select_menu);
+        }
+
+        for button in message.buttons {
+            message_builder = message_builder.button(button);
+        }
+
+        let channel_id: ChannelId = channel_id.into();
+        let message = channel_id
+            .send_message(&self.ctx.http, message_builder)
+            .await?;
+
+        Ok(DiscordId(message.id.0.get()))
+    }
+}
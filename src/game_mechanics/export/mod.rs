@@ -0,0 +1,660 @@
+use std::{collections::HashMap, fmt};
+
+use chrono::Utc;
+use entity::entities::{
+    category, channel, currency, guild, player, role, task, team, trade, trade_item, wallet,
+    wallet_balance,
+};
+use sea_orm::{
+    prelude::DateTimeWithTimeZone, sea_query::Expr, ActiveModelTrait, ColumnTrait, DbErr,
+    EntityTrait, QueryFilter, Set, TransactionTrait,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use serenity::model::channel::ChannelType;
+
+use crate::{
+    config::GuildSettings,
+    db_wrapper::{
+        helpers::{get_database_guild, save_resource, TrackedKind},
+        ledger::{self, LedgerEntry, LedgerError},
+        payload::{read_payload, PayloadKind, PAYLOAD_VERSION},
+        DBWrapper, TaskResult,
+    },
+    discord::DiscordBackend,
+    task_runner::{
+        error::TaskError,
+        schedule::{NewTask, TaskRecurrence},
+        tasks::{
+            category::CreateCategory,
+            channel::ChannelCreateData,
+            reconcile::{guild_resources, Resource},
+            role::CreateRole,
+            DatabaseId, DiscordId, TaskType,
+        },
+    },
+};
+
+#[cfg(test)]
+mod tests;
+
+/// The version of the export document that's written now. A change to
+/// `GameExport` that would stop old exports from reading has to bump it.
+/// Version 2 added trades, which older exports import without.
+pub const EXPORT_VERSION: i32 = 2;
+
+/// The oldest export version that can still be imported
+pub const OLDEST_EXPORT_VERSION: i32 = 1;
+
+/// Everything that makes up a game in a guild. This can be imported into
+/// another guild, or into the same one after a reset.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GameExport {
+    pub version: i32,
+    pub exported_at: DateTimeWithTimeZone,
+    pub guild: guild::Model,
+    pub categories: Vec<category::Model>,
+    pub channels: Vec<channel::Model>,
+    pub roles: Vec<role::Model>,
+    pub teams: Vec<team::Model>,
+    pub players: Vec<player::Model>,
+    /// Currencies aren't tied to a guild, so these are all of them
+    pub currencies: Vec<currency::Model>,
+    /// The wallets the teams use
+    pub wallets: Vec<wallet::Model>,
+    /// What's in the wallets. The transactions behind these aren't exported,
+    /// so importing mints them again. Exports from before balances were kept
+    /// don't have these.
+    #[serde(default)]
+    pub wallet_balances: Vec<wallet_balance::Model>,
+    /// Trades between the teams, whatever their status
+    #[serde(default)]
+    pub trades: Vec<trade::Model>,
+    #[serde(default)]
+    pub trade_items: Vec<trade_item::Model>,
+    pub tasks: Vec<ExportedTask>,
+}
+
+/// A task that was waiting to run. Tasks that depend on other tasks aren't
+/// exported, since they're only part of a graph.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ExportedTask {
+    pub payload: Value,
+    pub payload_version: i32,
+    pub priority: i32,
+    pub run_at: Option<DateTimeWithTimeZone>,
+    pub recurrence: Option<Value>,
+}
+
+#[derive(Debug)]
+pub enum ImportError {
+    /// The document was written by another version of the bot
+    UnsupportedVersion(i32),
+    /// The guild already has a game in it
+    NotEmpty,
+    GuildNotFound,
+    InvalidGuildSettings(String),
+    InvalidTask(String),
+    Task(TaskError),
+    Ledger(LedgerError),
+    Database(DbErr),
+}
+
+impl fmt::Display for ImportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ImportError::UnsupportedVersion(version) => write!(
+                f,
+                "Exports of version {} can't be imported, only versions {} to {}",
+                version, OLDEST_EXPORT_VERSION, EXPORT_VERSION
+            ),
+            ImportError::NotEmpty => write!(f, "The server already has teams or players"),
+            ImportError::GuildNotFound => write!(f, "The server isn't known"),
+            ImportError::InvalidGuildSettings(why) => {
+                write!(f, "The exported settings are invalid: {}", why)
+            }
+            ImportError::InvalidTask(why) => write!(f, "An exported task is invalid: {}", why),
+            ImportError::Task(why) => write!(f, "{}", why),
+            ImportError::Ledger(why) => write!(f, "{}", why),
+            ImportError::Database(why) => write!(f, "{}", why),
+        }
+    }
+}
+
+impl From<DbErr> for ImportError {
+    fn from(why: DbErr) -> Self {
+        ImportError::Database(why)
+    }
+}
+
+impl From<LedgerError> for ImportError {
+    fn from(why: LedgerError) -> Self {
+        ImportError::Ledger(why)
+    }
+}
+
+impl From<TaskError> for ImportError {
+    fn from(why: TaskError) -> Self {
+        ImportError::Task(why)
+    }
+}
+
+/// How an import went
+#[derive(Debug, Clone, Default)]
+pub struct ImportReport {
+    /// Resources that were found in the guild by name
+    pub matched: Vec<Resource>,
+    /// Resources that had to be created
+    pub created: Vec<Resource>,
+    pub teams: usize,
+    pub players: usize,
+    pub trades: usize,
+    pub tasks: usize,
+}
+
+impl fmt::Display for ImportReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Imported {} teams, {} players, {} trades and {} tasks",
+            self.teams, self.players, self.trades, self.tasks
+        )?;
+        writeln!(
+            f,
+            "Used {} channels and roles already in the server, and created {}",
+            self.matched.len(),
+            self.created.len()
+        )?;
+
+        for resource in &self.created {
+            writeln!(f, "Created {:?} {}", resource.kind, resource.name)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Export the game in a guild
+pub async fn export_game(db: &DBWrapper, guild_id: DiscordId) -> Result<GameExport, DbErr> {
+    let guild_id_value = *guild_id as i64;
+
+    let teams = team::Entity::find()
+        .filter(team::Column::FkGuildId.eq(guild_id_value))
+        .all(&**db)
+        .await?;
+
+    let wallet_ids: Vec<i32> = teams.iter().filter_map(|team| team.wallet).collect();
+
+    let trades = trade::Entity::find()
+        .filter(trade::Column::FkGuildId.eq(guild_id_value))
+        .all(&**db)
+        .await?;
+    let trade_ids: Vec<i32> = trades.iter().map(|trade| trade.id).collect();
+
+    let mut tasks = Vec::new();
+    for database_task in task::Entity::find()
+        .filter(task::Column::Status.eq(serde_json::to_value(TaskResult::Pending).unwrap()))
+        .filter(task::Column::Dependencies.is_null())
+        .all(&**db)
+        .await?
+    {
+        // Tasks that can't be read anymore are left for the dead letter queue
+        let Ok(task_type) = read_payload::<TaskType>(
+            PayloadKind::Task,
+            database_task.payload_version,
+            database_task.payload,
+        ) else {
+            continue;
+        };
+
+        if task_type.guild_id() != Some(guild_id) {
+            continue;
+        }
+
+        tasks.push(ExportedTask {
+            payload: serde_json::to_value(&task_type).unwrap(),
+            payload_version: PAYLOAD_VERSION,
+            priority: database_task.priority,
+            run_at: database_task.run_at,
+            recurrence: database_task.recurrence,
+        });
+    }
+
+    Ok(GameExport {
+        version: EXPORT_VERSION,
+        exported_at: Utc::now().into(),
+        guild: get_database_guild(db, guild_id).await?,
+        categories: category::Entity::find()
+            .filter(category::Column::FkGuildId.eq(guild_id_value))
+            .all(&**db)
+            .await?,
+        channels: channel::Entity::find()
+            .filter(channel::Column::FkGuildId.eq(guild_id_value))
+            .all(&**db)
+            .await?,
+        roles: role::Entity::find()
+            .filter(role::Column::FkGuildId.eq(guild_id_value))
+            .all(&**db)
+            .await?,
+        players: player::Entity::find()
+            .filter(player::Column::FkGuildId.eq(guild_id_value))
+            .all(&**db)
+            .await?,
+        teams,
+        currencies: currency::Entity::find().all(&**db).await?,
+        wallets: wallet::Entity::find()
+            .filter(wallet::Column::Id.is_in(wallet_ids.clone()))
+            .all(&**db)
+            .await?,
+        wallet_balances: wallet_balance::Entity::find()
+            .filter(wallet_balance::Column::FkWalletId.is_in(wallet_ids))
+            .all(&**db)
+            .await?,
+        trade_items: trade_item::Entity::find()
+            .filter(trade_item::Column::FkTradeId.is_in(trade_ids))
+            .all(&**db)
+            .await?,
+        trades,
+        tasks,
+    })
+}
+
+/// Import a game into a guild that doesn't have one yet. Channels, categories
+/// and roles are matched to the ones in the guild by name, and any that
+/// aren't there are created. Every Discord id in the export is swapped for
+/// the one it was matched to, including the guild's own.
+pub async fn import_game(
+    db: &DBWrapper,
+    discord: &dyn DiscordBackend,
+    guild_id: DiscordId,
+    export: &GameExport,
+) -> Result<ImportReport, ImportError> {
+    if !(OLDEST_EXPORT_VERSION..=EXPORT_VERSION).contains(&export.version) {
+        return Err(ImportError::UnsupportedVersion(export.version));
+    }
+
+    let settings = GuildSettings::from_json(export.guild.settings.clone())
+        .map_err(|why| ImportError::InvalidGuildSettings(why.to_string()))?;
+
+    let guild_id_value = *guild_id as i64;
+    let has_game = team::Entity::find()
+        .filter(team::Column::FkGuildId.eq(guild_id_value))
+        .one(&**db)
+        .await?
+        .is_some()
+        || player::Entity::find()
+            .filter(player::Column::FkGuildId.eq(guild_id_value))
+            .one(&**db)
+            .await?
+            .is_some();
+    if has_game {
+        return Err(ImportError::NotEmpty);
+    }
+
+    let discord_guild = discord
+        .guild(guild_id)
+        .await
+        .ok_or(ImportError::GuildNotFound)?;
+
+    // The guild takes the exported settings
+    let mut database_guild: guild::ActiveModel = get_database_guild(db, guild_id).await?.into();
+    database_guild.settings = Set(export.guild.settings.clone());
+    database_guild.update(&**db).await?;
+
+    let mut report = ImportReport::default();
+    let mut ids = IdMap::default();
+    ids.insert(export.guild.discord_id, guild_id);
+
+    let mut unmatched = guild_resources(&discord_guild);
+
+    // Categories first, so channels can be created in them
+    for database_category in &export.categories {
+        let resource = Resource {
+            kind: TrackedKind::Category,
+            id: DiscordId::from(database_category.discord_id),
+            name: database_category.name.clone(),
+        };
+
+        let new_id = match take_match(&mut unmatched, &resource) {
+            Some(new_id) => {
+                report.matched.push(resource);
+                new_id
+            }
+            None => {
+                let created = db
+                    .run(
+                        guild_id,
+                        CreateCategory {
+                            name: resource.name.clone(),
+                        },
+                    )
+                    .await?;
+                report.created.push(resource);
+                DiscordId::from(created.discord_id)
+            }
+        };
+        ids.insert(database_category.discord_id, new_id);
+    }
+
+    for database_channel in &export.channels {
+        let resource = Resource {
+            kind: TrackedKind::Channel,
+            id: DiscordId::from(database_channel.discord_id),
+            name: database_channel.name.clone(),
+        };
+
+        let new_id = match take_match(&mut unmatched, &resource) {
+            Some(new_id) => {
+                report.matched.push(resource);
+                new_id
+            }
+            None => {
+                // A team's channels go in the team's category
+                let category_id = export
+                    .teams
+                    .iter()
+                    .find(|team| {
+                        [
+                            team.fk_general_channel_id,
+                            team.fk_trade_channel_id,
+                            team.fk_menu_channel_id,
+                        ]
+                        .contains(&Some(database_channel.discord_id))
+                    })
+                    .and_then(|team| team.fk_team_category_id)
+                    .and_then(|category_id| ids.get(category_id));
+
+                let created = db
+                    .run(
+                        guild_id,
+                        ChannelCreateData {
+                            name: resource.name.clone(),
+                            category_id,
+                            kind: ChannelType::Text,
+                        },
+                    )
+                    .await?;
+                report.created.push(resource);
+                DiscordId::from(created.discord_id)
+            }
+        };
+        ids.insert(database_channel.discord_id, new_id);
+    }
+
+    for database_role in &export.roles {
+        let resource = Resource {
+            kind: TrackedKind::Role,
+            id: DiscordId::from(database_role.discord_id),
+            name: database_role.name.clone(),
+        };
+
+        let new_id = match take_match(&mut unmatched, &resource) {
+            Some(new_id) => {
+                report.matched.push(resource);
+                new_id
+            }
+            None => {
+                let created = db
+                    .run(
+                        guild_id,
+                        CreateRole {
+                            name: resource.name.clone(),
+                            color: settings.team_role_color,
+                        },
+                    )
+                    .await?;
+                report.created.push(resource);
+                DiscordId::from(created.discord_id)
+            }
+        };
+        ids.insert(database_role.discord_id, new_id);
+    }
+
+    let txn = db.begin().await?;
+
+    // Make sure everything that was matched is tracked
+    for resource in &report.matched {
+        let new_id = ids.get(*resource.id as i64).unwrap();
+        save_resource(&txn, resource.kind, guild_id, new_id, &resource.name, None).await?;
+    }
+
+    // Currencies are shared between guilds, so only new ones are added
+    let mut currency_ids = HashMap::new();
+    for exported_currency in &export.currencies {
+        let existing = currency::Entity::find()
+            .filter(currency::Column::Name.eq(exported_currency.name.clone()))
+            .one(&txn)
+            .await?;
+
+        let currency_id = match existing {
+            Some(database_currency) => database_currency.id,
+            None => {
+                currency::ActiveModel {
+                    name: Set(exported_currency.name.clone()),
+                    description: Set(exported_currency.description.clone()),
+                    currency_type: Set(exported_currency.currency_type.clone()),
+                    emoji: Set(exported_currency.emoji.clone()),
+                    ..Default::default()
+                }
+                .insert(&txn)
+                .await?
+                .id
+            }
+        };
+        currency_ids.insert(exported_currency.id, currency_id);
+    }
+
+    let mut wallet_ids = HashMap::new();
+    for exported_wallet in &export.wallets {
+        let database_wallet = wallet::ActiveModel {
+            name: Set(exported_wallet.name.clone()),
+            ..Default::default()
+        }
+        .insert(&txn)
+        .await?;
+        wallet_ids.insert(exported_wallet.id, database_wallet.id);
+    }
+
+    for exported_balance in &export.wallet_balances {
+        let (Some(wallet_id), Some(currency_id)) = (
+            wallet_ids.get(&exported_balance.fk_wallet_id),
+            currency_ids.get(&exported_balance.fk_currency_id),
+        ) else {
+            continue;
+        };
+
+        // Balances have to come from the ledger, so they're minted again
+        if exported_balance.amount <= 0 {
+            continue;
+        }
+        let entry = LedgerEntry::mint(
+            DatabaseId(*wallet_id),
+            DatabaseId(*currency_id),
+            exported_balance.amount,
+        )
+        .memo("Imported balance");
+        ledger::record(&txn, &entry).await?;
+    }
+
+    // Rows get new database ids, so teams are remapped for their players
+    let mut team_ids = HashMap::new();
+    for exported_team in &export.teams {
+        let remap = |id: Option<i64>| id.and_then(|id| ids.get(id)).map(|id| *id as i64);
+
+        let database_team = team::ActiveModel {
+            name: Set(exported_team.name.clone()),
+            abreviation: Set(exported_team.abreviation.clone()),
+            created_at: Set(exported_team.created_at),
+            updated_at: Set(exported_team.updated_at),
+            emoji: Set(exported_team.emoji.clone()),
+            wallet: Set(exported_team
+                .wallet
+                .and_then(|id| wallet_ids.get(&id).copied())),
+            fk_bank_embed_id: Set(exported_team.fk_bank_embed_id),
+            fk_guild_id: Set(guild_id_value),
+            fk_team_role_id: Set(remap(exported_team.fk_team_role_id)),
+            fk_team_category_id: Set(remap(exported_team.fk_team_category_id)),
+            fk_general_channel_id: Set(remap(exported_team.fk_general_channel_id)),
+            fk_trade_channel_id: Set(remap(exported_team.fk_trade_channel_id)),
+            fk_menu_channel_id: Set(remap(exported_team.fk_menu_channel_id)),
+            ..Default::default()
+        }
+        .insert(&txn)
+        .await?;
+        team_ids.insert(exported_team.id, database_team.id);
+
+        if let Some(wallet_id) = database_team.wallet {
+            wallet::Entity::update_many()
+                .col_expr(wallet::Column::FkTeamId, Expr::value(database_team.id))
+                .filter(wallet::Column::Id.eq(wallet_id))
+                .exec(&txn)
+                .await?;
+        }
+        report.teams += 1;
+    }
+
+    // Members keep their ids between guilds
+    for exported_player in &export.players {
+        player::ActiveModel {
+            discord_id: Set(exported_player.discord_id),
+            name: Set(exported_player.name.clone()),
+            fk_team_id: Set(exported_player
+                .fk_team_id
+                .and_then(|id| team_ids.get(&id).copied())),
+            fk_guild_id: Set(guild_id_value),
+            ..Default::default()
+        }
+        .insert(&txn)
+        .await?;
+        report.players += 1;
+    }
+
+    // Trades only make sense between the teams they were made by
+    let mut trade_ids = HashMap::new();
+    for exported_trade in &export.trades {
+        let (Some(initiating_team_id), Some(receiving_team_id)) = (
+            team_ids.get(&exported_trade.fk_initiating_team_id),
+            team_ids.get(&exported_trade.fk_receiving_team_id),
+        ) else {
+            continue;
+        };
+
+        let database_trade = trade::ActiveModel {
+            fk_guild_id: Set(guild_id_value),
+            fk_initiating_team_id: Set(*initiating_team_id),
+            fk_receiving_team_id: Set(*receiving_team_id),
+            fk_awaiting_team_id: Set(exported_trade
+                .fk_awaiting_team_id
+                .and_then(|id| team_ids.get(&id).copied())),
+            status: Set(exported_trade.status.clone()),
+            created_at: Set(exported_trade.created_at),
+            updated_at: Set(exported_trade.updated_at),
+            proposed_at: Set(exported_trade.proposed_at),
+            expires_at: Set(exported_trade.expires_at),
+            ..Default::default()
+        }
+        .insert(&txn)
+        .await?;
+        trade_ids.insert(exported_trade.id, database_trade.id);
+        report.trades += 1;
+    }
+
+    for exported_item in &export.trade_items {
+        let (Some(trade_id), Some(team_id), Some(currency_id)) = (
+            trade_ids.get(&exported_item.fk_trade_id),
+            team_ids.get(&exported_item.fk_from_team_id),
+            currency_ids.get(&exported_item.fk_currency_id),
+        ) else {
+            continue;
+        };
+
+        trade_item::ActiveModel {
+            fk_trade_id: Set(*trade_id),
+            fk_from_team_id: Set(*team_id),
+            fk_currency_id: Set(*currency_id),
+            amount: Set(exported_item.amount),
+        }
+        .insert(&txn)
+        .await?;
+    }
+
+    txn.commit().await?;
+
+    for exported_task in &export.tasks {
+        let task_type: TaskType = read_payload(
+            PayloadKind::Task,
+            exported_task.payload_version,
+            exported_task.payload.clone(),
+        )
+        .map_err(|why| ImportError::InvalidTask(why.to_string()))?;
+
+        let mut payload = serde_json::to_value(&task_type).unwrap();
+        ids.remap_value(&mut payload);
+        let task_type: TaskType = serde_json::from_value(payload)
+            .map_err(|why| ImportError::InvalidTask(why.to_string()))?;
+
+        let mut new_task = NewTask::new(task_type).priority(exported_task.priority.into());
+        if let Some(run_at) = exported_task.run_at {
+            new_task = new_task.run_at(run_at.with_timezone(&Utc));
+        }
+        if let Some(recurrence) = &exported_task.recurrence {
+            new_task.recurrence = Some(
+                serde_json::from_value::<TaskRecurrence>(recurrence.clone())
+                    .map_err(|why| ImportError::InvalidTask(why.to_string()))?,
+            );
+        }
+
+        db.add_task(new_task).await;
+        report.tasks += 1;
+    }
+
+    Ok(report)
+}
+
+/// The Discord ids of an export, and what they are in the guild being
+/// imported into
+#[derive(Debug, Default)]
+struct IdMap(HashMap<u64, DiscordId>);
+
+impl IdMap {
+    fn insert(&mut self, old_id: i64, new_id: DiscordId) {
+        self.0.insert(old_id as u64, new_id);
+    }
+
+    fn get(&self, old_id: i64) -> Option<DiscordId> {
+        self.0.get(&(old_id as u64)).copied()
+    }
+
+    /// Swap every Discord id in a payload. Discord ids are far larger than
+    /// any database id, so nothing else gets swapped by mistake.
+    fn remap_value(&self, value: &mut Value) {
+        match value {
+            Value::Number(number) => {
+                if let Some(new_id) = number.as_u64().and_then(|id| self.0.get(&id)) {
+                    *value = Value::from(**new_id);
+                }
+            }
+            Value::Array(values) => values.iter_mut().for_each(|value| self.remap_value(value)),
+            Value::Object(values) => values
+                .values_mut()
+                .for_each(|value| self.remap_value(value)),
+            _ => {}
+        }
+    }
+}
+
+/// Find what an exported resource is in the guild, so nothing is matched
+/// twice. The resource itself is used if it's still there, e.g. when a game
+/// is imported back into the guild it came from.
+fn take_match(unmatched: &mut Vec<Resource>, resource: &Resource) -> Option<DiscordId> {
+    let index = unmatched
+        .iter()
+        .position(|guild_resource| guild_resource.id == resource.id)
+        .or_else(|| {
+            unmatched.iter().position(|guild_resource| {
+                guild_resource.kind == resource.kind && guild_resource.name == resource.name
+            })
+        })?;
+
+    Some(unmatched.remove(index).id)
+}
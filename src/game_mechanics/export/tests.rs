@@ -0,0 +1,281 @@
+use chrono::{Duration, Utc};
+use entity::entities::{task, team, trade, trade_item};
+use sea_orm::{ColumnTrait, EntityTrait, QueryFilter};
+
+use crate::{
+    db_wrapper::{
+        ledger::team_wallet,
+        payload::{read_payload, PayloadKind},
+    },
+    discord::DiscordBackend,
+    game_mechanics::{
+        export::{export_game, import_game, ImportError},
+        trade::{set_trade_item, start_trade},
+    },
+    task_runner::{
+        schedule::NewTask,
+        tasks::{
+            message::SendChannelMessage, test_helpers::TestHelpers, DatabaseId, DiscordId,
+            TaskType, TypedTask,
+        },
+    },
+};
+
+#[tokio::test]
+async fn should_import_into_another_guild() {
+    let test_helper = TestHelpers::new().await;
+    let team_name = TestHelpers::generate_name();
+
+    let exported_team = test_helper.create_team(&team_name).await;
+    let general_channel_id = DiscordId::from(exported_team.fk_general_channel_id.unwrap());
+
+    // A message waiting to go out to the team
+    test_helper
+        .db
+        .add_task(
+            NewTask::new(
+                SendChannelMessage {
+                    channel_id: general_channel_id,
+                    message: "Hello".to_string(),
+                    ..Default::default()
+                }
+                .into_task(test_helper.guild_id),
+            )
+            .run_at(Utc::now() + Duration::days(1)),
+        )
+        .await;
+
+    let export = export_game(&test_helper.db, test_helper.guild_id)
+        .await
+        .unwrap();
+    assert_eq!(export.teams.len(), 1);
+    assert_eq!(export.tasks.len(), 1);
+
+    let other_guild_id = test_helper.discord.add_guild();
+    let report = import_game(
+        &test_helper.db,
+        &*test_helper.discord,
+        other_guild_id,
+        &export,
+    )
+    .await
+    .unwrap();
+    assert_eq!(report.teams, 1);
+    assert_eq!(report.tasks, 1);
+    assert!(report.matched.is_empty());
+    assert_eq!(
+        report.created.len(),
+        export.channels.len() + export.categories.len() + export.roles.len()
+    );
+
+    // The team points at what was created in the other guild
+    let imported_team = team::Entity::find()
+        .filter(team::Column::FkGuildId.eq(*other_guild_id as i64))
+        .one(&*test_helper.db)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(imported_team.name, team_name);
+
+    let other_guild = test_helper.discord.guild(other_guild_id).await.unwrap();
+    let imported_role_id = DiscordId::from(imported_team.fk_team_role_id.unwrap());
+    assert!(other_guild
+        .roles
+        .iter()
+        .any(|role| role.id == imported_role_id));
+    let imported_channel_id = DiscordId::from(imported_team.fk_general_channel_id.unwrap());
+    assert_ne!(imported_channel_id, general_channel_id);
+    assert!(other_guild
+        .channels
+        .iter()
+        .any(|channel| channel.id == imported_channel_id));
+
+    // So does the task
+    let imported_task = task::Entity::find()
+        .all(&*test_helper.db)
+        .await
+        .unwrap()
+        .into_iter()
+        .filter_map(|database_task| {
+            read_payload::<TaskType>(
+                PayloadKind::Task,
+                database_task.payload_version,
+                database_task.payload,
+            )
+            .ok()
+        })
+        .find(|task_type| task_type.guild_id() == Some(other_guild_id))
+        .unwrap();
+    let payload = serde_json::to_string(&imported_task).unwrap();
+    assert!(payload.contains(&format!(":{}", *imported_channel_id)));
+    assert!(!payload.contains(&format!(":{}", *general_channel_id)));
+
+    test_helper.cleanup().await;
+}
+
+#[tokio::test]
+async fn should_import_trades_between_the_imported_teams() {
+    let test_helper = TestHelpers::new().await;
+    let first_name = TestHelpers::generate_name();
+    let second_name = TestHelpers::generate_name();
+
+    let first_team = DatabaseId(test_helper.create_team(&first_name).await.id);
+    let second_team = DatabaseId(test_helper.create_team(&second_name).await.id);
+    let database_currency = test_helper.create_currency().await;
+    let wallet_id = team_wallet(&*test_helper.db, *first_team).await.unwrap();
+    test_helper
+        .db
+        .mint(wallet_id, DatabaseId(database_currency.id), 100, None)
+        .await
+        .unwrap();
+
+    let database_trade = start_trade(
+        &test_helper.db,
+        test_helper.guild_id,
+        first_team,
+        second_team,
+    )
+    .await
+    .unwrap();
+    set_trade_item(
+        &test_helper.db,
+        DatabaseId(database_trade.id),
+        first_team,
+        true,
+        &database_currency.name,
+        30,
+    )
+    .await
+    .unwrap();
+
+    let export = export_game(&test_helper.db, test_helper.guild_id)
+        .await
+        .unwrap();
+    assert_eq!(export.trades.len(), 1);
+    assert_eq!(export.trade_items.len(), 1);
+
+    let other_guild_id = test_helper.discord.add_guild();
+    let report = import_game(
+        &test_helper.db,
+        &*test_helper.discord,
+        other_guild_id,
+        &export,
+    )
+    .await
+    .unwrap();
+    assert_eq!(report.trades, 1);
+
+    // The trade is between the imported teams, not the exported ones
+    let imported_teams = team::Entity::find()
+        .filter(team::Column::FkGuildId.eq(*other_guild_id as i64))
+        .all(&*test_helper.db)
+        .await
+        .unwrap();
+    let imported_team_id = |name: &str| {
+        imported_teams
+            .iter()
+            .find(|imported_team| imported_team.name == name)
+            .unwrap()
+            .id
+    };
+    let imported_first_team = imported_team_id(&first_name);
+    let imported_second_team = imported_team_id(&second_name);
+
+    let imported_trade = trade::Entity::find()
+        .filter(trade::Column::FkGuildId.eq(*other_guild_id as i64))
+        .one(&*test_helper.db)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(imported_trade.fk_initiating_team_id, imported_first_team);
+    assert_eq!(imported_trade.fk_receiving_team_id, imported_second_team);
+    assert_eq!(imported_trade.status, database_trade.status);
+
+    let imported_items = trade_item::Entity::find()
+        .filter(trade_item::Column::FkTradeId.eq(imported_trade.id))
+        .all(&*test_helper.db)
+        .await
+        .unwrap();
+    assert_eq!(imported_items.len(), 1);
+    assert_eq!(imported_items[0].fk_from_team_id, imported_first_team);
+    assert_eq!(imported_items[0].fk_currency_id, database_currency.id);
+    assert_eq!(imported_items[0].amount, 30);
+
+    test_helper.cleanup().await;
+}
+
+#[tokio::test]
+async fn should_import_exports_without_trades() {
+    let test_helper = TestHelpers::new().await;
+
+    test_helper.create_team(&TestHelpers::generate_name()).await;
+
+    let mut export = serde_json::to_value(
+        export_game(&test_helper.db, test_helper.guild_id)
+            .await
+            .unwrap(),
+    )
+    .unwrap();
+    let fields = export.as_object_mut().unwrap();
+    fields.remove("trades");
+    fields.remove("trade_items");
+    fields.insert("version".to_string(), 1.into());
+    let export = serde_json::from_value(export).unwrap();
+
+    let other_guild_id = test_helper.discord.add_guild();
+    let report = import_game(
+        &test_helper.db,
+        &*test_helper.discord,
+        other_guild_id,
+        &export,
+    )
+    .await
+    .unwrap();
+    assert_eq!(report.teams, 1);
+    assert_eq!(report.trades, 0);
+
+    test_helper.cleanup().await;
+}
+
+#[tokio::test]
+async fn should_not_import_over_a_game() {
+    let test_helper = TestHelpers::new().await;
+
+    test_helper.create_team(&TestHelpers::generate_name()).await;
+
+    let export = export_game(&test_helper.db, test_helper.guild_id)
+        .await
+        .unwrap();
+    let result = import_game(
+        &test_helper.db,
+        &*test_helper.discord,
+        test_helper.guild_id,
+        &export,
+    )
+    .await;
+    assert!(matches!(result, Err(ImportError::NotEmpty)));
+
+    test_helper.cleanup().await;
+}
+
+#[tokio::test]
+async fn should_reject_other_versions() {
+    let test_helper = TestHelpers::new().await;
+
+    let mut export = export_game(&test_helper.db, test_helper.guild_id)
+        .await
+        .unwrap();
+    export.version += 1;
+
+    let other_guild_id = test_helper.discord.add_guild();
+    let result = import_game(
+        &test_helper.db,
+        &*test_helper.discord,
+        other_guild_id,
+        &export,
+    )
+    .await;
+    assert!(matches!(result, Err(ImportError::UnsupportedVersion(_))));
+
+    test_helper.cleanup().await;
+}
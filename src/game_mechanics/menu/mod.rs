@@ -3,24 +3,25 @@ use entity::entities::{player, role, team};
 use sea_orm::{ColumnTrait, EntityTrait, QueryFilter};
 use serde::{Deserialize, Serialize};
 use serenity::{all::*, utils::MessageBuilder};
+use tracing::log;
 
 use crate::{
-    db_wrapper::{
-        helpers::{get_guild, get_or_create_player, get_player_team},
-        TaskResult, TaskReturnData,
-    },
+    db_wrapper::helpers::{get_guild, get_or_create_player, get_player_team},
     task_runner::tasks::{
-        channel::{ChannelCreateData, ChannelHandler, ChannelTasks},
+        channel::ChannelCreateData,
         message::{
             message_component::{MessageComponent, MessageData},
-            MessageHandler, MessageTasks, SendChannelMessage,
+            SendChannelMessage,
         },
-        role::{AddRoleToUser, RemoveRoleFromUser, RoleHandler, RoleTasks},
-        DatabaseId, DiscordId, TaskType,
+        role::{AddRoleToUser, RemoveRoleFromUser},
+        DatabaseId, DiscordId,
     },
 };
 
-use super::{MechanicFunction, MechanicHandler, MechanicHandlerWrapper};
+use super::{
+    trade::{TradeJobs, TradeMechanicsHandler},
+    MechanicFunction, MechanicHandler, MechanicHandlerWrapper,
+};
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct MenuMechanicsHandler {
@@ -57,7 +58,10 @@ impl MechanicHandler for MenuMechanicsHandler {
                 self.start_trade_menu(handler, *channel_id).await
             }
             MenuJobs::OpenComms { channel_id } => self.open_comms(handler, *channel_id).await,
-            MenuJobs::JoinTeam { channel_id, joining_team_id} => self.join_team(handler, *channel_id, *joining_team_id).await,
+            MenuJobs::JoinTeam {
+                channel_id,
+                joining_team_id,
+            } => self.join_team(handler, *channel_id, *joining_team_id).await,
             MenuJobs::RoleChangeMenu { team_names } => {
                 self.team_change_menu(handler, team_names.clone()).await
             }
@@ -67,29 +71,68 @@ impl MechanicHandler for MenuMechanicsHandler {
 
 impl MenuMechanicsHandler {
     async fn start_trade_menu(&self, handler: MechanicHandlerWrapper, channel_id: DiscordId) {
-        // Send a message to the channel
-        let _message_create_status = handler
-            .db
-            .add_await_task(TaskType::MessageHandler(MessageHandler {
-                guild_id: self.guild_id,
-                task: MessageTasks::SendChannelMessage(SendChannelMessage {
-                    channel_id,
-                    message: MessageBuilder::new().push("Trade started!").build(),
-                    select_menu: None,
-                    buttons: Vec::new(),
-                }),
-            }))
-            .await;
-
         // Get the team of the interacting player
-        let _database_team = get_player_team(
-            handler.ctx.clone(),
+        let database_team = match get_player_team(
+            &*handler.discord,
             handler.db.clone(),
             self.guild_id,
-            handler.interaction.unwrap().member.unwrap().user.id.into(),
+            handler.interaction.unwrap().user.id.into(),
         )
         .await
-        .unwrap();
+        {
+            Ok(database_team) => database_team,
+            Err(why) => {
+                log::warn!("Couldn't find the team starting a trade: {:?}", why);
+                return;
+            }
+        };
+
+        // Every other team in the game can be traded with
+        let other_teams = team::Entity::find()
+            .filter(team::Column::FkGuildId.eq(*self.guild_id as i64))
+            .filter(team::Column::Id.ne(database_team.id))
+            .all(&*handler.db)
+            .await
+            .unwrap();
+
+        let message = if other_teams.is_empty() {
+            "There are no other teams to trade with"
+        } else {
+            "Who do you want to trade with?"
+        };
+
+        // Discord fits 25 buttons on a message
+        let buttons = other_teams
+            .into_iter()
+            .take(25)
+            .map(|other_team| {
+                MessageComponent::new(
+                    CreateButton::new("")
+                        .style(ButtonStyle::Primary)
+                        .label(format!("Trade with {}", other_team.name)),
+                    Some(MessageData::Function(MechanicFunction::Trade(
+                        TradeMechanicsHandler {
+                            guild_id: self.guild_id,
+                            task: TradeJobs::Start {
+                                receiving_team_id: DatabaseId(other_team.id),
+                            },
+                        },
+                    ))),
+                )
+            })
+            .collect();
+
+        let _message_create_status = handler
+            .run(
+                self.guild_id,
+                SendChannelMessage {
+                    channel_id,
+                    message: message.to_string(),
+                    select_menu: None,
+                    buttons,
+                },
+            )
+            .await;
     }
 
     async fn open_comms(&self, handler: MechanicHandlerWrapper, channel_id: DiscordId) {
@@ -107,22 +150,28 @@ impl MenuMechanicsHandler {
 
         // Send a message to the channel
         let _message_create_status = handler
-            .db
-            .add_await_task(TaskType::MessageHandler(MessageHandler {
-                guild_id: self.guild_id,
-                task: MessageTasks::SendChannelMessage(SendChannelMessage {
+            .run(
+                self.guild_id,
+                SendChannelMessage {
                     channel_id,
                     message: MessageBuilder::new().push("Comms opened!").build(),
                     select_menu: None,
                     buttons: Vec::new(),
-                }),
-            }))
+                },
+            )
             .await;
     }
 
-    async fn join_team(&self, handler: MechanicHandlerWrapper, channel_id: DiscordId, joining_team_id: DatabaseId) {
+    async fn join_team(
+        &self,
+        handler: MechanicHandlerWrapper,
+        channel_id: DiscordId,
+        joining_team_id: DatabaseId,
+    ) {
         // Get the guild from the database
-        get_guild(handler.ctx.clone(), handler.db.clone(), self.guild_id).await;
+        get_guild(&*handler.discord, handler.db.clone(), self.guild_id)
+            .await
+            .unwrap();
 
         // Get the interacting user
         let user = handler.interaction.unwrap().member.unwrap().user;
@@ -130,7 +179,7 @@ impl MenuMechanicsHandler {
 
         // Get the player from the database
         let database_player = get_or_create_player(
-            handler.ctx.clone(),
+            &*handler.discord,
             handler.db.clone(),
             self.guild_id,
             user_id,
@@ -157,20 +206,19 @@ impl MenuMechanicsHandler {
 
             // Remove the role from the player
             let _role_remove_status = handler
-                .db
-                .add_await_task(TaskType::RoleHandler(RoleHandler {
-                    guild_id: self.guild_id,
-                    task: RoleTasks::RemoveRoleFromUser(RemoveRoleFromUser {
+                .run(
+                    self.guild_id,
+                    RemoveRoleFromUser {
                         user_id,
                         role_id: DiscordId::from(team_role.discord_id),
-                    }),
-                }))
+                    },
+                )
                 .await;
         }
 
         // Get the team from the database
         let team_database = team::Entity::find()
-        .filter(team::Column::Id.eq(*joining_team_id as i64))
+            .filter(team::Column::Id.eq(*joining_team_id as i64))
             .one(&*handler.db)
             .await
             .unwrap()
@@ -185,29 +233,27 @@ impl MenuMechanicsHandler {
 
         // Add the role to the player
         let _role_add_status = handler
-            .db
-            .add_await_task(TaskType::RoleHandler(RoleHandler {
-                guild_id: self.guild_id,
-                task: RoleTasks::AddRoleToUser(AddRoleToUser {
+            .run(
+                self.guild_id,
+                AddRoleToUser {
                     user_id,
                     role_id: DiscordId::from(team_role_database.discord_id),
-                }),
-            }))
+                },
+            )
             .await;
 
         // Send a message to the channel
         // @<role> you have a new member, <player>!
         let _message_create_status = handler
-            .db
-            .add_await_task(TaskType::MessageHandler(MessageHandler {
-                guild_id: self.guild_id,
-                task: MessageTasks::SendChannelMessage(SendChannelMessage {
+            .run(
+                self.guild_id,
+                SendChannelMessage {
                     channel_id,
                     message: MessageBuilder::new().push("Comms opened!").build(),
                     select_menu: None,
                     buttons: Vec::new(),
-                }),
-            }))
+                },
+            )
             .await;
     }
 
@@ -218,6 +264,7 @@ impl MenuMechanicsHandler {
         for team_name in team_names {
             let team = team::Entity::find()
                 .filter(team::Column::Name.eq(team_name))
+                .filter(team::Column::FkGuildId.eq(*self.guild_id as i64))
                 .one(&*handler.db)
                 .await
                 .unwrap()
@@ -227,30 +274,24 @@ impl MenuMechanicsHandler {
         }
 
         // Make a team change channel
-        let team_change_channel_status = handler
-            .db
-            .add_await_task(TaskType::ChannelHandler(ChannelHandler {
-                task: ChannelTasks::Create(ChannelCreateData {
+        let team_change_channel_model = handler
+            .run(
+                self.guild_id,
+                ChannelCreateData {
                     name: "team-change".to_string(),
                     category_id: None,
                     kind: ChannelType::Text,
-                }),
-                guild_id: self.guild_id,
-            }))
-            .await;
-
-        let team_change_channel_model = match team_change_channel_status {
-            TaskResult::Completed(TaskReturnData::ChannelModel(channel_model)) => channel_model,
-            _ => panic!("Failed to create team change channel"),
-        };
+                },
+            )
+            .await
+            .expect("Failed to create team change channel");
 
         // Create a message in the channel with buttons for each team
         // Add a team menu to the team channel
         let _message_create_status = handler
-            .db
-            .add_await_task(TaskType::MessageHandler(MessageHandler {
-                guild_id: DiscordId::from(self.guild_id),
-                task: MessageTasks::SendChannelMessage(SendChannelMessage {
+            .run(
+                DiscordId::from(self.guild_id),
+                SendChannelMessage {
                     channel_id: DiscordId::from(team_change_channel_model.discord_id),
                     message: MessageBuilder::new()
                         .push("Choose the team you'd like to join")
@@ -279,8 +320,8 @@ impl MenuMechanicsHandler {
                             )
                         })
                         .collect(),
-                }),
-            }))
+                },
+            )
             .await;
     }
 }
@@ -1,13 +1,34 @@
+use std::sync::Arc;
+
 use async_trait::async_trait;
+use entity::entities::message_component_data;
+use sea_orm::EntityTrait;
 use serde::{Deserialize, Serialize};
-use serenity::{all::ComponentInteraction, prelude::Context};
+use serenity::all::ComponentInteraction;
+use tracing::log;
 
-use crate::db_wrapper::DBWrapper;
+use crate::{
+    db_wrapper::DBWrapper,
+    discord::DiscordBackend,
+    task_runner::{
+        error::TaskError,
+        schedule::TaskPriority,
+        tasks::{DiscordId, TypedTask},
+    },
+};
 
-use self::{menu::MenuMechanicsHandler, team::TeamMechanicsHandler};
+use self::{
+    menu::MenuMechanicsHandler, reset::ResetMechanicsHandler, scenario::ScenarioMechanicsHandler,
+    team::TeamMechanicsHandler, trade::TradeMechanicsHandler,
+};
 
+pub mod export;
 pub mod menu;
+pub mod reset;
+pub mod saga;
+pub mod scenario;
 pub mod team;
+pub mod trade;
 
 #[async_trait]
 pub trait MechanicHandler: Send + Sync {
@@ -18,12 +39,37 @@ pub trait MechanicHandler: Send + Sync {
 pub enum MechanicFunction {
     Team(TeamMechanicsHandler),
     Menu(MenuMechanicsHandler),
+    Reset(ResetMechanicsHandler),
+    Scenario(ScenarioMechanicsHandler),
+    Trade(TradeMechanicsHandler),
 }
 
 pub struct MechanicHandlerWrapper {
     pub db: DBWrapper,
     pub interaction: Option<ComponentInteraction>,
-    pub ctx: Context,
+    pub discord: Arc<dyn DiscordBackend>,
+}
+
+impl MechanicHandlerWrapper {
+    /// How urgent the tasks this mechanic adds are. A player pressing a
+    /// button is waiting on them, so they go ahead of the game's own work.
+    pub fn priority(&self) -> TaskPriority {
+        match self.interaction {
+            Some(_) => TaskPriority::Interactive,
+            None => TaskPriority::Automation,
+        }
+    }
+
+    /// Add a task at this mechanic's priority and wait for its output
+    pub async fn run<T: TypedTask>(
+        &self,
+        guild_id: DiscordId,
+        task: T,
+    ) -> Result<T::Output, TaskError> {
+        self.db
+            .run_with_priority(guild_id, task, self.priority())
+            .await
+    }
 }
 
 impl MechanicFunction {
@@ -35,6 +81,49 @@ impl MechanicFunction {
             MechanicFunction::Menu(menu_mechanics_handler) => {
                 menu_mechanics_handler.handle(handler).await
             }
+            MechanicFunction::Reset(reset_mechanics_handler) => {
+                reset_mechanics_handler.handle(handler).await
+            }
+            MechanicFunction::Scenario(scenario_mechanics_handler) => {
+                scenario_mechanics_handler.handle(handler).await
+            }
+            MechanicFunction::Trade(trade_mechanics_handler) => {
+                trade_mechanics_handler.handle(handler).await
+            }
+        }
+    }
+}
+
+/// Check that a button confirming something only admins can do was pressed
+/// by an admin, and use it up so it only works once, even if it's pressed
+/// again before the first press is handled. Returns the interaction if the
+/// button should go ahead.
+pub async fn use_admin_button(handler: &MechanicHandlerWrapper) -> Option<&ComponentInteraction> {
+    let interaction = handler.interaction.as_ref()?;
+
+    let is_admin = interaction
+        .member
+        .as_ref()
+        .and_then(|member| member.permissions)
+        .map_or(false, |permissions| permissions.administrator());
+    if !is_admin {
+        log::warn!(
+            "{} pressed a button for admins without being one",
+            interaction.user.id
+        );
+        return None;
+    }
+
+    let component_id = uuid::Uuid::parse_str(&interaction.data.custom_id).ok()?;
+    match message_component_data::Entity::delete_by_id(component_id)
+        .exec(&*handler.db)
+        .await
+    {
+        Ok(deleted) if deleted.rows_affected > 0 => Some(interaction),
+        Ok(_) => None,
+        Err(why) => {
+            log::error!("Couldn't use up a button: {}", why);
+            None
         }
     }
 }
@@ -0,0 +1,287 @@
+use std::fmt;
+
+use async_trait::async_trait;
+use chrono::Utc;
+use entity::entities::{category, channel, player, reset_snapshot, role, team};
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, DbErr, EntityTrait, QueryFilter, Set, TransactionTrait,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    db_wrapper::{helpers::TrackedKind, ledger::archive_wallets, DBWrapper},
+    task_runner::{
+        schedule::{NewTask, TaskPriority},
+        tasks::{
+            category::DeleteCategory, channel::DeleteChannel, message::SendChannelMessage,
+            reconcile::Resource, role::DeleteRole, DatabaseId, DiscordId, TypedTask,
+        },
+    },
+};
+
+use super::{export::export_game, use_admin_button, MechanicHandler, MechanicHandlerWrapper};
+
+#[cfg(test)]
+mod tests;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ResetMechanicsHandler {
+    pub guild_id: DiscordId,
+    pub task: ResetJobs,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum ResetJobs {
+    /// Someone pressed the button to go ahead with a reset. Only what was
+    /// listed to them is deleted.
+    Confirm { plan: ResetPlan, snapshot: bool },
+}
+
+/// Everything a reset deletes. This is only ever what the bot has in its
+/// tables, so anything made by hand in the guild is left alone.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct ResetPlan {
+    pub channels: Vec<Resource>,
+    pub categories: Vec<Resource>,
+    pub roles: Vec<Resource>,
+    pub teams: Vec<(DatabaseId, String)>,
+    pub players: Vec<DatabaseId>,
+}
+
+impl ResetPlan {
+    pub fn is_empty(&self) -> bool {
+        self.channels.is_empty()
+            && self.categories.is_empty()
+            && self.roles.is_empty()
+            && self.teams.is_empty()
+            && self.players.is_empty()
+    }
+}
+
+impl fmt::Display for ResetPlan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} channels, {} categories, {} roles, {} teams and {} players",
+            self.channels.len(),
+            self.categories.len(),
+            self.roles.len(),
+            self.teams.len(),
+            self.players.len()
+        )?;
+
+        for resource in self
+            .categories
+            .iter()
+            .chain(&self.channels)
+            .chain(&self.roles)
+        {
+            writeln!(f, "{:?} {}", resource.kind, resource.name)?;
+        }
+        for (_, name) in &self.teams {
+            writeln!(f, "Team {}", name)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// How a reset went
+#[derive(Debug, Clone, Default)]
+pub struct ResetOutcome {
+    /// The row the snapshot was saved in, if one was taken
+    pub snapshot_id: Option<i32>,
+    /// The deletions that failed. Their rows are left in place, so running
+    /// the reset again retries them.
+    pub failed: Vec<(Resource, String)>,
+}
+
+#[async_trait]
+impl MechanicHandler for ResetMechanicsHandler {
+    async fn handle(&self, handler: MechanicHandlerWrapper) {
+        match &self.task {
+            ResetJobs::Confirm { plan, snapshot } => {
+                self.confirm_reset(handler, plan, *snapshot).await
+            }
+        }
+    }
+}
+
+impl ResetMechanicsHandler {
+    async fn confirm_reset(
+        &self,
+        handler: MechanicHandlerWrapper,
+        plan: &ResetPlan,
+        snapshot: bool,
+    ) {
+        let Some(interaction) = use_admin_button(&handler).await else {
+            return;
+        };
+
+        let message = match reset(&handler.db, self.guild_id, plan, snapshot).await {
+            Ok(outcome) => {
+                let mut message = format!("Reset the game, deleting {}", plan);
+                if let Some(snapshot_id) = outcome.snapshot_id {
+                    message.push_str(&format!("Saved a snapshot as #{}\n", snapshot_id));
+                }
+                for (resource, why) in &outcome.failed {
+                    message.push_str(&format!("Couldn't delete {}: {}\n", resource.name, why));
+                }
+                message
+            }
+            Err(why) => format!("Couldn't reset the game: {}", why),
+        };
+
+        // The reset leaves the channel it was asked for in alone, unless the
+        // bot made it
+        let _message_create_status = handler
+            .run(
+                self.guild_id,
+                SendChannelMessage {
+                    channel_id: DiscordId::from(interaction.channel_id),
+                    message,
+                    ..Default::default()
+                },
+            )
+            .await;
+    }
+}
+
+/// Work out what a reset of the guild would delete
+pub async fn plan_reset(db: &DBWrapper, guild_id: DiscordId) -> Result<ResetPlan, DbErr> {
+    let guild_id_value = *guild_id as i64;
+    let mut plan = ResetPlan::default();
+
+    for database_channel in channel::Entity::find()
+        .filter(channel::Column::FkGuildId.eq(guild_id_value))
+        .all(&**db)
+        .await?
+    {
+        plan.channels.push(Resource {
+            kind: TrackedKind::Channel,
+            id: DiscordId::from(database_channel.discord_id),
+            name: database_channel.name,
+        });
+    }
+
+    for database_category in category::Entity::find()
+        .filter(category::Column::FkGuildId.eq(guild_id_value))
+        .all(&**db)
+        .await?
+    {
+        plan.categories.push(Resource {
+            kind: TrackedKind::Category,
+            id: DiscordId::from(database_category.discord_id),
+            name: database_category.name,
+        });
+    }
+
+    for database_role in role::Entity::find()
+        .filter(role::Column::FkGuildId.eq(guild_id_value))
+        .all(&**db)
+        .await?
+    {
+        plan.roles.push(Resource {
+            kind: TrackedKind::Role,
+            id: DiscordId::from(database_role.discord_id),
+            name: database_role.name,
+        });
+    }
+
+    plan.teams = team::Entity::find()
+        .filter(team::Column::FkGuildId.eq(guild_id_value))
+        .all(&**db)
+        .await?
+        .into_iter()
+        .map(|database_team| (DatabaseId(database_team.id), database_team.name))
+        .collect();
+
+    plan.players = player::Entity::find()
+        .filter(player::Column::FkGuildId.eq(guild_id_value))
+        .all(&**db)
+        .await?
+        .into_iter()
+        .map(|database_player| DatabaseId(database_player.id))
+        .collect();
+
+    Ok(plan)
+}
+
+/// Save an export of the guild's game, returning the snapshot's id. Importing
+/// the snapshot's payload undoes the reset.
+pub async fn take_snapshot(db: &DBWrapper, guild_id: DiscordId) -> Result<i32, DbErr> {
+    let export = export_game(db, guild_id).await?;
+
+    let database_snapshot = reset_snapshot::ActiveModel {
+        fk_guild_id: Set(*guild_id as i64),
+        created_at: Set(Utc::now().into()),
+        payload: Set(serde_json::to_value(&export).unwrap()),
+        ..Default::default()
+    }
+    .insert(&**db)
+    .await?;
+
+    Ok(database_snapshot.id)
+}
+
+/// Delete everything in the plan. Players and teams go first, then channels,
+/// then the categories they were in and finally roles. The delete tasks
+/// clean up the rows of what they delete. Wallets are kept without an owner,
+/// so the ledger stays whole, and what was in them is burned.
+pub async fn reset(
+    db: &DBWrapper,
+    guild_id: DiscordId,
+    plan: &ResetPlan,
+    snapshot: bool,
+) -> Result<ResetOutcome, DbErr> {
+    let mut outcome = ResetOutcome::default();
+
+    if snapshot {
+        outcome.snapshot_id = Some(take_snapshot(db, guild_id).await?);
+    }
+
+    let team_ids: Vec<i32> = plan.teams.iter().map(|(id, _)| **id).collect();
+    let player_ids: Vec<i32> = plan.players.iter().map(|id| **id).collect();
+
+    let txn = db.begin().await?;
+    archive_wallets(&txn, &team_ids, &player_ids).await?;
+    player::Entity::delete_many()
+        .filter(player::Column::Id.is_in(player_ids))
+        .exec(&txn)
+        .await?;
+    team::Entity::delete_many()
+        .filter(team::Column::Id.is_in(team_ids))
+        .exec(&txn)
+        .await?;
+    txn.commit().await?;
+
+    let stages = [&plan.channels, &plan.categories, &plan.roles];
+    for resources in stages {
+        let tasks = resources
+            .iter()
+            .map(|resource| {
+                let task = match resource.kind {
+                    TrackedKind::Channel => DeleteChannel { id: resource.id }.into_task(guild_id),
+                    TrackedKind::Category => DeleteCategory {
+                        discord_id: resource.id,
+                    }
+                    .into_task(guild_id),
+                    TrackedKind::Role => DeleteRole {
+                        role_id: resource.id,
+                    }
+                    .into_task(guild_id),
+                };
+
+                NewTask::new(task).priority(TaskPriority::Bulk)
+            })
+            .collect();
+
+        for (resource, result) in resources.iter().zip(db.add_await_tasks(tasks).await) {
+            if let Some(why) = result.error() {
+                outcome.failed.push((resource.clone(), why.to_string()));
+            }
+        }
+    }
+
+    Ok(outcome)
+}
@@ -0,0 +1,250 @@
+use entity::entities::{reset_snapshot, team, wallet, wallet_balance};
+use sea_orm::{ColumnTrait, EntityTrait, QueryFilter};
+use serenity::model::prelude::ChannelType;
+
+use crate::{
+    db_wrapper::ledger::team_wallet,
+    discord::DiscordBackend,
+    game_mechanics::{
+        export::{import_game, GameExport},
+        reset::{plan_reset, reset},
+    },
+    task_runner::tasks::{
+        channel::ChannelCreateData,
+        test_helpers::{
+            DatabaseConstruct, DatabaseStatus, DiscordConstruct, DiscordStatus, TestHelpers,
+        },
+        DatabaseId,
+    },
+};
+
+#[tokio::test]
+async fn should_only_plan_tracked_resources() {
+    let test_helper = TestHelpers::new().await;
+    let tracked_name = TestHelpers::generate_name();
+    let untracked_name = TestHelpers::generate_name();
+
+    test_helper
+        .db
+        .run(
+            test_helper.guild_id,
+            ChannelCreateData {
+                name: tracked_name.clone(),
+                category_id: None,
+                kind: ChannelType::Text,
+            },
+        )
+        .await
+        .unwrap();
+    test_helper
+        .discord
+        .create_channel(
+            test_helper.guild_id,
+            &untracked_name,
+            ChannelType::Text,
+            None,
+        )
+        .await
+        .unwrap();
+
+    let plan = plan_reset(&test_helper.db, test_helper.guild_id)
+        .await
+        .unwrap();
+
+    assert_eq!(plan.channels.len(), 1);
+    assert_eq!(plan.channels[0].name, tracked_name);
+
+    // Planning doesn't delete anything
+    assert_eq!(
+        test_helper
+            .check_discord_status(DiscordConstruct::Channel { name: tracked_name })
+            .await,
+        DiscordStatus::Exists
+    );
+
+    test_helper.cleanup().await;
+}
+
+#[tokio::test]
+async fn should_reset_only_what_the_bot_made() {
+    let test_helper = TestHelpers::new().await;
+    let team_name = TestHelpers::generate_name();
+    let untracked_name = TestHelpers::generate_name();
+
+    test_helper.create_team(&team_name).await;
+    test_helper
+        .discord
+        .create_channel(
+            test_helper.guild_id,
+            &untracked_name,
+            ChannelType::Text,
+            None,
+        )
+        .await
+        .unwrap();
+
+    let plan = plan_reset(&test_helper.db, test_helper.guild_id)
+        .await
+        .unwrap();
+    let outcome = reset(&test_helper.db, test_helper.guild_id, &plan, true)
+        .await
+        .unwrap();
+
+    assert!(outcome.failed.is_empty(), "{:?}", outcome.failed);
+
+    // The team is gone from both
+    for construct in [
+        DiscordConstruct::Channel {
+            name: team_name.clone(),
+        },
+        DiscordConstruct::Category {
+            name: team_name.clone(),
+        },
+        DiscordConstruct::Role {
+            name: team_name.clone(),
+        },
+    ] {
+        assert_eq!(
+            test_helper.check_discord_status(construct).await,
+            DiscordStatus::DoesNotExist
+        );
+    }
+    assert_eq!(
+        test_helper
+            .check_database_status(DatabaseConstruct::Team { name: team_name })
+            .await,
+        DatabaseStatus::DoesNotExist
+    );
+    assert!(plan_reset(&test_helper.db, test_helper.guild_id)
+        .await
+        .unwrap()
+        .is_empty());
+
+    // What was made by hand is left alone
+    assert_eq!(
+        test_helper
+            .check_discord_status(DiscordConstruct::Channel {
+                name: untracked_name
+            })
+            .await,
+        DiscordStatus::Exists
+    );
+
+    // The snapshot has the team from before
+    let snapshot = reset_snapshot::Entity::find_by_id(outcome.snapshot_id.unwrap())
+        .one(&*test_helper.db)
+        .await
+        .unwrap()
+        .unwrap();
+    let snapshot: GameExport = serde_json::from_value(snapshot.payload).unwrap();
+    assert_eq!(snapshot.teams.len(), 1);
+
+    test_helper.cleanup().await;
+}
+
+#[tokio::test]
+async fn should_skip_snapshot_when_not_asked() {
+    let test_helper = TestHelpers::new().await;
+    test_helper.create_team(&TestHelpers::generate_name()).await;
+
+    let plan = plan_reset(&test_helper.db, test_helper.guild_id)
+        .await
+        .unwrap();
+    let outcome = reset(&test_helper.db, test_helper.guild_id, &plan, false)
+        .await
+        .unwrap();
+
+    assert_eq!(outcome.snapshot_id, None);
+    assert!(team::Entity::find()
+        .filter(team::Column::FkGuildId.eq(*test_helper.guild_id as i64))
+        .one(&*test_helper.db)
+        .await
+        .unwrap()
+        .is_none());
+
+    test_helper.cleanup().await;
+}
+
+#[tokio::test]
+async fn should_keep_the_ledger_of_deleted_teams() {
+    let test_helper = TestHelpers::new().await;
+    let db = &test_helper.db;
+
+    let database_team = test_helper.create_team(&TestHelpers::generate_name()).await;
+    let team_wallet_id = team_wallet(&**db, database_team.id).await.unwrap();
+    let other_wallet_id = test_helper.create_wallet().await;
+    let currency_id = DatabaseId(test_helper.create_currency().await.id);
+
+    db.mint(team_wallet_id, currency_id, 100, None)
+        .await
+        .unwrap();
+    db.transfer(team_wallet_id, other_wallet_id, currency_id, 40, None)
+        .await
+        .unwrap();
+
+    let plan = plan_reset(db, test_helper.guild_id).await.unwrap();
+    reset(db, test_helper.guild_id, &plan, false).await.unwrap();
+
+    // The team's wallet is kept without an owner and emptied, and both sides
+    // of the transfer still add up
+    let kept_wallet = wallet::Entity::find_by_id(*team_wallet_id)
+        .one(&**db)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(kept_wallet.fk_team_id, None);
+    assert_eq!(
+        db.balances(team_wallet_id)
+            .await
+            .unwrap()
+            .get(&*currency_id),
+        Some(&0)
+    );
+    for wallet_id in [team_wallet_id, other_wallet_id] {
+        assert_eq!(
+            db.balances(wallet_id).await.unwrap(),
+            db.transaction_balances(wallet_id).await.unwrap()
+        );
+    }
+    assert_eq!(db.wallet_history(other_wallet_id).await.unwrap().len(), 1);
+
+    test_helper.cleanup().await;
+}
+
+#[tokio::test]
+async fn should_not_double_currency_when_restoring_a_snapshot() {
+    let test_helper = TestHelpers::new().await;
+    let db = &test_helper.db;
+
+    let database_team = test_helper.create_team(&TestHelpers::generate_name()).await;
+    let team_wallet_id = team_wallet(&**db, database_team.id).await.unwrap();
+    let currency_id = DatabaseId(test_helper.create_currency().await.id);
+    db.mint(team_wallet_id, currency_id, 100, None)
+        .await
+        .unwrap();
+
+    let plan = plan_reset(db, test_helper.guild_id).await.unwrap();
+    let outcome = reset(db, test_helper.guild_id, &plan, true).await.unwrap();
+
+    let snapshot = reset_snapshot::Entity::find_by_id(outcome.snapshot_id.unwrap())
+        .one(&**db)
+        .await
+        .unwrap()
+        .unwrap();
+    let snapshot: GameExport = serde_json::from_value(snapshot.payload).unwrap();
+    import_game(db, &*test_helper.discord, test_helper.guild_id, &snapshot)
+        .await
+        .unwrap();
+
+    let supply: i64 = wallet_balance::Entity::find()
+        .filter(wallet_balance::Column::FkCurrencyId.eq(*currency_id))
+        .all(&**db)
+        .await
+        .unwrap()
+        .iter()
+        .map(|balance| balance.amount)
+        .sum();
+    assert_eq!(supply, 100);
+
+    test_helper.cleanup().await;
+}
@@ -0,0 +1,204 @@
+use entity::entities::team;
+use sea_orm::{DbErr, EntityTrait, TransactionTrait};
+use tracing::log;
+
+use crate::{
+    db_wrapper::{ledger::archive_wallets, DBWrapper, TaskResult},
+    task_runner::{
+        error::TaskError,
+        graph::{TaskGraph, TaskGraphResults},
+        schedule::{NewTask, TaskPriority},
+        tasks::{DatabaseId, DiscordId, TaskType, TypedTask},
+    },
+};
+
+/// A mechanic made of several steps that should either all happen or not at
+/// all. Each step that changes something registers how to undo it, and if a
+/// later step fails, `rollback` undoes everything in reverse order.
+///
+/// ```ignore
+/// let mut saga = Saga::new("create team", db.clone()).priority(handler.priority());
+///
+/// let result = async {
+///     let role = saga.run(guild_id, CreateRole { .. }).await?;
+///     let category = saga.run(guild_id, CreateCategory { .. }).await?;
+///     Ok(())
+/// }
+/// .await;
+///
+/// if result.is_err() {
+///     saga.rollback().await;
+/// }
+/// ```
+pub struct Saga {
+    name: String,
+    db: DBWrapper,
+    /// The priority every task of the saga runs at, undoing included
+    priority: TaskPriority,
+    compensations: Vec<Compensation>,
+}
+
+/// Something that undoes a step of a saga
+#[derive(Debug, Clone)]
+pub enum Compensation {
+    /// Add a task and wait for it
+    Task(TaskType),
+    /// Delete a team that was added to the database
+    DeleteTeam(DatabaseId),
+}
+
+impl Saga {
+    pub fn new(name: impl Into<String>, db: DBWrapper) -> Self {
+        Self {
+            name: name.into(),
+            db,
+            priority: TaskPriority::default(),
+            compensations: Vec::new(),
+        }
+    }
+
+    /// Run the saga's tasks at this priority instead of the default
+    pub fn priority(mut self, priority: TaskPriority) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    /// Register how to undo a step that has already happened
+    pub fn on_rollback(&mut self, compensation: Compensation) {
+        self.compensations.push(compensation);
+    }
+
+    /// Run a typed task, registering its undo if it has one
+    pub async fn run<T: TypedTask>(
+        &mut self,
+        guild_id: DiscordId,
+        task: T,
+    ) -> Result<T::Output, TaskError> {
+        let output = self
+            .db
+            .run_with_priority(guild_id, task, self.priority)
+            .await?;
+
+        if let Some(undo) = T::undo(guild_id, &output) {
+            self.on_rollback(Compensation::Task(undo));
+        }
+
+        Ok(output)
+    }
+
+    /// Run a graph of tasks, registering the undo of every typed task in it
+    /// that completed. This fails if any task in the graph didn't complete.
+    pub async fn run_graph(&mut self, graph: TaskGraph) -> Result<TaskGraphResults, TaskError> {
+        let undos = graph.undos();
+        let results = self.db.add_await_task_graph(graph, self.priority).await?;
+
+        for (node, undo) in undos {
+            if let TaskResult::Completed(data) = results.get(node) {
+                if let Some(task) = undo.task(data.clone()) {
+                    self.on_rollback(Compensation::Task(task));
+                }
+            }
+        }
+
+        match results.error() {
+            Some(error) => Err(error),
+            None => Ok(results),
+        }
+    }
+
+    /// Undo every step so far, newest first. Every compensation is tried
+    /// even if some of them fail.
+    pub async fn rollback(self) {
+        log::warn!(
+            "Rolling back {} ({} steps)",
+            self.name,
+            self.compensations.len()
+        );
+
+        for compensation in self.compensations.into_iter().rev() {
+            let result = match &compensation {
+                Compensation::Task(task) => match self
+                    .db
+                    .add_await_task(NewTask::new(task.clone()).priority(self.priority))
+                    .await
+                {
+                    TaskResult::Completed(_) => Ok(()),
+                    status => Err(status
+                        .error()
+                        .map(|error| error.to_string())
+                        .unwrap_or_default()),
+                },
+                Compensation::DeleteTeam(id) => {
+                    self.delete_team(*id).await.map_err(|why| why.to_string())
+                }
+            };
+
+            if let Err(why) = result {
+                log::error!(
+                    "Couldn't roll back {} with {:?}: {}",
+                    self.name,
+                    compensation,
+                    why
+                );
+            }
+        }
+    }
+
+    /// Delete a team, keeping its wallet without an owner if it has one
+    async fn delete_team(&self, id: DatabaseId) -> Result<(), DbErr> {
+        let txn = self.db.begin().await?;
+        archive_wallets(&txn, &[*id], &[]).await?;
+        team::Entity::delete_by_id(*id).exec(&txn).await?;
+
+        txn.commit().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use entity::entities::task;
+    use sea_orm::EntityTrait;
+    use serenity::model::prelude::ChannelType;
+
+    use super::Saga;
+    use crate::task_runner::{
+        graph::TaskGraph,
+        schedule::TaskPriority,
+        tasks::{category::CreateCategory, channel::ChannelCreateData, test_helpers::TestHelpers},
+    };
+
+    #[tokio::test]
+    async fn should_run_graphs_at_the_saga_priority() {
+        let test_helper = TestHelpers::new().await;
+
+        let mut graph = TaskGraph::new();
+        let category = graph.add_typed(
+            test_helper.guild_id,
+            CreateCategory {
+                name: TestHelpers::generate_name(),
+            },
+        );
+        graph.add_typed(
+            test_helper.guild_id,
+            ChannelCreateData {
+                name: TestHelpers::generate_name(),
+                category_id: Some(graph.discord_id(category)),
+                kind: ChannelType::Text,
+            },
+        );
+
+        Saga::new("test", test_helper.db.clone())
+            .priority(TaskPriority::Interactive)
+            .run_graph(graph)
+            .await
+            .unwrap();
+
+        let tasks = task::Entity::find().all(&*test_helper.db).await.unwrap();
+        assert_eq!(tasks.len(), 2);
+        assert!(tasks
+            .iter()
+            .all(|task| TaskPriority::from(task.priority) == TaskPriority::Interactive));
+
+        test_helper.cleanup().await;
+    }
+}
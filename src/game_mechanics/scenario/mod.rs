@@ -0,0 +1,733 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt, fs,
+    path::{Path, PathBuf},
+};
+
+use async_trait::async_trait;
+use entity::entities::{currency, team};
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, DbErr, EntityTrait, QueryFilter, Set, TransactionTrait,
+};
+use serde::{Deserialize, Serialize};
+use serenity::{model::prelude::ChannelType, prelude::TypeMapKey};
+
+use crate::{
+    db_wrapper::{
+        ledger::{self, team_wallet, LedgerEntry, LedgerError},
+        DBWrapper,
+    },
+    task_runner::{
+        error::TaskError,
+        graph::TaskGraph,
+        tasks::{
+            category::CreateCategory, channel::ChannelCreateData, message::SendChannelMessage,
+            DatabaseId, DiscordId,
+        },
+    },
+};
+
+use super::{
+    menu::{MenuJobs, MenuMechanicsHandler},
+    saga::Saga,
+    team::{ChannelPurpose, TeamChannel, TeamJobs, TeamMechanicsHandler, TeamSetup},
+    use_admin_button, MechanicHandler, MechanicHandlerWrapper,
+};
+
+#[cfg(test)]
+mod tests;
+
+/// The scenario `/initialize` uses if it isn't given one
+pub const DEFAULT_SCENARIO: &str = include_str!("../../../scenarios/default.toml");
+
+/// Where stored scenarios are looked for if the config doesn't say
+pub const DEFAULT_SCENARIO_DIR: &str = "scenarios";
+
+/// Discord won't make a channel or role with a longer name than this
+const MAX_NAME_LENGTH: usize = 100;
+
+/// The directory stored scenarios are read from, kept in the client's data
+/// so commands can get to it
+pub struct ScenarioDirectory;
+
+impl TypeMapKey for ScenarioDirectory {
+    type Value = PathBuf;
+}
+
+/// A description of a game to set up in a guild, read from a TOML or JSON
+/// file
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct Scenario {
+    pub name: String,
+    pub teams: Vec<ScenarioTeam>,
+    /// The channels every team gets in its category. `{team}` in a name is
+    /// replaced with the team's name, and `{abbreviation}` with its
+    /// abbreviation.
+    #[serde(default = "default_team_channels")]
+    pub team_channels: Vec<TeamChannel>,
+    /// Channels everyone shares
+    #[serde(default)]
+    pub shared_channels: Vec<SharedChannel>,
+    /// Currencies that are added if there isn't one with the same name yet
+    #[serde(default)]
+    pub currencies: Vec<ScenarioCurrency>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct ScenarioTeam {
+    pub name: String,
+    pub abbreviation: Option<String>,
+    pub emoji: Option<String>,
+    /// The color of the team's role, e.g. `0xff0000`. Without it, the
+    /// guild's `team_role_color` is used.
+    pub color: Option<u32>,
+    /// How much of each currency the team's wallet starts with, by the
+    /// currency's name
+    #[serde(default)]
+    pub balances: BTreeMap<String, i64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct SharedChannel {
+    pub name: String,
+    /// The category to put the channel in, which is made if it's the first
+    /// channel to use it
+    pub category: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct ScenarioCurrency {
+    pub name: String,
+    pub description: Option<String>,
+    pub emoji: Option<String>,
+}
+
+fn default_team_channels() -> Vec<TeamChannel> {
+    vec![TeamChannel {
+        name: "{team}".to_string(),
+        purposes: vec![ChannelPurpose::General, ChannelPurpose::Menu],
+    }]
+}
+
+/// What a scenario file is written in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScenarioFormat {
+    Toml,
+    Json,
+}
+
+impl ScenarioFormat {
+    /// JSON if the file ends in `.json`, otherwise TOML
+    pub fn from_filename(filename: &str) -> Self {
+        if filename.to_lowercase().ends_with(".json") {
+            ScenarioFormat::Json
+        } else {
+            ScenarioFormat::Toml
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ScenarioError {
+    Parse(String),
+    Invalid(String),
+    /// There's no stored scenario with this name
+    NotFound(String),
+    /// These teams are already in the guild
+    TeamsExist(Vec<String>),
+    Task(TaskError),
+    Ledger(LedgerError),
+    Database(DbErr),
+}
+
+impl fmt::Display for ScenarioError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScenarioError::Parse(why) => write!(f, "The scenario can't be read: {}", why),
+            ScenarioError::Invalid(why) => write!(f, "The scenario is invalid: {}", why),
+            ScenarioError::NotFound(name) => write!(f, "There's no scenario called {}", name),
+            ScenarioError::TeamsExist(names) => {
+                write!(f, "The server already has teams {}", names.join(", "))
+            }
+            ScenarioError::Task(why) => write!(f, "{}", why),
+            ScenarioError::Ledger(why) => write!(f, "{}", why),
+            ScenarioError::Database(why) => write!(f, "{}", why),
+        }
+    }
+}
+
+impl From<DbErr> for ScenarioError {
+    fn from(why: DbErr) -> Self {
+        ScenarioError::Database(why)
+    }
+}
+
+impl From<LedgerError> for ScenarioError {
+    fn from(why: LedgerError) -> Self {
+        ScenarioError::Ledger(why)
+    }
+}
+
+impl From<TaskError> for ScenarioError {
+    fn from(why: TaskError) -> Self {
+        ScenarioError::Task(why)
+    }
+}
+
+impl Scenario {
+    /// Read a scenario and check it
+    pub fn parse(contents: &str, format: ScenarioFormat) -> Result<Self, ScenarioError> {
+        let scenario: Self = match format {
+            ScenarioFormat::Toml => {
+                toml::from_str(contents).map_err(|why| ScenarioError::Parse(why.to_string()))?
+            }
+            ScenarioFormat::Json => serde_json::from_str(contents)
+                .map_err(|why| ScenarioError::Parse(why.to_string()))?,
+        };
+
+        scenario.validate()?;
+
+        Ok(scenario)
+    }
+
+    /// Read a stored scenario from `dir`, either `<name>.toml` or
+    /// `<name>.json`
+    pub fn load(dir: &Path, name: &str) -> Result<Self, ScenarioError> {
+        // Only files right in the directory can be used
+        if name.is_empty() || name.contains(['/', '\\']) || name.starts_with('.') {
+            return Err(ScenarioError::NotFound(name.to_string()));
+        }
+
+        for (extension, format) in [
+            ("toml", ScenarioFormat::Toml),
+            ("json", ScenarioFormat::Json),
+        ] {
+            let path = dir.join(format!("{}.{}", name, extension));
+            if let Ok(contents) = fs::read_to_string(&path) {
+                return Self::parse(&contents, format);
+            }
+        }
+
+        Err(ScenarioError::NotFound(name.to_string()))
+    }
+
+    /// The scenario that's used if none is given
+    pub fn default_scenario() -> Self {
+        Self::parse(DEFAULT_SCENARIO, ScenarioFormat::Toml)
+            .expect("The default scenario is invalid")
+    }
+
+    /// Check everything that can be checked without looking at the guild
+    pub fn validate(&self) -> Result<(), ScenarioError> {
+        let invalid = |why: String| Err(ScenarioError::Invalid(why));
+
+        if self.name.trim().is_empty() {
+            return invalid("it needs a name".to_string());
+        }
+        if self.teams.is_empty() {
+            return invalid("it needs at least one team".to_string());
+        }
+        if self.team_channels.is_empty() {
+            return invalid("teams need at least one channel".to_string());
+        }
+
+        let mut currency_names = HashSet::new();
+        for scenario_currency in &self.currencies {
+            if scenario_currency.name.trim().is_empty() {
+                return invalid("a currency has no name".to_string());
+            }
+            if !currency_names.insert(scenario_currency.name.as_str()) {
+                return invalid(format!(
+                    "there's more than one currency called {}",
+                    scenario_currency.name
+                ));
+            }
+        }
+
+        let mut team_names = HashSet::new();
+        let mut abbreviations = HashSet::new();
+        for scenario_team in &self.teams {
+            if scenario_team.name.trim().is_empty() {
+                return invalid("a team has no name".to_string());
+            }
+            if scenario_team.name.len() > MAX_NAME_LENGTH {
+                return invalid(format!("the team name {} is too long", scenario_team.name));
+            }
+            if !team_names.insert(scenario_team.name.to_lowercase()) {
+                return invalid(format!(
+                    "there's more than one team called {}",
+                    scenario_team.name
+                ));
+            }
+            if let Some(abbreviation) = &scenario_team.abbreviation {
+                if !abbreviations.insert(abbreviation.to_lowercase()) {
+                    return invalid(format!(
+                        "there's more than one team abbreviated {}",
+                        abbreviation
+                    ));
+                }
+            }
+            if scenario_team.color.map_or(false, |color| color > 0xffffff) {
+                return invalid(format!(
+                    "team {}'s color isn't an RGB color",
+                    scenario_team.name
+                ));
+            }
+
+            for (currency_name, amount) in &scenario_team.balances {
+                if !currency_names.contains(currency_name.as_str()) {
+                    return invalid(format!(
+                        "team {} starts with {}, which isn't one of the currencies",
+                        scenario_team.name, currency_name
+                    ));
+                }
+                if *amount < 0 {
+                    return invalid(format!(
+                        "team {} starts with less than no {}",
+                        scenario_team.name, currency_name
+                    ));
+                }
+            }
+
+            for team_channel in &self.team_channels {
+                if team_channel.name.contains("{abbreviation}")
+                    && scenario_team.abbreviation.is_none()
+                {
+                    return invalid(format!(
+                        "the channel {} needs an abbreviation, which team {} doesn't have",
+                        team_channel.name, scenario_team.name
+                    ));
+                }
+            }
+
+            let setup = self.team_setup(scenario_team);
+            for team_channel in &setup.channels {
+                if team_channel.name.trim().is_empty() || team_channel.name.len() > MAX_NAME_LENGTH
+                {
+                    return invalid(format!(
+                        "team {} would get a channel called {:?}",
+                        scenario_team.name, team_channel.name
+                    ));
+                }
+            }
+        }
+
+        for team_channel in &self.team_channels {
+            let purposes: HashSet<_> = team_channel.purposes.iter().collect();
+            if purposes.len() != team_channel.purposes.len() {
+                return invalid(format!(
+                    "the channel {} has the same purpose twice",
+                    team_channel.name
+                ));
+            }
+        }
+        for purpose in [
+            ChannelPurpose::General,
+            ChannelPurpose::Trade,
+            ChannelPurpose::Menu,
+        ] {
+            let count = self
+                .team_channels
+                .iter()
+                .filter(|team_channel| team_channel.purposes.contains(&purpose))
+                .count();
+            if count > 1 {
+                return invalid(format!("more than one team channel is for {:?}", purpose));
+            }
+        }
+
+        for shared_channel in &self.shared_channels {
+            if shared_channel.name.trim().is_empty() || shared_channel.name.len() > MAX_NAME_LENGTH
+            {
+                return invalid(format!(
+                    "the shared channel {:?} has an invalid name",
+                    shared_channel.name
+                ));
+            }
+            if let Some(category) = &shared_channel.category {
+                if category.trim().is_empty() || category.len() > MAX_NAME_LENGTH {
+                    return invalid(format!(
+                        "the shared channel {} has an invalid category",
+                        shared_channel.name
+                    ));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// What's made for a team, with its channels' names filled in
+    pub fn team_setup(&self, scenario_team: &ScenarioTeam) -> TeamSetup {
+        let channels = self
+            .team_channels
+            .iter()
+            .map(|team_channel| TeamChannel {
+                name: team_channel
+                    .name
+                    .replace("{team}", &scenario_team.name)
+                    .replace(
+                        "{abbreviation}",
+                        scenario_team.abbreviation.as_deref().unwrap_or_default(),
+                    ),
+                purposes: team_channel.purposes.clone(),
+            })
+            .collect();
+
+        TeamSetup {
+            name: scenario_team.name.clone(),
+            abbreviation: scenario_team.abbreviation.clone(),
+            emoji: scenario_team.emoji.clone(),
+            color: scenario_team.color,
+            channels,
+        }
+    }
+}
+
+/// What setting up a scenario in a guild would do
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ScenarioPlan {
+    pub scenario: Scenario,
+    /// The scenario's currencies that are already in the database, which
+    /// are used as they are
+    pub existing_currencies: Vec<String>,
+}
+
+impl fmt::Display for ScenarioPlan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let scenario = &self.scenario;
+
+        writeln!(
+            f,
+            "{}: {} teams, {} shared channels and {} currencies",
+            scenario.name,
+            scenario.teams.len(),
+            scenario.shared_channels.len(),
+            scenario.currencies.len()
+        )?;
+
+        for scenario_team in &scenario.teams {
+            let setup = scenario.team_setup(scenario_team);
+
+            write!(f, "Team {}", setup.name)?;
+            if let Some(abbreviation) = &setup.abbreviation {
+                write!(f, " ({})", abbreviation)?;
+            }
+            if let Some(emoji) = &setup.emoji {
+                write!(f, " {}", emoji)?;
+            }
+            let channel_names: Vec<_> = setup
+                .channels
+                .iter()
+                .map(|team_channel| format!("#{}", team_channel.name))
+                .collect();
+            writeln!(f, ": {}", channel_names.join(", "))?;
+
+            for (currency_name, amount) in &scenario_team.balances {
+                writeln!(f, "  starting with {} {}", amount, currency_name)?;
+            }
+        }
+
+        for shared_channel in &scenario.shared_channels {
+            match &shared_channel.category {
+                Some(category) => {
+                    writeln!(f, "Shared channel #{} in {}", shared_channel.name, category)?
+                }
+                None => writeln!(f, "Shared channel #{}", shared_channel.name)?,
+            }
+        }
+
+        for scenario_currency in &scenario.currencies {
+            if self.existing_currencies.contains(&scenario_currency.name) {
+                writeln!(f, "Currency {} (already exists)", scenario_currency.name)?;
+            } else {
+                writeln!(f, "Currency {}", scenario_currency.name)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// How setting up a scenario went
+#[derive(Debug, Clone, Default)]
+pub struct ScenarioOutcome {
+    pub teams: Vec<String>,
+    pub shared_channels: usize,
+    /// The teams that couldn't be made, and why. Whatever was made for them
+    /// has been undone.
+    pub failed: Vec<(String, String)>,
+}
+
+impl fmt::Display for ScenarioOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Set up {} teams and {} shared channels",
+            self.teams.len(),
+            self.shared_channels
+        )?;
+
+        for (name, why) in &self.failed {
+            writeln!(f, "Couldn't make team {}: {}", name, why)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ScenarioMechanicsHandler {
+    pub guild_id: DiscordId,
+    pub task: ScenarioJobs,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum ScenarioJobs {
+    /// Someone pressed the button to go ahead with setting up a scenario
+    Confirm { scenario: Scenario },
+}
+
+#[async_trait]
+impl MechanicHandler for ScenarioMechanicsHandler {
+    async fn handle(&self, handler: MechanicHandlerWrapper) {
+        match &self.task {
+            ScenarioJobs::Confirm { scenario } => self.confirm_scenario(handler, scenario).await,
+        }
+    }
+}
+
+impl ScenarioMechanicsHandler {
+    async fn confirm_scenario(&self, handler: MechanicHandlerWrapper, scenario: &Scenario) {
+        let Some(interaction) = use_admin_button(&handler).await else {
+            return;
+        };
+
+        let message = match apply_scenario(&handler, self.guild_id, scenario).await {
+            Ok(outcome) => outcome.to_string(),
+            Err(why) => format!("Couldn't set up {}: {}", scenario.name, why),
+        };
+
+        let _message_create_status = handler
+            .run(
+                self.guild_id,
+                SendChannelMessage {
+                    channel_id: DiscordId::from(interaction.channel_id),
+                    message,
+                    ..Default::default()
+                },
+            )
+            .await;
+    }
+}
+
+/// Work out what setting up a scenario would do, checking that none of its
+/// teams are in the guild yet
+pub async fn plan_scenario(
+    db: &DBWrapper,
+    guild_id: DiscordId,
+    scenario: Scenario,
+) -> Result<ScenarioPlan, ScenarioError> {
+    scenario.validate()?;
+
+    let team_names: Vec<String> = scenario
+        .teams
+        .iter()
+        .map(|scenario_team| scenario_team.name.clone())
+        .collect();
+    let existing_teams: Vec<String> = team::Entity::find()
+        .filter(team::Column::FkGuildId.eq(*guild_id as i64))
+        .filter(team::Column::Name.is_in(team_names))
+        .all(&**db)
+        .await?
+        .into_iter()
+        .map(|database_team| database_team.name)
+        .collect();
+    if !existing_teams.is_empty() {
+        return Err(ScenarioError::TeamsExist(existing_teams));
+    }
+
+    let currency_names: Vec<String> = scenario
+        .currencies
+        .iter()
+        .map(|scenario_currency| scenario_currency.name.clone())
+        .collect();
+    let existing_currencies = currency::Entity::find()
+        .filter(currency::Column::Name.is_in(currency_names))
+        .all(&**db)
+        .await?
+        .into_iter()
+        .map(|database_currency| database_currency.name)
+        .collect();
+
+    Ok(ScenarioPlan {
+        scenario,
+        existing_currencies,
+    })
+}
+
+/// Set up a scenario in a guild. Currencies and shared channels are made
+/// first, then each team along with a wallet holding its starting balances,
+/// and finally the menu to change teams with. A team that can't be made is
+/// undone and skipped, so the others still get made.
+pub async fn apply_scenario(
+    handler: &MechanicHandlerWrapper,
+    guild_id: DiscordId,
+    scenario: &Scenario,
+) -> Result<ScenarioOutcome, ScenarioError> {
+    // Nothing might have changed since the plan was shown
+    let plan = plan_scenario(&handler.db, guild_id, scenario.clone()).await?;
+    let mut outcome = ScenarioOutcome::default();
+
+    let currency_ids = add_currencies(&handler.db, &plan.scenario).await?;
+
+    // Shared channels, each category made before the channels in it
+    if !scenario.shared_channels.is_empty() {
+        let mut graph = TaskGraph::new();
+        let mut categories = HashMap::new();
+        for shared_channel in &scenario.shared_channels {
+            let category_id = shared_channel.category.as_ref().map(|category_name| {
+                let category = *categories.entry(category_name).or_insert_with(|| {
+                    graph.add_typed(
+                        guild_id,
+                        CreateCategory {
+                            name: category_name.clone(),
+                        },
+                    )
+                });
+                graph.discord_id(category)
+            });
+
+            graph.add_typed(
+                guild_id,
+                ChannelCreateData {
+                    name: shared_channel.name.clone(),
+                    category_id,
+                    kind: ChannelType::Text,
+                },
+            );
+        }
+
+        let mut saga = Saga::new(
+            format!("making the shared channels of {}", scenario.name),
+            handler.db.clone(),
+        )
+        .priority(handler.priority());
+        if let Err(why) = saga.run_graph(graph).await {
+            saga.rollback().await;
+            return Err(why.into());
+        }
+        outcome.shared_channels = scenario.shared_channels.len();
+    }
+
+    for scenario_team in &scenario.teams {
+        let setup = scenario.team_setup(scenario_team);
+        let team_handler = TeamMechanicsHandler {
+            guild_id,
+            task: TeamJobs::CreateTeam {
+                name: setup.name.clone(),
+            },
+        };
+
+        let database_team = match team_handler.create_team_from(handler, &setup).await {
+            Ok(database_team) => database_team,
+            Err(why) => {
+                outcome.failed.push((setup.name, why.to_string()));
+                continue;
+            }
+        };
+
+        add_team_wallet(
+            &handler.db,
+            database_team,
+            &scenario_team.balances,
+            &currency_ids,
+        )
+        .await?;
+        outcome.teams.push(setup.name);
+    }
+
+    if !outcome.teams.is_empty() {
+        MenuMechanicsHandler {
+            guild_id,
+            task: MenuJobs::RoleChangeMenu {
+                team_names: outcome.teams.clone(),
+            },
+        }
+        .handle(MechanicHandlerWrapper {
+            db: handler.db.clone(),
+            interaction: None,
+            discord: handler.discord.clone(),
+        })
+        .await;
+    }
+
+    Ok(outcome)
+}
+
+/// Add the scenario's currencies that aren't in the database yet, returning
+/// the ids of all of them by name
+async fn add_currencies(
+    db: &DBWrapper,
+    scenario: &Scenario,
+) -> Result<HashMap<String, i32>, DbErr> {
+    let txn = db.begin().await?;
+    let mut currency_ids = HashMap::new();
+
+    for scenario_currency in &scenario.currencies {
+        let existing = currency::Entity::find()
+            .filter(currency::Column::Name.eq(scenario_currency.name.clone()))
+            .one(&txn)
+            .await?;
+
+        let currency_id = match existing {
+            Some(database_currency) => database_currency.id,
+            None => {
+                currency::ActiveModel {
+                    name: Set(scenario_currency.name.clone()),
+                    description: Set(scenario_currency.description.clone()),
+                    emoji: Set(scenario_currency.emoji.clone()),
+                    ..Default::default()
+                }
+                .insert(&txn)
+                .await?
+                .id
+            }
+        };
+        currency_ids.insert(scenario_currency.name.clone(), currency_id);
+    }
+
+    txn.commit().await?;
+
+    Ok(currency_ids)
+}
+
+/// Give a team a wallet with its starting balances, which are minted
+async fn add_team_wallet(
+    db: &DBWrapper,
+    database_team: team::Model,
+    balances: &BTreeMap<String, i64>,
+    currency_ids: &HashMap<String, i32>,
+) -> Result<(), LedgerError> {
+    let txn = db.begin().await?;
+
+    let wallet_id = team_wallet(&txn, database_team.id).await?;
+    for (currency_name, amount) in balances {
+        // There's nothing to mint
+        if *amount == 0 {
+            continue;
+        }
+
+        let entry = LedgerEntry::mint(wallet_id, DatabaseId(currency_ids[currency_name]), *amount)
+            .memo("Starting balance");
+        ledger::record(&txn, &entry).await?;
+    }
+
+    txn.commit().await?;
+
+    Ok(())
+}
@@ -0,0 +1,241 @@
+use entity::entities::{currency, team, wallet_balance};
+use sea_orm::{ActiveModelTrait, ColumnTrait, EntityTrait, QueryFilter, Set};
+
+use crate::{
+    discord::DiscordBackend,
+    game_mechanics::{
+        scenario::{apply_scenario, plan_scenario, Scenario, ScenarioError, ScenarioFormat},
+        MechanicHandlerWrapper,
+    },
+    task_runner::tasks::test_helpers::{DiscordConstruct, DiscordStatus, TestHelpers},
+};
+
+fn two_team_scenario(first: &str, second: &str, currency_name: &str) -> String {
+    format!(
+        r#"
+name = "Test"
+
+[[teams]]
+name = "{first}"
+abbreviation = "ONE"
+color = 0xff0000
+
+[teams.balances]
+"{currency_name}" = 100
+
+[[teams]]
+name = "{second}"
+abbreviation = "TWO"
+
+[[team_channels]]
+name = "{{team}}"
+purposes = ["general", "menu"]
+
+[[team_channels]]
+name = "{{abbreviation}}-trade"
+purposes = ["trade"]
+
+[[shared_channels]]
+name = "announcements"
+category = "Control"
+
+[[currencies]]
+name = "{currency_name}"
+"#
+    )
+}
+
+#[test]
+fn should_read_default_scenario() {
+    let scenario = Scenario::default_scenario();
+
+    let names: Vec<_> = scenario
+        .teams
+        .iter()
+        .map(|team| team.name.as_str())
+        .collect();
+    assert_eq!(names, ["Airship", "Galleon", "Submarine"]);
+}
+
+#[test]
+fn should_read_toml_and_json_the_same() {
+    let toml_scenario = Scenario::parse(
+        &two_team_scenario("Red", "Blue", "Gold"),
+        ScenarioFormat::Toml,
+    )
+    .unwrap();
+    let json = serde_json::to_string(&toml_scenario).unwrap();
+    let json_scenario = Scenario::parse(&json, ScenarioFormat::from_filename("game.json")).unwrap();
+
+    assert_eq!(toml_scenario, json_scenario);
+
+    let setup = toml_scenario.team_setup(&toml_scenario.teams[0]);
+    let channel_names: Vec<_> = setup
+        .channels
+        .iter()
+        .map(|channel| channel.name.as_str())
+        .collect();
+    assert_eq!(channel_names, ["Red", "ONE-trade"]);
+}
+
+#[test]
+fn should_reject_invalid_scenarios() {
+    let scenarios = [
+        // Balances have to be in one of the currencies
+        r#"
+name = "Test"
+[[teams]]
+name = "Red"
+balances = { Gold = 10 }
+"#,
+        // Team names can't repeat
+        r#"
+name = "Test"
+[[teams]]
+name = "Red"
+[[teams]]
+name = "red"
+"#,
+        // `{abbreviation}` needs every team to have one
+        r#"
+name = "Test"
+[[teams]]
+name = "Red"
+[[team_channels]]
+name = "{abbreviation}"
+"#,
+        // Nothing that isn't known
+        r#"
+name = "Test"
+players = 4
+[[teams]]
+name = "Red"
+"#,
+    ];
+
+    for contents in scenarios {
+        assert!(
+            Scenario::parse(contents, ScenarioFormat::Toml).is_err(),
+            "{}",
+            contents
+        );
+    }
+}
+
+#[tokio::test]
+async fn should_refuse_teams_already_in_guild() {
+    let test_helper = TestHelpers::new().await;
+    let name = TestHelpers::generate_name();
+
+    team::ActiveModel {
+        name: Set(name.clone()),
+        fk_guild_id: Set(*test_helper.guild_id as i64),
+        ..Default::default()
+    }
+    .insert(&*test_helper.db)
+    .await
+    .unwrap();
+
+    let scenario = Scenario::parse(
+        &two_team_scenario(&name, &TestHelpers::generate_name(), "Gold"),
+        ScenarioFormat::Toml,
+    )
+    .unwrap();
+
+    let result = plan_scenario(&test_helper.db, test_helper.guild_id, scenario).await;
+    assert!(matches!(result, Err(ScenarioError::TeamsExist(names)) if names == vec![name.clone()]));
+
+    test_helper.cleanup().await;
+}
+
+#[tokio::test]
+async fn should_set_up_scenario() {
+    let test_helper = TestHelpers::new().await;
+    let first = TestHelpers::generate_name();
+    let second = TestHelpers::generate_name();
+    let currency_name = TestHelpers::generate_name();
+
+    let scenario = Scenario::parse(
+        &two_team_scenario(&first, &second, &currency_name),
+        ScenarioFormat::Toml,
+    )
+    .unwrap();
+
+    let outcome = apply_scenario(
+        &MechanicHandlerWrapper {
+            db: test_helper.db.clone(),
+            interaction: None,
+            discord: test_helper.discord.clone(),
+        },
+        test_helper.guild_id,
+        &scenario,
+    )
+    .await
+    .unwrap();
+    assert_eq!(outcome.teams, [first.clone(), second.clone()]);
+    assert!(outcome.failed.is_empty());
+
+    for name in [
+        first.clone(),
+        "ONE-trade".to_string(),
+        "announcements".to_string(),
+        "team-change".to_string(),
+    ] {
+        assert_eq!(
+            test_helper
+                .check_discord_status(DiscordConstruct::Channel { name })
+                .await,
+            DiscordStatus::Exists
+        );
+    }
+    assert_eq!(
+        test_helper
+            .check_discord_status(DiscordConstruct::Category {
+                name: "Control".to_string()
+            })
+            .await,
+        DiscordStatus::Exists
+    );
+
+    // The first team's role has the scenario's color, the second the guild's
+    let roles = test_helper
+        .discord
+        .guild(test_helper.guild_id)
+        .await
+        .unwrap()
+        .roles;
+    for (name, color) in [(&first, 0xff0000), (&second, 0x00ff00)] {
+        let role = roles.iter().find(|role| &role.name == name).unwrap();
+        assert_eq!(role.color, color);
+    }
+
+    // The first team starts with its balance
+    let first_team = team::Entity::find()
+        .filter(team::Column::Name.eq(first))
+        .one(&*test_helper.db)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(first_team.abreviation.as_deref(), Some("ONE"));
+    assert!(first_team.fk_trade_channel_id.is_some());
+    assert_ne!(
+        first_team.fk_trade_channel_id,
+        first_team.fk_menu_channel_id
+    );
+
+    let database_currency = currency::Entity::find()
+        .filter(currency::Column::Name.eq(currency_name))
+        .one(&*test_helper.db)
+        .await
+        .unwrap()
+        .unwrap();
+    let balance =
+        wallet_balance::Entity::find_by_id((first_team.wallet.unwrap(), database_currency.id))
+            .one(&*test_helper.db)
+            .await
+            .unwrap()
+            .unwrap();
+    assert_eq!(balance.amount, 100);
+
+    test_helper.cleanup().await;
+}
@@ -8,27 +8,36 @@ use serenity::{
     model::prelude::{ChannelType, RoleId},
     utils::MessageBuilder,
 };
+use tracing::log;
 
 use crate::{
-    db_wrapper::{helpers::get_guild, TaskResult, TaskReturnData},
+    db_wrapper::helpers::{get_guild, guild_settings},
     game_mechanics::{
         menu::{MenuJobs, MenuMechanicsHandler},
+        saga::{Compensation, Saga},
         MechanicFunction,
     },
-    task_runner::tasks::{
-        category::{CategoryHandler, CategoryTasks},
-        channel::{ChannelCreateData, ChannelHandler, ChannelTasks},
-        message::{
-            message_component::{MessageComponent, MessageData},
-            MessageHandler, MessageTasks, SendChannelMessage,
+    task_runner::{
+        error::TaskError,
+        graph::TaskGraph,
+        tasks::{
+            category::CreateCategory,
+            channel::ChannelCreateData,
+            message::{
+                message_component::{MessageComponent, MessageData},
+                SendChannelMessage,
+            },
+            role::CreateRole,
+            DatabaseId, DiscordId,
         },
-        role::{CreateRole, RoleHandler, RoleTasks},
-        DiscordId, TaskType, DatabaseId,
     },
 };
 
 use super::{MechanicHandler, MechanicHandlerWrapper};
 
+#[cfg(test)]
+mod tests;
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct TeamMechanicsHandler {
     pub guild_id: DiscordId,
@@ -43,6 +52,60 @@ pub enum TeamJobs {
     DeleteTeam,
 }
 
+/// Everything that's made for a new team
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct TeamSetup {
+    pub name: String,
+    pub abbreviation: Option<String>,
+    pub emoji: Option<String>,
+    /// The color of the team's role, instead of the guild's `team_role_color`
+    pub color: Option<u32>,
+    /// The channels in the team's category
+    pub channels: Vec<TeamChannel>,
+}
+
+impl TeamSetup {
+    /// A team with a single channel named after it, that's both its general
+    /// and menu channel
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            abbreviation: None,
+            emoji: None,
+            color: None,
+            channels: vec![TeamChannel {
+                name: name.to_string(),
+                purposes: vec![ChannelPurpose::General, ChannelPurpose::Menu],
+            }],
+        }
+    }
+
+    /// The first channel with a purpose
+    fn channel_with(&self, purpose: ChannelPurpose) -> Option<usize> {
+        self.channels
+            .iter()
+            .position(|channel| channel.purposes.contains(&purpose))
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct TeamChannel {
+    pub name: String,
+    #[serde(default)]
+    pub purposes: Vec<ChannelPurpose>,
+}
+
+/// What a team's channel is used for, which decides what the bot posts in it
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum ChannelPurpose {
+    /// Gets the welcome message
+    General,
+    Trade,
+    /// Gets the team menu
+    Menu,
+}
+
 #[async_trait]
 impl MechanicHandler for TeamMechanicsHandler {
     async fn handle(&self, handler: MechanicHandlerWrapper) {
@@ -57,94 +120,122 @@ impl MechanicHandler for TeamMechanicsHandler {
 
 impl TeamMechanicsHandler {
     async fn create_team(&self, handler: MechanicHandlerWrapper, name: &str) {
-        
+        if let Err(why) = self.create_team_from(&handler, &TeamSetup::new(name)).await {
+            log::error!("Couldn't create team {}: {}", name, why);
+        }
+    }
+
+    /// Make a team and everything it has in the guild. If any step fails,
+    /// the ones before it are undone so there's no half made team left in
+    /// the guild.
+    pub async fn create_team_from(
+        &self,
+        handler: &MechanicHandlerWrapper,
+        setup: &TeamSetup,
+    ) -> Result<team::Model, TaskError> {
+        let mut saga = Saga::new(format!("creating team {}", setup.name), handler.db.clone())
+            .priority(handler.priority());
+
+        let result = self.try_create_team(handler, setup, &mut saga).await;
+        if result.is_err() {
+            saga.rollback().await;
+        }
+
+        result
+    }
+
+    async fn try_create_team(
+        &self,
+        handler: &MechanicHandlerWrapper,
+        setup: &TeamSetup,
+        saga: &mut Saga,
+    ) -> Result<team::Model, TaskError> {
+        let name = setup.name.as_str();
+        if setup.channels.is_empty() {
+            return Err(TaskError::permanent("A team needs at least one channel"));
+        }
+
         // Get the guild
         let (_discord_guild, database_guild) =
-        get_guild(handler.ctx, handler.db.clone(), self.guild_id).await;
+            get_guild(&*handler.discord, handler.db.clone(), self.guild_id).await?;
+        let settings = guild_settings(&database_guild)?;
 
         // Add the team to the database
-        let mut team_model: team::ActiveModel = team::ActiveModel {
+        let team_model = team::ActiveModel {
             name: Set(name.to_string()),
+            abreviation: Set(setup.abbreviation.clone()),
+            emoji: Set(setup.emoji.clone()),
             fk_guild_id: Set(database_guild.discord_id),
             ..Default::default()
         }
         .insert(&*handler.db)
-        .await
-        .unwrap().into();
+        .await?;
 
+        saga.on_rollback(Compensation::DeleteTeam(DatabaseId(team_model.id)));
 
-        // Create the role
-        let role_create_status = handler
-            .db
-            .add_await_task(TaskType::RoleHandler(RoleHandler {
-                guild_id: DiscordId(*self.guild_id),
-                task: RoleTasks::CreateRole(CreateRole {
-                    name: name.to_string(),
-                    color: 0x00ff00,
-                }),
-            }))
-            .await;
-
-        let role_model = match role_create_status {
-            TaskResult::Completed(TaskReturnData::RoleModel(role_model)) => role_model,
-            _ => panic!("Role not created"),
-        };
+        let mut team_model: team::ActiveModel = team_model.into();
 
-        team_model.fk_team_role_id = Set(Some(role_model.discord_id));
+        let mut graph = TaskGraph::new();
+
+        // Create the role
+        let role = graph.add_typed(
+            self.guild_id,
+            CreateRole {
+                name: name.to_string(),
+                color: setup.color.unwrap_or(settings.team_role_color),
+            },
+        );
 
         // Create the team category
-        let category_create_status = handler
-            .db
-            .add_await_task(TaskType::CategoryHandler(CategoryHandler {
-                guild_id: DiscordId(*self.guild_id),
-                task: CategoryTasks::Create {
-                    name: name.to_string(),
-                },
-            }))
-            .await;
-
-        let category_model = match category_create_status {
-            TaskResult::Completed(TaskReturnData::CategoryModel(category_model)) => category_model,
-            _ => panic!("Category not created"),
-        };
-
-        // Create the team channel
-        let channel_create_status = handler
-            .db
-            .add_await_task(TaskType::ChannelHandler(ChannelHandler {
-                guild_id: DiscordId(*self.guild_id),
-                task: ChannelTasks::Create(ChannelCreateData {
-                    name: name.to_string(),
-                    category_id: Some(DiscordId::from(category_model.discord_id)),
-                    kind: ChannelType::Text,
-                }),
-            }))
-            .await;
-
-        let channel_model = match channel_create_status {
-            TaskResult::Completed(TaskReturnData::ChannelModel(channel_model)) => channel_model,
-            _ => panic!("Channel not created"),
-        };
-
-        team_model.fk_menu_channel_id = Set(Some(channel_model.discord_id));
-
-        // Write a message in the team channel that pings the role of the
+        let category = graph.add_typed(
+            self.guild_id,
+            CreateCategory {
+                name: name.to_string(),
+            },
+        );
+
+        // Create the team's channels
+        let channels: Vec<_> = setup
+            .channels
+            .iter()
+            .map(|team_channel| {
+                graph.add_typed(
+                    self.guild_id,
+                    ChannelCreateData {
+                        name: team_channel.name.clone(),
+                        category_id: Some(graph.discord_id(category)),
+                        kind: ChannelType::Text,
+                    },
+                )
+            })
+            .collect();
+
+        // The welcome message and menu go in the first channel, unless
+        // another is meant for them
+        let general_channel = channels[setup.channel_with(ChannelPurpose::General).unwrap_or(0)];
+        let menu_channel = channels[setup.channel_with(ChannelPurpose::Menu).unwrap_or(0)];
+        let trade_channel = setup
+            .channel_with(ChannelPurpose::Trade)
+            .map(|index| channels[index]);
+
+        let channel_id = graph.discord_id(menu_channel);
+
+        let role_id: RoleId = graph.discord_id(role).into();
+
+        // Write a message in the general channel that pings the role of the
         // players
-        let _message_create_status = handler
-            .db
-            .add_await_task(TaskType::MessageHandler(MessageHandler {
-                guild_id: DiscordId(*self.guild_id),
-                task: MessageTasks::SendChannelMessage(SendChannelMessage {
-                    channel_id: DiscordId::from(channel_model.discord_id),
-                    message: MessageBuilder::new()
-                        .push("Welcome to the team ")
-                        .mention(&RoleId(DiscordId::from(role_model.discord_id).into()))
-                        .push("!")
-                        .build(),
-                    ..Default::default()
-                }),
-            }))
-            .await;
+        graph.add_typed(
+            self.guild_id,
+            SendChannelMessage {
+                channel_id: graph.discord_id(general_channel),
+                message: MessageBuilder::new()
+                    .push("Welcome to the team ")
+                    .mention(&role_id)
+                    .push("!")
+                    .build(),
+                ..Default::default()
+            },
+        );
 
         fn sound_button(name: &str, _emoji: ReactionType) -> CreateButton {
             // To add an emoji to buttons, use .emoji(). The method accepts anything ReactionType or
@@ -154,95 +245,105 @@ impl TeamMechanicsHandler {
         }
 
         // Add a team menu to the team channel
-        let _message_create_status = handler
-            .db
-            .add_await_task(TaskType::MessageHandler(MessageHandler {
-                guild_id: DiscordId::from(self.guild_id),
-                task: MessageTasks::SendChannelMessage(SendChannelMessage {
-                    channel_id: DiscordId::from(channel_model.discord_id),
-                    message: MessageBuilder::new()
-                        .push("Welcome to the team ")
-                        .mention(&RoleId(DiscordId::from(role_model.discord_id).into()))
-                        .push("!")
-                        .build(),
-                    select_menu: Some(MessageComponent::new(
-                        CreateSelectMenu::new(
-                            "",
-                            CreateSelectMenuKind::String {
-                                options: vec![
-                                    CreateSelectMenuOption::new("🐈 meow", "Cat"),
-                                    CreateSelectMenuOption::new("🐕 woof", "Dog"),
-                                    CreateSelectMenuOption::new("🐎 neigh", "Horse"),
-                                    CreateSelectMenuOption::new("🦙 hoooooooonk", "Alpaca"),
-                                    CreateSelectMenuOption::new("🦀 crab rave", "Ferris"),
-                                ],
+        graph.add_typed(
+            self.guild_id,
+            SendChannelMessage {
+                channel_id,
+                message: MessageBuilder::new()
+                    .push("Welcome to the team ")
+                    .mention(&role_id)
+                    .push("!")
+                    .build(),
+                select_menu: Some(MessageComponent::new(
+                    CreateSelectMenu::new(
+                        "",
+                        CreateSelectMenuKind::String {
+                            options: vec![
+                                CreateSelectMenuOption::new("🐈 meow", "Cat"),
+                                CreateSelectMenuOption::new("🐕 woof", "Dog"),
+                                CreateSelectMenuOption::new("🐎 neigh", "Horse"),
+                                CreateSelectMenuOption::new("🦙 hoooooooonk", "Alpaca"),
+                                CreateSelectMenuOption::new("🦀 crab rave", "Ferris"),
+                            ],
+                        },
+                    )
+                    .placeholder("No animal selected"),
+                    None,
+                )),
+                buttons: vec![
+                    MessageComponent::new(
+                        CreateButton::new("")
+                            .style(ButtonStyle::Primary)
+                            .disabled(false)
+                            .label("Start Trade")
+                            .emoji("💱".parse::<ReactionType>().unwrap()),
+                        Some(MessageData::Function(MechanicFunction::Menu(
+                            MenuMechanicsHandler {
+                                guild_id: self.guild_id,
+                                task: MenuJobs::StartTradeMenu { channel_id },
+                            },
+                        ))),
+                    ),
+                    MessageComponent::new(
+                        CreateButton::new("")
+                            .style(ButtonStyle::Primary)
+                            .disabled(false)
+                            .label("Open Comms")
+                            .emoji("💬".parse::<ReactionType>().unwrap()),
+                        Some(MessageData::Function(MechanicFunction::Menu(
+                            MenuMechanicsHandler {
+                                guild_id: self.guild_id,
+                                task: MenuJobs::OpenComms { channel_id },
                             },
-                        )
-                        .placeholder("No animal selected"),
+                        ))),
+                    ),
+                    MessageComponent::new(
+                        CreateButton::new("")
+                            .style(ButtonStyle::Primary)
+                            .disabled(false)
+                            .label("Update Bank")
+                            .emoji("💰".parse::<ReactionType>().unwrap()),
                         None,
-                    )),
-                    buttons: vec![
-                        MessageComponent::new(
-                            CreateButton::new("")
-                                .style(ButtonStyle::Primary)
-                                .disabled(false)
-                                .label("Start Trade")
-                                .emoji("💱".parse::<ReactionType>().unwrap()),
-                            Some(MessageData::Function(MechanicFunction::Menu(
-                                MenuMechanicsHandler {
-                                    guild_id: self.guild_id,
-                                    task: MenuJobs::StartTradeMenu {
-                                        channel_id: DiscordId::from(channel_model.discord_id),
-                                    },
+                    ),
+                    MessageComponent::new(
+                        CreateButton::new("")
+                            .style(ButtonStyle::Primary)
+                            .disabled(false)
+                            .label("Join Team")
+                            .emoji("👋".parse::<ReactionType>().unwrap()),
+                        Some(MessageData::Function(MechanicFunction::Menu(
+                            MenuMechanicsHandler {
+                                guild_id: self.guild_id,
+                                task: MenuJobs::JoinTeam {
+                                    channel_id,
+                                    joining_team_id: DatabaseId::from(&team_model.id),
                                 },
-                            ))),
-                        ),
-                        MessageComponent::new(
-                            CreateButton::new("")
-                                .style(ButtonStyle::Primary)
-                                .disabled(false)
-                                .label("Open Comms")
-                                .emoji("💬".parse::<ReactionType>().unwrap()),
-                            Some(MessageData::Function(MechanicFunction::Menu(
-                                MenuMechanicsHandler {
-                                    guild_id: self.guild_id,
-                                    task: MenuJobs::OpenComms {
-                                        channel_id: DiscordId::from(channel_model.discord_id),
-                                    },
-                                },
-                            ))),
-                        ),
-                        MessageComponent::new(
-                            CreateButton::new("")
-                                .style(ButtonStyle::Primary)
-                                .disabled(false)
-                                .label("Update Bank")
-                                .emoji("💰".parse::<ReactionType>().unwrap()),
-                            None,
-                        ),
-                        MessageComponent::new(
-                            CreateButton::new("")
-                                .style(ButtonStyle::Primary)
-                                .disabled(false)
-                                .label("Join Team")
-                                .emoji("👋".parse::<ReactionType>().unwrap()),
-                            Some(MessageData::Function(MechanicFunction::Menu(
-                                MenuMechanicsHandler {
-                                    guild_id: self.guild_id,
-                                    task: MenuJobs::JoinTeam {
-                                        channel_id: DiscordId::from(channel_model.discord_id),
-                                        joining_team_id: DatabaseId::from(&team_model.id),
-                                    },
-                                },
-                            ))),
-                        ),
-                    ],
-                }),
-            }))
-            .await;
+                            },
+                        ))),
+                    ),
+                ],
+            },
+        );
+
+        // Create everything, each task waiting on the ones it uses
+        let results = saga.run_graph(graph).await?;
+
+        let role_model = results.output(role)?;
+
+        team_model.fk_team_role_id = Set(Some(role_model.discord_id));
+
+        let category_model = results.output(category)?;
+
+        team_model.fk_team_category_id = Set(Some(category_model.discord_id));
+
+        team_model.fk_general_channel_id = Set(Some(results.output(general_channel)?.discord_id));
+        team_model.fk_menu_channel_id = Set(Some(results.output(menu_channel)?.discord_id));
+        if let Some(trade_channel) = trade_channel {
+            team_model.fk_trade_channel_id = Set(Some(results.output(trade_channel)?.discord_id));
+        }
 
         // Update the team in the database
-        team_model.update(&*handler.db).await;
+        Ok(team_model.update(&*handler.db).await?)
     }
 
     async fn add_player_to_team(&self, _handler: MechanicHandlerWrapper) {
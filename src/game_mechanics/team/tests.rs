@@ -0,0 +1,104 @@
+use entity::entities::{guild, team};
+use sea_orm::{ActiveModelTrait, ColumnTrait, EntityTrait, QueryFilter, Set};
+use serenity::model::prelude::ChannelType;
+
+use crate::{
+    config::GuildSettings,
+    db_wrapper::helpers::get_database_guild,
+    discord::DiscordBackend,
+    game_mechanics::{
+        team::{TeamJobs, TeamMechanicsHandler},
+        MechanicHandler, MechanicHandlerWrapper,
+    },
+    task_runner::tasks::test_helpers::TestHelpers,
+};
+
+#[tokio::test]
+async fn should_create_team() {
+    let test_helper = TestHelpers::new().await;
+    let name = TestHelpers::generate_name();
+
+    TeamMechanicsHandler {
+        guild_id: test_helper.guild_id,
+        task: TeamJobs::CreateTeam { name: name.clone() },
+    }
+    .handle(MechanicHandlerWrapper {
+        db: test_helper.db.clone(),
+        interaction: None,
+        discord: test_helper.discord.clone(),
+    })
+    .await;
+
+    let team_model = team::Entity::find()
+        .filter(team::Column::Name.eq(name.clone()))
+        .one(&*test_helper.db)
+        .await
+        .unwrap()
+        .unwrap();
+
+    let guild = test_helper
+        .discord
+        .guild(test_helper.guild_id)
+        .await
+        .unwrap();
+
+    // The team's role
+    let role = guild.roles.iter().find(|role| role.name == name).unwrap();
+    assert_eq!(team_model.fk_team_role_id, Some(*role.id as i64));
+
+    // Its category, with the menu channel in it
+    let category = guild
+        .channels
+        .iter()
+        .find(|channel| channel.kind == ChannelType::Category && channel.name == name)
+        .unwrap();
+    let menu_channel = guild
+        .channels
+        .iter()
+        .find(|channel| channel.kind == ChannelType::Text && channel.name == name)
+        .unwrap();
+    assert_eq!(menu_channel.parent_id, Some(category.id));
+    assert_eq!(team_model.fk_menu_channel_id, Some(*menu_channel.id as i64));
+
+    // The welcome message and the team menu
+    let messages = test_helper.discord.messages();
+    assert_eq!(messages.len(), 2);
+    assert!(messages
+        .iter()
+        .all(|message| message.channel_id == menu_channel.id));
+    assert!(messages
+        .iter()
+        .any(|message| message.has_select_menu && message.buttons == 4));
+
+    test_helper.cleanup().await;
+}
+
+#[tokio::test]
+async fn should_color_team_roles_with_the_guild_setting() {
+    let test_helper = TestHelpers::new().await;
+    let name = TestHelpers::generate_name();
+
+    let mut database_guild: guild::ActiveModel =
+        get_database_guild(&test_helper.db, test_helper.guild_id)
+            .await
+            .unwrap()
+            .into();
+    database_guild.settings = Set(serde_json::to_value(GuildSettings {
+        team_role_color: 0x123456,
+        ..Default::default()
+    })
+    .unwrap());
+    database_guild.update(&*test_helper.db).await.unwrap();
+
+    test_helper.create_team(&name).await;
+
+    let guild = test_helper
+        .discord
+        .guild(test_helper.guild_id)
+        .await
+        .unwrap();
+    let role = guild.roles.iter().find(|role| role.name == name).unwrap();
+    assert_eq!(role.color, 0x123456);
+
+    test_helper.cleanup().await;
+}
@@ -0,0 +1,915 @@
+use std::{fmt, str::FromStr};
+
+use async_trait::async_trait;
+use chrono::{Duration, Utc};
+use entity::entities::{currency, team, trade, trade_item};
+use sea_orm::{
+    sea_query::Expr, ActiveModelTrait, ColumnTrait, ConnectionTrait, DbErr, EntityTrait,
+    QueryFilter, Set, TransactionTrait,
+};
+use serde::{Deserialize, Serialize};
+use serenity::{all::ButtonStyle, builder::CreateButton};
+use tracing::log;
+
+use crate::{
+    db_wrapper::{
+        helpers::{get_guild_player, get_player_team, GameDatabaseError},
+        ledger::{self, team_wallet, LedgerEntry, LedgerError},
+        DBWrapper,
+    },
+    task_runner::{
+        schedule::{NewTask, TaskPriority},
+        tasks::{
+            message::{
+                message_component::{MessageComponent, MessageData},
+                SendChannelMessage,
+            },
+            DatabaseId, DiscordId, TypedTask,
+        },
+    },
+};
+
+use super::{MechanicFunction, MechanicHandler, MechanicHandlerWrapper};
+
+#[cfg(test)]
+mod tests;
+
+/// How long a trade that's still being worked out can go without either
+/// team doing anything before it expires
+pub const TRADE_EXPIRY_HOURS: i64 = 24;
+
+/// Where a trade is at. Trades start as a draft that the initiating team
+/// fills in, then go back and forth between the teams until one accepts,
+/// which carries the trade out.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TradeStatus {
+    /// The awaited team is working out what to offer
+    Draft,
+    /// The initiating team sent its first offer
+    Proposed,
+    /// A team sent an offer back
+    Countered,
+    /// The awaited team took the last offer, and the trade is waiting to be
+    /// carried out
+    Accepted,
+    Executed,
+    Cancelled,
+    Expired,
+}
+
+impl TradeStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TradeStatus::Draft => "draft",
+            TradeStatus::Proposed => "proposed",
+            TradeStatus::Countered => "countered",
+            TradeStatus::Accepted => "accepted",
+            TradeStatus::Executed => "executed",
+            TradeStatus::Cancelled => "cancelled",
+            TradeStatus::Expired => "expired",
+        }
+    }
+
+    /// Trades that are finished can't change anymore
+    pub fn is_finished(&self) -> bool {
+        matches!(
+            self,
+            TradeStatus::Executed | TradeStatus::Cancelled | TradeStatus::Expired
+        )
+    }
+
+    /// What a trade goes to when someone does something to it, and whose
+    /// turn it is after
+    pub fn transition(
+        self,
+        action: TradeAction,
+        actor: TradeActor,
+        proposed_before: bool,
+    ) -> Result<(TradeStatus, Turn), TradeError> {
+        use TradeStatus::*;
+
+        let next = match (self, action, actor) {
+            (Draft, TradeAction::Edit, TradeActor::Awaited) => (Draft, Turn::Same),
+            (Draft, TradeAction::Send, TradeActor::Awaited) if proposed_before => {
+                (Countered, Turn::Other)
+            }
+            (Draft, TradeAction::Send, TradeActor::Awaited) => (Proposed, Turn::Other),
+            (Proposed | Countered, TradeAction::Counter, TradeActor::Awaited) => {
+                (Draft, Turn::Same)
+            }
+            (Proposed | Countered, TradeAction::Accept, TradeActor::Awaited) => {
+                (Accepted, Turn::Nobody)
+            }
+            (
+                Draft | Proposed | Countered | Accepted,
+                TradeAction::Cancel,
+                TradeActor::Awaited | TradeActor::Waiting,
+            ) => (Cancelled, Turn::Nobody),
+            (Accepted, TradeAction::Execute, _) => (Executed, Turn::Nobody),
+            (Draft | Proposed | Countered, TradeAction::Expire, TradeActor::System) => {
+                (Expired, Turn::Nobody)
+            }
+            _ => {
+                return Err(TradeError::NotAllowed {
+                    status: self,
+                    action,
+                })
+            }
+        };
+
+        Ok(next)
+    }
+}
+
+impl fmt::Display for TradeStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl FromStr for TradeStatus {
+    type Err = String;
+
+    fn from_str(status: &str) -> Result<Self, Self::Err> {
+        [
+            TradeStatus::Draft,
+            TradeStatus::Proposed,
+            TradeStatus::Countered,
+            TradeStatus::Accepted,
+            TradeStatus::Executed,
+            TradeStatus::Cancelled,
+            TradeStatus::Expired,
+        ]
+        .into_iter()
+        .find(|known| known.as_str() == status)
+        .ok_or_else(|| format!("{:?} isn't a trade status", status))
+    }
+}
+
+/// Something done to a trade
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TradeAction {
+    /// Change what's in a draft
+    Edit,
+    /// Send a draft to the other team
+    Send,
+    /// Turn an offer back into a draft to change it
+    Counter,
+    Accept,
+    Cancel,
+    /// Move the funds of an accepted trade
+    Execute,
+    Expire,
+}
+
+/// Who's doing something to a trade
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TradeActor {
+    /// The team whose turn it is
+    Awaited,
+    /// The other team in the trade
+    Waiting,
+    /// The bot, e.g. when a trade expires
+    System,
+}
+
+/// Whose turn it is after a transition
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Turn {
+    Same,
+    Other,
+    Nobody,
+}
+
+#[derive(Debug)]
+pub enum TradeError {
+    NotFound,
+    NotAllowed {
+        status: TradeStatus,
+        action: TradeAction,
+    },
+    /// The team isn't one of the two in the trade
+    NotInTrade,
+    /// The player isn't on a team in the guild
+    NotOnTeam,
+    SameTeam,
+    TeamNotFound,
+    CurrencyNotFound(String),
+    InvalidAmount,
+    NoItems,
+    Expired,
+    InsufficientFunds {
+        team: String,
+        currency: String,
+    },
+    /// Someone else changed the trade at the same time
+    Conflict,
+    Database(DbErr),
+}
+
+impl fmt::Display for TradeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TradeError::NotFound => write!(f, "There's no such trade"),
+            TradeError::NotAllowed { status, action } => {
+                write!(f, "Can't {:?} a trade that's {}", action, status)
+            }
+            TradeError::NotInTrade => write!(f, "Your team isn't part of that trade"),
+            TradeError::NotOnTeam => write!(f, "You need to be on a team to trade"),
+            TradeError::SameTeam => write!(f, "A team can't trade with itself"),
+            TradeError::TeamNotFound => write!(f, "That isn't a team in this server"),
+            TradeError::CurrencyNotFound(name) => write!(f, "There's no currency called {}", name),
+            TradeError::InvalidAmount => write!(f, "Amounts can't be negative"),
+            TradeError::NoItems => write!(f, "The trade doesn't have anything in it yet"),
+            TradeError::Expired => write!(f, "The trade has expired"),
+            TradeError::InsufficientFunds { team, currency } => {
+                write!(f, "{} doesn't have enough {}", team, currency)
+            }
+            TradeError::Conflict => write!(f, "The trade changed in the meantime, try again"),
+            TradeError::Database(why) => write!(f, "{}", why),
+        }
+    }
+}
+
+impl From<DbErr> for TradeError {
+    fn from(why: DbErr) -> Self {
+        TradeError::Database(why)
+    }
+}
+
+impl From<GameDatabaseError> for TradeError {
+    fn from(why: GameDatabaseError) -> Self {
+        match why {
+            GameDatabaseError::Database(why) => TradeError::Database(why),
+            _ => TradeError::NotOnTeam,
+        }
+    }
+}
+
+/// The status stored on a trade's row
+pub fn trade_status(database_trade: &trade::Model) -> TradeStatus {
+    // Only ever written from a `TradeStatus`
+    database_trade.status.parse().unwrap()
+}
+
+/// How a team relates to a trade
+fn trade_actor(
+    database_trade: &trade::Model,
+    team_id: DatabaseId,
+) -> Result<TradeActor, TradeError> {
+    if database_trade.fk_awaiting_team_id == Some(*team_id) {
+        Ok(TradeActor::Awaited)
+    } else if [
+        database_trade.fk_initiating_team_id,
+        database_trade.fk_receiving_team_id,
+    ]
+    .contains(&*team_id)
+    {
+        Ok(TradeActor::Waiting)
+    } else {
+        Err(TradeError::NotInTrade)
+    }
+}
+
+/// The team in the trade that isn't this one
+fn other_team(database_trade: &trade::Model, team_id: i32) -> i32 {
+    if team_id == database_trade.fk_initiating_team_id {
+        database_trade.fk_receiving_team_id
+    } else {
+        database_trade.fk_initiating_team_id
+    }
+}
+
+async fn find_trade<C: ConnectionTrait>(
+    db: &C,
+    trade_id: DatabaseId,
+) -> Result<trade::Model, TradeError> {
+    trade::Entity::find_by_id(*trade_id)
+        .one(db)
+        .await?
+        .ok_or(TradeError::NotFound)
+}
+
+/// Start a draft trade between two teams in a guild, which the initiating
+/// team fills in first
+pub async fn start_trade(
+    db: &DBWrapper,
+    guild_id: DiscordId,
+    initiating_team_id: DatabaseId,
+    receiving_team_id: DatabaseId,
+) -> Result<trade::Model, TradeError> {
+    if initiating_team_id == receiving_team_id {
+        return Err(TradeError::SameTeam);
+    }
+
+    let teams = team::Entity::find()
+        .filter(team::Column::Id.is_in([*initiating_team_id, *receiving_team_id]))
+        .filter(team::Column::FkGuildId.eq(*guild_id as i64))
+        .all(&**db)
+        .await?;
+    if teams.len() != 2 {
+        return Err(TradeError::TeamNotFound);
+    }
+
+    let now = Utc::now();
+    let database_trade = trade::ActiveModel {
+        fk_guild_id: Set(*guild_id as i64),
+        fk_initiating_team_id: Set(*initiating_team_id),
+        fk_receiving_team_id: Set(*receiving_team_id),
+        fk_awaiting_team_id: Set(Some(*initiating_team_id)),
+        status: Set(TradeStatus::Draft.as_str().to_string()),
+        created_at: Set(now.into()),
+        updated_at: Set(now.into()),
+        proposed_at: Set(None),
+        expires_at: Set(Some((now + Duration::hours(TRADE_EXPIRY_HOURS)).into())),
+        ..Default::default()
+    }
+    .insert(&**db)
+    .await?;
+
+    Ok(database_trade)
+}
+
+/// Set how much of a currency one side of a draft hands over, as the team
+/// whose draft it is. An amount of 0 takes the currency out of the trade.
+pub async fn set_trade_item(
+    db: &DBWrapper,
+    trade_id: DatabaseId,
+    team_id: DatabaseId,
+    giving: bool,
+    currency_name: &str,
+    amount: i64,
+) -> Result<trade::Model, TradeError> {
+    if amount < 0 {
+        return Err(TradeError::InvalidAmount);
+    }
+
+    let database_trade = act_on_trade(db, trade_id, Some(team_id), TradeAction::Edit).await?;
+
+    let database_currency = currency::Entity::find()
+        .filter(currency::Column::Name.eq(currency_name))
+        .one(&**db)
+        .await?
+        .ok_or_else(|| TradeError::CurrencyNotFound(currency_name.to_string()))?;
+
+    let from_team_id = if giving {
+        *team_id
+    } else {
+        other_team(&database_trade, *team_id)
+    };
+
+    let existing =
+        trade_item::Entity::find_by_id((database_trade.id, from_team_id, database_currency.id))
+            .one(&**db)
+            .await?;
+
+    match (existing, amount) {
+        (Some(existing), 0) => {
+            trade_item::Entity::delete(trade_item::ActiveModel::from(existing))
+                .exec(&**db)
+                .await?;
+        }
+        (Some(existing), amount) => {
+            let mut existing: trade_item::ActiveModel = existing.into();
+            existing.amount = Set(amount);
+            existing.update(&**db).await?;
+        }
+        (None, 0) => {}
+        (None, amount) => {
+            trade_item::ActiveModel {
+                fk_trade_id: Set(database_trade.id),
+                fk_from_team_id: Set(from_team_id),
+                fk_currency_id: Set(database_currency.id),
+                amount: Set(amount),
+            }
+            .insert(&**db)
+            .await?;
+        }
+    }
+
+    Ok(database_trade)
+}
+
+/// Move a trade along, as a team or as the bot if `team_id` is `None`.
+/// Executing goes through `execute_trade` instead.
+pub async fn act_on_trade(
+    db: &DBWrapper,
+    trade_id: DatabaseId,
+    team_id: Option<DatabaseId>,
+    action: TradeAction,
+) -> Result<trade::Model, TradeError> {
+    if action == TradeAction::Execute {
+        return execute_trade(db, trade_id, team_id, None).await;
+    }
+
+    let database_trade = find_trade(&**db, trade_id).await?;
+    let status = trade_status(&database_trade);
+    let actor = match team_id {
+        Some(team_id) => trade_actor(&database_trade, team_id)?,
+        None => TradeActor::System,
+    };
+
+    // Trades that have run out of time can only expire
+    let now = Utc::now();
+    let expired = database_trade
+        .expires_at
+        .map_or(false, |expires_at| expires_at < now);
+    if expired
+        && action != TradeAction::Expire
+        && !status.is_finished()
+        && status != TradeStatus::Accepted
+    {
+        trade::Entity::update_many()
+            .col_expr(
+                trade::Column::Status,
+                Expr::value(TradeStatus::Expired.as_str()),
+            )
+            .col_expr(trade::Column::FkAwaitingTeamId, Expr::value(None::<i32>))
+            .col_expr(trade::Column::UpdatedAt, Expr::value(now))
+            .filter(trade::Column::Id.eq(database_trade.id))
+            .filter(trade::Column::Status.eq(status.as_str()))
+            .exec(&**db)
+            .await?;
+        return Err(TradeError::Expired);
+    }
+
+    let (next, turn) = status.transition(action, actor, database_trade.proposed_at.is_some())?;
+
+    if action == TradeAction::Send {
+        let has_items = trade_item::Entity::find()
+            .filter(trade_item::Column::FkTradeId.eq(database_trade.id))
+            .one(&**db)
+            .await?
+            .is_some();
+        if !has_items {
+            return Err(TradeError::NoItems);
+        }
+    }
+
+    let awaiting = match turn {
+        Turn::Same => database_trade.fk_awaiting_team_id,
+        Turn::Other => database_trade
+            .fk_awaiting_team_id
+            .map(|awaiting| other_team(&database_trade, awaiting)),
+        Turn::Nobody => None,
+    };
+
+    let mut proposed_at = database_trade.proposed_at;
+    let mut expires_at = database_trade.expires_at;
+    if action == TradeAction::Send {
+        proposed_at = proposed_at.or_else(|| Some(now.into()));
+    }
+    if matches!(
+        action,
+        TradeAction::Send | TradeAction::Counter | TradeAction::Edit
+    ) {
+        // Anything a team does gives the trade more time
+        expires_at = Some((now + Duration::hours(TRADE_EXPIRY_HOURS)).into());
+    }
+
+    // Only move on from the status this was worked out from, so two
+    // presses at once can't both go through
+    let updated = trade::Entity::update_many()
+        .col_expr(trade::Column::Status, Expr::value(next.as_str()))
+        .col_expr(trade::Column::FkAwaitingTeamId, Expr::value(awaiting))
+        .col_expr(trade::Column::UpdatedAt, Expr::value(now))
+        .col_expr(trade::Column::ProposedAt, Expr::value(proposed_at))
+        .col_expr(trade::Column::ExpiresAt, Expr::value(expires_at))
+        .filter(trade::Column::Id.eq(database_trade.id))
+        .filter(trade::Column::Status.eq(status.as_str()))
+        .exec(&**db)
+        .await?;
+    if updated.rows_affected == 0 {
+        return Err(TradeError::Conflict);
+    }
+
+    find_trade(&**db, trade_id).await
+}
+
+/// Carry out an accepted trade, moving everything in it between the teams'
+/// wallets. Either all of it moves or none of it does, and a team can't give
+/// more than it has. If it doesn't go through, the trade stays accepted so it
+/// can be tried again. The ledger records the player who carried it out.
+pub async fn execute_trade(
+    db: &DBWrapper,
+    trade_id: DatabaseId,
+    team_id: Option<DatabaseId>,
+    initiating_player_id: Option<DatabaseId>,
+) -> Result<trade::Model, TradeError> {
+    let txn = db.begin().await?;
+
+    let database_trade = find_trade(&txn, trade_id).await?;
+    let actor = match team_id {
+        Some(team_id) => trade_actor(&database_trade, team_id)?,
+        None => TradeActor::System,
+    };
+    let status = trade_status(&database_trade);
+    let (next, _) = status.transition(TradeAction::Execute, actor, true)?;
+
+    let updated = trade::Entity::update_many()
+        .col_expr(trade::Column::Status, Expr::value(next.as_str()))
+        .col_expr(trade::Column::UpdatedAt, Expr::value(Utc::now()))
+        .filter(trade::Column::Id.eq(database_trade.id))
+        .filter(trade::Column::Status.eq(status.as_str()))
+        .exec(&txn)
+        .await?;
+    if updated.rows_affected == 0 {
+        return Err(TradeError::Conflict);
+    }
+
+    let items = trade_item::Entity::find()
+        .filter(trade_item::Column::FkTradeId.eq(database_trade.id))
+        .all(&txn)
+        .await?;
+    for item in items {
+        let to_team_id = other_team(&database_trade, item.fk_from_team_id);
+        let entry = LedgerEntry::transfer(
+            team_wallet(&txn, item.fk_from_team_id).await?,
+            team_wallet(&txn, to_team_id).await?,
+            DatabaseId(item.fk_currency_id),
+            item.amount,
+        )
+        .initiated_by(initiating_player_id)
+        .memo(format!("Trade #{}", database_trade.id));
+
+        // Dropping the transaction rolls back what was moved so far
+        match ledger::record(&txn, &entry).await {
+            Ok(_) => {}
+            Err(LedgerError::InsufficientFunds { .. }) => {
+                let team_name = team::Entity::find_by_id(item.fk_from_team_id)
+                    .one(&txn)
+                    .await?
+                    .map(|database_team| database_team.name)
+                    .unwrap_or_default();
+                let currency_name = currency::Entity::find_by_id(item.fk_currency_id)
+                    .one(&txn)
+                    .await?
+                    .map(|database_currency| database_currency.name)
+                    .unwrap_or_default();
+
+                return Err(TradeError::InsufficientFunds {
+                    team: team_name,
+                    currency: currency_name,
+                });
+            }
+            Err(LedgerError::Database(why)) => return Err(why.into()),
+            Err(_) => return Err(TradeError::InvalidAmount),
+        }
+    }
+
+    txn.commit().await?;
+
+    find_trade(&**db, trade_id).await
+}
+
+/// Expire every trade that's still being worked out but has run out of time,
+/// returning the ones that were
+pub async fn expire_trades(db: &DBWrapper) -> Result<Vec<trade::Model>, DbErr> {
+    let open_statuses = [
+        TradeStatus::Draft,
+        TradeStatus::Proposed,
+        TradeStatus::Countered,
+    ]
+    .map(|status| status.as_str());
+
+    let overdue = trade::Entity::find()
+        .filter(trade::Column::Status.is_in(open_statuses))
+        .filter(trade::Column::ExpiresAt.lt(Utc::now()))
+        .all(&**db)
+        .await?;
+
+    let mut expired = Vec::new();
+    for database_trade in overdue {
+        match act_on_trade(db, DatabaseId(database_trade.id), None, TradeAction::Expire).await {
+            Ok(database_trade) => expired.push(database_trade),
+            // Someone got to it first
+            Err(TradeError::NotAllowed { .. } | TradeError::Conflict) => {}
+            Err(TradeError::Database(why)) => return Err(why),
+            Err(why) => log::warn!("Couldn't expire trade {}: {}", database_trade.id, why),
+        }
+    }
+
+    Ok(expired)
+}
+
+/// A trade with the names of everything in it
+#[derive(Debug, Clone)]
+pub struct TradeSummary {
+    pub trade: trade::Model,
+    pub initiating_team: team::Model,
+    pub receiving_team: team::Model,
+    pub items: Vec<(trade_item::Model, currency::Model)>,
+}
+
+impl TradeSummary {
+    pub async fn load(db: &DBWrapper, database_trade: trade::Model) -> Result<Self, TradeError> {
+        let initiating_team = team::Entity::find_by_id(database_trade.fk_initiating_team_id)
+            .one(&**db)
+            .await?
+            .ok_or(TradeError::TeamNotFound)?;
+        let receiving_team = team::Entity::find_by_id(database_trade.fk_receiving_team_id)
+            .one(&**db)
+            .await?
+            .ok_or(TradeError::TeamNotFound)?;
+        let mut items = Vec::new();
+        for item in trade_item::Entity::find()
+            .filter(trade_item::Column::FkTradeId.eq(database_trade.id))
+            .all(&**db)
+            .await?
+        {
+            let database_currency = currency::Entity::find_by_id(item.fk_currency_id)
+                .one(&**db)
+                .await?
+                .ok_or_else(|| TradeError::CurrencyNotFound(item.fk_currency_id.to_string()))?;
+            items.push((item, database_currency));
+        }
+
+        Ok(Self {
+            trade: database_trade,
+            initiating_team,
+            receiving_team,
+            items,
+        })
+    }
+
+    fn team(&self, team_id: i32) -> &team::Model {
+        if team_id == self.initiating_team.id {
+            &self.initiating_team
+        } else {
+            &self.receiving_team
+        }
+    }
+
+    /// The buttons a team gets for the trade, which depend on whose turn it is
+    pub fn buttons(
+        &self,
+        guild_id: DiscordId,
+        team_id: i32,
+    ) -> Vec<MessageComponent<CreateButton>> {
+        let Ok(actor) = trade_actor(&self.trade, DatabaseId(team_id)) else {
+            return Vec::new();
+        };
+
+        let actions: Vec<(TradeAction, &str, ButtonStyle)> =
+            match (trade_status(&self.trade), actor) {
+                (TradeStatus::Draft, TradeActor::Awaited) => vec![
+                    (TradeAction::Send, "Send", ButtonStyle::Primary),
+                    (TradeAction::Cancel, "Cancel", ButtonStyle::Danger),
+                ],
+                (TradeStatus::Proposed | TradeStatus::Countered, TradeActor::Awaited) => vec![
+                    (TradeAction::Accept, "Accept", ButtonStyle::Success),
+                    (TradeAction::Counter, "Counter", ButtonStyle::Secondary),
+                    (TradeAction::Cancel, "Decline", ButtonStyle::Danger),
+                ],
+                (TradeStatus::Proposed | TradeStatus::Countered, TradeActor::Waiting) => {
+                    vec![(TradeAction::Cancel, "Withdraw", ButtonStyle::Danger)]
+                }
+                (TradeStatus::Accepted, _) => vec![
+                    (TradeAction::Execute, "Try again", ButtonStyle::Primary),
+                    (TradeAction::Cancel, "Cancel", ButtonStyle::Danger),
+                ],
+                _ => vec![],
+            };
+
+        actions
+            .into_iter()
+            .map(|(action, label, style)| {
+                MessageComponent::new(
+                    CreateButton::new("").style(style).label(label),
+                    Some(MessageData::Function(MechanicFunction::Trade(
+                        TradeMechanicsHandler {
+                            guild_id,
+                            task: TradeJobs::Act {
+                                trade_id: DatabaseId(self.trade.id),
+                                action,
+                            },
+                        },
+                    ))),
+                )
+            })
+            .collect()
+    }
+}
+
+impl fmt::Display for TradeSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Trade #{} between {} and {} ({})",
+            self.trade.id, self.initiating_team.name, self.receiving_team.name, self.trade.status
+        )?;
+
+        for giving_team in [&self.initiating_team, &self.receiving_team] {
+            let given: Vec<String> = self
+                .items
+                .iter()
+                .filter(|(item, _)| item.fk_from_team_id == giving_team.id)
+                .map(|(item, database_currency)| {
+                    format!("{} {}", item.amount, database_currency.name)
+                })
+                .collect();
+
+            if given.is_empty() {
+                writeln!(f, "{} gives nothing", giving_team.name)?;
+            } else {
+                writeln!(f, "{} gives {}", giving_team.name, given.join(", "))?;
+            }
+        }
+
+        if let Some(awaiting) = self.trade.fk_awaiting_team_id {
+            writeln!(f, "Waiting on {}", self.team(awaiting).name)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Tell the teams in a trade what happened to it, with the buttons each of
+/// them can press now. Drafts are only shown to the team working on them.
+pub async fn post_trade_update(
+    db: &DBWrapper,
+    guild_id: DiscordId,
+    summary: &TradeSummary,
+    note: &str,
+) {
+    let is_draft = trade_status(&summary.trade) == TradeStatus::Draft;
+
+    for database_team in [&summary.initiating_team, &summary.receiving_team] {
+        if is_draft && summary.trade.fk_awaiting_team_id != Some(database_team.id) {
+            continue;
+        }
+
+        let Some(channel_id) = database_team
+            .fk_trade_channel_id
+            .or(database_team.fk_menu_channel_id)
+            .or(database_team.fk_general_channel_id)
+        else {
+            log::warn!("Team {} has no channel to tell about trades", database_team.name);
+            continue;
+        };
+
+        db.add_task(
+            NewTask::new(
+                SendChannelMessage {
+                    channel_id: DiscordId::from(channel_id),
+                    message: format!("{}\n{}", note, summary),
+                    buttons: summary.buttons(guild_id, database_team.id),
+                    ..Default::default()
+                }
+                .into_task(guild_id),
+            )
+            .priority(TaskPriority::Interactive),
+        )
+        .await;
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TradeMechanicsHandler {
+    pub guild_id: DiscordId,
+    pub task: TradeJobs,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum TradeJobs {
+    /// Start a trade with a team, on behalf of whoever pressed the button's
+    /// team
+    Start { receiving_team_id: DatabaseId },
+    Act {
+        trade_id: DatabaseId,
+        action: TradeAction,
+    },
+}
+
+#[async_trait]
+impl MechanicHandler for TradeMechanicsHandler {
+    async fn handle(&self, handler: MechanicHandlerWrapper) {
+        let Some(interaction) = &handler.interaction else {
+            log::warn!("Trades can only be changed with their buttons");
+            return;
+        };
+
+        // Recorded on the ledger if the trade goes through
+        let player_id = get_guild_player(
+            &handler.db,
+            self.guild_id,
+            DiscordId::from(interaction.user.id),
+        )
+        .await
+        .ok()
+        .flatten()
+        .map(|database_player| DatabaseId(database_player.id));
+
+        let result = match get_player_team(
+            &*handler.discord,
+            handler.db.clone(),
+            self.guild_id,
+            DiscordId::from(interaction.user.id),
+        )
+        .await
+        {
+            Ok(database_team) => match &self.task {
+                TradeJobs::Start { receiving_team_id } => {
+                    self.start(&handler, &database_team, *receiving_team_id)
+                        .await
+                }
+                TradeJobs::Act { trade_id, action } => {
+                    self.act(&handler, &database_team, player_id, *trade_id, *action)
+                        .await
+                }
+            },
+            Err(why) => Err(why.into()),
+        };
+
+        if let Err(why) = result {
+            let _message_create_status = handler
+                .run(
+                    self.guild_id,
+                    SendChannelMessage {
+                        channel_id: DiscordId::from(interaction.channel_id),
+                        message: why.to_string(),
+                        ..Default::default()
+                    },
+                )
+                .await;
+        }
+    }
+}
+
+impl TradeMechanicsHandler {
+    async fn start(
+        &self,
+        handler: &MechanicHandlerWrapper,
+        database_team: &team::Model,
+        receiving_team_id: DatabaseId,
+    ) -> Result<(), TradeError> {
+        let database_trade = start_trade(
+            &handler.db,
+            self.guild_id,
+            DatabaseId(database_team.id),
+            receiving_team_id,
+        )
+        .await?;
+
+        let summary = TradeSummary::load(&handler.db, database_trade).await?;
+        post_trade_update(
+            &handler.db,
+            self.guild_id,
+            &summary,
+            &format!(
+                "Started a trade with {}. Add what each team gives with `/trade item`, then press Send.",
+                summary.receiving_team.name
+            ),
+        )
+        .await;
+
+        Ok(())
+    }
+
+    async fn act(
+        &self,
+        handler: &MechanicHandlerWrapper,
+        database_team: &team::Model,
+        player_id: Option<DatabaseId>,
+        trade_id: DatabaseId,
+        action: TradeAction,
+    ) -> Result<(), TradeError> {
+        let team_id = DatabaseId(database_team.id);
+        let mut database_trade = match action {
+            TradeAction::Execute => {
+                execute_trade(&handler.db, trade_id, Some(team_id), player_id).await?
+            }
+            _ => act_on_trade(&handler.db, trade_id, Some(team_id), action).await?,
+        };
+
+        let mut note = match action {
+            TradeAction::Edit => return Ok(()),
+            TradeAction::Send => format!("{} sent an offer", database_team.name),
+            TradeAction::Counter => format!(
+                "{} is working on a counter offer. Change it with `/trade item`, then press Send.",
+                database_team.name
+            ),
+            TradeAction::Accept => format!("{} accepted the trade", database_team.name),
+            TradeAction::Cancel => format!("{} called off the trade", database_team.name),
+            TradeAction::Execute => "The trade went through".to_string(),
+            TradeAction::Expire => "The trade expired".to_string(),
+        };
+
+        // Accepting a trade carries it out straight away
+        if action == TradeAction::Accept {
+            match execute_trade(&handler.db, trade_id, Some(team_id), player_id).await {
+                Ok(executed) => {
+                    database_trade = executed;
+                    note.push_str(", and it went through");
+                }
+                Err(why) => note.push_str(&format!(", but it couldn't go through: {}", why)),
+            }
+        }
+
+        let summary = TradeSummary::load(&handler.db, database_trade).await?;
+        post_trade_update(&handler.db, self.guild_id, &summary, &note).await;
+
+        Ok(())
+    }
+}
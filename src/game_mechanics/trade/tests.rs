@@ -0,0 +1,249 @@
+use chrono::{Duration, Utc};
+use entity::entities::{currency, trade};
+use sea_orm::{ActiveModelTrait, EntityTrait, Set};
+
+use crate::{
+    db_wrapper::ledger::team_wallet,
+    game_mechanics::trade::{
+        act_on_trade, expire_trades, set_trade_item, start_trade, TradeAction, TradeActor,
+        TradeError, TradeStatus, Turn,
+    },
+    task_runner::tasks::{test_helpers::TestHelpers, DatabaseId},
+};
+
+async fn mint(test_helper: &TestHelpers, team_id: DatabaseId, currency_id: i32, amount: i64) {
+    let wallet_id = team_wallet(&*test_helper.db, *team_id).await.unwrap();
+    test_helper
+        .db
+        .mint(wallet_id, DatabaseId(currency_id), amount, None)
+        .await
+        .unwrap();
+}
+
+async fn balance(test_helper: &TestHelpers, team_id: DatabaseId, currency_id: i32) -> i64 {
+    let wallet_id = team_wallet(&*test_helper.db, *team_id).await.unwrap();
+    test_helper
+        .db
+        .balances(wallet_id)
+        .await
+        .unwrap()
+        .get(&currency_id)
+        .copied()
+        .unwrap_or(0)
+}
+
+/// Two teams with a proposed trade where the first gives 30 of a currency it
+/// has `starting` of
+async fn proposed_trade(
+    test_helper: &TestHelpers,
+    starting: i64,
+) -> (DatabaseId, DatabaseId, currency::Model, trade::Model) {
+    let first_team = DatabaseId(
+        test_helper
+            .create_team(&TestHelpers::generate_name())
+            .await
+            .id,
+    );
+    let second_team = DatabaseId(
+        test_helper
+            .create_team(&TestHelpers::generate_name())
+            .await
+            .id,
+    );
+    let database_currency = test_helper.create_currency().await;
+    mint(test_helper, first_team, database_currency.id, starting).await;
+
+    let database_trade = start_trade(
+        &test_helper.db,
+        test_helper.guild_id,
+        first_team,
+        second_team,
+    )
+    .await
+    .unwrap();
+    let trade_id = DatabaseId(database_trade.id);
+
+    set_trade_item(
+        &test_helper.db,
+        trade_id,
+        first_team,
+        true,
+        &database_currency.name,
+        30,
+    )
+    .await
+    .unwrap();
+    let database_trade = act_on_trade(
+        &test_helper.db,
+        trade_id,
+        Some(first_team),
+        TradeAction::Send,
+    )
+    .await
+    .unwrap();
+
+    (first_team, second_team, database_currency, database_trade)
+}
+
+#[test]
+fn should_follow_trade_transitions() {
+    assert_eq!(
+        TradeStatus::Draft
+            .transition(TradeAction::Send, TradeActor::Awaited, false)
+            .ok(),
+        Some((TradeStatus::Proposed, Turn::Other))
+    );
+    assert_eq!(
+        TradeStatus::Draft
+            .transition(TradeAction::Send, TradeActor::Awaited, true)
+            .ok(),
+        Some((TradeStatus::Countered, Turn::Other))
+    );
+    assert_eq!(
+        TradeStatus::Proposed
+            .transition(TradeAction::Counter, TradeActor::Awaited, true)
+            .ok(),
+        Some((TradeStatus::Draft, Turn::Same))
+    );
+    assert_eq!(
+        TradeStatus::Countered
+            .transition(TradeAction::Cancel, TradeActor::Waiting, true)
+            .ok(),
+        Some((TradeStatus::Cancelled, Turn::Nobody))
+    );
+
+    // Only the team whose turn it is can accept
+    assert!(TradeStatus::Proposed
+        .transition(TradeAction::Accept, TradeActor::Waiting, true)
+        .is_err());
+    // Finished trades stay finished
+    assert!(TradeStatus::Executed
+        .transition(TradeAction::Cancel, TradeActor::Awaited, true)
+        .is_err());
+    // Teams can't expire trades
+    assert!(TradeStatus::Draft
+        .transition(TradeAction::Expire, TradeActor::Awaited, false)
+        .is_err());
+}
+
+#[tokio::test]
+async fn should_move_funds_when_accepted() {
+    let test_helper = TestHelpers::new().await;
+
+    let (first_team, second_team, database_currency, database_trade) =
+        proposed_trade(&test_helper, 100).await;
+    let trade_id = DatabaseId(database_trade.id);
+    assert_eq!(database_trade.status, TradeStatus::Proposed.as_str());
+    assert_eq!(database_trade.fk_awaiting_team_id, Some(*second_team));
+
+    // The team that sent the offer has to wait
+    let result = act_on_trade(
+        &test_helper.db,
+        trade_id,
+        Some(first_team),
+        TradeAction::Accept,
+    )
+    .await;
+    assert!(matches!(result, Err(TradeError::NotAllowed { .. })));
+
+    act_on_trade(
+        &test_helper.db,
+        trade_id,
+        Some(second_team),
+        TradeAction::Accept,
+    )
+    .await
+    .unwrap();
+    let database_trade = act_on_trade(
+        &test_helper.db,
+        trade_id,
+        Some(second_team),
+        TradeAction::Execute,
+    )
+    .await
+    .unwrap();
+    assert_eq!(database_trade.status, TradeStatus::Executed.as_str());
+
+    assert_eq!(
+        balance(&test_helper, first_team, database_currency.id).await,
+        70
+    );
+    assert_eq!(
+        balance(&test_helper, second_team, database_currency.id).await,
+        30
+    );
+
+    test_helper.cleanup().await;
+}
+
+#[tokio::test]
+async fn should_not_overdraw_a_team() {
+    let test_helper = TestHelpers::new().await;
+
+    let (first_team, second_team, database_currency, database_trade) =
+        proposed_trade(&test_helper, 10).await;
+    let trade_id = DatabaseId(database_trade.id);
+
+    act_on_trade(
+        &test_helper.db,
+        trade_id,
+        Some(second_team),
+        TradeAction::Accept,
+    )
+    .await
+    .unwrap();
+    let result = act_on_trade(
+        &test_helper.db,
+        trade_id,
+        Some(second_team),
+        TradeAction::Execute,
+    )
+    .await;
+    assert!(matches!(result, Err(TradeError::InsufficientFunds { .. })));
+
+    // Nothing moved, and the trade can be tried again
+    assert_eq!(
+        balance(&test_helper, first_team, database_currency.id).await,
+        10
+    );
+    assert_eq!(
+        balance(&test_helper, second_team, database_currency.id).await,
+        0
+    );
+    let database_trade = trade::Entity::find_by_id(*trade_id)
+        .one(&*test_helper.db)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(database_trade.status, TradeStatus::Accepted.as_str());
+
+    test_helper.cleanup().await;
+}
+
+#[tokio::test]
+async fn should_expire_old_trades() {
+    let test_helper = TestHelpers::new().await;
+
+    let (_, second_team, _, database_trade) = proposed_trade(&test_helper, 100).await;
+    let trade_id = DatabaseId(database_trade.id);
+
+    let mut overdue: trade::ActiveModel = database_trade.into();
+    overdue.expires_at = Set(Some((Utc::now() - Duration::hours(1)).into()));
+    overdue.update(&*test_helper.db).await.unwrap();
+
+    let expired = expire_trades(&test_helper.db).await.unwrap();
+    assert!(expired
+        .iter()
+        .any(|database_trade| database_trade.id == *trade_id));
+
+    let result = act_on_trade(
+        &test_helper.db,
+        trade_id,
+        Some(second_team),
+        TradeAction::Accept,
+    )
+    .await;
+    assert!(matches!(result, Err(TradeError::NotAllowed { .. })));
+
+    test_helper.cleanup().await;
+}
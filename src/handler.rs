@@ -1,9 +1,25 @@
 use crate::{
-    commands::{fake_trade::FakeTrade, initialize_game::InitializeGame, nuke::Nuke},
-    db_wrapper::DBWrapper,
+    commands::{
+        bank::BankCommand, export::ExportGame, import::ImportGame, initialize_game::InitializeGame,
+        nuke::Nuke, reconcile::ReconcileGuild, trade::TradeCommand, wallet::WalletCommand,
+    },
+    db_wrapper::{
+        payload::{read_payload, PayloadKind},
+        retention::RetentionPolicy,
+        DBWrapper,
+    },
+    discord::{DiscordChannel, DiscordMember, DiscordRole, SerenityBackend},
     game_mechanics::MechanicHandlerWrapper,
     task_runner::{
-        tasks::{message::message_component::MessageData, run_tests},
+        schedule::{NewTask, TaskPriority},
+        tasks::{
+            maintenance::{
+                MaintenanceHandler, MaintenanceTasks, EXPIRE_TRADES_INTERVAL, RETENTION_INTERVAL,
+            },
+            message::message_component::{mark_components_sent, MessageData},
+            reconcile::{ReconcileHandler, RECONCILE_INTERVAL},
+            DiscordId, TaskType,
+        },
         TaskRunner,
     },
 };
@@ -11,35 +27,83 @@ use crate::{
 use crate::commands::GameCommand;
 
 use entity::entities::message_component_data;
-use sea_orm::EntityTrait;
+use sea_orm::{DbErr, EntityTrait};
 use serenity::{
     all::{ComponentInteractionDataKind, Interaction},
     async_trait,
-    builder::{CreateInteractionResponse, CreateInteractionResponseMessage},
-    model::{gateway::Ready, id::GuildId},
+    builder::{
+        CreateActionRow, CreateAttachment, CreateInteractionResponse,
+        CreateInteractionResponseMessage, EditInteractionResponse,
+    },
+    model::{
+        channel::{Channel, GuildChannel, Message},
+        gateway::Ready,
+        guild::{Member, Role},
+        id::{GuildId, RoleId},
+    },
     prelude::*,
 };
 use std::{
-    sync::atomic::{AtomicBool, Ordering},
-    time::Duration,
+    num::NonZeroU64,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
 };
 use tracing::{info, log};
 
 pub struct Handler {
     pub is_loop_running: AtomicBool,
-    pub run_tests: bool,
+    pub task_workers: usize,
+    /// Only register commands in this guild, if it's set
+    pub test_guild_id: Option<NonZeroU64>,
+    pub retention: RetentionPolicy,
     pub db: DBWrapper,
 }
 
+impl Handler {
+    /// Look up the data stored for a message component, upgrading it if it
+    /// was written by an older version
+    async fn get_component_data(&self, custom_id: &str) -> Result<Option<MessageData>, String> {
+        let id = uuid::Uuid::parse_str(custom_id).map_err(|why| why.to_string())?;
+
+        let component_data = message_component_data::Entity::find_by_id(id)
+            .one(&*self.db)
+            .await
+            .map_err(|why| why.to_string())?
+            .ok_or_else(|| "There's no data for this component".to_string())?;
+
+        read_payload(
+            PayloadKind::Component,
+            component_data.payload_version,
+            component_data.payload,
+        )
+        .map_err(|why| why.to_string())
+    }
+
+    /// Gateway events are only told about once, so there's nothing to do
+    /// about a failed sync other than leave it for a reconcile to find
+    fn log_sync_error(what: String, result: Result<(), DbErr>) {
+        if let Err(why) = result {
+            log::warn!("Couldn't sync {}: {}", what, why);
+        }
+    }
+}
+
 #[async_trait]
 impl EventHandler for Handler {
     async fn interaction_create(&self, ctx: Context, interaction: Interaction) {
         match interaction {
             Interaction::Command(command) => {
                 let command_handler = match command.data.name.as_str() {
-                    "trade" => FakeTrade::run,
+                    "trade" => TradeCommand::run,
+                    "wallet" => WalletCommand::run,
+                    "bank" => BankCommand::run,
                     "initialize" => InitializeGame::run,
                     "reset" => Nuke::run,
+                    "reconcile" => ReconcileGuild::run,
+                    "export" => ExportGame::run,
+                    "import" => ImportGame::run,
                     _ => unreachable!(),
                 };
 
@@ -55,27 +119,82 @@ impl EventHandler for Handler {
                     info!("Cannot respond to slash command: {}", why);
                 }
 
-                let _content = command_handler(
+                let response = command_handler(
                     &command.data.options(),
                     command.guild_id.unwrap(),
+                    command.user.id,
                     self.db.clone(),
                     ctx.clone(),
                 )
                 .await;
+
+                let mut edit = EditInteractionResponse::new().content(response.truncated_content());
+
+                // Save the data behind any buttons
+                let mut buttons = Vec::new();
+                let mut component_ids = Vec::new();
+                for button in response.buttons {
+                    match button.build(self.db.clone()).await {
+                        Ok((button, id)) => {
+                            buttons.push(button);
+                            component_ids.push(id);
+                        }
+                        Err(why) => log::error!("Couldn't save a button: {}", why),
+                    }
+                }
+                if !buttons.is_empty() {
+                    edit = edit.components(vec![CreateActionRow::Buttons(buttons)]);
+                }
+                if let Some(attachment) = response.attachment {
+                    edit = edit.new_attachment(CreateAttachment::bytes(
+                        attachment.data,
+                        attachment.filename,
+                    ));
+                }
+
+                match command.edit_response(&ctx.http, edit).await {
+                    Ok(message) => {
+                        if let Err(why) = mark_components_sent(
+                            self.db.clone(),
+                            component_ids,
+                            DiscordId::from(command.channel_id),
+                            DiscordId(message.id.0.get()),
+                        )
+                        .await
+                        {
+                            log::error!("Couldn't mark buttons as sent: {}", why);
+                        }
+                    }
+                    Err(why) => info!("Cannot edit slash command response: {}", why),
+                }
             }
             Interaction::Component(component) => {
-                // Get the payload of the custom_id
-                let payload = message_component_data::Entity::find_by_id(
-                    uuid::Uuid::parse_str(&component.data.custom_id).unwrap(),
-                )
-                .one(&*self.db)
-                .await
-                .unwrap()
-                .unwrap()
-                .payload;
+                // Get the data behind the custom_id
+                let task = match self.get_component_data(&component.data.custom_id).await {
+                    Ok(task) => task,
+                    Err(why) => {
+                        log::warn!(
+                            "Can't handle component {}: {}",
+                            component.data.custom_id,
+                            why
+                        );
 
-                // Deserialize the payload
-                let task = *serde_json::from_value::<Box<Option<MessageData>>>(payload).unwrap();
+                        if let Err(why) = component
+                            .create_response(
+                                &ctx.http,
+                                CreateInteractionResponse::Message(
+                                    CreateInteractionResponseMessage::new()
+                                        .content("That interaction is no longer valid!")
+                                        .ephemeral(true),
+                                ),
+                            )
+                            .await
+                        {
+                            info!("Cannot respond to slash command: {}", why);
+                        }
+                        return;
+                    }
+                };
 
                 // The task might be none, in which case return
                 if task.is_none() {
@@ -103,14 +222,20 @@ impl EventHandler for Handler {
                     // it's a function, run it
                     match task {
                         MessageData::Task(task_type) => {
-                            let _ = self.db.add_await_task(task_type).await;
+                            // A player is waiting on this
+                            let _ = self
+                                .db
+                                .add_await_task(
+                                    NewTask::new(task_type).priority(TaskPriority::Interactive),
+                                )
+                                .await;
                         }
                         MessageData::Function(mechanic_function) => {
                             mechanic_function
                                 .handle(MechanicHandlerWrapper {
                                     db: self.db.clone(),
                                     interaction: Some(component),
-                                    ctx: ctx.clone(),
+                                    discord: Arc::new(SerenityBackend::new(ctx.clone())),
                                 })
                                 .await;
                         }
@@ -121,17 +246,101 @@ impl EventHandler for Handler {
         }
     }
 
+    async fn channel_create(&self, _ctx: Context, channel: GuildChannel) {
+        Self::log_sync_error(
+            format!("created channel {}", channel.id),
+            self.db
+                .sync_channel_create(
+                    DiscordId::from(channel.guild_id),
+                    &DiscordChannel::from(&channel),
+                )
+                .await,
+        );
+    }
+
+    async fn channel_update(&self, _ctx: Context, _old: Option<Channel>, new: Channel) {
+        // Only guild channels are tracked
+        if let Some(channel) = new.guild() {
+            Self::log_sync_error(
+                format!("changed channel {}", channel.id),
+                self.db.sync_channel(&DiscordChannel::from(&channel)).await,
+            );
+        }
+    }
+
+    async fn channel_delete(
+        &self,
+        _ctx: Context,
+        channel: GuildChannel,
+        _messages: Option<Vec<Message>>,
+    ) {
+        Self::log_sync_error(
+            format!("deleted channel {}", channel.id),
+            self.db
+                .sync_channel_delete(&DiscordChannel::from(&channel))
+                .await,
+        );
+    }
+
+    async fn guild_role_create(&self, _ctx: Context, new: Role) {
+        Self::log_sync_error(
+            format!("created role {}", new.id),
+            self.db.sync_role_create(&DiscordRole::from(&new)).await,
+        );
+    }
+
+    async fn guild_role_update(&self, _ctx: Context, _old: Option<Role>, new: Role) {
+        Self::log_sync_error(
+            format!("changed role {}", new.id),
+            self.db.sync_role(&DiscordRole::from(&new)).await,
+        );
+    }
+
+    async fn guild_role_delete(
+        &self,
+        _ctx: Context,
+        _guild_id: GuildId,
+        removed_role_id: RoleId,
+        _removed_role: Option<Role>,
+    ) {
+        Self::log_sync_error(
+            format!("deleted role {}", removed_role_id),
+            self.db
+                .sync_role_delete(DiscordId::from(removed_role_id))
+                .await,
+        );
+    }
+
+    async fn guild_member_update(&self, _ctx: Context, _old: Option<Member>, new: Member) {
+        Self::log_sync_error(
+            format!("member {}", new.user.id),
+            self.db
+                .sync_member(DiscordId::from(new.guild_id), &DiscordMember::from(&new))
+                .await,
+        );
+    }
+
     async fn ready(&self, ctx: Context, ready: Ready) {
         info!("{} is connected!", ready.user.name);
 
-        for guild in ctx.cache.guilds().iter() {
+        let guilds = match self.test_guild_id {
+            Some(test_guild_id) => vec![GuildId(test_guild_id)],
+            None => ctx.cache.guilds(),
+        };
+
+        for guild in guilds.iter() {
             GuildId(guild.0)
                 .set_application_commands(
                     &ctx.http,
                     vec![
-                        FakeTrade::register(),
+                        TradeCommand::register(),
+                        WalletCommand::register(),
+                        BankCommand::register(),
                         InitializeGame::register(),
                         Nuke::register(),
+                        ReconcileGuild::register(),
+                        ExportGame::register(),
+                        ImportGame::register(),
                     ],
                 )
                 .await
@@ -145,30 +354,80 @@ impl EventHandler for Handler {
         if !self.is_loop_running.load(Ordering::Relaxed) {
             let db_clone = self.db.clone();
             let ctx_clone = ctx.clone();
+            let task_workers = self.task_workers;
             tokio::spawn(async move {
-                let runner = TaskRunner {
-                    ctx: ctx_clone,
-                    db: db_clone,
-                };
+                let runner = TaskRunner::new(ctx_clone, db_clone, task_workers);
 
                 // // Seed an example test
                 // runner.sample_tasks().await;
 
                 loop {
                     runner.run_tasks().await;
-                    tokio::time::sleep(Duration::from_millis(100)).await;
+                    runner.wait_for_tasks().await;
                 }
             });
 
-            // If the testing flag is active, start a thread and run the tests
+            // Clean up old tasks and components every so often
             let db_clone = self.db.clone();
-            if self.run_tests {
-                tokio::spawn(async move {
-                    run_tests(ctx.clone(), db_clone).await;
-                    // Log test complete
-                    log::info!("Tests complete");
-                });
-            }
+            let retention = self.retention.clone();
+            tokio::spawn(async move {
+                let mut interval = tokio::time::interval(RETENTION_INTERVAL);
+
+                loop {
+                    interval.tick().await;
+
+                    db_clone
+                        .add_task(
+                            NewTask::new(TaskType::MaintenanceHandler(MaintenanceHandler {
+                                task: MaintenanceTasks::Retention(retention.clone()),
+                            }))
+                            .priority(TaskPriority::Bulk),
+                        )
+                        .await;
+                }
+            });
+
+            // Expire trades that have been left alone for too long
+            let db_clone = self.db.clone();
+            tokio::spawn(async move {
+                let mut interval = tokio::time::interval(EXPIRE_TRADES_INTERVAL);
+
+                loop {
+                    interval.tick().await;
+
+                    db_clone
+                        .add_task(
+                            NewTask::new(TaskType::MaintenanceHandler(MaintenanceHandler {
+                                task: MaintenanceTasks::ExpireTrades,
+                            }))
+                            .priority(TaskPriority::Bulk),
+                        )
+                        .await;
+                }
+            });
+
+            // Check every guild for drift every so often, only reporting it
+            let db_clone = self.db.clone();
+            let ctx_clone = ctx.clone();
+            tokio::spawn(async move {
+                let mut interval = tokio::time::interval(RECONCILE_INTERVAL);
+
+                loop {
+                    interval.tick().await;
+
+                    for guild_id in ctx_clone.cache.guilds() {
+                        db_clone
+                            .add_task(
+                                NewTask::new(TaskType::ReconcileHandler(ReconcileHandler {
+                                    guild_id: DiscordId::from(guild_id),
+                                    repair: false,
+                                }))
+                                .priority(TaskPriority::Bulk),
+                            )
+                            .await;
+                    }
+                }
+            });
 
             // Now that the loop is running, we set the bool to true
             self.is_loop_running.swap(true, Ordering::Relaxed);
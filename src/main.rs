@@ -2,32 +2,52 @@
 
 use clap::Parser;
 
-use db_wrapper::DBWrapper;
+use config::Config;
+use db_wrapper::{retention::RetentionPolicy, DBWrapper};
+use game_mechanics::scenario::ScenarioDirectory;
 use handler::Handler;
 
-use eyre::Result;
+use eyre::{eyre, Result};
 use migration::{Migrator, MigratorTrait};
 use sea_orm::{prelude::*, Database};
 use serenity::{all::ApplicationId, prelude::*};
-use std::{env, num::NonZeroU64, sync::atomic::AtomicBool};
-use tracing::{info, Level};
+use std::{env, path::PathBuf, sync::atomic::AtomicBool, time::Duration};
+use tracing::{error, info, warn, Level};
 use tracing_subscriber::EnvFilter;
 
 pub mod commands;
+pub mod config;
 pub mod console;
 pub mod db_wrapper;
+pub mod discord;
 pub mod game_mechanics;
 pub mod handler;
 pub mod task_runner;
 
-pub const TEST_GUILD_ID: u64 = 345993194322001923;
-
 #[derive(Parser, Debug)]
 #[clap(author, version, about, long_about = None)]
 struct Args {
-    /// Test flag
-    #[clap(short, long)]
-    test: bool,
+    /// The config file to read. Without this, `MEGAGAME_CONFIG` or
+    /// `megagame.toml` is used if there is one.
+    #[clap(long)]
+    config: Option<PathBuf>,
+
+    /// How many tasks can run at the same time, overriding the config
+    #[clap(long)]
+    task_workers: Option<usize>,
+
+    /// How many days to keep finished tasks for
+    #[clap(long, default_value_t = 7)]
+    keep_tasks_days: u64,
+
+    /// Move old tasks to the archive table instead of deleting them
+    #[clap(long)]
+    archive_tasks: bool,
+
+    /// Report any queued tasks or message components whose payload can't be
+    /// read anymore, then exit
+    #[clap(long)]
+    check_payloads: bool,
 }
 
 #[tokio::main]
@@ -58,36 +78,18 @@ async fn main() -> Result<()> {
     // tui_logger::set_level_for_target("tokio_tungstenite",
     // log::LevelFilter::Off);
 
-    let append_info = |mut f: EnvFilter, list: &[&str], level: &str| {
-        for l in list {
-            f = f.add_directive(format!("{}={}", l, level).parse().unwrap());
-        }
-        f
-    };
+    let args = Args::parse();
+
+    let mut config = Config::load(args.config.as_deref())?;
+    if let Some(task_workers) = args.task_workers {
+        config.task_workers = task_workers;
+        config.validate()?;
+    }
 
-    let list = &[
-        "tokio_util",
-        "h2",
-        "rustls",
-        "serenity",
-        "tungstenite",
-        "async_tungstenite",
-        "hyper",
-        "trust_dns_resolver",
-        "trust_dns_proto",
-        "reqwest",
-        "mio",
-        "want",
-        "kube",
-        "tower",
-        "tokio_tungstenite",
-    ];
-
-    let off_list = &["sqlx"];
-
-    let filter = EnvFilter::from_default_env();
-    let filter = append_info(filter.add_directive(Level::TRACE.into()), off_list, "off");
-    let filter = append_info(filter.add_directive(Level::TRACE.into()), list, "info");
+    let filter = config.log_directives().into_iter().fold(
+        EnvFilter::from_default_env().add_directive(Level::TRACE.into()),
+        |filter, directive| filter.add_directive(directive),
+    );
 
     tracing_subscriber::FmtSubscriber::builder()
         .with_max_level(Level::TRACE)
@@ -95,31 +97,74 @@ async fn main() -> Result<()> {
         .try_init()
         .unwrap();
 
-    // Configure the client with your Discord bot token in the environment.
-    let token = env::var("DISCORD_TOKEN").expect("Expected a token in the environment");
-
-    let args = Args::parse();
-
     let gateway_intents = GatewayIntents::all();
 
-    let db: DatabaseConnection =
-        match Database::connect("postgres://postgres:postgres@db/postgres").await {
-            Ok(db) => db,
-            Err(err) => panic!("Error connecting to database: {:?}", err),
-        };
+    let db: DatabaseConnection = match Database::connect(config.database_url.as_str()).await {
+        Ok(db) => db,
+        Err(err) => panic!("Error connecting to database: {:?}", err),
+    };
 
     let db_wrapper = DBWrapper::new(db.clone());
 
-        // Run any migrations
-        Migrator::up(&db, None).await?;
+    // Run any migrations
+    Migrator::up(&db, None).await?;
+
+    // Check the stored payloads instead of starting the bot
+    if args.check_payloads {
+        let bad_payloads = db_wrapper.check_payloads().await?;
+
+        for bad_payload in &bad_payloads {
+            warn!(
+                "{} {}: {}",
+                bad_payload.table, bad_payload.id, bad_payload.error
+            );
+        }
+
+        info!("Found {} payloads that can't be read", bad_payloads.len());
+
+        return Ok(());
+    }
+
+    // Don't start with settings that would make tasks fail later on
+    let bad_guild_settings = db_wrapper.check_guild_settings().await?;
+    for (guild_id, why) in &bad_guild_settings {
+        error!(
+            "The settings of guild {} can't be used: {}",
+            guild_id.0, why
+        );
+    }
+    if !bad_guild_settings.is_empty() {
+        return Err(eyre!(
+            "{} guilds have settings that can't be used",
+            bad_guild_settings.len()
+        ));
+    }
+
+    let application_id = config.application_id()?;
+
+    // Configure the client with your Discord bot token in the environment.
+    let token = env::var("DISCORD_TOKEN").expect("Expected a token in the environment");
+
+    // Listen for task notifications so nothing has to poll the task table
+    let listener_db = db_wrapper.clone();
+    tokio::spawn(async move {
+        listener_db.listen_for_task_notifications().await;
+    });
 
     // Start the Serenity client in a new Tokio thread
     let serenity_handle = tokio::spawn(async move {
         let mut client = Client::builder(&token, gateway_intents)
-            .application_id(ApplicationId(NonZeroU64::new(451862707746897961).unwrap()))
+            .application_id(ApplicationId(application_id))
+            .type_map_insert::<ScenarioDirectory>(config.scenario_dir)
             .event_handler(Handler {
                 is_loop_running: AtomicBool::new(false),
-                run_tests: args.test,
+                task_workers: config.task_workers,
+                test_guild_id: config.test_guild_id,
+                retention: RetentionPolicy {
+                    keep_tasks_for: Duration::from_secs(args.keep_tasks_days * 24 * 60 * 60),
+                    archive_tasks: args.archive_tasks,
+                    ..Default::default()
+                },
                 db: db_wrapper,
             })
             .await
@@ -0,0 +1,128 @@
+use std::fmt::{self, Display};
+
+use sea_orm::DbErr;
+use serde::{Deserialize, Serialize};
+
+/// Why a task failed, and whether it's worth trying it again
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(from = "StoredTaskError")]
+pub struct TaskError {
+    pub kind: TaskErrorKind,
+    pub message: String,
+}
+
+/// How a `TaskError` can look in the database. Tasks that failed before
+/// errors had a kind only stored the message, and those can't be retried.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StoredTaskError {
+    Structured {
+        kind: TaskErrorKind,
+        message: String,
+    },
+    Message(String),
+}
+
+impl From<StoredTaskError> for TaskError {
+    fn from(stored: StoredTaskError) -> Self {
+        match stored {
+            StoredTaskError::Structured { kind, message } => Self { kind, message },
+            StoredTaskError::Message(message) => Self::permanent(message),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TaskErrorKind {
+    /// Something that might work if tried again later, like a 5xx from
+    /// Discord, a rate limit or a dropped connection
+    Retryable,
+    /// Something that will fail the same way every time, like a missing
+    /// channel or a request Discord rejects
+    Permanent,
+}
+
+impl TaskError {
+    pub fn retryable(message: impl Into<String>) -> Self {
+        Self {
+            kind: TaskErrorKind::Retryable,
+            message: message.into(),
+        }
+    }
+
+    pub fn permanent(message: impl Into<String>) -> Self {
+        Self {
+            kind: TaskErrorKind::Permanent,
+            message: message.into(),
+        }
+    }
+
+    pub fn is_retryable(&self) -> bool {
+        self.kind == TaskErrorKind::Retryable
+    }
+}
+
+impl Display for TaskError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}: {}", self.kind, self.message)
+    }
+}
+
+impl From<serenity::Error> for TaskError {
+    fn from(why: serenity::Error) -> Self {
+        let retryable = match &why {
+            serenity::Error::Http(http_error) => match http_error.status_code() {
+                Some(status) => status.is_server_error() || status.as_u16() == 429,
+                // The request never got a response, e.g. the connection
+                // dropped
+                None => !http_error.is_url_error(),
+            },
+            _ => false,
+        };
+
+        if retryable {
+            TaskError::retryable(why.to_string())
+        } else {
+            TaskError::permanent(why.to_string())
+        }
+    }
+}
+
+impl From<DbErr> for TaskError {
+    fn from(why: DbErr) -> Self {
+        match why {
+            DbErr::Conn(_) => TaskError::retryable(why.to_string()),
+            _ => TaskError::permanent(why.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::db_wrapper::TaskResult;
+
+    #[test]
+    fn should_read_errors_stored_as_a_message() {
+        let status: TaskResult =
+            serde_json::from_value(json!({ "Error": "Missing permissions" })).unwrap();
+
+        let TaskResult::Error(error) = status else {
+            panic!("Expected an error, got {:?}", status);
+        };
+        assert_eq!(error.kind, TaskErrorKind::Permanent);
+        assert_eq!(error.message, "Missing permissions");
+    }
+
+    #[test]
+    fn should_round_trip_errors() {
+        let error = TaskError::retryable("Discord is down");
+        let read: TaskError =
+            serde_json::from_value(serde_json::to_value(&error).unwrap()).unwrap();
+
+        assert_eq!(read.kind, TaskErrorKind::Retryable);
+        assert_eq!(read.message, "Discord is down");
+    }
+}
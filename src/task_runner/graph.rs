@@ -0,0 +1,464 @@
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    marker::PhantomData,
+};
+
+use entity::entities::task;
+use sea_orm::{prelude::*, DatabaseConnection};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{
+    db_wrapper::{TaskResult, TaskReturnData},
+    task_runner::{
+        error::TaskError,
+        tasks::{DatabaseId, DiscordId, TaskType, TypedTask},
+    },
+};
+
+/// Discord ids at or above this are placeholders for the output of another
+/// task in a graph. Real snowflakes are nowhere near this big.
+const PLACEHOLDER_BASE: u64 = u64::MAX - u32::MAX as u64;
+
+/// A set of tasks that are added to the queue together, where a task can wait
+/// for others to complete and use the Discord ids they return.
+///
+/// ```ignore
+/// let mut graph = TaskGraph::new();
+/// let category = graph.add(create_category_task);
+/// let channel = graph.add(TaskType::ChannelHandler(ChannelHandler {
+///     guild_id,
+///     task: ChannelTasks::Create(ChannelCreateData {
+///         name: "general".to_string(),
+///         category_id: Some(graph.discord_id(category)),
+///         kind: ChannelType::Text,
+///     }),
+/// }));
+/// ```
+#[derive(Debug, Default)]
+pub struct TaskGraph {
+    nodes: Vec<TaskGraphNode>,
+    /// Every placeholder handed out by `discord_id`. Only these are filled
+    /// in, so numbers in a payload that happen to be as big aren't mistaken
+    /// for one.
+    placeholders: RefCell<HashSet<u64>>,
+}
+
+#[derive(Debug)]
+pub(crate) struct TaskGraphNode {
+    pub task: TaskType,
+    pub depends_on: Vec<TaskNode>,
+    pub undo: Option<TaskUndo>,
+}
+
+/// How to undo a typed task in a graph once it's completed
+#[derive(Debug, Clone, Copy)]
+pub struct TaskUndo {
+    guild_id: DiscordId,
+    undo: fn(DiscordId, TaskReturnData) -> Option<TaskType>,
+}
+
+impl TaskUndo {
+    /// The task that undoes a completed task, given what it returned
+    pub fn task(&self, data: TaskReturnData) -> Option<TaskType> {
+        (self.undo)(self.guild_id, data)
+    }
+}
+
+/// A task that has been added to a `TaskGraph`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TaskNode(usize);
+
+/// A typed task that has been added to a `TaskGraph`, which remembers the
+/// type of its output
+#[derive(Debug)]
+pub struct TypedTaskNode<T: TypedTask> {
+    pub node: TaskNode,
+    output: PhantomData<fn() -> T>,
+}
+
+impl<T: TypedTask> Clone for TypedTaskNode<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T: TypedTask> Copy for TypedTaskNode<T> {}
+
+impl<T: TypedTask> From<TypedTaskNode<T>> for TaskNode {
+    fn from(node: TypedTaskNode<T>) -> Self {
+        node.node
+    }
+}
+
+impl TaskGraph {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a task to the graph. It will wait for any task whose
+    /// `discord_id` it uses.
+    pub fn add(&mut self, task: TaskType) -> TaskNode {
+        self.add_after(task, &[])
+    }
+
+    /// Add a typed task to the graph, so its output can be read from the
+    /// results without matching on it
+    pub fn add_typed<T: TypedTask>(&mut self, guild_id: DiscordId, task: T) -> TypedTaskNode<T> {
+        let node = self.add(task.into_task(guild_id));
+
+        self.nodes[node.0].undo = Some(TaskUndo {
+            guild_id,
+            undo: |guild_id, data| {
+                T::output(data)
+                    .ok()
+                    .and_then(|output| T::undo(guild_id, &output))
+            },
+        });
+
+        TypedTaskNode {
+            node,
+            output: PhantomData,
+        }
+    }
+
+    /// Add a task that also waits for `depends_on` to complete, even if it
+    /// doesn't use their output
+    pub fn add_after(&mut self, task: TaskType, depends_on: &[TaskNode]) -> TaskNode {
+        self.nodes.push(TaskGraphNode {
+            task,
+            depends_on: depends_on.to_vec(),
+            undo: None,
+        });
+
+        TaskNode(self.nodes.len() - 1)
+    }
+
+    /// A stand-in for the Discord id that `node` returns once it completes.
+    /// This can be used anywhere in a later task, including inside strings
+    /// like mentions, and will be swapped for the real id before that task
+    /// runs.
+    pub fn discord_id(&self, node: impl Into<TaskNode>) -> DiscordId {
+        let placeholder = PLACEHOLDER_BASE + node.into().0 as u64;
+        self.placeholders.borrow_mut().insert(placeholder);
+
+        DiscordId(placeholder)
+    }
+
+    /// How to undo each typed task in the graph, in the order they were added
+    pub fn undos(&self) -> Vec<(TaskNode, TaskUndo)> {
+        self.nodes
+            .iter()
+            .enumerate()
+            .filter_map(|(index, node)| node.undo.map(|undo| (TaskNode(index), undo)))
+            .collect()
+    }
+
+    /// The tasks in the order they were added, and the placeholders that
+    /// were handed out for them
+    pub(crate) fn into_parts(self) -> (Vec<TaskGraphNode>, HashSet<u64>) {
+        (self.nodes, self.placeholders.into_inner())
+    }
+}
+
+/// The database ids of the tasks in a graph once it's been added to the queue
+#[derive(Debug, Clone)]
+pub struct TaskGraphIds(pub(crate) Vec<DatabaseId>);
+
+impl TaskGraphIds {
+    pub fn get(&self, node: impl Into<TaskNode>) -> DatabaseId {
+        self.0[node.into().0]
+    }
+}
+
+/// The results of every task in a graph once they've all finished
+#[derive(Debug, Clone)]
+pub struct TaskGraphResults(pub(crate) Vec<TaskResult>);
+
+impl TaskGraphResults {
+    pub fn get(&self, node: impl Into<TaskNode>) -> &TaskResult {
+        &self.0[node.into().0]
+    }
+
+    /// The output of a typed task in the graph
+    pub fn output<T: TypedTask>(&self, node: TypedTaskNode<T>) -> Result<T::Output, TaskError> {
+        self.get(node).clone().output::<T>()
+    }
+
+    /// Why the first task in the graph that didn't complete failed, if any
+    /// didn't
+    pub fn error(&self) -> Option<TaskError> {
+        self.0.iter().find_map(TaskResult::error)
+    }
+}
+
+/// The tasks that have to complete before a task can run, and how their
+/// outputs are filled into its payload. This is stored with the task.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct TaskDependencies {
+    pub parents: Vec<DatabaseId>,
+    pub bindings: Vec<TaskBinding>,
+}
+
+/// A spot in a task's payload that gets the Discord id returned by `parent`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TaskBinding {
+    pub parent: DatabaseId,
+    /// JSON pointer to the value in the payload
+    pub pointer: String,
+    /// The placeholder id to swap out
+    pub placeholder: u64,
+}
+
+impl TaskDependencies {
+    /// Work out the dependencies of a task in a graph from its serialized
+    /// payload, given the database ids of the tasks added before it. A task
+    /// can only depend on tasks added before it.
+    pub(crate) fn for_node(
+        node: &TaskGraphNode,
+        payload: &Value,
+        placeholders: &HashSet<u64>,
+        earlier_ids: &[DatabaseId],
+    ) -> Result<Self, DbErr> {
+        let mut dependencies = TaskDependencies::default();
+
+        for parent in &node.depends_on {
+            dependencies.add_parent(earlier_parent(earlier_ids, parent.0)?);
+        }
+
+        let mut bindings = Vec::new();
+        find_placeholders(payload, placeholders, String::new(), &mut bindings);
+
+        for (pointer, placeholder) in bindings {
+            let parent = earlier_parent(earlier_ids, (placeholder - PLACEHOLDER_BASE) as usize)?;
+
+            dependencies.add_parent(parent);
+            dependencies.bindings.push(TaskBinding {
+                parent,
+                pointer,
+                placeholder,
+            });
+        }
+
+        Ok(dependencies)
+    }
+
+    fn add_parent(&mut self, parent: DatabaseId) {
+        if !self.parents.contains(&parent) {
+            self.parents.push(parent);
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.parents.is_empty()
+    }
+}
+
+/// The database id of the task at `index` in the graph, which has to have
+/// been added already
+fn earlier_parent(earlier_ids: &[DatabaseId], index: usize) -> Result<DatabaseId, DbErr> {
+    earlier_ids.get(index).copied().ok_or_else(|| {
+        DbErr::Custom(format!(
+            "Task {} in the graph depends on task {}, which wasn't added before it",
+            earlier_ids.len(),
+            index
+        ))
+    })
+}
+
+/// Collect the JSON pointer and value of every placeholder in `value`
+fn find_placeholders(
+    value: &Value,
+    placeholders: &HashSet<u64>,
+    pointer: String,
+    found: &mut Vec<(String, u64)>,
+) {
+    match value {
+        Value::Number(number) => {
+            if let Some(number) = number.as_u64().filter(|n| placeholders.contains(n)) {
+                found.push((pointer, number));
+            }
+        }
+        Value::String(string) => {
+            // Placeholders can be part of some text, like a mention, so
+            // check every run of digits
+            let digits: Vec<&str> = string
+                .split(|c: char| !c.is_ascii_digit())
+                .filter(|run| !run.is_empty())
+                .collect();
+
+            for run in digits {
+                if let Some(number) = run.parse::<u64>().ok().filter(|n| placeholders.contains(n)) {
+                    found.push((pointer.clone(), number));
+                }
+            }
+        }
+        Value::Array(values) => {
+            for (index, value) in values.iter().enumerate() {
+                find_placeholders(value, placeholders, format!("{}/{}", pointer, index), found);
+            }
+        }
+        Value::Object(map) => {
+            for (key, value) in map {
+                let key = key.replace('~', "~0").replace('/', "~1");
+                find_placeholders(value, placeholders, format!("{}/{}", pointer, key), found);
+            }
+        }
+        Value::Null | Value::Bool(_) => {}
+    }
+}
+
+/// Whether a task's dependencies let it run yet
+pub enum DependencyState {
+    /// Some of the parents haven't finished yet
+    Waiting,
+    /// A parent failed, so this task can't run
+    Failed(TaskError),
+    /// Everything is done, and this is the payload with the outputs filled in
+    Ready(Value),
+}
+
+/// Check the parents of a task, filling their outputs into the payload if
+/// they're all done
+pub async fn resolve_dependencies(
+    db: &DatabaseConnection,
+    dependencies: &TaskDependencies,
+    mut payload: Value,
+) -> Result<DependencyState, DbErr> {
+    let parents: HashMap<i32, task::Model> = task::Entity::find()
+        .filter(task::Column::Id.is_in(dependencies.parents.iter().map(|id| id.0)))
+        .all(db)
+        .await?
+        .into_iter()
+        .map(|parent| (parent.id, parent))
+        .collect();
+
+    let mut outputs: HashMap<DatabaseId, TaskReturnData> = HashMap::new();
+
+    for parent_id in &dependencies.parents {
+        let Some(parent) = parents.get(&parent_id.0) else {
+            return Ok(DependencyState::Failed(TaskError::permanent(format!(
+                "Task {} this depends on doesn't exist",
+                parent_id.0
+            ))));
+        };
+
+        match serde_json::from_value::<TaskResult>(parent.status.clone()) {
+            Ok(TaskResult::Pending | TaskResult::Running) => return Ok(DependencyState::Waiting),
+            Ok(TaskResult::Completed(data)) => {
+                outputs.insert(*parent_id, data);
+            }
+            Ok(TaskResult::Error(error) | TaskResult::DeadLetter(error)) => {
+                return Ok(DependencyState::Failed(TaskError::permanent(format!(
+                    "Task {} this depends on failed: {}",
+                    parent_id.0, error
+                ))))
+            }
+            Ok(TaskResult::Cancelled) => {
+                return Ok(DependencyState::Failed(TaskError::permanent(format!(
+                    "Task {} this depends on was cancelled",
+                    parent_id.0
+                ))))
+            }
+            Err(why) => {
+                return Ok(DependencyState::Failed(TaskError::permanent(format!(
+                    "Task {} this depends on has an unreadable status: {}",
+                    parent_id.0, why
+                ))))
+            }
+        }
+    }
+
+    for binding in &dependencies.bindings {
+        let Some(discord_id) = outputs.get(&binding.parent).and_then(|data| data.discord_id()) else {
+            return Ok(DependencyState::Failed(TaskError::permanent(format!(
+                "Task {} this depends on didn't return a Discord id",
+                binding.parent.0
+            ))));
+        };
+
+        let Some(value) = payload.pointer_mut(&binding.pointer) else {
+            return Ok(DependencyState::Failed(TaskError::permanent(format!(
+                "Nothing at {} to fill in",
+                binding.pointer
+            ))));
+        };
+
+        match value {
+            Value::String(string) => {
+                *string =
+                    string.replace(&binding.placeholder.to_string(), &discord_id.0.to_string());
+            }
+            value => *value = Value::from(*discord_id),
+        }
+    }
+
+    Ok(DependencyState::Ready(payload))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::task_runner::tasks::message::{MessageHandler, MessageTasks, SendChannelMessage};
+
+    use super::*;
+
+    fn message(channel_id: DiscordId, message: String) -> TaskType {
+        TaskType::MessageHandler(MessageHandler {
+            guild_id: DiscordId(1),
+            task: MessageTasks::SendChannelMessage(SendChannelMessage {
+                channel_id,
+                message,
+                ..Default::default()
+            }),
+        })
+    }
+
+    fn dependencies(
+        graph: TaskGraph,
+        earlier_ids: &[DatabaseId],
+    ) -> Result<TaskDependencies, DbErr> {
+        let (nodes, placeholders) = graph.into_parts();
+        let node = nodes.last().unwrap();
+        let payload = serde_json::to_value(&node.task).unwrap();
+
+        TaskDependencies::for_node(node, &payload, &placeholders, earlier_ids)
+    }
+
+    #[test]
+    fn should_bind_placeholders_from_the_graph() {
+        let mut graph = TaskGraph::new();
+        let first = graph.add(message(DiscordId(2), String::new()));
+        let channel_id = graph.discord_id(first);
+        graph.add(message(channel_id, format!("See <#{}>", *channel_id)));
+
+        let dependencies = dependencies(graph, &[DatabaseId(10)]).unwrap();
+        assert_eq!(dependencies.parents, vec![DatabaseId(10)]);
+        assert_eq!(dependencies.bindings.len(), 2);
+    }
+
+    #[test]
+    fn should_ignore_big_numbers_that_are_not_placeholders() {
+        let mut graph = TaskGraph::new();
+        graph.add(message(
+            DiscordId(PLACEHOLDER_BASE + 3),
+            format!("{} is a big number", PLACEHOLDER_BASE + 7),
+        ));
+
+        let dependencies = dependencies(graph, &[]).unwrap();
+        assert!(dependencies.is_empty());
+        assert!(dependencies.bindings.is_empty());
+    }
+
+    #[test]
+    fn should_reject_placeholders_for_later_tasks() {
+        let mut graph = TaskGraph::new();
+        let later = graph.discord_id(TaskNode(1));
+        graph.add(message(later, String::new()));
+        graph.add(message(DiscordId(2), String::new()));
+
+        let (nodes, placeholders) = graph.into_parts();
+        let payload = serde_json::to_value(&nodes[0].task).unwrap();
+        assert!(TaskDependencies::for_node(&nodes[0], &payload, &placeholders, &[]).is_err());
+    }
+}
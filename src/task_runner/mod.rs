@@ -1,27 +1,172 @@
+use std::{
+    cmp::Reverse,
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use chrono::Utc;
 use entity::entities::task;
-use sea_orm::{prelude::*, Set};
+use sea_orm::{
+    prelude::*, sea_query::Expr, Condition, DbBackend, QueryOrder, Set, Statement, TransactionTrait,
+};
+use serde_json::Value;
 use serenity::client::Context;
+use tokio::sync::Semaphore;
 use tracing::log;
+use uuid::Uuid;
 
 use crate::{
-    db_wrapper::{DBWrapper, TaskResult},
-    task_runner::tasks::TaskType,
+    db_wrapper::{
+        payload::{read_payload, PayloadKind},
+        DBWrapper, TaskResult,
+    },
+    discord::{DiscordBackend, SerenityBackend},
+    task_runner::{
+        error::TaskError,
+        graph::{resolve_dependencies, DependencyState, TaskDependencies},
+        rate_limit::RateLimits,
+        retry::RetryPolicy,
+        schedule::{TaskPriority, TaskRecurrence},
+        tasks::{DatabaseId, TaskResource, TaskRun, TaskType},
+    },
 };
 
+pub mod error;
+pub mod graph;
+pub mod rate_limit;
+pub mod retry;
+pub mod schedule;
 pub mod tasks;
 
+/// How often the runner checks for tasks if it hasn't been notified of any.
+/// Notifications should wake it well before this for new tasks, and this is
+/// how scheduled tasks are picked up once they're due.
+const RUNNER_FALLBACK_POLL: Duration = Duration::from_secs(1);
+
+/// How many tasks can run at once if nothing else is configured
+pub const DEFAULT_TASK_WORKERS: usize = 4;
+
+/// How long a runner owns a task it claimed. If it doesn't renew the lease
+/// in time, e.g. because the bot crashed, another runner can take the task.
+const LEASE_DURATION: Duration = Duration::from_secs(60);
+
+/// How often a runner renews the leases of the tasks it's working on
+const LEASE_RENEW_INTERVAL: Duration = Duration::from_secs(20);
+
+/// Set a task as running under a lease, if it's pending or its lease has
+/// expired. `SKIP LOCKED` means that if another runner is claiming the same
+/// task at the same time, one of them gets nothing back instead of waiting.
+const CLAIM_TASK: &str = r#"
+    UPDATE task
+    SET status = $1, lease_owner = $2, lease_expires_at = $3, attempts = attempts + 1,
+        started_at = now()
+    WHERE id = (
+        SELECT id FROM task
+        WHERE id = $4 AND (status = $5 OR (status = $1 AND lease_expires_at < now()))
+        FOR UPDATE SKIP LOCKED
+    )
+    RETURNING *
+"#;
+
+/// `CLAIM_TASK` for SQLite. Only one connection can write at a time there, so
+/// there's nothing to lock, and the current time is passed in as `$6`.
+const CLAIM_TASK_SQLITE: &str = r#"
+    UPDATE task
+    SET status = $1, lease_owner = $2, lease_expires_at = $3, attempts = attempts + 1,
+        started_at = $6
+    WHERE id = $4 AND (status = $5 OR (status = $1 AND lease_expires_at < $6))
+    RETURNING *
+"#;
+
+/// Runs pending tasks on a bounded pool of workers. Tasks that don't share a
+/// resource run in parallel, while tasks that touch the same channel, role or
+/// member are run one at a time, in the order they were added.
+///
+/// More urgent tasks are started first, and less urgent ones hold back when
+/// the Discord route they use is close to its rate limit.
+///
+/// Each task is claimed with a lease before it runs, so several instances of
+/// the bot can share the same queue without running a task twice.
+#[derive(Clone)]
 pub struct TaskRunner {
-    pub ctx: Context,
+    pub discord: Arc<dyn DiscordBackend>,
     pub db: DBWrapper,
+    pub retry_policy: RetryPolicy,
+    /// Identifies this runner as the owner of the tasks it claims
+    pub worker_id: String,
+    rate_limits: RateLimits,
+    workers: Arc<Semaphore>,
+    in_flight: Arc<Mutex<InFlight>>,
+}
+
+/// A task that's waiting to run, once its payload has been read
+struct WaitingTask {
+    db_task: task::Model,
+    task_payload: TaskType,
+    resources: Vec<TaskResource>,
+    /// Whether it can start now, rather than being scheduled for later or
+    /// backing off before another attempt
+    due: bool,
+}
+
+/// Bookkeeping for the tasks that have been handed to a worker but haven't
+/// finished yet
+#[derive(Default)]
+struct InFlight {
+    tasks: HashSet<DatabaseId>,
+    resources: HashSet<TaskResource>,
 }
 
 impl TaskRunner {
+    pub fn new(ctx: Context, db: DBWrapper, workers: usize) -> Self {
+        Self {
+            rate_limits: RateLimits::new(&ctx.http),
+            ..Self::with_backend(Arc::new(SerenityBackend::new(ctx)), db, workers)
+        }
+    }
+
+    /// A runner that sends Discord calls to `discord`, e.g. a `FakeDiscord`.
+    /// Without serenity's ratelimiter to read from, tasks never hold back
+    /// for rate limits.
+    pub fn with_backend(discord: Arc<dyn DiscordBackend>, db: DBWrapper, workers: usize) -> Self {
+        Self {
+            discord,
+            db,
+            rate_limits: RateLimits::none(),
+            retry_policy: RetryPolicy::default(),
+            worker_id: format!(
+                "{}-{}",
+                sys_info::hostname().unwrap_or_else(|_| "unknown".to_string()),
+                Uuid::new_v4()
+            ),
+            workers: Arc::new(Semaphore::new(workers.max(1))),
+            in_flight: Arc::default(),
+        }
+    }
+
+    /// Hand every pending task that can run to a worker. This returns once
+    /// all of them have been started, not once they're finished.
     pub async fn run_tasks(&self) {
         let pending_as_value = serde_json::to_value(TaskResult::Pending).unwrap();
+        let running_as_value = serde_json::to_value(TaskResult::Running).unwrap();
+        let now = Utc::now();
 
-        // Get all the incomplete tasks from the database
+        // Get all the incomplete tasks from the database, oldest first. This
+        // includes tasks that aren't due yet, since they still hold their
+        // place on the resources they touch, and tasks whose runner stopped
+        // renewing their lease.
         let incomplete_tasks: Vec<task::Model> = match task::Entity::find()
-            .filter(task::Column::Status.eq(pending_as_value))
+            .filter(
+                Condition::any()
+                    .add(task::Column::Status.eq(pending_as_value))
+                    .add(
+                        Condition::all()
+                            .add(task::Column::Status.eq(running_as_value.clone()))
+                            .add(task::Column::LeaseExpiresAt.lt(now)),
+                    ),
+            )
+            .order_by_asc(task::Column::Id)
             .all(&*self.db)
             .await
         {
@@ -29,38 +174,487 @@ impl TaskRunner {
             Err(why) => panic!("Error getting tasks: {:?}", why),
         };
 
-        // Print the tasks if there are any
-        if !incomplete_tasks.is_empty() {
-            log::info!("Found {} tasks", incomplete_tasks.len());
-        }
+        let mut waiting_tasks = Vec::new();
+
+        // Read every task that could run
+        for mut db_task in incomplete_tasks {
+            let id = DatabaseId(db_task.id);
 
-        // Iterate through open tasks in the DB
-        for db_task in incomplete_tasks {
-            let task_payload: TaskType = match serde_json::from_value(db_task.payload.clone()) {
+            // Already being worked on
+            if self.in_flight.lock().unwrap().tasks.contains(&id) {
+                continue;
+            }
+
+            // Still waiting on the tasks it depends on
+            let Some(payload) = self.resolve_payload(&db_task).await else {
+                continue;
+            };
+            db_task.payload = payload;
+
+            // Payloads written by an older version are upgraded here, but
+            // stored as they were written
+            let task_payload: TaskType = match read_payload(
+                PayloadKind::Task,
+                db_task.payload_version,
+                db_task.payload.clone(),
+            ) {
                 Ok(task) => task,
                 Err(why) => {
-                    panic!(
-                        "Error parsing task: {:?}\nThe payload was {:?}",
-                        why, &db_task.payload
+                    self.fail_unclaimed_task(
+                        &db_task,
+                        TaskResult::DeadLetter(TaskError::permanent(format!(
+                            "Error parsing task: {}",
+                            why
+                        ))),
                     )
+                    .await;
+                    continue;
+                }
+            };
+
+            let due = db_task.status == running_as_value
+                || (db_task.run_at.map_or(true, |run_at| run_at <= now)
+                    && db_task
+                        .next_attempt_at
+                        .map_or(true, |next_attempt_at| next_attempt_at <= now));
+
+            waiting_tasks.push(WaitingTask {
+                resources: task_payload.route().resources(),
+                due,
+                db_task,
+                task_payload,
+            });
+        }
+
+        // Tasks that touch the same resource still run in the order they
+        // were added, whatever their priority, so only the oldest task
+        // waiting on each resource can start
+        let mut oldest_waiting: HashMap<TaskResource, DatabaseId> = HashMap::new();
+        for waiting_task in &waiting_tasks {
+            for resource in &waiting_task.resources {
+                oldest_waiting
+                    .entry(*resource)
+                    .or_insert(DatabaseId(waiting_task.db_task.id));
+            }
+        }
+
+        // Most urgent first, then oldest first
+        waiting_tasks.sort_by_key(|waiting_task| {
+            (
+                Reverse(waiting_task.db_task.priority),
+                waiting_task.db_task.id,
+            )
+        });
+
+        let mut started = 0;
+
+        for WaitingTask {
+            db_task,
+            task_payload,
+            resources,
+            due,
+        } in waiting_tasks
+        {
+            let id = DatabaseId(db_task.id);
+
+            if !due {
+                continue;
+            }
+
+            {
+                let in_flight = self.in_flight.lock().unwrap();
+
+                if resources.iter().any(|resource| {
+                    oldest_waiting[resource] != id || in_flight.resources.contains(resource)
+                }) {
+                    continue;
+                }
+            }
+
+            // Leave room in Discord's rate limits for more urgent tasks. The
+            // task is picked up again on a later pass.
+            if let Some(bucket) = task_payload.route().rate_limit_bucket() {
+                if self
+                    .rate_limits
+                    .should_wait(bucket, TaskPriority::from(db_task.priority))
+                    .await
+                {
+                    continue;
+                }
+            }
+
+            // Wait for a free worker
+            let permit = self.workers.clone().acquire_owned().await.unwrap();
+
+            // Make sure nobody else runs the task
+            let db_task = match self.claim_task(id).await {
+                Ok(Some(claimed_task)) => task::Model {
+                    payload: db_task.payload,
+                    ..claimed_task
+                },
+                // Someone else got to it first
+                Ok(None) => continue,
+                Err(why) => {
+                    log::error!("Error claiming task {}: {:?}", id.0, why);
+                    continue;
                 }
             };
 
-            log::info!("Working on task: {:?}", task_payload);
+            {
+                let mut in_flight = self.in_flight.lock().unwrap();
+                in_flight.tasks.insert(id);
+                in_flight.resources.extend(resources.iter().copied());
+            }
+
+            started += 1;
+
+            let runner = self.clone();
+            tokio::spawn(async move {
+                runner.run_task(db_task, task_payload).await;
+
+                {
+                    let mut in_flight = runner.in_flight.lock().unwrap();
+                    in_flight.tasks.remove(&id);
+                    for resource in &resources {
+                        in_flight.resources.remove(resource);
+                    }
+                }
+
+                drop(permit);
+
+                // Tasks that were waiting on this one might be able to run now
+                runner.db.notifications.notify_new_task();
+            });
+        }
+
+        // Print the tasks if there are any
+        if started > 0 {
+            log::info!("Started {} tasks", started);
+        }
+    }
+
+    /// Take ownership of a task by setting it as running with a lease. This
+    /// returns `None` if another runner claimed it first, or if it isn't
+    /// waiting to run anymore.
+    async fn claim_task(&self, id: DatabaseId) -> Result<Option<task::Model>, DbErr> {
+        let now: DateTimeWithTimeZone = Utc::now().into();
+        let lease_expires_at = now + chrono::Duration::from_std(LEASE_DURATION).unwrap();
+
+        let mut values = vec![
+            serde_json::to_value(TaskResult::Running).unwrap().into(),
+            self.worker_id.clone().into(),
+            lease_expires_at.into(),
+            id.0.into(),
+            serde_json::to_value(TaskResult::Pending).unwrap().into(),
+        ];
+
+        let backend = self.db.get_database_backend();
+        let claim_task = match backend {
+            DbBackend::Sqlite => {
+                values.push(now.into());
+                CLAIM_TASK_SQLITE
+            }
+            _ => CLAIM_TASK,
+        };
+
+        task::Entity::find()
+            .from_raw_sql(Statement::from_sql_and_values(backend, claim_task, values))
+            .one(&*self.db)
+            .await
+    }
+
+    /// Keep renewing the lease on a task until this is dropped or aborted
+    async fn renew_lease(self, id: DatabaseId) {
+        let mut interval = tokio::time::interval(LEASE_RENEW_INTERVAL);
+        // The first tick is immediate, and the lease was just taken
+        interval.tick().await;
+
+        loop {
+            interval.tick().await;
+
+            let lease_expires_at: DateTimeWithTimeZone =
+                (Utc::now() + chrono::Duration::from_std(LEASE_DURATION).unwrap()).into();
+
+            if let Err(why) = task::Entity::update_many()
+                .col_expr(task::Column::LeaseExpiresAt, Expr::value(lease_expires_at))
+                .filter(task::Column::Id.eq(id.0))
+                .filter(task::Column::LeaseOwner.eq(self.worker_id.clone()))
+                .exec(&*self.db)
+                .await
+            {
+                log::error!("Error renewing the lease on task {}: {:?}", id.0, why);
+            }
+        }
+    }
+
+    /// Save a task that this runner was working on, giving up its lease. If
+    /// the lease was taken over in the meantime, the other runner's result
+    /// is kept instead.
+    async fn release_task(&self, mut db_task_active_model: task::ActiveModel) {
+        let id = DatabaseId::from(&db_task_active_model.id);
+
+        db_task_active_model.lease_owner = Set(None);
+        db_task_active_model.lease_expires_at = Set(None);
+
+        match task::Entity::update(db_task_active_model)
+            .filter(task::Column::LeaseOwner.eq(self.worker_id.clone()))
+            .exec(&*self.db)
+            .await
+        {
+            Ok(_) => self.db.announce_task_updated(id),
+            Err(why) => log::warn!(
+                "Couldn't save a task, another runner might have taken it over: {:?}",
+                why
+            ),
+        }
+    }
+
+    /// The task's payload with the outputs of the tasks it depends on filled
+    /// in, or `None` if it can't run yet. If any of those tasks failed, this
+    /// task is failed as well.
+    async fn resolve_payload(&self, db_task: &task::Model) -> Option<Value> {
+        let Some(dependencies) = &db_task.dependencies else {
+            return Some(db_task.payload.clone());
+        };
 
-            // Complete the tasks
-            let task_status = task_payload
-                .route()
-                .handle(self.ctx.clone(), self.db.clone())
+        let dependencies = match serde_json::from_value::<TaskDependencies>(dependencies.clone()) {
+            Ok(dependencies) => dependencies,
+            Err(why) => {
+                self.fail_unclaimed_task(
+                    db_task,
+                    TaskResult::DeadLetter(TaskError::permanent(format!(
+                        "Error parsing task dependencies: {}",
+                        why
+                    ))),
+                )
                 .await;
+                return None;
+            }
+        };
+
+        match resolve_dependencies(&self.db, &dependencies, db_task.payload.clone()).await {
+            Ok(DependencyState::Waiting) => None,
+            Ok(DependencyState::Ready(payload)) => Some(payload),
+            Ok(DependencyState::Failed(error)) => {
+                self.fail_unclaimed_task(db_task, TaskResult::Error(error))
+                    .await;
+                None
+            }
+            Err(why) => {
+                log::error!(
+                    "Error checking the dependencies of task {}: {:?}",
+                    db_task.id,
+                    why
+                );
+                None
+            }
+        }
+    }
+
+    /// Fail a task that can't be run without claiming it first. Nothing is
+    /// changed if the task's status changed since it was read, e.g. because
+    /// it was cancelled.
+    async fn fail_unclaimed_task(&self, db_task: &task::Model, task_status: TaskResult) {
+        let mut db_task_active_model: task::ActiveModel = db_task.clone().into();
+
+        if let TaskResult::Error(error) | TaskResult::DeadLetter(error) = &task_status {
+            log::error!("Task {} can't run: {}", db_task.id, error);
+            db_task_active_model.last_error = Set(Some(error.to_string()));
+        }
+
+        db_task_active_model.status = Set(serde_json::to_value(&task_status).unwrap());
+        db_task_active_model.finished_at = Set(Some(Utc::now().into()));
+
+        match task::Entity::update(db_task_active_model)
+            .filter(task::Column::Status.eq(db_task.status.clone()))
+            .exec(&*self.db)
+            .await
+        {
+            Ok(_) => self.db.announce_task_updated(DatabaseId(db_task.id)),
+            Err(why) => log::warn!("Couldn't fail task {}: {:?}", db_task.id, why),
+        }
+
+        // Anything depending on this task can be failed right away
+        self.db.notifications.notify_new_task();
+    }
+
+    async fn run_task(&self, db_task: task::Model, task_payload: TaskType) {
+        log::info!("Working on task: {:?}", task_payload);
 
-            // Set the task as completed
-            let mut db_task_active_model: task::ActiveModel = db_task.into();
-            db_task_active_model.status = Set(serde_json::to_value(&task_status).unwrap());
-            db_task_active_model.update(&*self.db).await.unwrap();
+        // This was counted when the task was claimed
+        let attempts = db_task.attempts;
+
+        // Hold on to the task while it runs
+        let heartbeat = tokio::spawn(self.clone().renew_lease(DatabaseId(db_task.id)));
+        let started = Instant::now();
+
+        let task_status = if attempts > self.retry_policy.max_attempts {
+            // The task has already been attempted as many times as it's
+            // allowed, and its runner died while working on it, so it
+            // might be what's taking them down
+            TaskResult::DeadLetter(TaskError::permanent(format!(
+                "Gave up after {} attempts",
+                attempts - 1
+            )))
+        } else {
+            // Complete the task. This runs on its own so that a panicking
+            // task only fails itself.
+            let (discord, db) = (self.discord.clone(), self.db.clone());
+            let run = TaskRun {
+                id: DatabaseId(db_task.id),
+                // Tasks added before keys were stored get one that's just as
+                // stable
+                idempotency_key: db_task
+                    .idempotency_key
+                    .clone()
+                    .unwrap_or_else(|| format!("task-{}", db_task.id)),
+                attempt: attempts,
+            };
+            let handle =
+                tokio::spawn(async move { task_payload.route().handle(&*discord, db, run).await });
+            match handle.await {
+                Ok(task_status) => task_status,
+                Err(why) => {
+                    TaskResult::Error(TaskError::permanent(format!("Task panicked: {}", why)))
+                }
+            }
+        };
+
+        heartbeat.abort();
+
+        let payload = db_task.payload.clone();
+        let mut db_task_active_model: task::ActiveModel = db_task.clone().into();
+        // Keep the payload with any dependency outputs filled in
+        db_task_active_model.payload = Set(payload);
+        db_task_active_model.duration_ms = Set(Some(started.elapsed().as_millis() as i64));
+
+        let task_status = match task_status {
+            TaskResult::Error(error) => {
+                db_task_active_model.last_error = Set(Some(error.to_string()));
+
+                if !error.is_retryable() {
+                    log::error!("Task failed after {} attempts: {}", attempts, error);
+                    TaskResult::Error(error)
+                } else if self.retry_policy.should_retry(attempts) {
+                    // Leave the task pending so it gets picked up again
+                    // after backing off
+                    let delay = self.retry_policy.backoff(attempts);
+
+                    log::warn!(
+                        "Task failed on attempt {}, retrying in {:?}: {}",
+                        attempts,
+                        delay,
+                        error
+                    );
+
+                    db_task_active_model.status =
+                        Set(serde_json::to_value(&TaskResult::Pending).unwrap());
+                    db_task_active_model.next_attempt_at = Set(Some(
+                        (Utc::now() + chrono::Duration::from_std(delay).unwrap()).into(),
+                    ));
+                    self.release_task(db_task_active_model).await;
+                    return;
+                } else {
+                    log::error!(
+                        "Task ran out of retries after {} attempts: {}",
+                        attempts,
+                        error
+                    );
+                    TaskResult::DeadLetter(error)
+                }
+            }
+            TaskResult::DeadLetter(error) => {
+                log::error!("Task was dead-lettered: {}", error);
+                db_task_active_model.last_error = Set(Some(error.to_string()));
+                TaskResult::DeadLetter(error)
+            }
+            task_status => task_status,
+        };
+
+        // Set the task as completed
+        db_task_active_model.status = Set(serde_json::to_value(&task_status).unwrap());
+        db_task_active_model.finished_at = Set(Some(Utc::now().into()));
+        if let Err(why) = self.finish_task(db_task_active_model, &db_task).await {
+            log::warn!(
+                "Couldn't save a finished task, it'll run again once its lease expires: {:?}",
+                why
+            );
         }
     }
 
+    /// Save a finished task and, if it's recurring, add its next run in the
+    /// same transaction, so a run can't finish without the schedule going on
+    async fn finish_task(
+        &self,
+        mut db_task_active_model: task::ActiveModel,
+        db_task: &task::Model,
+    ) -> Result<(), DbErr> {
+        db_task_active_model.lease_owner = Set(None);
+        db_task_active_model.lease_expires_at = Set(None);
+
+        let next_run = Self::next_run(db_task);
+
+        let txn = self.db.begin().await?;
+        task::Entity::update(db_task_active_model)
+            .filter(task::Column::LeaseOwner.eq(self.worker_id.clone()))
+            .exec(&txn)
+            .await?;
+        if let Some(next_run) = next_run.clone() {
+            next_run.insert(&txn).await?;
+        }
+        txn.commit().await?;
+
+        self.db.announce_task_updated(DatabaseId(db_task.id));
+        if next_run.is_some() {
+            self.db.announce_task_inserted();
+        }
+
+        Ok(())
+    }
+
+    /// The next run of a recurring task. This is added whether or not this
+    /// run succeeded, so one failure doesn't stop the schedule.
+    fn next_run(db_task: &task::Model) -> Option<task::ActiveModel> {
+        let recurrence = db_task.recurrence.as_ref()?;
+
+        let recurrence: TaskRecurrence = match serde_json::from_value(recurrence.clone()) {
+            Ok(recurrence) => recurrence,
+            Err(why) => {
+                log::error!(
+                    "Error parsing the recurrence of task {}, it won't run again: {:?}",
+                    db_task.id,
+                    why
+                );
+                return None;
+            }
+        };
+
+        let now = Utc::now();
+        let last_run = db_task
+            .run_at
+            .map(|run_at| run_at.with_timezone(&Utc))
+            .unwrap_or(now);
+
+        Some(task::ActiveModel {
+            payload: Set(db_task.payload.clone()),
+            payload_version: Set(db_task.payload_version),
+            priority: Set(db_task.priority),
+            // This is a new run, so it can create its own resources
+            idempotency_key: Set(Some(Uuid::new_v4().to_string())),
+            status: Set(serde_json::to_value(&TaskResult::Pending).unwrap()),
+            run_at: Set(Some(recurrence.next_run(last_run, now).into())),
+            recurrence: Set(db_task.recurrence.clone()),
+            created_at: Set(now.into()),
+            ..Default::default()
+        })
+    }
+
+    /// Wait until there might be work to do, either because a task was added
+    /// or because the fallback poll interval passed
+    pub async fn wait_for_tasks(&self) {
+        let _ = tokio::time::timeout(RUNNER_FALLBACK_POLL, self.db.notifications.new_task()).await;
+    }
+
     // pub async fn sample_tasks(&mut self) {
     //     let task = TaskType::MessageUser(MessageUser {
     //         player_id: 133358326439346176,
@@ -0,0 +1,71 @@
+use std::{collections::HashMap, sync::Arc, time::SystemTime};
+
+use serenity::http::{Http, Ratelimit, RatelimitingBucket};
+use tokio::sync::{Mutex, RwLock};
+
+use crate::task_runner::schedule::TaskPriority;
+
+/// What serenity's ratelimiter knows about each Discord route bucket
+type Buckets = Arc<RwLock<HashMap<RatelimitingBucket, Arc<Mutex<Ratelimit>>>>>;
+
+/// Reads the rate limits Discord reported for each route, so less urgent
+/// tasks can wait before using up a bucket that more urgent ones need.
+#[derive(Clone)]
+pub struct RateLimits {
+    buckets: Option<Buckets>,
+}
+
+impl RateLimits {
+    pub fn new(http: &Http) -> Self {
+        Self {
+            buckets: http
+                .ratelimiter
+                .as_ref()
+                .map(|ratelimiter| ratelimiter.routes()),
+        }
+    }
+
+    /// Rate limits that never hold anything back
+    pub fn none() -> Self {
+        Self { buckets: None }
+    }
+
+    /// Whether a task should wait before making a request in `bucket`.
+    /// Interactive tasks never wait. Automation leaves the last request in
+    /// the window free, and bulk work leaves half of the window free.
+    pub async fn should_wait(&self, bucket: RatelimitingBucket, priority: TaskPriority) -> bool {
+        if priority == TaskPriority::Interactive {
+            return false;
+        }
+
+        let Some(buckets) = &self.buckets else {
+            return false;
+        };
+
+        // Discord hasn't said anything about this bucket yet
+        let Some(ratelimit) = buckets.read().await.get(&bucket).cloned() else {
+            return false;
+        };
+
+        // Serenity holds the lock while a request is waiting on the bucket,
+        // so it's already as busy as it can be
+        let Ok(ratelimit) = ratelimit.try_lock() else {
+            return true;
+        };
+
+        // The window has already reset
+        if !ratelimit
+            .reset()
+            .map_or(false, |reset| reset > SystemTime::now())
+        {
+            return false;
+        }
+
+        let reserve = match priority {
+            TaskPriority::Bulk => (ratelimit.limit() / 2).max(1),
+            _ => 1,
+        };
+
+        ratelimit.remaining() <= reserve
+    }
+}
@@ -0,0 +1,41 @@
+use std::time::Duration;
+
+/// How tasks that fail with a retryable error are tried again
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    /// How many times a task is attempted before it's marked as failed
+    pub max_attempts: i32,
+    /// How long to wait after the first failed attempt. This doubles with
+    /// every attempt after that.
+    pub base_delay: Duration,
+    /// The longest to ever wait between attempts
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            base_delay: Duration::from_secs(2),
+            max_delay: Duration::from_secs(5 * 60),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Whether a task that has been attempted `attempts` times can be tried
+    /// again
+    pub fn should_retry(&self, attempts: i32) -> bool {
+        attempts < self.max_attempts
+    }
+
+    /// How long to wait before the next attempt of a task that has been
+    /// attempted `attempts` times
+    pub fn backoff(&self, attempts: i32) -> Duration {
+        let exponent = attempts.saturating_sub(1).clamp(0, 16) as u32;
+
+        self.base_delay
+            .saturating_mul(2u32.pow(exponent))
+            .min(self.max_delay)
+    }
+}
@@ -0,0 +1,125 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::task_runner::tasks::TaskType;
+
+/// A task to add to the queue, along with when it should run. Anything that
+/// takes a `NewTask` also takes a plain `TaskType`, which runs right away.
+///
+/// ```ignore
+/// db.add_task(NewTask::new(task).run_in(Duration::from_secs(30 * 60))).await;
+/// ```
+#[derive(Debug, Clone)]
+pub struct NewTask {
+    pub task: TaskType,
+    /// Don't run the task before this time
+    pub run_at: Option<DateTime<Utc>>,
+    /// Run the task again on this schedule after each run
+    pub recurrence: Option<TaskRecurrence>,
+    /// How urgent the task is compared to the others that are waiting
+    pub priority: TaskPriority,
+}
+
+impl NewTask {
+    pub fn new(task: TaskType) -> Self {
+        Self {
+            task,
+            run_at: None,
+            recurrence: None,
+            priority: TaskPriority::default(),
+        }
+    }
+
+    /// Run the task at the given time instead of right away
+    pub fn run_at(mut self, run_at: DateTime<Utc>) -> Self {
+        self.run_at = Some(run_at);
+        self
+    }
+
+    /// Run the task once the given delay has passed
+    pub fn run_in(self, delay: Duration) -> Self {
+        self.run_at(Utc::now() + chrono::Duration::from_std(delay).unwrap())
+    }
+
+    /// Run the task again every `interval` after its first run
+    pub fn every(mut self, interval: Duration) -> Self {
+        self.recurrence = Some(TaskRecurrence::Every(interval));
+        self
+    }
+
+    pub fn priority(mut self, priority: TaskPriority) -> Self {
+        self.priority = priority;
+        self
+    }
+}
+
+impl From<TaskType> for NewTask {
+    fn from(task: TaskType) -> Self {
+        Self::new(task)
+    }
+}
+
+/// How urgent a task is. Waiting tasks run most urgent first, and less
+/// urgent ones hold back when Discord's rate limits are running low so there
+/// is room left for more urgent ones.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum TaskPriority {
+    /// Work that touches lots of things at once, like resetting a game
+    Bulk,
+    /// Things the game does on its own
+    #[default]
+    Automation,
+    /// Things a player is waiting on, like the response to a button press
+    Interactive,
+}
+
+impl From<TaskPriority> for i32 {
+    fn from(priority: TaskPriority) -> Self {
+        match priority {
+            TaskPriority::Bulk => 0,
+            TaskPriority::Automation => 1,
+            TaskPriority::Interactive => 2,
+        }
+    }
+}
+
+impl From<i32> for TaskPriority {
+    fn from(priority: i32) -> Self {
+        match priority {
+            i32::MIN..=0 => TaskPriority::Bulk,
+            1 => TaskPriority::Automation,
+            _ => TaskPriority::Interactive,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub enum TaskRecurrence {
+    /// Run again a fixed amount of time after the last scheduled run
+    Every(Duration),
+}
+
+impl TaskRecurrence {
+    /// When to run next, given when the last run was scheduled. Runs that
+    /// were missed, e.g. while the bot was down, are skipped rather than all
+    /// being run at once.
+    pub fn next_run(&self, last_run: DateTime<Utc>, now: DateTime<Utc>) -> DateTime<Utc> {
+        match self {
+            TaskRecurrence::Every(interval) => {
+                let interval = chrono::Duration::from_std(*interval)
+                    .unwrap()
+                    .max(chrono::Duration::seconds(1));
+
+                let mut next_run = last_run + interval;
+                if next_run <= now {
+                    let missed = (now - next_run).num_milliseconds() / interval.num_milliseconds();
+                    next_run = next_run + interval * (missed as i32 + 1);
+                }
+
+                next_run
+            }
+        }
+    }
+}
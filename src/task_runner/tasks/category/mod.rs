@@ -1,25 +1,25 @@
 use async_trait::async_trait;
 use entity::entities::category;
-use sea_orm::{ActiveModelTrait, ColumnTrait, EntityTrait, ModelTrait, QueryFilter, Set};
+use sea_orm::{ColumnTrait, DbErr, EntityTrait, QueryFilter};
 use serde::{Deserialize, Serialize};
 use serenity::{
-    builder::CreateChannel,
-    client::Context,
-    model::{
-        channel::{ChannelType, PermissionOverwriteType},
-        permissions::Permissions,
-        prelude::PermissionOverwrite,
-    },
+    http::{RatelimitingBucket, Route},
+    model::channel::ChannelType,
 };
 use tracing::log;
 
-use super::{DiscordId, Task, TaskTest};
+use super::{unexpected_output, DiscordId, Task, TaskResource, TaskRun, TaskType, TypedTask};
 use crate::{
-    db_wrapper::{helpers::get_guild, DBWrapper, TaskResult, TaskReturnData},
-    task_runner::tasks::{assert_not_error, category::tests::tests::test_create_category},
+    db_wrapper::{
+        helpers::{find_untracked_channel, forget_resource, get_guild, save_resource, TrackedKind},
+        DBWrapper, TaskResult, TaskReturnData,
+    },
+    discord::DiscordBackend,
+    task_runner::error::TaskError,
 };
 
-pub mod tests;
+#[cfg(test)]
+mod tests;
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct CategoryHandler {
@@ -33,95 +33,192 @@ pub enum CategoryTasks {
     Delete { discord_id: DiscordId },
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CreateCategory {
+    pub name: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DeleteCategory {
+    pub discord_id: DiscordId,
+}
+
+impl TypedTask for CreateCategory {
+    type Output = category::Model;
+
+    fn into_task(self, guild_id: DiscordId) -> TaskType {
+        TaskType::CategoryHandler(CategoryHandler {
+            guild_id,
+            task: CategoryTasks::Create { name: self.name },
+        })
+    }
+
+    fn output(data: TaskReturnData) -> Result<Self::Output, TaskError> {
+        match data {
+            TaskReturnData::CategoryModel(category) => Ok(category),
+            data => Err(unexpected_output(data)),
+        }
+    }
+
+    fn undo(guild_id: DiscordId, category: &Self::Output) -> Option<TaskType> {
+        Some(
+            DeleteCategory {
+                discord_id: DiscordId::from(category.discord_id),
+            }
+            .into_task(guild_id),
+        )
+    }
+}
+
+impl TypedTask for DeleteCategory {
+    type Output = ();
+
+    fn into_task(self, guild_id: DiscordId) -> TaskType {
+        TaskType::CategoryHandler(CategoryHandler {
+            guild_id,
+            task: CategoryTasks::Delete {
+                discord_id: self.discord_id,
+            },
+        })
+    }
+
+    fn output(data: TaskReturnData) -> Result<Self::Output, TaskError> {
+        match data {
+            TaskReturnData::None => Ok(()),
+            data => Err(unexpected_output(data)),
+        }
+    }
+}
+
 #[async_trait]
 impl Task for CategoryHandler {
-    async fn handle(&self, ctx: Context, db: DBWrapper) -> TaskResult {
+    async fn handle(
+        &self,
+        discord: &dyn DiscordBackend,
+        db: DBWrapper,
+        run: TaskRun,
+    ) -> TaskResult {
         match &self.task {
-            CategoryTasks::Create { name } => self.handle_category_create(name, ctx, db).await,
+            CategoryTasks::Create { name } => {
+                self.handle_category_create(name, discord, db, &run).await
+            }
             CategoryTasks::Delete { discord_id } => {
-                self.handle_category_delete(discord_id, ctx, db).await
+                self.handle_category_delete(discord_id, discord, db).await
             }
         }
+        .into()
+    }
+
+    fn resources(&self) -> Vec<TaskResource> {
+        match &self.task {
+            CategoryTasks::Create { .. } => Vec::new(),
+            CategoryTasks::Delete { discord_id } => vec![TaskResource::Channel(*discord_id)],
+        }
+    }
+
+    fn rate_limit_bucket(&self) -> Option<RatelimitingBucket> {
+        Some(match &self.task {
+            CategoryTasks::Create { .. } => Route::GuildChannels {
+                guild_id: self.guild_id.into(),
+            }
+            .ratelimiting_bucket(),
+            CategoryTasks::Delete { discord_id } => Route::Channel {
+                channel_id: (*discord_id).into(),
+            }
+            .ratelimiting_bucket(),
+        })
     }
 }
 
 impl CategoryHandler {
-    async fn handle_category_create(&self, name: &str, ctx: Context, db: DBWrapper) -> TaskResult {
-        let (discord_guild, database_guild) =
-            get_guild(ctx.clone(), db.clone(), self.guild_id).await;
-
-        let everyone_role = discord_guild.role_by_name("@everyone").unwrap();
-
-        // Create the category
-        let discord_category = discord_guild
-            .create_channel(
-                &ctx.http,
-                CreateChannel::new(name)
-                    .kind(ChannelType::Category)
-                    .permissions(vec![PermissionOverwrite {
-                        allow: Permissions::VIEW_CHANNEL,
-                        deny: Permissions::SEND_TTS_MESSAGES,
-                        kind: PermissionOverwriteType::Role(everyone_role.id),
-                    }]),
+    async fn handle_category_create(
+        &self,
+        name: &str,
+        discord: &dyn DiscordBackend,
+        db: DBWrapper,
+        run: &TaskRun,
+    ) -> Result<TaskReturnData, TaskError> {
+        // An earlier attempt might have already created and saved it
+        if let Some(database_category) = category::Entity::find()
+            .filter(category::Column::IdempotencyKey.eq(run.idempotency_key.clone()))
+            .one(&*db)
+            .await?
+        {
+            return Ok(TaskReturnData::CategoryModel(database_category));
+        }
+
+        let (discord_guild, database_guild) = get_guild(discord, db.clone(), self.guild_id).await?;
+
+        // Or it might have created it, but stopped before saving it
+        let untracked_category = if run.is_retry() {
+            find_untracked_channel(
+                &discord_guild,
+                db.clone(),
+                name,
+                ChannelType::Category,
+                None,
             )
-            .await
-            .unwrap();
+            .await?
+        } else {
+            None
+        };
+
+        let discord_category = match untracked_category {
+            Some(discord_category) => {
+                log::info!(
+                    "Using category {} from an earlier attempt",
+                    *discord_category.id
+                );
+                discord_category
+            }
+            // Create the category
+            None => discord.create_category(self.guild_id, name).await?,
+        };
 
         // Save the category to the database
-        let database_category = category::ActiveModel {
-            name: Set(discord_category.name),
-            discord_id: Set(*DiscordId(discord_category.id.0.get()) as i64),
-            fk_guild_id: Set(Some(database_guild.discord_id)),
-            ..Default::default()
-        }
-        .insert(&*db)
-        .await
-        .unwrap();
+        save_resource(
+            &*db,
+            TrackedKind::Category,
+            DiscordId::from(database_guild.discord_id),
+            discord_category.id,
+            &discord_category.name,
+            Some(run.idempotency_key.clone()),
+        )
+        .await?;
+
+        let database_category = category::Entity::find_by_id(*discord_category.id as i64)
+            .one(&*db)
+            .await?
+            .ok_or_else(|| DbErr::RecordNotFound(format!("Category {}", *discord_category.id)))?;
 
-        TaskResult::Completed(TaskReturnData::CategoryModel(database_category))
+        Ok(TaskReturnData::CategoryModel(database_category))
     }
 
     async fn handle_category_delete(
         &self,
         category_discord_id: &DiscordId,
-        ctx: Context,
+        discord: &dyn DiscordBackend,
         db: DBWrapper,
-    ) -> TaskResult {
-        // Delete the category from Discord
-        ctx.cache
-            .channel(*category_discord_id)
-            .unwrap()
-            .delete(&ctx.http)
-            .await
-            .unwrap();
+    ) -> Result<TaskReturnData, TaskError> {
+        // Delete the category from Discord, unless someone already has
+        let in_guild = match discord.channel(*category_discord_id).await {
+            Some(discord_category) => {
+                discord.delete_channel(discord_category.id).await?;
+                true
+            }
+            None => false,
+        };
 
         // Delete the category from the database
-        let category = category::Entity::find()
-            .filter(category::Column::DiscordId.eq(**category_discord_id as i64))
-            .one(&*db)
-            .await
-            .unwrap()
-            .unwrap();
+        let in_database = forget_resource(&db, TrackedKind::Category, *category_discord_id).await?;
 
-        category.delete(&*db).await.unwrap();
+        if !in_guild && !in_database {
+            return Err(TaskError::permanent(format!(
+                "Category {} is neither in the guild nor the database",
+                **category_discord_id
+            )));
+        }
 
-        TaskResult::Completed(TaskReturnData::None)
+        Ok(TaskReturnData::None)
     }
 }
-
-#[async_trait]
-impl TaskTest for CategoryHandler {
-    async fn run_tests(ctx: Context, db: DBWrapper) {
-        log::info!("Testing categories");
-        assert_not_error(test_create_category(ctx, db).await);
-    }
-}
-
-#[derive(Debug)]
-pub enum CategoryCreateError {
-    CategoryAlreadyExists,
-    CategoryNotCreated,
-    CategoryNotInDatabase,
-    CategoryNotDeleted,
-    CategoryNotSaved,
-}
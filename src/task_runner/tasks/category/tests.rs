@@ -1,81 +1,89 @@
-pub mod tests {
-
-    use serenity::prelude::Context;
-
-    use crate::{
-        db_wrapper::{DBWrapper, TaskResult::Completed, TaskReturnData},
-        task_runner::tasks::{
-            category::{CategoryCreateError, CategoryHandler, CategoryTasks},
-            test_helpers::{self, DatabaseStatus, DiscordStatus, TestHelpers},
-            DiscordId, TaskType,
+use crate::{
+    discord::DiscordBackend,
+    task_runner::tasks::{
+        category::{CreateCategory, DeleteCategory},
+        test_helpers::{
+            DatabaseConstruct, DatabaseStatus, DiscordConstruct, DiscordStatus, TestHelpers,
         },
-        TEST_GUILD_ID,
-    };
+        DiscordId,
+    },
+};
 
-    pub async fn test_create_category(
-        ctx: Context,
-        db: DBWrapper,
-    ) -> Result<(), CategoryCreateError> {
-        let test_helper = TestHelpers::new(ctx, db.clone()).await;
+#[tokio::test]
+async fn should_create_and_delete_category() {
+    let test_helper = TestHelpers::new().await;
+    let name = TestHelpers::generate_name();
 
-        // Create a test team
-        let test_team = test_helper.generate_team().await;
+    let category_model = test_helper
+        .db
+        .run(test_helper.guild_id, CreateCategory { name: name.clone() })
+        .await
+        .unwrap();
 
-        // Create the create category task
-        let category_task_result = db
-            .add_await_task(TaskType::CategoryHandler(CategoryHandler {
-                guild_id: DiscordId(TEST_GUILD_ID),
-                task: CategoryTasks::Create {
-                    name: test_team.name.clone(),
-                },
-            }))
-            .await;
+    // Check that the category was created and saved
+    assert_eq!(
+        test_helper
+            .check_discord_status(DiscordConstruct::Category { name: name.clone() })
+            .await,
+        DiscordStatus::Exists
+    );
+    assert_eq!(
+        test_helper
+            .check_database_status(DatabaseConstruct::Category { name: name.clone() })
+            .await,
+        DatabaseStatus::Exists
+    );
 
-        // Make sure we got a category back
-        let category_model = match category_task_result {
-            Completed(TaskReturnData::CategoryModel(model)) => model,
-            _ => return Err(CategoryCreateError::CategoryNotCreated),
-        };
+    test_helper
+        .db
+        .run(
+            test_helper.guild_id,
+            DeleteCategory {
+                discord_id: DiscordId::from(category_model.discord_id),
+            },
+        )
+        .await
+        .unwrap();
 
-        // Check if the category was created
-        if let DiscordStatus::DoesNotExist = test_helper
-            .check_discord_status(test_helpers::DiscordConstruct::Category {
-                name: test_team.name.clone(),
-            })
-            .await
-        {
-            return Err(CategoryCreateError::CategoryNotCreated);
-        }
+    // Check that it's gone from both
+    assert_eq!(
+        test_helper
+            .check_discord_status(DiscordConstruct::Category { name: name.clone() })
+            .await,
+        DiscordStatus::DoesNotExist
+    );
+    assert_eq!(
+        test_helper
+            .check_database_status(DatabaseConstruct::Category { name })
+            .await,
+        DatabaseStatus::DoesNotExist
+    );
 
-        // Check if the category was saved to the database
-        if let DatabaseStatus::DoesNotExist = test_helper
-            .check_database_status(test_helpers::DatabaseConstruct::Category {
-                name: test_team.name.clone(),
-            })
-            .await
-        {
-            return Err(CategoryCreateError::CategoryNotSaved);
-        }
+    test_helper.cleanup().await;
+}
 
-        // Create the delete category task
-        db.add_await_task(TaskType::CategoryHandler(CategoryHandler {
-            guild_id: DiscordId(345993194322001923),
-            task: CategoryTasks::Delete {
-                discord_id: DiscordId::from(category_model.discord_id),
+#[tokio::test]
+async fn should_fail_to_delete_missing_category() {
+    let test_helper = TestHelpers::new().await;
+
+    let result = test_helper
+        .db
+        .run(
+            test_helper.guild_id,
+            DeleteCategory {
+                discord_id: DiscordId(1),
             },
-        }))
+        )
         .await;
 
-        // Check if the category was deleted
-        if let DiscordStatus::Exists = test_helper
-            .check_discord_status(test_helpers::DiscordConstruct::Category {
-                name: test_team.name,
-            })
-            .await
-        {
-            return Err(CategoryCreateError::CategoryNotDeleted);
-        }
+    assert!(result.is_err());
+    assert!(test_helper
+        .discord
+        .guild(test_helper.guild_id)
+        .await
+        .unwrap()
+        .channels
+        .is_empty());
 
-        Ok(())
-    }
+    test_helper.cleanup().await;
 }
@@ -1,21 +1,29 @@
 use async_trait::async_trait;
 use entity::entities::channel;
-use sea_orm::{ActiveModelTrait, ColumnTrait, EntityTrait, ModelTrait, QueryFilter, Set};
+use sea_orm::{ColumnTrait, DbErr, EntityTrait, QueryFilter};
 use serde::{Deserialize, Serialize};
-use serenity::{builder::CreateChannel, client::Context, model::channel::ChannelType};
+use serenity::{
+    http::{RatelimitingBucket, Route},
+    model::channel::ChannelType,
+};
 use tracing::log;
 
-use super::{DiscordId, Task, TaskTest};
+use super::{unexpected_output, DiscordId, Task, TaskResource, TaskRun, TaskType, TypedTask};
 use crate::{
     db_wrapper::{
-        helpers::get_guild,
+        helpers::{
+            find_untracked_channel, forget_resource, get_guild, save_channel_kind, save_resource,
+            TrackedKind,
+        },
         DBWrapper, TaskResult,
         TaskReturnData::{self, ChannelModel},
     },
-    task_runner::tasks::{assert_not_error, channel::tests::tests::test_create_channel},
+    discord::DiscordBackend,
+    task_runner::error::TaskError,
 };
 
-pub mod tests;
+#[cfg(test)]
+mod tests;
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct ChannelHandler {
@@ -36,16 +44,97 @@ pub struct ChannelCreateData {
     pub kind: ChannelType,
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DeleteChannel {
+    pub id: DiscordId,
+}
+
+impl TypedTask for ChannelCreateData {
+    type Output = channel::Model;
+
+    fn into_task(self, guild_id: DiscordId) -> TaskType {
+        TaskType::ChannelHandler(ChannelHandler {
+            guild_id,
+            task: ChannelTasks::Create(self),
+        })
+    }
+
+    fn output(data: TaskReturnData) -> Result<Self::Output, TaskError> {
+        match data {
+            ChannelModel(channel) => Ok(channel),
+            data => Err(unexpected_output(data)),
+        }
+    }
+
+    fn undo(guild_id: DiscordId, channel: &Self::Output) -> Option<TaskType> {
+        Some(
+            DeleteChannel {
+                id: DiscordId::from(channel.discord_id),
+            }
+            .into_task(guild_id),
+        )
+    }
+}
+
+impl TypedTask for DeleteChannel {
+    type Output = ();
+
+    fn into_task(self, guild_id: DiscordId) -> TaskType {
+        TaskType::ChannelHandler(ChannelHandler {
+            guild_id,
+            task: ChannelTasks::Delete { id: self.id },
+        })
+    }
+
+    fn output(data: TaskReturnData) -> Result<Self::Output, TaskError> {
+        match data {
+            TaskReturnData::None => Ok(()),
+            data => Err(unexpected_output(data)),
+        }
+    }
+}
+
 #[async_trait]
 impl Task for ChannelHandler {
-    async fn handle(&self, ctx: Context, db: DBWrapper) -> TaskResult {
+    async fn handle(
+        &self,
+        discord: &dyn DiscordBackend,
+        db: DBWrapper,
+        run: TaskRun,
+    ) -> TaskResult {
         match &self.task {
             ChannelTasks::Create(channel_create_task) => {
-                self.handle_channel_create(channel_create_task, ctx, db)
+                self.handle_channel_create(channel_create_task, discord, db, &run)
                     .await
             }
-            ChannelTasks::Delete { id } => self.handle_channel_delete(*id, ctx, db).await,
+            ChannelTasks::Delete { id } => self.handle_channel_delete(*id, discord, db).await,
         }
+        .into()
+    }
+
+    fn resources(&self) -> Vec<TaskResource> {
+        match &self.task {
+            // Keep the category around until the channel is created in it
+            ChannelTasks::Create(data) => data
+                .category_id
+                .map(TaskResource::Channel)
+                .into_iter()
+                .collect(),
+            ChannelTasks::Delete { id } => vec![TaskResource::Channel(*id)],
+        }
+    }
+
+    fn rate_limit_bucket(&self) -> Option<RatelimitingBucket> {
+        Some(match &self.task {
+            ChannelTasks::Create(_) => Route::GuildChannels {
+                guild_id: self.guild_id.into(),
+            }
+            .ratelimiting_bucket(),
+            ChannelTasks::Delete { id } => Route::Channel {
+                channel_id: (*id).into(),
+            }
+            .ratelimiting_bucket(),
+        })
     }
 }
 
@@ -53,72 +142,99 @@ impl ChannelHandler {
     async fn handle_channel_create(
         &self,
         data: &ChannelCreateData,
-        ctx: Context,
+        discord: &dyn DiscordBackend,
         db: DBWrapper,
-    ) -> TaskResult {
-        let (discord_guild, database_guild) =
-            get_guild(ctx.clone(), db.clone(), self.guild_id).await;
-
-        let mut channel_builder = CreateChannel::new(data.name.clone()).kind(data.kind);
-
-        if let Some(category) = &data.category_id {
-            channel_builder = channel_builder.category(*category);
+        run: &TaskRun,
+    ) -> Result<TaskReturnData, TaskError> {
+        // An earlier attempt might have already created and saved it
+        if let Some(database_channel) = channel::Entity::find()
+            .filter(channel::Column::IdempotencyKey.eq(run.idempotency_key.clone()))
+            .one(&*db)
+            .await?
+        {
+            return Ok(ChannelModel(database_channel));
         }
 
-        // Create the channel
-        let discord_channel = discord_guild
-            .create_channel(&ctx.http, channel_builder)
-            .await
-            .unwrap();
+        let (discord_guild, database_guild) = get_guild(discord, db.clone(), self.guild_id).await?;
+
+        // Or it might have created it, but stopped before saving it
+        let untracked_channel = if run.is_retry() {
+            find_untracked_channel(
+                &discord_guild,
+                db.clone(),
+                &data.name,
+                data.kind,
+                data.category_id,
+            )
+            .await?
+        } else {
+            None
+        };
+
+        let discord_channel = match untracked_channel {
+            Some(discord_channel) => {
+                log::info!(
+                    "Using channel {} from an earlier attempt",
+                    *discord_channel.id
+                );
+                discord_channel
+            }
+            // Create the channel
+            None => {
+                discord
+                    .create_channel(self.guild_id, &data.name, data.kind, data.category_id)
+                    .await?
+            }
+        };
 
         // Add it to the database
-        let database_category = channel::ActiveModel {
-            discord_id: Set(*DiscordId::from(discord_channel.id) as i64),
-            fk_guild_id: Set(Some(database_guild.discord_id)),
-            name: Set(data.name.clone()),
-            ..Default::default()
-        }
-        .insert(&*db)
-        .await
-        .unwrap();
+        save_resource(
+            &*db,
+            TrackedKind::Channel,
+            DiscordId::from(database_guild.discord_id),
+            discord_channel.id,
+            &data.name,
+            Some(run.idempotency_key.clone()),
+        )
+        .await?;
+        save_channel_kind(&*db, discord_channel.id, data.kind).await?;
+
+        let database_channel = channel::Entity::find_by_id(*discord_channel.id as i64)
+            .one(&*db)
+            .await?
+            .ok_or_else(|| DbErr::RecordNotFound(format!("Channel {}", *discord_channel.id)))?;
 
         // Return the database model
-        TaskResult::Completed(ChannelModel(database_category))
+        Ok(ChannelModel(database_channel))
     }
 
     async fn handle_channel_delete(
         &self,
         id: DiscordId,
-        ctx: Context,
+        discord: &dyn DiscordBackend,
         db: DBWrapper,
-    ) -> TaskResult {
-        // Delete the channel from Discord
-        ctx.cache
-            .channel(*id)
-            .unwrap()
-            .delete(&ctx.http)
-            .await
-            .unwrap();
+    ) -> Result<TaskReturnData, TaskError> {
+        // Delete the channel from Discord. Someone might have already deleted
+        // it by hand, in which case only the database is behind.
+        let in_guild = match discord.channel(id).await {
+            Some(discord_channel) => {
+                discord.delete_channel(discord_channel.id).await?;
+                true
+            }
+            None => false,
+        };
 
         // Delete the channel from the database
-        let channel = channel::Entity::find()
-            .filter(channel::Column::DiscordId.eq(*id as i64))
-            .one(&*db)
-            .await
-            .unwrap()
-            .unwrap();
-
-        channel.delete(&*db).await.unwrap();
+        let in_database = forget_resource(&db, TrackedKind::Channel, id).await?;
 
-        TaskResult::Completed(TaskReturnData::None)
-    }
-}
+        if !in_guild && !in_database {
+            return Err(TaskError::permanent(format!(
+                "Channel {} is neither in the guild nor the database",
+                *id
+            )));
+        }
 
-#[async_trait]
-impl TaskTest for ChannelHandler {
-    async fn run_tests(ctx: Context, db: DBWrapper) {
-        log::info!("Testing categories");
-        assert_not_error(test_create_channel(ctx, db).await);
+        Ok(TaskReturnData::None)
     }
 }
 
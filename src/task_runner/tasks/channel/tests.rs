@@ -1,76 +1,205 @@
-pub mod tests {
+use chrono::{Duration, Utc};
+use serenity::model::prelude::ChannelType;
 
-    use entity::entities::category;
+use crate::{
+    db_wrapper::TaskResult,
+    discord::DiscordBackend,
+    task_runner::{
+        schedule::NewTask,
+        tasks::{
+            category::CreateCategory,
+            channel::{ChannelCreateData, DeleteChannel},
+            message::SendChannelMessage,
+            test_helpers::{
+                DatabaseConstruct, DatabaseStatus, DiscordConstruct, DiscordStatus, TestHelpers,
+            },
+            DiscordId, TypedTask,
+        },
+    },
+};
 
-    use sea_orm::{ColumnTrait, EntityTrait, QueryFilter};
-    use serenity::{model::prelude::ChannelType, prelude::Context};
+#[tokio::test]
+async fn should_create_and_delete_channel() {
+    let test_helper = TestHelpers::new().await;
+    let name = TestHelpers::generate_name();
 
-    use crate::{
-        db_wrapper::DBWrapper,
-        task_runner::tasks::{
-            category::CategoryCreateError,
-            channel::{ChannelCreateData, ChannelHandler, ChannelTasks},
-            test_helpers::TestHelpers,
-            DiscordId, TaskType,
-        },
-        TEST_GUILD_ID,
-    };
-
-    pub async fn test_create_channel(
-        ctx: Context,
-        db: DBWrapper,
-    ) -> Result<(), CategoryCreateError> {
-        let test_helper = TestHelpers::new(ctx.clone(), db.clone()).await;
-
-        // Create a test team
-        let test_team = test_helper.generate_team().await;
-
-        // Add the task to create the channel
-        db.add_await_task(TaskType::ChannelHandler(ChannelHandler {
-            guild_id: DiscordId(TEST_GUILD_ID),
-            task: ChannelTasks::Create(ChannelCreateData {
-                name: test_team.name.clone(),
+    let channel_model = test_helper
+        .db
+        .run(
+            test_helper.guild_id,
+            ChannelCreateData {
+                name: name.clone(),
                 category_id: None,
                 kind: ChannelType::Text,
-            }),
-        }))
-        .await;
+            },
+        )
+        .await
+        .unwrap();
+
+    // Check that the channel was created and saved
+    assert_eq!(
+        test_helper
+            .check_discord_status(DiscordConstruct::Channel { name: name.clone() })
+            .await,
+        DiscordStatus::Exists
+    );
+    assert_eq!(
+        test_helper
+            .check_database_status(DatabaseConstruct::Channel { name: name.clone() })
+            .await,
+        DatabaseStatus::Exists
+    );
+
+    test_helper
+        .db
+        .run(
+            test_helper.guild_id,
+            DeleteChannel {
+                id: DiscordId::from(channel_model.discord_id),
+            },
+        )
+        .await
+        .unwrap();
+
+    // Check that it's gone from both
+    assert_eq!(
+        test_helper
+            .check_discord_status(DiscordConstruct::Channel { name: name.clone() })
+            .await,
+        DiscordStatus::DoesNotExist
+    );
+    assert_eq!(
+        test_helper
+            .check_database_status(DatabaseConstruct::Channel { name })
+            .await,
+        DatabaseStatus::DoesNotExist
+    );
+
+    test_helper.cleanup().await;
+}
+
+#[tokio::test]
+async fn should_create_channel_in_category() {
+    let test_helper = TestHelpers::new().await;
+    let name = TestHelpers::generate_name();
+
+    let category_model = test_helper
+        .db
+        .run(test_helper.guild_id, CreateCategory { name: name.clone() })
+        .await
+        .unwrap();
+    let category_id = DiscordId::from(category_model.discord_id);
+
+    let channel_model = test_helper
+        .db
+        .run(
+            test_helper.guild_id,
+            ChannelCreateData {
+                name,
+                category_id: Some(category_id),
+                kind: ChannelType::Text,
+            },
+        )
+        .await
+        .unwrap();
+
+    let discord_channel = test_helper
+        .discord
+        .channel(DiscordId::from(channel_model.discord_id))
+        .await
+        .unwrap();
+    assert_eq!(discord_channel.parent_id, Some(category_id));
+
+    test_helper.cleanup().await;
+}
+
+#[tokio::test]
+async fn should_delete_channel_already_deleted_by_hand() {
+    let test_helper = TestHelpers::new().await;
+    let name = TestHelpers::generate_name();
 
-        // Check if the category was created
-        if ctx
-            .cache
-            .guild(TEST_GUILD_ID)
-            .unwrap()
-            .channels
-            .iter()
-            .filter(|(_, channel)| {
-                if let ChannelType::Category = channel.kind {
-                    channel.name == test_team.name
-                } else {
-                    false
+    let channel_model = test_helper
+        .db
+        .run(
+            test_helper.guild_id,
+            ChannelCreateData {
+                name: name.clone(),
+                category_id: None,
+                kind: ChannelType::Text,
+            },
+        )
+        .await
+        .unwrap();
+    let channel_id = DiscordId::from(channel_model.discord_id);
+
+    test_helper
+        .discord
+        .delete_channel(channel_id)
+        .await
+        .unwrap();
+
+    // Deleting it again only has to clean up the database
+    test_helper
+        .db
+        .run(test_helper.guild_id, DeleteChannel { id: channel_id })
+        .await
+        .unwrap();
+
+    assert_eq!(
+        test_helper
+            .check_database_status(DatabaseConstruct::Channel { name })
+            .await,
+        DatabaseStatus::DoesNotExist
+    );
+
+    test_helper.cleanup().await;
+}
+
+#[tokio::test]
+async fn should_wait_for_scheduled_tasks_on_the_same_channel() {
+    let test_helper = TestHelpers::new().await;
+
+    let channel_model = test_helper
+        .db
+        .run(
+            test_helper.guild_id,
+            ChannelCreateData {
+                name: TestHelpers::generate_name(),
+                category_id: None,
+                kind: ChannelType::Text,
+            },
+        )
+        .await
+        .unwrap();
+    let channel_id = DiscordId::from(channel_model.discord_id);
+
+    // Scheduled for later, but added before the channel is deleted
+    let message_id = test_helper
+        .db
+        .add_task(
+            NewTask::new(
+                SendChannelMessage {
+                    channel_id,
+                    message: "Hello".to_string(),
+                    ..Default::default()
                 }
-            })
-            .count()
-            != 1
-        {
-            return Err(CategoryCreateError::CategoryNotCreated);
-        }
-
-        // Check if the category was saved to the database
-        if category::Entity::find()
-            .filter(category::Column::Name.eq(test_team.name.clone()))
-            .one(&*db)
-            .await
-            .unwrap()
-            .is_none()
-        {
-            return Err(CategoryCreateError::CategoryNotInDatabase);
-        }
-
-        // Check if the channel name is the team name
-
-        // TODO: Cleanup
-
-        Ok(())
-    }
+                .into_task(test_helper.guild_id),
+            )
+            .run_at(Utc::now() + Duration::seconds(2)),
+        )
+        .await;
+
+    test_helper
+        .db
+        .run(test_helper.guild_id, DeleteChannel { id: channel_id })
+        .await
+        .unwrap();
+
+    // The message went out before the channel was deleted
+    assert!(matches!(
+        test_helper.db.await_task(message_id).await,
+        TaskResult::Completed(_)
+    ));
+
+    test_helper.cleanup().await;
 }
@@ -0,0 +1,99 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use tracing::log;
+
+use super::{DiscordId, Task, TaskRun};
+use crate::{
+    db_wrapper::{retention::RetentionPolicy, DBWrapper, TaskResult, TaskReturnData},
+    discord::DiscordBackend,
+    game_mechanics::trade::{expire_trades, post_trade_update, TradeSummary},
+    task_runner::error::TaskError,
+};
+
+/// How often the retention job is added to the queue
+pub const RETENTION_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// How often trades that have run out of time are looked for
+pub const EXPIRE_TRADES_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+/// Housekeeping that isn't tied to a guild
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MaintenanceHandler {
+    pub task: MaintenanceTasks,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum MaintenanceTasks {
+    /// Prune old tasks and components that can't be used anymore
+    Retention(RetentionPolicy),
+    /// Expire trades nobody has done anything with for too long, and tell the
+    /// teams in them
+    ExpireTrades,
+}
+
+#[async_trait]
+impl Task for MaintenanceHandler {
+    async fn handle(
+        &self,
+        discord: &dyn DiscordBackend,
+        db: DBWrapper,
+        _run: TaskRun,
+    ) -> TaskResult {
+        match &self.task {
+            MaintenanceTasks::Retention(policy) => self.handle_retention(policy, discord, db).await,
+            MaintenanceTasks::ExpireTrades => self.handle_expire_trades(db).await,
+        }
+        .into()
+    }
+}
+
+impl MaintenanceHandler {
+    async fn handle_retention(
+        &self,
+        policy: &RetentionPolicy,
+        discord: &dyn DiscordBackend,
+        db: DBWrapper,
+    ) -> Result<TaskReturnData, TaskError> {
+        let pruned_tasks = db.prune_tasks(policy).await?;
+        let unsent_components = db.prune_unsent_components(policy).await?;
+
+        // Components in channels that are gone can't be pressed anymore.
+        // Channels Discord couldn't tell us about are left for the next run.
+        let mut gone_channels: Vec<i64> = Vec::new();
+        for channel_id in db.component_channels().await? {
+            match discord.channel_exists(DiscordId::from(channel_id)).await {
+                Ok(true) => {}
+                Ok(false) => gone_channels.push(channel_id),
+                Err(why) => log::warn!("Couldn't check if channel {} exists: {}", channel_id, why),
+            }
+        }
+        let orphaned_components = db.delete_components_in_channels(gone_channels).await?;
+
+        log::info!(
+            "Pruned {} tasks, {} unsent components and {} components in deleted channels",
+            pruned_tasks,
+            unsent_components,
+            orphaned_components
+        );
+
+        Ok(TaskReturnData::None)
+    }
+
+    async fn handle_expire_trades(&self, db: DBWrapper) -> Result<TaskReturnData, TaskError> {
+        let expired = expire_trades(&db).await?;
+
+        for database_trade in expired {
+            let guild_id = DiscordId::from(database_trade.fk_guild_id);
+            match TradeSummary::load(&db, database_trade).await {
+                Ok(summary) => {
+                    post_trade_update(&db, guild_id, &summary, "The trade expired").await
+                }
+                Err(why) => log::warn!("Couldn't tell teams about an expired trade: {}", why),
+            }
+        }
+
+        Ok(TaskReturnData::None)
+    }
+}
@@ -1,12 +1,17 @@
+use chrono::Utc;
 use entity::entities::message_component_data;
-use sea_orm::{ActiveModelTrait, Set};
+use sea_orm::{
+    sea_query::Expr, ActiveModelTrait, ColumnTrait, DbErr, EntityTrait, QueryFilter, Set,
+};
 use serde::{Deserialize, Serialize};
 use serenity::builder::{CreateButton, CreateSelectMenu};
 
 use uuid::Uuid;
 
 use crate::{
-    db_wrapper::DBWrapper, game_mechanics::MechanicFunction, task_runner::tasks::TaskType,
+    db_wrapper::{payload::PAYLOAD_VERSION, DBWrapper},
+    game_mechanics::MechanicFunction,
+    task_runner::tasks::{DiscordId, TaskType},
 };
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -29,8 +34,9 @@ impl<C: SerenityComponent> MessageComponent<C> {
     }
 
     /// Finalize the component and add it to the database, then return the
-    /// internal component
-    pub async fn build(self, db: DBWrapper) -> C {
+    /// internal component and the id of its data. The data is kept as unsent
+    /// until `mark_components_sent` is called with its id.
+    pub async fn build(self, db: DBWrapper) -> Result<(C, Uuid), DbErr> {
         // Serialize the data
         let data = serde_json::to_value(&self.data).unwrap();
 
@@ -38,15 +44,49 @@ impl<C: SerenityComponent> MessageComponent<C> {
         let database_data = message_component_data::ActiveModel {
             id_uuid: Set(Uuid::new_v4()),
             payload: Set(data),
+            payload_version: Set(PAYLOAD_VERSION),
+            sent: Set(false),
+            created_at: Set(Utc::now().into()),
             ..Default::default()
         }
         .insert(&*db)
-        .await
-        .unwrap();
+        .await?;
 
         // Update the component with the id
-        self.component.update_id(database_data.id_uuid)
+        Ok((
+            self.component.update_id(database_data.id_uuid),
+            database_data.id_uuid,
+        ))
+    }
+}
+
+/// Record the message that some built components were sent in, so they
+/// aren't cleaned up as unsent
+pub async fn mark_components_sent(
+    db: DBWrapper,
+    ids: Vec<Uuid>,
+    channel_id: DiscordId,
+    message_id: DiscordId,
+) -> Result<(), DbErr> {
+    if ids.is_empty() {
+        return Ok(());
     }
+
+    message_component_data::Entity::update_many()
+        .col_expr(message_component_data::Column::Sent, Expr::value(true))
+        .col_expr(
+            message_component_data::Column::ChannelId,
+            Expr::value(*channel_id as i64),
+        )
+        .col_expr(
+            message_component_data::Column::MessageId,
+            Expr::value(*message_id as i64),
+        )
+        .filter(message_component_data::Column::IdUuid.is_in(ids))
+        .exec(&*db)
+        .await?;
+
+    Ok(())
 }
 
 // pub async fn component_with_data<C: SerenityComponent>(
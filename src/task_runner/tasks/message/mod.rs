@@ -4,18 +4,23 @@ use async_trait::async_trait;
 
 use serde::{Deserialize, Serialize};
 use serenity::{
-    builder::{CreateButton, CreateMessage, CreateSelectMenu},
-    client::Context,
+    builder::{CreateButton, CreateSelectMenu},
+    http::{RatelimitingBucket, Route},
     model::prelude::ChannelId,
 };
-use tracing::log;
 
-use self::message_component::MessageComponent;
+use self::message_component::{mark_components_sent, MessageComponent};
 
-use super::{DiscordId, Task, TaskTest};
-use crate::db_wrapper::{helpers::get_guild, DBWrapper, TaskResult, TaskReturnData};
+use super::{unexpected_output, DiscordId, Task, TaskResource, TaskRun, TaskType, TypedTask};
+use crate::{
+    db_wrapper::{helpers::get_guild, DBWrapper, TaskResult, TaskReturnData},
+    discord::{DiscordBackend, OutgoingMessage},
+    task_runner::error::TaskError,
+};
 
 pub mod message_component;
+#[cfg(test)]
+mod tests;
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct MessageHandler {
@@ -47,15 +52,60 @@ impl Default for SendChannelMessage {
     }
 }
 
+impl TypedTask for SendChannelMessage {
+    /// The id of the sent message
+    type Output = DiscordId;
+
+    fn into_task(self, guild_id: DiscordId) -> TaskType {
+        TaskType::MessageHandler(MessageHandler {
+            guild_id,
+            task: MessageTasks::SendChannelMessage(self),
+        })
+    }
+
+    fn output(data: TaskReturnData) -> Result<Self::Output, TaskError> {
+        match data {
+            TaskReturnData::MessageId(id) => Ok(id),
+            data => Err(unexpected_output(data)),
+        }
+    }
+}
+
 #[async_trait]
 impl Task for MessageHandler {
-    async fn handle(&self, ctx: Context, db: DBWrapper) -> TaskResult {
+    async fn handle(
+        &self,
+        discord: &dyn DiscordBackend,
+        db: DBWrapper,
+        _run: TaskRun,
+    ) -> TaskResult {
         match &self.task {
             MessageTasks::SendChannelMessage(send_channel_message) => {
-                self.handle_send_channel_message(send_channel_message.clone(), ctx, db)
+                self.handle_send_channel_message(send_channel_message.clone(), discord, db)
                     .await
             }
         }
+        .into()
+    }
+
+    fn resources(&self) -> Vec<TaskResource> {
+        match &self.task {
+            // Keep messages in the same channel in order
+            MessageTasks::SendChannelMessage(task) => vec![TaskResource::Channel(task.channel_id)],
+        }
+    }
+
+    fn rate_limit_bucket(&self) -> Option<RatelimitingBucket> {
+        match &self.task {
+            MessageTasks::SendChannelMessage(task) => {
+                NonZeroU64::new(*task.channel_id).map(|channel_id| {
+                    Route::ChannelMessages {
+                        channel_id: ChannelId(channel_id),
+                    }
+                    .ratelimiting_bucket()
+                })
+            }
+        }
     }
 }
 
@@ -63,43 +113,52 @@ impl MessageHandler {
     async fn handle_send_channel_message(
         &self,
         send_channel_message: SendChannelMessage,
-        ctx: Context,
+        discord: &dyn DiscordBackend,
         db: DBWrapper,
-    ) -> TaskResult {
+    ) -> Result<TaskReturnData, TaskError> {
         let (_discord_guild, _database_guild) =
-            get_guild(ctx.clone(), db.clone(), self.guild_id).await;
+            get_guild(discord, db.clone(), self.guild_id).await?;
 
-        let channel_id = ChannelId(NonZeroU64::new(*send_channel_message.channel_id).unwrap());
+        if *send_channel_message.channel_id == 0 {
+            return Err(TaskError::permanent("Can't send a message to channel 0"));
+        }
+
+        // Set up the message
+        let mut message = OutgoingMessage {
+            content: send_channel_message.message,
+            ..Default::default()
+        };
 
-        // Set up the message builder
-        let mut message_builder = CreateMessage::new().content(send_channel_message.message);
+        let mut component_ids = Vec::new();
 
         // Add the select menu if there is one
         if let Some(select_menu) = send_channel_message.select_menu {
-            message_builder = message_builder.select_menu(select_menu.build(db.clone()).await);
+            let (select_menu, id) = select_menu.build(db.clone()).await?;
+            message.select_menu = Some(select_menu);
+            component_ids.push(id);
         }
 
         // Add any buttons
         for message_component_button in send_channel_message.buttons {
-            message_builder =
-                message_builder.button(message_component_button.build(db.clone()).await);
+            let (button, id) = message_component_button.build(db.clone()).await?;
+            message.buttons.push(button);
+            component_ids.push(id);
         }
 
         // Send the message
-        let message = channel_id
-            .send_message(&ctx.http, message_builder)
-            .await
-            .unwrap();
-
-        TaskResult::Completed(TaskReturnData::MessageId(DiscordId(message.id.into())))
-    }
-}
-
-#[async_trait]
-impl TaskTest for MessageHandler {
-    async fn run_tests(_ctx: Context, _db: DBWrapper) {
-        log::info!("Testing categories");
-        // assert_not_error(test_create_channel(ctx, db).await);
+        let message_id = discord
+            .send_message(send_channel_message.channel_id, message)
+            .await?;
+
+        mark_components_sent(
+            db,
+            component_ids,
+            send_channel_message.channel_id,
+            message_id,
+        )
+        .await?;
+
+        Ok(TaskReturnData::MessageId(message_id))
     }
 }
 
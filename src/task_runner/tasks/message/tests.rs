@@ -0,0 +1,85 @@
+use entity::entities::message_component_data;
+use sea_orm::EntityTrait;
+use serenity::{builder::CreateButton, model::prelude::ChannelType};
+
+use crate::task_runner::tasks::{
+    channel::ChannelCreateData,
+    message::{message_component::MessageComponent, SendChannelMessage},
+    test_helpers::TestHelpers,
+    DiscordId,
+};
+
+#[tokio::test]
+async fn should_send_message_with_components() {
+    let test_helper = TestHelpers::new().await;
+
+    let channel_model = test_helper
+        .db
+        .run(
+            test_helper.guild_id,
+            ChannelCreateData {
+                name: TestHelpers::generate_name(),
+                category_id: None,
+                kind: ChannelType::Text,
+            },
+        )
+        .await
+        .unwrap();
+    let channel_id = DiscordId::from(channel_model.discord_id);
+
+    let message_id = test_helper
+        .db
+        .run(
+            test_helper.guild_id,
+            SendChannelMessage {
+                channel_id,
+                message: "Hello!".to_string(),
+                buttons: vec![MessageComponent::new(CreateButton::new("hello"), None)],
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+
+    // Check that the message was sent
+    let messages = test_helper.discord.messages();
+    assert_eq!(messages.len(), 1);
+    assert_eq!(messages[0].id, message_id);
+    assert_eq!(messages[0].channel_id, channel_id);
+    assert_eq!(messages[0].content, "Hello!");
+    assert_eq!(messages[0].buttons, 1);
+
+    // Check that its button was saved as sent
+    let components = message_component_data::Entity::find()
+        .all(&*test_helper.db)
+        .await
+        .unwrap();
+    assert_eq!(components.len(), 1);
+    assert!(components[0].sent);
+    assert_eq!(components[0].channel_id, Some(*channel_id as i64));
+    assert_eq!(components[0].message_id, Some(*message_id as i64));
+
+    test_helper.cleanup().await;
+}
+
+#[tokio::test]
+async fn should_fail_to_send_to_missing_channel() {
+    let test_helper = TestHelpers::new().await;
+
+    let result = test_helper
+        .db
+        .run(
+            test_helper.guild_id,
+            SendChannelMessage {
+                channel_id: DiscordId(1),
+                message: "Hello?".to_string(),
+                ..Default::default()
+            },
+        )
+        .await;
+
+    assert!(result.is_err());
+    assert!(test_helper.discord.messages().is_empty());
+
+    test_helper.cleanup().await;
+}
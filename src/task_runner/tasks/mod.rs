@@ -1,4 +1,4 @@
-use std::{fmt::Debug, num::NonZeroU64, ops::Deref};
+use std::{num::NonZeroU64, ops::Deref};
 
 use async_trait::async_trait;
 
@@ -6,21 +6,28 @@ use sea_orm::{ActiveModelTrait, ActiveValue};
 use serde::{Deserialize, Serialize};
 use serenity::{
     all::{RoleId, UserId},
-    client::Context,
+    http::RatelimitingBucket,
     model::prelude::{ChannelId, GuildId},
 };
 
-use crate::db_wrapper::{DBWrapper, TaskResult};
+use crate::{
+    db_wrapper::{DBWrapper, TaskResult, TaskReturnData},
+    discord::DiscordBackend,
+    task_runner::error::TaskError,
+};
 
 use self::{
-    category::CategoryHandler, channel::ChannelHandler, message::MessageHandler, role::RoleHandler,
-    thread::ThreadHandler,
+    category::CategoryHandler, channel::ChannelHandler, maintenance::MaintenanceHandler,
+    message::MessageHandler, reconcile::ReconcileHandler, role::RoleHandler, thread::ThreadHandler,
 };
 
 pub mod category;
 pub mod channel;
+pub mod maintenance;
 pub mod message;
+pub mod reconcile;
 pub mod role;
+#[cfg(test)]
 pub mod test_helpers;
 pub mod thread;
 
@@ -38,7 +45,9 @@ pub struct DbTask {
 pub enum TaskType {
     CategoryHandler(CategoryHandler),
     ChannelHandler(ChannelHandler),
+    MaintenanceHandler(MaintenanceHandler),
     MessageHandler(MessageHandler),
+    ReconcileHandler(ReconcileHandler),
     RoleHandler(RoleHandler),
     ThreadHandler(ThreadHandler),
 }
@@ -48,38 +57,102 @@ impl TaskType {
         match self {
             TaskType::CategoryHandler(task_handler) => task_handler,
             TaskType::ChannelHandler(task_handler) => task_handler,
+            TaskType::MaintenanceHandler(task_handler) => task_handler,
             TaskType::MessageHandler(task_handler) => task_handler,
+            TaskType::ReconcileHandler(task_handler) => task_handler,
             TaskType::RoleHandler(task_handler) => task_handler,
             TaskType::ThreadHandler(task_handler) => task_handler,
         }
     }
+
+    /// The guild the task is for, if it's for one
+    pub fn guild_id(&self) -> Option<DiscordId> {
+        match self {
+            TaskType::CategoryHandler(task_handler) => Some(task_handler.guild_id),
+            TaskType::ChannelHandler(task_handler) => Some(task_handler.guild_id),
+            TaskType::MaintenanceHandler(_) => None,
+            TaskType::MessageHandler(task_handler) => Some(task_handler.guild_id),
+            TaskType::ReconcileHandler(task_handler) => Some(task_handler.guild_id),
+            TaskType::RoleHandler(task_handler) => Some(task_handler.guild_id),
+            TaskType::ThreadHandler(task_handler) => Some(DiscordId(task_handler.guild_id)),
+        }
+    }
 }
 
 #[async_trait]
 pub trait Task: Send + Sync {
-    async fn handle(&self, ctx: Context, db: DBWrapper) -> TaskResult;
+    /// Run the task. All Discord calls go through `discord`, so tasks can be
+    /// run against a `FakeDiscord`.
+    async fn handle(&self, discord: &dyn DiscordBackend, db: DBWrapper, run: TaskRun)
+        -> TaskResult;
+
+    /// The guild resources this task touches. Tasks that share a resource are
+    /// never run at the same time, and run in the order they were added.
+    fn resources(&self) -> Vec<TaskResource> {
+        Vec::new()
+    }
+
+    /// The Discord rate limit bucket of the main request this task makes,
+    /// so less urgent tasks can hold back when it's running low
+    fn rate_limit_bucket(&self) -> Option<RatelimitingBucket> {
+        None
+    }
 }
 
-#[async_trait]
-pub trait TaskTest: Send + Sync {
-    async fn run_tests(ctx: Context, db: DBWrapper);
+/// The attempt of a task that's being handled
+#[derive(Debug, Clone)]
+pub struct TaskRun {
+    pub id: DatabaseId,
+    /// The same for every attempt of the task. Anything the task creates is
+    /// saved with this, so if the bot stops before the task is marked as
+    /// completed, the next attempt can find it instead of creating another.
+    pub idempotency_key: String,
+    /// Starts at 1
+    pub attempt: i32,
 }
 
-pub async fn run_tests(ctx: Context, db: DBWrapper) {
-    CategoryHandler::run_tests(ctx, db).await;
+impl TaskRun {
+    /// Whether an earlier attempt might have already done some of the work
+    pub fn is_retry(&self) -> bool {
+        self.attempt > 1
+    }
 }
 
-pub fn assert_not_error<T>(result: Result<(), T>)
-where
-    T: Debug,
-{
-    match result {
-        Ok(_) => {}
-        Err(e) => panic!("Error: {:?}", e),
+/// A task with a known output type, so callers get back e.g. a
+/// `channel::Model` instead of having to match on `TaskReturnData`. These are
+/// run with `DBWrapper::run`.
+pub trait TypedTask: Send {
+    type Output;
+
+    /// The task to add to the queue
+    fn into_task(self, guild_id: DiscordId) -> TaskType;
+
+    /// Get the output out of what the task returned
+    fn output(data: TaskReturnData) -> Result<Self::Output, TaskError>;
+
+    /// A task that undoes this one, given its output. A `Saga` adds this if a
+    /// later step fails.
+    fn undo(_guild_id: DiscordId, _output: &Self::Output) -> Option<TaskType> {
+        None
     }
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+/// The error for a task that returned something other than what its
+/// `TypedTask` impl expects
+pub fn unexpected_output(data: TaskReturnData) -> TaskError {
+    TaskError::permanent(format!("The task returned unexpected data: {:?}", data))
+}
+
+/// A guild resource that tasks can touch. Categories are channels as far as
+/// Discord is concerned, so they're a `Channel` as well.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TaskResource {
+    Channel(DiscordId),
+    Role(DiscordId),
+    Member(DiscordId),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct DiscordId(pub u64);
 
 impl Deref for DiscordId {
@@ -181,7 +254,7 @@ impl From<i64> for DiscordId {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct DatabaseId(pub i32);
 
 impl Deref for DatabaseId {
@@ -0,0 +1,512 @@
+use std::{collections::HashSet, fmt, time::Duration};
+
+use async_trait::async_trait;
+use entity::entities::{category, channel, guild, player, role, team};
+use sea_orm::{ActiveModelTrait, ColumnTrait, EntityTrait, QueryFilter, Set};
+use serde::{Deserialize, Serialize};
+use serenity::model::channel::ChannelType;
+use tracing::log;
+
+use super::{unexpected_output, DiscordId, Task, TaskRun, TaskType, TypedTask};
+use crate::{
+    db_wrapper::{
+        helpers::{
+            forget_resource, get_guild, guild_settings, move_resource, save_channel_kind,
+            saved_channel_kind, team_using, TrackedKind,
+        },
+        DBWrapper, TaskResult, TaskReturnData,
+    },
+    discord::{DiscordBackend, DiscordGuild},
+    task_runner::error::TaskError,
+};
+
+#[cfg(test)]
+mod tests;
+
+/// How often every guild is checked for drift. These checks only report what
+/// they find.
+pub const RECONCILE_INTERVAL: Duration = Duration::from_secs(6 * 60 * 60);
+
+/// Compare the channels, categories and roles in the database with the ones
+/// in the guild
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ReconcileHandler {
+    pub guild_id: DiscordId,
+    /// Fix what's found instead of only reporting it
+    pub repair: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Reconcile {
+    pub repair: bool,
+}
+
+impl TypedTask for Reconcile {
+    type Output = ReconcileReport;
+
+    fn into_task(self, guild_id: DiscordId) -> TaskType {
+        TaskType::ReconcileHandler(ReconcileHandler {
+            guild_id,
+            repair: self.repair,
+        })
+    }
+
+    fn output(data: TaskReturnData) -> Result<Self::Output, TaskError> {
+        match data {
+            TaskReturnData::ReconcileReport(report) => Ok(report),
+            data => Err(unexpected_output(data)),
+        }
+    }
+}
+
+/// A guild resource, as either the database or the guild knows it
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Resource {
+    pub kind: TrackedKind,
+    pub id: DiscordId,
+    pub name: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum Repair {
+    /// The database now has the name the resource has in the guild
+    Renamed {
+        resource: Resource,
+        old_name: String,
+    },
+    /// A resource in the guild with the same name is tracked in place of the
+    /// missing one
+    Adopted {
+        missing: Resource,
+        adopted: DiscordId,
+    },
+    /// A team's category, role or channel was created again
+    Recreated {
+        missing: Resource,
+        created: DiscordId,
+    },
+    /// The missing resource was removed from the database
+    Dropped { missing: Resource },
+}
+
+/// What a reconcile found, and what it did about it if it was repairing
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct ReconcileReport {
+    /// In the database, but not in the guild anymore
+    pub missing: Vec<Resource>,
+    /// In the guild, but not in the database. These are never touched, other
+    /// than being adopted in place of a missing resource.
+    pub orphaned: Vec<Resource>,
+    /// In both, but under a different name in the guild. This has the name
+    /// the database has.
+    pub renamed: Vec<(Resource, String)>,
+    pub repairs: Vec<Repair>,
+}
+
+impl ReconcileReport {
+    pub fn is_clean(&self) -> bool {
+        self.missing.is_empty() && self.renamed.is_empty()
+    }
+}
+
+impl fmt::Display for ReconcileReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} missing, {} untracked and {} renamed",
+            self.missing.len(),
+            self.orphaned.len(),
+            self.renamed.len()
+        )?;
+
+        for resource in &self.missing {
+            writeln!(
+                f,
+                "Missing {:?} {} ({})",
+                resource.kind, resource.name, *resource.id
+            )?;
+        }
+        for resource in &self.orphaned {
+            writeln!(
+                f,
+                "Untracked {:?} {} ({})",
+                resource.kind, resource.name, *resource.id
+            )?;
+        }
+        for (resource, guild_name) in &self.renamed {
+            writeln!(
+                f,
+                "{:?} {} is called {} in the guild",
+                resource.kind, resource.name, guild_name
+            )?;
+        }
+        for repair in &self.repairs {
+            match repair {
+                Repair::Renamed { resource, old_name } => {
+                    writeln!(f, "Renamed {} to {}", old_name, resource.name)?
+                }
+                Repair::Adopted { missing, adopted } => {
+                    writeln!(f, "Adopted {} in place of {}", **adopted, missing.name)?
+                }
+                Repair::Recreated { missing, .. } => writeln!(f, "Recreated {}", missing.name)?,
+                Repair::Dropped { missing } => writeln!(f, "Dropped {}", missing.name)?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Task for ReconcileHandler {
+    async fn handle(
+        &self,
+        discord: &dyn DiscordBackend,
+        db: DBWrapper,
+        _run: TaskRun,
+    ) -> TaskResult {
+        self.handle_reconcile(discord, db)
+            .await
+            .map(TaskReturnData::ReconcileReport)
+            .into()
+    }
+}
+
+impl ReconcileHandler {
+    async fn handle_reconcile(
+        &self,
+        discord: &dyn DiscordBackend,
+        db: DBWrapper,
+    ) -> Result<ReconcileReport, TaskError> {
+        let (discord_guild, database_guild) = get_guild(discord, db.clone(), self.guild_id).await?;
+
+        let tracked = self.tracked_resources(&db).await?;
+        let in_guild = guild_resources(&discord_guild);
+
+        let mut report = ReconcileReport::default();
+
+        for resource in &tracked {
+            match in_guild
+                .iter()
+                .find(|guild_resource| guild_resource.id == resource.id)
+            {
+                Some(guild_resource) if guild_resource.name != resource.name => report
+                    .renamed
+                    .push((resource.clone(), guild_resource.name.clone())),
+                Some(_) => {}
+                None => report.missing.push(resource.clone()),
+            }
+        }
+
+        let tracked_ids: HashSet<DiscordId> = tracked.iter().map(|resource| resource.id).collect();
+        report.orphaned = in_guild
+            .into_iter()
+            .filter(|resource| !tracked_ids.contains(&resource.id))
+            .collect();
+
+        if self.repair {
+            self.repair_drift(&mut report, discord, &discord_guild, &database_guild, &db)
+                .await?;
+        }
+
+        if !report.is_clean() {
+            log::warn!("Guild {} has drifted: {}", *self.guild_id, report);
+        }
+
+        Ok(report)
+    }
+
+    /// Everything the database has for this guild. Categories and roles come
+    /// first, so that a repaired category is in place before the channels in
+    /// it are repaired.
+    async fn tracked_resources(&self, db: &DBWrapper) -> Result<Vec<Resource>, TaskError> {
+        let guild_id = *self.guild_id as i64;
+        let mut resources = Vec::new();
+
+        for category in category::Entity::find()
+            .filter(category::Column::FkGuildId.eq(guild_id))
+            .all(&**db)
+            .await?
+        {
+            resources.push(Resource {
+                kind: TrackedKind::Category,
+                id: DiscordId::from(category.discord_id),
+                name: category.name,
+            });
+        }
+
+        for role in role::Entity::find()
+            .filter(role::Column::FkGuildId.eq(guild_id))
+            .all(&**db)
+            .await?
+        {
+            resources.push(Resource {
+                kind: TrackedKind::Role,
+                id: DiscordId::from(role.discord_id),
+                name: role.name,
+            });
+        }
+
+        for channel in channel::Entity::find()
+            .filter(channel::Column::FkGuildId.eq(guild_id))
+            .all(&**db)
+            .await?
+        {
+            resources.push(Resource {
+                kind: TrackedKind::Channel,
+                id: DiscordId::from(channel.discord_id),
+                name: channel.name,
+            });
+        }
+
+        Ok(resources)
+    }
+
+    async fn repair_drift(
+        &self,
+        report: &mut ReconcileReport,
+        discord: &dyn DiscordBackend,
+        discord_guild: &DiscordGuild,
+        database_guild: &guild::Model,
+        db: &DBWrapper,
+    ) -> Result<(), TaskError> {
+        // The guild is right about names
+        for (resource, guild_name) in &report.renamed {
+            rename_resource(db, resource, guild_name).await?;
+
+            report.repairs.push(Repair::Renamed {
+                resource: Resource {
+                    name: guild_name.clone(),
+                    ..resource.clone()
+                },
+                old_name: resource.name.clone(),
+            });
+        }
+
+        let mut adoptable = report.orphaned.clone();
+        // The categories a recreated channel can go in, including ones
+        // recreated along the way
+        let mut categories: HashSet<DiscordId> = discord_guild
+            .channels
+            .iter()
+            .filter(|discord_channel| discord_channel.kind == ChannelType::Category)
+            .map(|discord_channel| discord_channel.id)
+            .collect();
+
+        for missing in &report.missing {
+            // Someone might have made it again by hand
+            if let Some(index) = adoptable
+                .iter()
+                .position(|orphan| orphan.kind == missing.kind && orphan.name == missing.name)
+            {
+                let adopted = adoptable.remove(index);
+                move_resource(
+                    db,
+                    missing.kind,
+                    self.guild_id,
+                    missing.id,
+                    adopted.id,
+                    &missing.name,
+                )
+                .await?;
+                if let Some(discord_channel) = discord_guild
+                    .channels
+                    .iter()
+                    .find(|discord_channel| discord_channel.id == adopted.id)
+                    .filter(|_| missing.kind == TrackedKind::Channel)
+                {
+                    save_channel_kind(&**db, adopted.id, discord_channel.kind).await?;
+                }
+
+                report.repairs.push(Repair::Adopted {
+                    missing: missing.clone(),
+                    adopted: adopted.id,
+                });
+                continue;
+            }
+
+            // Teams can't do without their category, role and channels
+            if let Some(team) = team_using(&**db, missing.kind, self.guild_id, missing.id).await? {
+                let created = self
+                    .recreate(missing, &team, discord, database_guild, &categories, db)
+                    .await?;
+                if missing.kind == TrackedKind::Category {
+                    categories.insert(created);
+                }
+
+                report.repairs.push(Repair::Recreated {
+                    missing: missing.clone(),
+                    created,
+                });
+                continue;
+            }
+
+            forget_resource(db, missing.kind, missing.id).await?;
+
+            report.repairs.push(Repair::Dropped {
+                missing: missing.clone(),
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Make a team's missing resource again and track it in place of the old
+    /// one. A channel keeps its kind and goes back in the team's category, and
+    /// a role goes back to the team's players with the guild's team color.
+    async fn recreate(
+        &self,
+        missing: &Resource,
+        team: &team::Model,
+        discord: &dyn DiscordBackend,
+        database_guild: &guild::Model,
+        categories: &HashSet<DiscordId>,
+        db: &DBWrapper,
+    ) -> Result<DiscordId, TaskError> {
+        let (created, channel_kind) = match missing.kind {
+            TrackedKind::Category => {
+                let created = discord
+                    .create_category(self.guild_id, &missing.name)
+                    .await?;
+                (created.id, None)
+            }
+            TrackedKind::Role => {
+                let settings = guild_settings(database_guild)?;
+                let created = discord
+                    .create_role(self.guild_id, &missing.name, settings.team_role_color)
+                    .await?;
+                (created.id, None)
+            }
+            TrackedKind::Channel => {
+                let kind = channel::Entity::find_by_id(*missing.id as i64)
+                    .one(&**db)
+                    .await?
+                    .map_or(ChannelType::Text, |database_channel| {
+                        saved_channel_kind(&database_channel)
+                    });
+                // The team's category might have been recreated already, in
+                // which case the team points at the new one
+                let parent_id = team
+                    .fk_team_category_id
+                    .map(DiscordId::from)
+                    .filter(|parent_id| categories.contains(parent_id));
+
+                let created = discord
+                    .create_channel(self.guild_id, &missing.name, kind, parent_id)
+                    .await?;
+                (created.id, Some(kind))
+            }
+        };
+
+        move_resource(
+            db,
+            missing.kind,
+            self.guild_id,
+            missing.id,
+            created,
+            &missing.name,
+        )
+        .await?;
+
+        if let Some(kind) = channel_kind {
+            save_channel_kind(&**db, created, kind).await?;
+        }
+        if missing.kind == TrackedKind::Role {
+            self.give_team_role(team, created, discord, db).await?;
+        }
+
+        Ok(created)
+    }
+
+    /// Give every player on a team its recreated role. A player that's left
+    /// the guild is skipped.
+    async fn give_team_role(
+        &self,
+        team: &team::Model,
+        role_id: DiscordId,
+        discord: &dyn DiscordBackend,
+        db: &DBWrapper,
+    ) -> Result<(), TaskError> {
+        for database_player in player::Entity::find()
+            .filter(player::Column::FkTeamId.eq(team.id))
+            .all(&**db)
+            .await?
+        {
+            let user_id = DiscordId::from(database_player.discord_id);
+            if let Err(why) = discord
+                .add_member_role(self.guild_id, user_id, role_id)
+                .await
+            {
+                log::warn!(
+                    "Couldn't give {} the role of team {}: {}",
+                    database_player.name,
+                    team.name,
+                    why
+                );
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Everything in the guild that could be tracked. The `@everyone` role has
+/// the guild's id, and is left out since the bot never makes it.
+pub(crate) fn guild_resources(discord_guild: &DiscordGuild) -> Vec<Resource> {
+    let channels = discord_guild
+        .channels
+        .iter()
+        .map(|discord_channel| Resource {
+            kind: TrackedKind::of_channel(discord_channel.kind),
+            id: discord_channel.id,
+            name: discord_channel.name.clone(),
+        });
+
+    let roles = discord_guild
+        .roles
+        .iter()
+        .filter(|discord_role| discord_role.id != discord_guild.id)
+        .map(|discord_role| Resource {
+            kind: TrackedKind::Role,
+            id: discord_role.id,
+            name: discord_role.name.clone(),
+        });
+
+    channels.chain(roles).collect()
+}
+
+async fn rename_resource(db: &DBWrapper, resource: &Resource, name: &str) -> Result<(), TaskError> {
+    let id = *resource.id as i64;
+
+    match resource.kind {
+        TrackedKind::Channel => {
+            channel::ActiveModel {
+                discord_id: Set(id),
+                name: Set(name.to_string()),
+                ..Default::default()
+            }
+            .update(&**db)
+            .await?;
+        }
+        TrackedKind::Category => {
+            category::ActiveModel {
+                discord_id: Set(id),
+                name: Set(name.to_string()),
+                ..Default::default()
+            }
+            .update(&**db)
+            .await?;
+        }
+        TrackedKind::Role => {
+            role::ActiveModel {
+                discord_id: Set(id),
+                name: Set(name.to_string()),
+                ..Default::default()
+            }
+            .update(&**db)
+            .await?;
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,309 @@
+use entity::entities::{channel, player, team};
+use sea_orm::{ActiveModelTrait, ColumnTrait, EntityTrait, QueryFilter, Set};
+use serenity::model::prelude::ChannelType;
+
+use crate::{
+    db_wrapper::helpers::TrackedKind,
+    discord::DiscordBackend,
+    game_mechanics::{
+        team::{TeamJobs, TeamMechanicsHandler},
+        MechanicHandler, MechanicHandlerWrapper,
+    },
+    task_runner::tasks::{
+        channel::ChannelCreateData,
+        reconcile::{Reconcile, Repair},
+        test_helpers::{DatabaseConstruct, DatabaseStatus, TestHelpers},
+        DiscordId,
+    },
+};
+
+async fn create_channel(test_helper: &TestHelpers, name: &str) -> DiscordId {
+    let channel_model = test_helper
+        .db
+        .run(
+            test_helper.guild_id,
+            ChannelCreateData {
+                name: name.to_string(),
+                category_id: None,
+                kind: ChannelType::Text,
+            },
+        )
+        .await
+        .unwrap();
+
+    DiscordId::from(channel_model.discord_id)
+}
+
+async fn find_team(test_helper: &TestHelpers, name: &str) -> team::Model {
+    team::Entity::find()
+        .filter(team::Column::Name.eq(name))
+        .one(&*test_helper.db)
+        .await
+        .unwrap()
+        .unwrap()
+}
+
+#[tokio::test]
+async fn should_find_nothing_in_a_guild_that_matches() {
+    let test_helper = TestHelpers::new().await;
+    create_channel(&test_helper, &TestHelpers::generate_name()).await;
+
+    let report = test_helper
+        .db
+        .run(test_helper.guild_id, Reconcile { repair: false })
+        .await
+        .unwrap();
+
+    assert!(report.is_clean());
+    assert!(report.repairs.is_empty());
+
+    test_helper.cleanup().await;
+}
+
+#[tokio::test]
+async fn should_drop_channel_deleted_by_hand() {
+    let test_helper = TestHelpers::new().await;
+    let name = TestHelpers::generate_name();
+    let channel_id = create_channel(&test_helper, &name).await;
+
+    test_helper
+        .discord
+        .delete_channel(channel_id)
+        .await
+        .unwrap();
+
+    // Only reporting leaves the database alone
+    let report = test_helper
+        .db
+        .run(test_helper.guild_id, Reconcile { repair: false })
+        .await
+        .unwrap();
+
+    assert_eq!(report.missing.len(), 1);
+    assert_eq!(report.missing[0].kind, TrackedKind::Channel);
+    assert_eq!(report.missing[0].id, channel_id);
+    assert_eq!(
+        test_helper
+            .check_database_status(DatabaseConstruct::Channel { name: name.clone() })
+            .await,
+        DatabaseStatus::Exists
+    );
+
+    let report = test_helper
+        .db
+        .run(test_helper.guild_id, Reconcile { repair: true })
+        .await
+        .unwrap();
+
+    assert!(matches!(report.repairs[..], [Repair::Dropped { .. }]));
+    assert_eq!(
+        test_helper
+            .check_database_status(DatabaseConstruct::Channel { name })
+            .await,
+        DatabaseStatus::DoesNotExist
+    );
+
+    test_helper.cleanup().await;
+}
+
+#[tokio::test]
+async fn should_adopt_channel_recreated_by_hand() {
+    let test_helper = TestHelpers::new().await;
+    let name = TestHelpers::generate_name();
+    let channel_id = create_channel(&test_helper, &name).await;
+
+    test_helper
+        .discord
+        .delete_channel(channel_id)
+        .await
+        .unwrap();
+    let recreated = test_helper
+        .discord
+        .create_channel(test_helper.guild_id, &name, ChannelType::Text, None)
+        .await
+        .unwrap();
+
+    let report = test_helper
+        .db
+        .run(test_helper.guild_id, Reconcile { repair: true })
+        .await
+        .unwrap();
+
+    assert_eq!(
+        report.repairs,
+        vec![Repair::Adopted {
+            missing: report.missing[0].clone(),
+            adopted: recreated.id,
+        }]
+    );
+
+    let channel_model = channel::Entity::find()
+        .filter(channel::Column::Name.eq(name))
+        .one(&*test_helper.db)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(DiscordId::from(channel_model.discord_id), recreated.id);
+
+    test_helper.cleanup().await;
+}
+
+#[tokio::test]
+async fn should_take_names_from_the_guild() {
+    let test_helper = TestHelpers::new().await;
+    let channel_id = create_channel(&test_helper, &TestHelpers::generate_name()).await;
+
+    let new_name = TestHelpers::generate_name();
+    test_helper.discord.rename_channel(channel_id, &new_name);
+
+    let report = test_helper
+        .db
+        .run(test_helper.guild_id, Reconcile { repair: true })
+        .await
+        .unwrap();
+
+    assert_eq!(report.renamed.len(), 1);
+    assert_eq!(
+        test_helper
+            .check_database_status(DatabaseConstruct::Channel { name: new_name })
+            .await,
+        DatabaseStatus::Exists
+    );
+
+    test_helper.cleanup().await;
+}
+
+#[tokio::test]
+async fn should_recreate_missing_team_channel() {
+    let test_helper = TestHelpers::new().await;
+    let name = TestHelpers::generate_name();
+
+    TeamMechanicsHandler {
+        guild_id: test_helper.guild_id,
+        task: TeamJobs::CreateTeam { name: name.clone() },
+    }
+    .handle(MechanicHandlerWrapper {
+        db: test_helper.db.clone(),
+        interaction: None,
+        discord: test_helper.discord.clone(),
+    })
+    .await;
+
+    let menu_channel_id = DiscordId::from(
+        find_team(&test_helper, &name)
+            .await
+            .fk_menu_channel_id
+            .unwrap(),
+    );
+    test_helper
+        .discord
+        .delete_channel(menu_channel_id)
+        .await
+        .unwrap();
+
+    let report = test_helper
+        .db
+        .run(test_helper.guild_id, Reconcile { repair: true })
+        .await
+        .unwrap();
+
+    let created = match &report.repairs[..] {
+        [Repair::Recreated { created, .. }] => *created,
+        repairs => panic!("Expected the channel to be recreated, got {:?}", repairs),
+    };
+
+    // The team uses the new channel
+    assert_eq!(
+        find_team(&test_helper, &name).await.fk_menu_channel_id,
+        Some(*created as i64)
+    );
+    assert!(test_helper.discord.channel(created).await.is_some());
+
+    test_helper.cleanup().await;
+}
+
+#[tokio::test]
+async fn should_recreate_missing_team_category_and_role_before_channels() {
+    let test_helper = TestHelpers::new().await;
+    let name = TestHelpers::generate_name();
+
+    let old_team = test_helper.create_team(&name).await;
+    let user_id = test_helper.discord.add_member(test_helper.guild_id);
+    player::ActiveModel {
+        discord_id: Set(*user_id as i64),
+        fk_guild_id: Set(*test_helper.guild_id as i64),
+        name: Set(TestHelpers::generate_name()),
+        fk_team_id: Set(Some(old_team.id)),
+        ..Default::default()
+    }
+    .insert(&*test_helper.db)
+    .await
+    .unwrap();
+
+    // Everything the team has in the guild goes, apart from its channels
+    // other than the menu
+    for channel_id in [
+        old_team.fk_menu_channel_id.unwrap(),
+        old_team.fk_team_category_id.unwrap(),
+    ] {
+        test_helper
+            .discord
+            .delete_channel(DiscordId::from(channel_id))
+            .await
+            .unwrap();
+    }
+    test_helper
+        .discord
+        .delete_role(
+            test_helper.guild_id,
+            DiscordId::from(old_team.fk_team_role_id.unwrap()),
+        )
+        .await
+        .unwrap();
+
+    let report = test_helper
+        .db
+        .run(test_helper.guild_id, Reconcile { repair: true })
+        .await
+        .unwrap();
+
+    let recreated: Vec<_> = report
+        .repairs
+        .iter()
+        .filter_map(|repair| match repair {
+            Repair::Recreated { missing, .. } => Some(missing.kind),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(
+        recreated,
+        [
+            TrackedKind::Category,
+            TrackedKind::Role,
+            TrackedKind::Channel
+        ]
+    );
+
+    // The team uses the new ones, and the menu is back in the category
+    let new_team = find_team(&test_helper, &name).await;
+    let category_id = DiscordId::from(new_team.fk_team_category_id.unwrap());
+    let role_id = DiscordId::from(new_team.fk_team_role_id.unwrap());
+    let menu_channel = test_helper
+        .discord
+        .channel(DiscordId::from(new_team.fk_menu_channel_id.unwrap()))
+        .await
+        .unwrap();
+    assert_eq!(menu_channel.kind, ChannelType::Text);
+    assert_eq!(menu_channel.parent_id, Some(category_id));
+
+    // And its player has the new role
+    let member = test_helper
+        .discord
+        .member(test_helper.guild_id, user_id)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(member.roles, [role_id]);
+
+    test_helper.cleanup().await;
+}
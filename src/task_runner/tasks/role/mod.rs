@@ -1,15 +1,23 @@
 use async_trait::async_trait;
 
 use entity::entities::role;
-use sea_orm::{ActiveModelTrait, Set};
+use sea_orm::{ColumnTrait, DbErr, EntityTrait, QueryFilter};
 use serde::{Deserialize, Serialize};
-use serenity::{builder::EditRole, client::Context};
+use serenity::http::{RatelimitingBucket, Route};
 use tracing::log;
 
-use super::{DiscordId, Task, TaskTest};
-use crate::db_wrapper::{helpers::get_guild, DBWrapper, TaskResult, TaskReturnData};
+use super::{unexpected_output, DiscordId, Task, TaskResource, TaskRun, TaskType, TypedTask};
+use crate::{
+    db_wrapper::{
+        helpers::{find_untracked_role, forget_resource, get_guild, save_resource, TrackedKind},
+        DBWrapper, TaskResult, TaskReturnData,
+    },
+    discord::DiscordBackend,
+    task_runner::error::TaskError,
+};
 
-// pub mod tests;
+#[cfg(test)]
+mod tests;
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct RoleHandler {
@@ -48,25 +56,150 @@ pub struct RemoveRoleFromUser {
     pub role_id: DiscordId,
 }
 
+impl TypedTask for CreateRole {
+    type Output = role::Model;
+
+    fn into_task(self, guild_id: DiscordId) -> TaskType {
+        TaskType::RoleHandler(RoleHandler {
+            guild_id,
+            task: RoleTasks::CreateRole(self),
+        })
+    }
+
+    fn output(data: TaskReturnData) -> Result<Self::Output, TaskError> {
+        match data {
+            TaskReturnData::RoleModel(role) => Ok(role),
+            data => Err(unexpected_output(data)),
+        }
+    }
+
+    fn undo(guild_id: DiscordId, role: &Self::Output) -> Option<TaskType> {
+        Some(
+            DeleteRole {
+                role_id: DiscordId::from(role.discord_id),
+            }
+            .into_task(guild_id),
+        )
+    }
+}
+
+impl TypedTask for DeleteRole {
+    type Output = ();
+
+    fn into_task(self, guild_id: DiscordId) -> TaskType {
+        TaskType::RoleHandler(RoleHandler {
+            guild_id,
+            task: RoleTasks::DeleteRole(self),
+        })
+    }
+
+    fn output(data: TaskReturnData) -> Result<Self::Output, TaskError> {
+        match data {
+            TaskReturnData::None => Ok(()),
+            data => Err(unexpected_output(data)),
+        }
+    }
+}
+
+impl TypedTask for AddRoleToUser {
+    type Output = ();
+
+    fn into_task(self, guild_id: DiscordId) -> TaskType {
+        TaskType::RoleHandler(RoleHandler {
+            guild_id,
+            task: RoleTasks::AddRoleToUser(self),
+        })
+    }
+
+    fn output(data: TaskReturnData) -> Result<Self::Output, TaskError> {
+        match data {
+            TaskReturnData::None => Ok(()),
+            data => Err(unexpected_output(data)),
+        }
+    }
+}
+
+impl TypedTask for RemoveRoleFromUser {
+    type Output = ();
+
+    fn into_task(self, guild_id: DiscordId) -> TaskType {
+        TaskType::RoleHandler(RoleHandler {
+            guild_id,
+            task: RoleTasks::RemoveRoleFromUser(self),
+        })
+    }
+
+    fn output(data: TaskReturnData) -> Result<Self::Output, TaskError> {
+        match data {
+            TaskReturnData::None => Ok(()),
+            data => Err(unexpected_output(data)),
+        }
+    }
+}
+
 #[async_trait]
 impl Task for RoleHandler {
-    async fn handle(&self, ctx: Context, db: DBWrapper) -> TaskResult {
+    async fn handle(
+        &self,
+        discord: &dyn DiscordBackend,
+        db: DBWrapper,
+        run: TaskRun,
+    ) -> TaskResult {
         match &self.task {
             RoleTasks::CreateRole(create_role_task) => {
-                self.handle_role_create(create_role_task, ctx, db).await
+                self.handle_role_create(create_role_task, discord, db, &run)
+                    .await
             }
             RoleTasks::DeleteRole(delete_role_task) => {
-                self.handle_role_delete(delete_role_task, ctx, db).await
+                self.handle_role_delete(delete_role_task, discord, db).await
             }
             RoleTasks::AddRoleToUser(add_role_to_user_task) => {
-                self.handle_add_role_to_user(add_role_to_user_task, ctx, db)
+                self.handle_add_role_to_user(add_role_to_user_task, discord, db)
                     .await
             }
             RoleTasks::RemoveRoleFromUser(remove_role_from_user_task) => {
-                self.handle_remove_role_from_user(remove_role_from_user_task, ctx, db)
+                self.handle_remove_role_from_user(remove_role_from_user_task, discord, db)
                     .await
             }
         }
+        .into()
+    }
+
+    fn resources(&self) -> Vec<TaskResource> {
+        match &self.task {
+            RoleTasks::CreateRole(_) => Vec::new(),
+            RoleTasks::DeleteRole(task) => vec![TaskResource::Role(task.role_id)],
+            RoleTasks::AddRoleToUser(task) => vec![
+                TaskResource::Role(task.role_id),
+                TaskResource::Member(task.user_id),
+            ],
+            RoleTasks::RemoveRoleFromUser(task) => vec![
+                TaskResource::Role(task.role_id),
+                TaskResource::Member(task.user_id),
+            ],
+        }
+    }
+
+    fn rate_limit_bucket(&self) -> Option<RatelimitingBucket> {
+        let guild_id = self.guild_id.into();
+
+        Some(match &self.task {
+            RoleTasks::CreateRole(_) => Route::GuildRoles { guild_id }.ratelimiting_bucket(),
+            RoleTasks::DeleteRole(task) => Route::GuildRole {
+                guild_id,
+                role_id: task.role_id.into(),
+            }
+            .ratelimiting_bucket(),
+            RoleTasks::AddRoleToUser(AddRoleToUser { user_id, role_id })
+            | RoleTasks::RemoveRoleFromUser(RemoveRoleFromUser { user_id, role_id }) => {
+                Route::GuildMemberRole {
+                    guild_id,
+                    user_id: (*user_id).into(),
+                    role_id: (*role_id).into(),
+                }
+                .ratelimiting_bucket()
+            }
+        })
     }
 }
 
@@ -74,94 +207,125 @@ impl RoleHandler {
     async fn handle_role_create(
         &self,
         task: &CreateRole,
-        ctx: Context,
+        discord: &dyn DiscordBackend,
         db: DBWrapper,
-    ) -> TaskResult {
+        run: &TaskRun,
+    ) -> Result<TaskReturnData, TaskError> {
+        // An earlier attempt might have already created and saved it
+        if let Some(role_database) = role::Entity::find()
+            .filter(role::Column::IdempotencyKey.eq(run.idempotency_key.clone()))
+            .one(&*db)
+            .await?
+        {
+            return Ok(TaskReturnData::RoleModel(role_database));
+        }
+
         let (discord_guild, _database_guild) =
-            get_guild(ctx.clone(), db.clone(), self.guild_id).await;
+            get_guild(discord, db.clone(), self.guild_id).await?;
+
+        // Or it might have created it, but stopped before saving it
+        let untracked_role = if run.is_retry() {
+            find_untracked_role(&discord_guild, db.clone(), &task.name).await?
+        } else {
+            None
+        };
 
-        // Create the role
-        let role_discord = discord_guild
-            .create_role(&ctx.http, EditRole::new().name(&task.name))
-            .await
-            .unwrap();
+        let role_discord = match untracked_role {
+            Some(role_discord) => {
+                log::info!("Using role {} from an earlier attempt", *role_discord.id);
+                role_discord
+            }
+            // Create the role
+            None => {
+                discord
+                    .create_role(self.guild_id, &task.name, task.color)
+                    .await?
+            }
+        };
 
         // TODO: Set the guild
 
         // Add the role to the database
-        let role_database = role::ActiveModel {
-            discord_id: Set(*DiscordId::from(role_discord.id.0) as i64),
-            fk_guild_id: Set(Some(*self.guild_id as i64)),
-            name: Set(role_discord.name),
-            ..Default::default()
-        };
+        save_resource(
+            &*db,
+            TrackedKind::Role,
+            self.guild_id,
+            role_discord.id,
+            &role_discord.name,
+            Some(run.idempotency_key.clone()),
+        )
+        .await?;
 
-        let role_database = role_database.insert(&*db).await.unwrap();
+        let role_database = role::Entity::find_by_id(*role_discord.id as i64)
+            .one(&*db)
+            .await?
+            .ok_or_else(|| DbErr::RecordNotFound(format!("Role {}", *role_discord.id)))?;
 
-        TaskResult::Completed(TaskReturnData::RoleModel(role_database))
+        Ok(TaskReturnData::RoleModel(role_database))
     }
 
     async fn handle_role_delete(
         &self,
         task: &DeleteRole,
-        ctx: Context,
+        discord: &dyn DiscordBackend,
         db: DBWrapper,
-    ) -> TaskResult {
+    ) -> Result<TaskReturnData, TaskError> {
         let (discord_guild, _database_guild) =
-            get_guild(ctx.clone(), db.clone(), self.guild_id).await;
+            get_guild(discord, db.clone(), self.guild_id).await?;
+
+        // Someone might have already deleted the role by hand
+        let in_guild = discord_guild
+            .roles
+            .iter()
+            .any(|discord_role| discord_role.id == task.role_id);
+        if in_guild {
+            discord.delete_role(self.guild_id, task.role_id).await?;
+        }
 
-        let _role_discord = discord_guild
-            .delete_role(&ctx.http, task.role_id)
-            .await
-            .unwrap();
+        let in_database = forget_resource(&db, TrackedKind::Role, task.role_id).await?;
 
-        // role_discord.delete(&ctx.http).await.unwrap();
+        if !in_guild && !in_database {
+            return Err(TaskError::permanent(format!(
+                "Role {} is neither in the guild nor the database",
+                *task.role_id
+            )));
+        }
 
-        TaskResult::Completed(TaskReturnData::None)
+        Ok(TaskReturnData::None)
     }
 
     async fn handle_add_role_to_user(
         &self,
         task: &AddRoleToUser,
-        ctx: Context,
+        discord: &dyn DiscordBackend,
         db: DBWrapper,
-    ) -> TaskResult {
+    ) -> Result<TaskReturnData, TaskError> {
         let (_discord_guild, _database_guild) =
-            get_guild(ctx.clone(), db.clone(), self.guild_id).await;
-
-        // Get the member
-        let mut member = ctx.cache.member(self.guild_id, task.user_id).unwrap();
+            get_guild(discord, db.clone(), self.guild_id).await?;
 
-        // Add the role
-        member.add_role(&ctx.http, task.role_id).await.unwrap();
+        // Add the role to the member
+        discord
+            .add_member_role(self.guild_id, task.user_id, task.role_id)
+            .await?;
 
-        TaskResult::Completed(TaskReturnData::None)
+        Ok(TaskReturnData::None)
     }
 
     async fn handle_remove_role_from_user(
         &self,
         task: &RemoveRoleFromUser,
-        ctx: Context,
+        discord: &dyn DiscordBackend,
         db: DBWrapper,
-    ) -> TaskResult {
+    ) -> Result<TaskReturnData, TaskError> {
         let (_discord_guild, _database_guild) =
-            get_guild(ctx.clone(), db.clone(), self.guild_id).await;
-
-        // Get the member
-        let mut member = ctx.cache.member(self.guild_id, task.user_id).unwrap();
+            get_guild(discord, db.clone(), self.guild_id).await?;
 
-        // Remove the role
-        member.remove_role(&ctx.http, task.role_id).await.unwrap();
+        // Remove the role from the member
+        discord
+            .remove_member_role(self.guild_id, task.user_id, task.role_id)
+            .await?;
 
-        TaskResult::Completed(TaskReturnData::None)
-    }
-}
-
-#[async_trait]
-impl TaskTest for RoleHandler {
-    async fn run_tests(_ctx: Context, _db: DBWrapper) {
-        log::info!("Testing categories");
-        // assert_not_error(test_create_channel(ctx, db).await);
+        Ok(TaskReturnData::None)
     }
 }
 
@@ -0,0 +1,166 @@
+use crate::{
+    discord::DiscordBackend,
+    task_runner::tasks::{
+        role::{AddRoleToUser, CreateRole, DeleteRole, RemoveRoleFromUser},
+        test_helpers::{
+            DatabaseConstruct, DatabaseStatus, DiscordConstruct, DiscordStatus, TestHelpers,
+        },
+        DiscordId,
+    },
+};
+
+#[tokio::test]
+async fn should_create_and_delete_role() {
+    let test_helper = TestHelpers::new().await;
+    let name = TestHelpers::generate_name();
+
+    let role_model = test_helper
+        .db
+        .run(
+            test_helper.guild_id,
+            CreateRole {
+                name: name.clone(),
+                color: 0x00ff00,
+            },
+        )
+        .await
+        .unwrap();
+
+    // Check that the role was created and saved
+    assert_eq!(
+        test_helper
+            .check_discord_status(DiscordConstruct::Role { name: name.clone() })
+            .await,
+        DiscordStatus::Exists
+    );
+    assert_eq!(
+        test_helper
+            .check_database_status(DatabaseConstruct::Role { name: name.clone() })
+            .await,
+        DatabaseStatus::Exists
+    );
+
+    // With the color it was asked for
+    let discord_role = test_helper
+        .discord
+        .guild(test_helper.guild_id)
+        .await
+        .unwrap()
+        .roles
+        .into_iter()
+        .find(|role| role.name == name)
+        .unwrap();
+    assert_eq!(discord_role.color, 0x00ff00);
+
+    test_helper
+        .db
+        .run(
+            test_helper.guild_id,
+            DeleteRole {
+                role_id: DiscordId::from(role_model.discord_id),
+            },
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(
+        test_helper
+            .check_discord_status(DiscordConstruct::Role { name })
+            .await,
+        DiscordStatus::DoesNotExist
+    );
+
+    test_helper.cleanup().await;
+}
+
+#[tokio::test]
+async fn should_add_and_remove_member_role() {
+    let test_helper = TestHelpers::new().await;
+    let user_id = test_helper.discord.add_member(test_helper.guild_id);
+
+    let role_model = test_helper
+        .db
+        .run(
+            test_helper.guild_id,
+            CreateRole {
+                name: TestHelpers::generate_name(),
+                color: 0x00ff00,
+            },
+        )
+        .await
+        .unwrap();
+    let role_id = DiscordId::from(role_model.discord_id);
+
+    test_helper
+        .db
+        .run(test_helper.guild_id, AddRoleToUser { user_id, role_id })
+        .await
+        .unwrap();
+
+    let member = test_helper
+        .discord
+        .member(test_helper.guild_id, user_id)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(member.roles, vec![role_id]);
+
+    test_helper
+        .db
+        .run(
+            test_helper.guild_id,
+            RemoveRoleFromUser { user_id, role_id },
+        )
+        .await
+        .unwrap();
+
+    let member = test_helper
+        .discord
+        .member(test_helper.guild_id, user_id)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(member.roles, vec![]);
+
+    test_helper.cleanup().await;
+}
+
+#[tokio::test]
+async fn should_fail_to_add_role_to_missing_member() {
+    let test_helper = TestHelpers::new().await;
+
+    let role_model = test_helper
+        .db
+        .run(
+            test_helper.guild_id,
+            CreateRole {
+                name: TestHelpers::generate_name(),
+                color: 0x00ff00,
+            },
+        )
+        .await
+        .unwrap();
+
+    let result = test_helper
+        .db
+        .run(
+            test_helper.guild_id,
+            AddRoleToUser {
+                user_id: DiscordId(1),
+                role_id: DiscordId::from(role_model.discord_id),
+            },
+        )
+        .await;
+
+    assert!(result.is_err());
+    assert_eq!(
+        test_helper
+            .discord
+            .member(test_helper.guild_id, DiscordId(1))
+            .await
+            .unwrap(),
+        None
+    );
+
+    test_helper.cleanup().await;
+}
@@ -1,11 +1,13 @@
 use async_trait::async_trait;
 
 use serde::{Deserialize, Serialize};
-use serenity::client::Context;
-use tracing::log;
 
-use super::{Task, TaskTest};
-use crate::db_wrapper::{DBWrapper, TaskResult};
+use super::{Task, TaskRun};
+use crate::{
+    db_wrapper::{DBWrapper, TaskResult, TaskReturnData},
+    discord::DiscordBackend,
+    task_runner::error::TaskError,
+};
 
 // pub mod tests;
 
@@ -35,11 +37,17 @@ pub enum DeleteThreadTasks {
 
 #[async_trait]
 impl Task for ThreadHandler {
-    async fn handle(&self, ctx: Context, db: DBWrapper) -> TaskResult {
+    async fn handle(
+        &self,
+        discord: &dyn DiscordBackend,
+        db: DBWrapper,
+        _run: TaskRun,
+    ) -> TaskResult {
         match &self.task {
-            ThreadTasks::Create(task) => self.handle_role_create(task, ctx, db).await,
-            ThreadTasks::Delete(task) => self.handle_role_delete(task, ctx, db).await,
+            ThreadTasks::Create(task) => self.handle_role_create(task, discord, db).await,
+            ThreadTasks::Delete(task) => self.handle_role_delete(task, discord, db).await,
         }
+        .into()
     }
 }
 
@@ -47,29 +55,19 @@ impl ThreadHandler {
     async fn handle_role_create(
         &self,
         _task: &CreateThreadTasks,
-        ctx: Context,
+        _discord: &dyn DiscordBackend,
         _db: DBWrapper,
-    ) -> TaskResult {
-        let _guild = ctx.cache.guild(self.guild_id).unwrap();
-
-        todo!()
+    ) -> Result<TaskReturnData, TaskError> {
+        Err(TaskError::permanent("Creating threads isn't supported yet"))
     }
 
     async fn handle_role_delete(
         &self,
         _task: &DeleteThreadTasks,
-        _ctx: Context,
+        _discord: &dyn DiscordBackend,
         _db: DBWrapper,
-    ) -> TaskResult {
-        todo!()
-    }
-}
-
-#[async_trait]
-impl TaskTest for ThreadHandler {
-    async fn run_tests(_ctx: Context, _db: DBWrapper) {
-        log::info!("Testing categories");
-        // assert_not_error(test_create_channel(ctx, db).await);
+    ) -> Result<TaskReturnData, TaskError> {
+        Err(TaskError::permanent("Deleting threads isn't supported yet"))
     }
 }
 